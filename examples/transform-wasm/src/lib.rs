@@ -0,0 +1,75 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Example module for `r7 run --transform-wasm`, which moves every request from `/v1/` to `/v2/` and marks it with
+//! an `x-replayed-by` header.
+//!
+//! Build it with `cargo build --release --target wasm32-unknown-unknown`, and pass
+//! `target/wasm32-unknown-unknown/release/transform_wasm_example.wasm` to `--transform-wasm`.
+
+use std::cell::RefCell;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// A request as `r7` passes it to `transform` and expects it back.
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+thread_local! {
+    /// The transformed request, kept until the next call so that `r7` can read it.
+    static OUTPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Allocate `len` bytes for `r7` to write a request to, which `transform` frees again.
+#[no_mangle]
+pub extern "C" fn alloc(len: i32) -> i32 {
+    let buffer = vec![0u8; len as usize].into_boxed_slice();
+    Box::into_raw(buffer) as *mut u8 as i32
+}
+
+/// Transform the request that `r7` wrote to the `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` and `len` have to be those of a buffer returned by `alloc`, which is freed.
+#[no_mangle]
+pub unsafe extern "C" fn transform(ptr: i32, len: i32) -> i64 {
+    let input = Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        ptr as *mut u8,
+        len as usize,
+    ));
+    // Panicking traps, which fails the replay with an error.
+    let mut request = serde_json::from_slice::<Request>(&input).expect("r7 passes valid requests");
+    request.url = request.url.replacen("/v1/", "/v2/", 1);
+    request
+        .headers
+        .push(("x-replayed-by".to_owned(), "r7".to_owned()));
+
+    OUTPUT.with(|output| {
+        let mut output = output.borrow_mut();
+        *output = serde_json::to_vec(&request).expect("requests can always be serialized");
+        ((output.as_ptr() as i64) << 32) | output.len() as i64
+    })
+}
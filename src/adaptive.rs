@@ -0,0 +1,193 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adapting the number of requests in flight to the latency of the target, with `--adaptive-concurrency`.
+//!
+//! The limit is adjusted with additive increase, multiplicative decrease: after every window of completed requests it
+//! grows by one as long as the p99 latency of the window stays close to the lowest p99 seen so far, and is cut to
+//! three quarters once it degrades beyond the threshold.
+
+use std::{
+    io::{
+        self,
+        Write,
+    },
+    sync::Mutex,
+    time::Duration,
+};
+
+use tokio::{
+    sync::Notify,
+    time::Instant,
+};
+
+use crate::summary::percentile;
+
+/// Minimum number of latencies a window is evaluated on, so the p99 of a small limit isn't just its slowest request.
+const MIN_WINDOW: usize = 20;
+/// Factor the limit is multiplied with once the latency degrades.
+const DECREASE_FACTOR: f64 = 0.75;
+/// Number of back-offs listed in the summary.
+const REPORTED_DECREASES: usize = 10;
+
+/// Limit on the number of requests in flight, adjusted to the latency of the completed requests.
+#[derive(Debug)]
+pub(crate) struct AdaptiveConcurrency {
+    /// Fraction the p99 latency of a window may exceed the baseline by before the limit is decreased.
+    threshold: f64,
+    started: Instant,
+    state: Mutex<State>,
+    released: Notify,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    in_flight: usize,
+    /// Latencies of the current window, in seconds.
+    window: Vec<f64>,
+    /// Lowest p99 latency of a window so far, in seconds.
+    baseline: Option<f64>,
+    initial: usize,
+    lowest: usize,
+    highest: usize,
+    increases: usize,
+    /// Time into the run and the limit before and after every decrease.
+    decreases: Vec<(Duration, usize, usize)>,
+}
+
+impl AdaptiveConcurrency {
+    /// Create a limit starting at the given number of requests in flight, that is decreased once the p99 latency
+    /// exceeds its baseline by more than `threshold_percent`.
+    pub(crate) fn new(initial: usize, threshold_percent: f64) -> Self {
+        Self {
+            threshold: threshold_percent / 100.,
+            started: Instant::now(),
+            state: Mutex::new(State {
+                limit: initial,
+                in_flight: 0,
+                window: Vec::new(),
+                baseline: None,
+                initial,
+                lowest: initial,
+                highest: initial,
+                increases: 0,
+                decreases: Vec::new(),
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Wait until fewer requests than the current limit are in flight.
+    pub(crate) async fn acquire(&self) -> Permit<'_> {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut state = self.state.lock().expect("lock shouldn't be poisoned");
+                if state.in_flight < state.limit {
+                    state.in_flight += 1;
+                    return Permit(self);
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Write the trajectory of the limit over the run.
+    pub(crate) fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let state = self.state.lock().expect("lock shouldn't be poisoned");
+        writeln!(
+            writer,
+            "Adaptive concurrency: started at {}, ended at {}, between {} and {}, {} increases, {} decreases",
+            state.initial,
+            state.limit,
+            state.lowest,
+            state.highest,
+            state.increases,
+            state.decreases.len()
+        )?;
+        for (elapsed, before, after) in state.decreases.iter().take(REPORTED_DECREASES) {
+            writeln!(
+                writer,
+                "  {:>8.1} s: {} -> {}",
+                elapsed.as_secs_f64(),
+                before,
+                after
+            )?;
+        }
+        if state.decreases.len() > REPORTED_DECREASES {
+            writeln!(
+                writer,
+                "  ... and {} more decreases",
+                state.decreases.len() - REPORTED_DECREASES
+            )?;
+        }
+        Ok(())
+    }
+
+    fn record(&self, latency: hifitime::Duration) {
+        let mut state = self.state.lock().expect("lock shouldn't be poisoned");
+        state.window.push(latency.to_seconds());
+        if state.window.len() < state.limit.max(MIN_WINDOW) {
+            return;
+        }
+
+        let mut window = std::mem::take(&mut state.window);
+        window.sort_by(f64::total_cmp);
+        let p99 = percentile(&window, 99.).expect("window shouldn't be empty");
+        let baseline = state.baseline.map_or(p99, |baseline| baseline.min(p99));
+        state.baseline = Some(baseline);
+        if p99 > baseline * (1. + self.threshold) {
+            let before = state.limit;
+            state.limit = ((before as f64 * DECREASE_FACTOR) as usize).max(1);
+            state.lowest = state.lowest.min(state.limit);
+            let after = state.limit;
+            state
+                .decreases
+                .push((self.started.elapsed(), before, after));
+        } else {
+            state.limit += 1;
+            state.highest = state.highest.max(state.limit);
+            state.increases += 1;
+            drop(state);
+            self.released.notify_one();
+        }
+    }
+}
+
+/// Slot of a request in flight, released when dropped.
+#[derive(Debug)]
+pub(crate) struct Permit<'a>(&'a AdaptiveConcurrency);
+
+impl Permit<'_> {
+    /// Feed back the latency of the completed request.
+    pub(crate) fn record(&self, latency: hifitime::Duration) {
+        self.0.record(latency);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0
+            .state
+            .lock()
+            .expect("lock shouldn't be poisoned")
+            .in_flight -= 1;
+        self.0.released.notify_one();
+    }
+}
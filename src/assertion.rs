@@ -0,0 +1,385 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Assertions on the summary of a run, to use `r7` as a gate in CI.
+//!
+//! An assertion compares a metric of the overall statistics against a threshold, e.g. `p99<500ms`,
+//! `error_rate<1%` or `mean_change<=10%`. Supported metrics are:
+//!
+//! * `p<N>` and `mean_latency`: the percentile or mean of the latencies of the successful requests, against a
+//!   duration in `us`, `ms`, `s` or `min`,
+//! * `error_rate`: the percentage of requests that failed,
+//! * `mean_change`: the mean change of the latencies compared to the original ones, in percent,
+//! * `requests` and `errors`: the number of requests and failed requests.
+
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::Duration;
+
+use crate::summary::Summary;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Percentile(f64),
+    MeanLatency,
+    ErrorRate,
+    MeanChange,
+    Requests,
+    Errors,
+}
+
+impl Metric {
+    fn value(self, summary: &Summary) -> Option<f64> {
+        match self {
+            Metric::Percentile(percentile) => summary.latency_percentile(percentile),
+            Metric::MeanLatency => summary.mean_latency(),
+            Metric::ErrorRate => Some(summary.error_rate()),
+            Metric::MeanChange => summary.mean_change(),
+            Metric::Requests => Some(summary.requests() as f64),
+            Metric::Errors => Some(summary.errors() as f64),
+        }
+    }
+
+    fn format(self, value: f64) -> String {
+        match self {
+            Metric::Percentile(_) | Metric::MeanLatency => {
+                Duration::from_seconds(value).approx().to_string()
+            }
+            Metric::ErrorRate | Metric::MeanChange => format!("{:.2}%", value),
+            Metric::Requests | Metric::Errors => value.to_string(),
+        }
+    }
+
+    /// Parse a threshold for this metric, durations are converted to seconds.
+    fn parse_threshold(self, s: &str) -> Result<f64> {
+        let s = s.trim();
+        match self {
+            Metric::Percentile(_) | Metric::MeanLatency => {
+                let unit_start = s
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .context("expected a duration with a unit, e.g. `500ms`")?;
+                let (number, unit) = s.split_at(unit_start);
+                let number = number
+                    .parse::<f64>()
+                    .with_context(|| format!("invalid duration `{}`", s))?;
+                let seconds_per_unit = match unit.trim() {
+                    "us" | "µs" => 1e-6,
+                    "ms" => 1e-3,
+                    "s" => 1.,
+                    "min" => 60.,
+                    unit => {
+                        anyhow::bail!("unknown unit `{}`, expected `us`, `ms`, `s` or `min`", unit)
+                    }
+                };
+                Ok(number * seconds_per_unit)
+            }
+            Metric::ErrorRate | Metric::MeanChange => s
+                .strip_suffix('%')
+                .unwrap_or(s)
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid percentage `{}`", s)),
+            Metric::Requests | Metric::Errors => {
+                s.parse().with_context(|| format!("invalid number `{}`", s))
+            }
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "mean_latency" => Metric::MeanLatency,
+            "error_rate" => Metric::ErrorRate,
+            "mean_change" => Metric::MeanChange,
+            "requests" => Metric::Requests,
+            "errors" => Metric::Errors,
+            _ => match s.strip_prefix('p').map(str::parse::<f64>) {
+                Some(Ok(percentile)) if (0. ..=100.).contains(&percentile) => {
+                    Metric::Percentile(percentile)
+                }
+                _ => anyhow::bail!(
+                    "unknown metric `{}`, expected `p<N>`, `mean_latency`, `error_rate`, `mean_change`, \
+                     `requests` or `errors`",
+                    s
+                ),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, actual: f64, expected: f64) -> bool {
+        match self {
+            Comparison::Lt => actual < expected,
+            Comparison::Le => actual <= expected,
+            Comparison::Gt => actual > expected,
+            Comparison::Ge => actual >= expected,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::Lt => f.write_str("<"),
+            Comparison::Le => f.write_str("<="),
+            Comparison::Gt => f.write_str(">"),
+            Comparison::Ge => f.write_str(">="),
+        }
+    }
+}
+
+/// Assertion on the summary of a run, see the module documentation for its syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Assertion {
+    /// The assertion as it was given.
+    source: String,
+    metric: Metric,
+    comparison: Comparison,
+    threshold: f64,
+}
+
+impl FromStr for Assertion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(operator_start) = s.find(['<', '>']) else {
+            anyhow::bail!("expected `<metric><operator><threshold>`, e.g. `p99<500ms`");
+        };
+        let (metric, rest) = s.split_at(operator_start);
+        let (comparison, threshold) = if let Some(threshold) = rest.strip_prefix("<=") {
+            (Comparison::Le, threshold)
+        } else if let Some(threshold) = rest.strip_prefix(">=") {
+            (Comparison::Ge, threshold)
+        } else if let Some(threshold) = rest.strip_prefix('<') {
+            (Comparison::Lt, threshold)
+        } else {
+            (Comparison::Gt, &rest[1..])
+        };
+        let metric = metric.trim().parse::<Metric>()?;
+
+        Ok(Self {
+            source: s.to_owned(),
+            metric,
+            comparison,
+            threshold: metric.parse_threshold(threshold)?,
+        })
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Outcome of evaluating an assertion against a summary.
+#[derive(Debug)]
+pub(crate) struct Outcome<'a> {
+    pub(crate) assertion: &'a Assertion,
+    /// Value of the metric, if the summary has one, e.g. no percentiles without any successful requests.
+    actual: Option<f64>,
+    pub(crate) passed: bool,
+}
+
+impl Assertion {
+    pub(crate) fn evaluate(&self, summary: &Summary) -> Outcome<'_> {
+        let actual = self.metric.value(summary);
+        Outcome {
+            assertion: self,
+            actual,
+            passed: actual.is_some_and(|actual| self.comparison.holds(actual, self.threshold)),
+        }
+    }
+}
+
+impl fmt::Display for Outcome<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metric = self.assertion.metric;
+        write!(
+            f,
+            "{} {}: actual {}, expected {} {}",
+            if self.passed { "passed" } else { "FAILED" },
+            self.assertion,
+            self.actual
+                .map_or_else(|| "n/a".to_owned(), |actual| metric.format(actual)),
+            self.assertion.comparison,
+            metric.format(self.assertion.threshold)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use reqwest::{
+        StatusCode,
+        Url,
+    };
+
+    use super::*;
+
+    fn assertion(s: &str) -> Assertion {
+        s.parse().unwrap()
+    }
+
+    /// Summary of 100 requests, taking 1 ms to 100 ms, of which the ones taking 96 ms and more failed.
+    fn summary() -> Summary {
+        let url = Url::parse("https://www.example.com/").unwrap();
+        let mut summary = Summary::new(None, None, true);
+        for milliseconds in 1..=100 {
+            let status = if milliseconds <= 95 {
+                StatusCode::OK
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            summary.record(
+                &url,
+                &BTreeMap::new(),
+                Some(status),
+                Some(Duration::from_milliseconds(milliseconds as f64)),
+                1,
+            );
+        }
+        summary.record_change(10., 1);
+        summary.record_change(20., 1);
+        summary
+    }
+
+    #[test]
+    fn parses_metrics_and_operators() {
+        for (s, metric, comparison, threshold) in [
+            ("p99<500ms", Metric::Percentile(99.), Comparison::Lt, 0.5),
+            ("p99.9 <= 2s", Metric::Percentile(99.9), Comparison::Le, 2.),
+            ("p50>100us", Metric::Percentile(50.), Comparison::Gt, 1e-4),
+            (
+                "mean_latency>=1min",
+                Metric::MeanLatency,
+                Comparison::Ge,
+                60.,
+            ),
+            (
+                "mean_latency<250µs",
+                Metric::MeanLatency,
+                Comparison::Lt,
+                2.5e-4,
+            ),
+            ("error_rate<1%", Metric::ErrorRate, Comparison::Lt, 1.),
+            ("error_rate<=0.5", Metric::ErrorRate, Comparison::Le, 0.5),
+            ("mean_change<=10%", Metric::MeanChange, Comparison::Le, 10.),
+            ("mean_change>-5 %", Metric::MeanChange, Comparison::Gt, -5.),
+            ("requests>=1000", Metric::Requests, Comparison::Ge, 1000.),
+            ("errors<1", Metric::Errors, Comparison::Lt, 1.),
+        ] {
+            let assertion = assertion(s);
+            assert_eq!(assertion.metric, metric, "{s}");
+            assert_eq!(assertion.comparison, comparison, "{s}");
+            assert!((assertion.threshold - threshold).abs() < 1e-12, "{s}");
+            assert_eq!(assertion.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_assertions() {
+        for (s, error) in [
+            ("p99=500ms", "expected `<metric><operator><threshold>`, e.g. `p99<500ms`"),
+            (
+                "latency<500ms",
+                "unknown metric `latency`, expected `p<N>`, `mean_latency`, `error_rate`, `mean_change`, `requests` \
+                 or `errors`",
+            ),
+            (
+                "p101<500ms",
+                "unknown metric `p101`, expected `p<N>`, `mean_latency`, `error_rate`, `mean_change`, `requests` \
+                 or `errors`",
+            ),
+            ("p99<500", "expected a duration with a unit, e.g. `500ms`"),
+            ("p99<500h", "unknown unit `h`, expected `us`, `ms`, `s` or `min`"),
+            ("p99<1.2.3ms", "invalid duration `1.2.3ms`"),
+            ("error_rate<one%", "invalid percentage `one%`"),
+            ("requests>many", "invalid number `many`"),
+        ] {
+            assert_eq!(s.parse::<Assertion>().unwrap_err().to_string(), error, "{s}");
+        }
+    }
+
+    #[test]
+    fn evaluates_against_summary() {
+        let summary = summary();
+        for (s, passed) in [
+            ("p50<=48ms", true),
+            ("p50<40ms", false),
+            ("p99<100ms", true),
+            ("p99>94ms", true),
+            ("mean_latency<50ms", true),
+            ("mean_latency>=50ms", false),
+            ("error_rate<=5%", true),
+            ("error_rate<5%", false),
+            ("mean_change<=15%", true),
+            ("mean_change<10%", false),
+            ("requests>=100", true),
+            ("errors<5", false),
+        ] {
+            assert_eq!(assertion(s).evaluate(&summary).passed, passed, "{s}");
+        }
+    }
+
+    #[test]
+    fn missing_metrics_fail() {
+        let summary = Summary::new(None, None, true);
+        let mean_change = assertion("mean_change<10%");
+        let outcome = mean_change.evaluate(&summary);
+        assert!(!outcome.passed);
+        assert_eq!(
+            outcome.to_string(),
+            "FAILED mean_change<10%: actual n/a, expected < 10.00%"
+        );
+    }
+
+    #[test]
+    fn formats_outcomes() {
+        let summary = summary();
+        let error_rate = assertion("error_rate<1%");
+        assert_eq!(
+            error_rate.evaluate(&summary).to_string(),
+            "FAILED error_rate<1%: actual 5.00%, expected < 1.00%"
+        );
+        let requests = assertion("requests>=50");
+        assert_eq!(
+            requests.evaluate(&summary).to_string(),
+            "passed requests>=50: actual 100, expected >= 50"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Latencies of a previous run, to compare a run against with `--baseline` instead of the original latencies, and
+//! its bodies, to compare the bodies against with `--compare-bodies`.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{
+        BufRead,
+        BufReader,
+    },
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// The fields of a response in the output of a previous run that are used as the baseline.
+#[derive(Debug, Deserialize)]
+struct BaselineResponse {
+    url: String,
+    /// Missing for requests that failed without a response, which are skipped.
+    required_time: Option<f64>,
+    /// Body of the response, if the previous run captured it.
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Mean latency of the responses of a previous run, in seconds, by path and query, and the body of the first
+/// response with one if bodies are kept.
+///
+/// URLs are matched by their path and query only, so that runs against different hosts can be compared.
+#[derive(Debug)]
+pub(crate) struct Baseline {
+    latencies: HashMap<String, f64>,
+    bodies: HashMap<String, String>,
+}
+
+impl Baseline {
+    /// Read the output of a previous run, as JSON lines, keeping the bodies of its responses with `keep_bodies`.
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P, keep_bodies: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open baseline file {}", path.display()))?;
+        let mut latencies: HashMap<String, (f64, usize)> = HashMap::new();
+        let mut bodies = HashMap::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line =
+                line.with_context(|| format!("Failed to read baseline file {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = serde_json::from_str::<BaselineResponse>(&line).with_context(|| {
+                format!(
+                    "Failed to parse line {} of baseline file {}",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+            let Some(required_time) = response.required_time else {
+                continue;
+            };
+            let url = Url::parse(&response.url).with_context(|| {
+                format!(
+                    "Invalid URL on line {} of baseline file {}",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+            let key = key(&url);
+            if let Some(body) = response.body.filter(|_| keep_bodies) {
+                bodies.entry(key.clone()).or_insert(body);
+            }
+            let (sum, count) = latencies.entry(key).or_default();
+            *sum += required_time;
+            *count += 1;
+        }
+
+        Ok(Self {
+            latencies: latencies
+                .into_iter()
+                .map(|(key, (sum, count))| (key, sum / count as f64))
+                .collect(),
+            bodies,
+        })
+    }
+
+    /// Mean latency of the responses of the baseline for the path and query of `url`, in seconds.
+    pub(crate) fn latency(&self, url: &Url) -> Option<f64> {
+        self.latencies.get(&key(url)).copied()
+    }
+
+    /// Body of the first response of the baseline for the path and query of `url` that has one.
+    pub(crate) fn body(&self, url: &Url) -> Option<&str> {
+        self.bodies.get(&key(url)).map(String::as_str)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.latencies.len()
+    }
+}
+
+fn key(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    }
+}
@@ -0,0 +1,487 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    hash::{
+        BuildHasher,
+        RandomState,
+    },
+    io::Read,
+    path::Path,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use clap::ValueEnum;
+use flate2::read::{
+    MultiGzDecoder,
+    ZlibDecoder,
+};
+use reqwest::{
+    header::CONTENT_TYPE,
+    Body,
+    RequestBuilder,
+};
+use serde_json::Value;
+use tokio::sync::Notify;
+
+/// Encodings advertised through `Accept-Encoding` when capturing bodies, unless overridden by the user.
+pub(crate) const DEFAULT_ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// Open a file to stream as the body of a request, without reading it into memory, alongside its size.
+pub(crate) async fn file_body(path: &Path) -> Result<(Body, u64)> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open body file {}", path.display()))?;
+    let size = file.metadata().await?.len();
+    Ok((Body::from(file), size))
+}
+
+/// Factor by which a decoded body may be larger than the body that was received, so that a body that decompresses
+/// to gigabytes doesn't exhaust the memory.
+const MAX_DECODED_RATIO: usize = 100;
+/// Size up to which a decoded body is accepted regardless of its ratio to the body that was received.
+const MIN_DECODED_LIMIT: usize = 1024 * 1024;
+
+/// Decode a response body according to the value of its `Content-Encoding` header.
+///
+/// Multiple encodings are undone in reverse order of application, as described in RFC 9110, section 8.4. Decoding
+/// fails if the body would be more than `MAX_DECODED_RATIO` times larger than `raw`, and larger than
+/// `MIN_DECODED_LIMIT`.
+pub(crate) fn decode(content_encoding: Option<&str>, raw: &[u8]) -> Result<Vec<u8>> {
+    let mut body = raw.to_vec();
+    let Some(content_encoding) = content_encoding else {
+        return Ok(body);
+    };
+    let limit = raw
+        .len()
+        .saturating_mul(MAX_DECODED_RATIO)
+        .max(MIN_DECODED_LIMIT);
+
+    for encoding in content_encoding.rsplit(',').map(str::trim) {
+        let decoder: Box<dyn Read + '_> = match encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => Box::new(MultiGzDecoder::new(&body[..])),
+            "deflate" => Box::new(ZlibDecoder::new(&body[..])),
+            "br" => Box::new(brotli_decompressor::Decompressor::new(&body[..], 4096)),
+            other => anyhow::bail!("Unsupported content-encoding: {}", other),
+        };
+        let mut decoded = Vec::new();
+        // One byte more than the limit is read to tell a body at the limit from one beyond it.
+        decoder
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decoded)
+            .with_context(|| format!("Failed to decode the {} content-encoding", encoding))?;
+        if decoded.len() > limit {
+            anyhow::bail!(
+                "Decoded body exceeds {} bytes, the limit for a body of {} bytes",
+                limit,
+                raw.len()
+            );
+        }
+        body = decoded;
+    }
+
+    Ok(body)
+}
+
+/// Whether a body of the given `Content-Type` is text, e.g. `text/html` or `application/problem+json`, rather than
+/// binary. Bodies without a `Content-Type` are taken to be text.
+pub(crate) fn is_text(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-www-form-urlencoded"
+        )
+}
+
+/// How the request body is reconstructed from the body field of a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum BodyFormat {
+    /// The field is sent as it is, without setting a `Content-Type`.
+    Raw,
+    /// The field contains form fields, which are sent as `application/x-www-form-urlencoded`.
+    Form,
+    /// The field contains form fields, which are sent as `multipart/form-data`.
+    Multipart,
+}
+
+impl BodyFormat {
+    /// Add the body, reconstructed from the value of the body field, to the request.
+    pub(crate) fn apply(self, builder: RequestBuilder, value: &Value) -> Result<RequestBuilder> {
+        match self {
+            BodyFormat::Raw => Ok(builder.body(match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })),
+            BodyFormat::Form => Ok(builder.form(&form_fields(value)?)),
+            BodyFormat::Multipart => {
+                let fields = form_fields(value)?;
+                let boundary = multipart_boundary(&fields);
+                let mut body = String::new();
+                for (name, value) in &fields {
+                    body.push_str(&format!(
+                        "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                        boundary,
+                        name.replace('"', "%22"),
+                        value
+                    ));
+                }
+                body.push_str(&format!("--{}--\r\n", boundary));
+                Ok(builder
+                    .header(
+                        CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", boundary),
+                    )
+                    .body(body))
+            }
+        }
+    }
+}
+
+/// Form fields of a body field, which has to be a JSON object, or a string containing one, e.g. in CSV files.
+///
+/// The values of the object have to be strings, numbers or booleans, or arrays of them for fields that are repeated.
+fn form_fields(value: &Value) -> Result<Vec<(String, String)>> {
+    let parsed;
+    let value = match value {
+        Value::String(value) => {
+            parsed = serde_json::from_str::<Value>(value).map_err(|err| {
+                anyhow::anyhow!("Body field is not a JSON object of form fields: {}", err)
+            })?;
+            &parsed
+        }
+        value => value,
+    };
+    let Value::Object(object) = value else {
+        anyhow::bail!("Body field is not a JSON object of form fields: {}", value);
+    };
+
+    let scalar = |name: &str, value: &Value| match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+        value => anyhow::bail!("Form field `{}` has an unsupported value: {}", name, value),
+    };
+    let mut fields = Vec::new();
+    for (name, value) in object {
+        match value {
+            Value::Array(values) => {
+                for value in values {
+                    fields.push((name.clone(), scalar(name, value)?));
+                }
+            }
+            value => fields.push((name.clone(), scalar(name, value)?)),
+        }
+    }
+
+    Ok(fields)
+}
+
+/// A random multipart boundary that doesn't occur in any of the fields.
+fn multipart_boundary(fields: &[(String, String)]) -> String {
+    loop {
+        let boundary = format!("r7-boundary-{:016x}", RandomState::new().hash_one(0));
+        if !fields
+            .iter()
+            .any(|(name, value)| name.contains(&boundary) || value.contains(&boundary))
+        {
+            return boundary;
+        }
+    }
+}
+
+/// Keeps track of the captured body bytes that are held in memory and haven't been written to the output yet.
+#[derive(Debug)]
+pub(crate) struct BufferedBytes {
+    limit: Option<usize>,
+    current: AtomicUsize,
+    released: Notify,
+}
+
+impl BufferedBytes {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            current: AtomicUsize::new(0),
+            released: Notify::new(),
+        }
+    }
+
+    /// Wait until the buffered bytes no longer exceed the limit, if there is one.
+    pub(crate) async fn wait_for_capacity(&self) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        loop {
+            // The future has to be created before checking, otherwise a release in between would be missed.
+            let released = self.released.notified();
+            if self.current.load(Ordering::Acquire) <= limit {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    pub(crate) fn add(&self, bytes: usize) {
+        self.current.fetch_add(bytes, Ordering::AcqRel);
+    }
+
+    pub(crate) fn release(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::AcqRel);
+        self.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{
+        write::{
+            GzEncoder,
+            ZlibEncoder,
+        },
+        Compression,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflate(body: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn br(body: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        brotli::BrotliCompress(&mut &body[..], &mut encoded, &Default::default()).unwrap();
+        encoded
+    }
+
+    const BODY: &[u8] = b"{\"items\": [1, 2, 3], \"name\": \"r7\"}";
+
+    #[test]
+    fn decode_round_trips() {
+        assert_eq!(decode(None, BODY).unwrap(), BODY);
+        for encoding in ["identity", ""] {
+            assert_eq!(decode(Some(encoding), BODY).unwrap(), BODY);
+        }
+        for encoding in ["gzip", "x-gzip", "GZIP"] {
+            assert_eq!(decode(Some(encoding), &gzip(BODY)).unwrap(), BODY);
+        }
+        assert_eq!(decode(Some("deflate"), &deflate(BODY)).unwrap(), BODY);
+        assert_eq!(decode(Some("br"), &br(BODY)).unwrap(), BODY);
+    }
+
+    #[test]
+    fn decode_stacked_encodings() {
+        // `gzip, br` was gzipped first, so brotli is undone first.
+        assert_eq!(decode(Some("gzip, br"), &br(&gzip(BODY))).unwrap(), BODY);
+        assert_eq!(
+            decode(Some("deflate,identity, gzip"), &gzip(&deflate(BODY))).unwrap(),
+            BODY
+        );
+        assert!(decode(Some("br, gzip"), &br(&gzip(BODY))).is_err());
+    }
+
+    #[test]
+    fn decode_concatenated_gzip_members() {
+        let mut raw = gzip(b"first ");
+        raw.extend(gzip(b"second"));
+        assert_eq!(decode(Some("gzip"), &raw).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn decode_rejects_unknown_encodings() {
+        assert_eq!(
+            decode(Some("zstd"), BODY).unwrap_err().to_string(),
+            "Unsupported content-encoding: zstd"
+        );
+        assert_eq!(
+            decode(Some("gzip, compress"), BODY)
+                .unwrap_err()
+                .to_string(),
+            "Unsupported content-encoding: compress"
+        );
+        assert_eq!(
+            decode(Some("gzip"), BODY).unwrap_err().to_string(),
+            "Failed to decode the gzip content-encoding"
+        );
+    }
+
+    #[test]
+    fn decode_limits_the_decoded_size() {
+        let at_limit = vec![0; MIN_DECODED_LIMIT];
+        assert_eq!(decode(Some("gzip"), &gzip(&at_limit)).unwrap(), at_limit);
+
+        let bomb = gzip(&vec![0; MIN_DECODED_LIMIT + 1]);
+        assert!(bomb.len() * MAX_DECODED_RATIO < MIN_DECODED_LIMIT);
+        assert_eq!(
+            decode(Some("gzip"), &bomb).unwrap_err().to_string(),
+            format!(
+                "Decoded body exceeds {} bytes, the limit for a body of {} bytes",
+                MIN_DECODED_LIMIT,
+                bomb.len()
+            )
+        );
+        // Stacking encodings doesn't get around the limit, which is relative to the body that was received.
+        let stacked = br(&gzip(&vec![0; 4 * MIN_DECODED_LIMIT]));
+        assert!(decode(Some("gzip, br"), &stacked).is_err());
+    }
+
+    #[test]
+    fn text_content_types() {
+        for content_type in [
+            None,
+            Some("text/html; charset=utf-8"),
+            Some("application/json"),
+            Some("Application/Problem+JSON"),
+            Some("image/svg+xml"),
+            Some("application/x-www-form-urlencoded"),
+        ] {
+            assert!(is_text(content_type), "{content_type:?}");
+        }
+        for content_type in [
+            "image/png",
+            "application/octet-stream",
+            "application/grpc",
+            "",
+        ] {
+            assert!(!is_text(Some(content_type)), "{content_type}");
+        }
+    }
+
+    fn apply(format: BodyFormat, value: &Value) -> Result<reqwest::Request> {
+        let builder = reqwest::Client::new().post("http://localhost/");
+        Ok(format.apply(builder, value)?.build()?)
+    }
+
+    fn body(request: &reqwest::Request) -> &str {
+        std::str::from_utf8(request.body().unwrap().as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn raw_bodies() {
+        let request = apply(BodyFormat::Raw, &json!("a=1&b=2")).unwrap();
+        assert_eq!(body(&request), "a=1&b=2");
+        assert_eq!(request.headers().get(CONTENT_TYPE), None);
+        let request = apply(BodyFormat::Raw, &json!({"a": 1})).unwrap();
+        assert_eq!(body(&request), "{\"a\":1}");
+    }
+
+    #[test]
+    fn form_bodies() {
+        let fields = json!({"name": "r 7", "count": 2, "tag": ["a", "b&c"], "ok": true});
+        let request = apply(BodyFormat::Form, &fields).unwrap();
+        assert_eq!(
+            request.headers()[CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(body(&request), "count=2&name=r+7&ok=true&tag=a&tag=b%26c");
+        // CSV files hold the fields as a string.
+        let request = apply(BodyFormat::Form, &json!(fields.to_string())).unwrap();
+        assert_eq!(body(&request), "count=2&name=r+7&ok=true&tag=a&tag=b%26c");
+    }
+
+    #[test]
+    fn multipart_bodies() {
+        let request = apply(
+            BodyFormat::Multipart,
+            &json!({"name": "r7", "tag": ["a", "b"], "quoted\"name": 1}),
+        )
+        .unwrap();
+        let content_type = request.headers()[CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap();
+        let part = |name: &str, value: &str| {
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+        };
+        assert_eq!(
+            body(&request),
+            format!(
+                "{}{}{}{}--{boundary}--\r\n",
+                part("name", "r7"),
+                part("quoted%22name", "1"),
+                part("tag", "a"),
+                part("tag", "b")
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_form_fields() {
+        for (value, error) in [
+            (
+                json!([1, 2]),
+                "Body field is not a JSON object of form fields: [1,2]",
+            ),
+            (
+                json!("a=1"),
+                "Body field is not a JSON object of form fields: expected value at line 1 column 1",
+            ),
+            (
+                json!({"a": {"b": 1}}),
+                "Form field `a` has an unsupported value: {\"b\":1}",
+            ),
+            (
+                json!({"a": [[1]]}),
+                "Form field `a` has an unsupported value: [1]",
+            ),
+            (
+                json!({"a": null}),
+                "Form field `a` has an unsupported value: null",
+            ),
+        ] {
+            for format in [BodyFormat::Form, BodyFormat::Multipart] {
+                assert_eq!(
+                    apply(format, &value).unwrap_err().to_string(),
+                    error,
+                    "{value}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Comparing response bodies by their content rather than byte for byte, with `--compare-bodies`.
+//!
+//! JSON bodies are compared value by value, skipping the fields of `--ignore-json-path` that are expected to differ,
+//! like timestamps or request IDs. Other bodies are compared as bytes.
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
+    io::{
+        self,
+        Write,
+    },
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Number of differing fields listed for a single response.
+const MAX_FIELDS: usize = 20;
+/// Number of URLs listed in the summary, the least similar first.
+const REPORTED_URLS: usize = 10;
+/// Number of fields listed for every URL in the summary, the most frequently differing first.
+const REPORTED_FIELDS: usize = 5;
+
+/// How similar two bodies are.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BodyDiff {
+    /// Share of the values of JSON bodies, or of the bytes of other bodies, that are the same, from 0 to 1.
+    pub(crate) similarity: f64,
+    /// Dotted paths of the first fields of JSON bodies that differ, with array elements by their index, e.g.
+    /// `items.0.price`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) fields: Vec<String>,
+}
+
+/// Compares bodies, ignoring the fields of `--ignore-json-path`.
+#[derive(Debug)]
+pub(crate) struct BodyComparer {
+    /// Paths of the ignored fields, split into their keys, where `*` matches any key or index.
+    ignored: Vec<Vec<String>>,
+}
+
+impl BodyComparer {
+    pub(crate) fn new(ignored: &[String]) -> Self {
+        Self {
+            ignored: ignored
+                .iter()
+                .map(|path| path.split('.').map(ToOwned::to_owned).collect())
+                .collect(),
+        }
+    }
+
+    /// Compare the `actual` body to the `expected` one, as JSON if both are JSON, and as bytes otherwise.
+    pub(crate) fn compare(&self, expected: &str, actual: &str) -> BodyDiff {
+        match (
+            serde_json::from_str::<Value>(expected),
+            serde_json::from_str::<Value>(actual),
+        ) {
+            (Ok(expected), Ok(actual)) => {
+                let mut counts = Counts::default();
+                self.diff(&mut Vec::new(), Some(&expected), Some(&actual), &mut counts);
+                BodyDiff {
+                    similarity: match counts.total {
+                        0 => 1.,
+                        total => counts.matched as f64 / total as f64,
+                    },
+                    fields: counts.fields,
+                }
+            }
+            _ => BodyDiff {
+                similarity: byte_similarity(expected.as_bytes(), actual.as_bytes()),
+                fields: Vec::new(),
+            },
+        }
+    }
+
+    fn is_ignored(&self, path: &[String]) -> bool {
+        self.ignored.iter().any(|ignored| {
+            ignored.len() == path.len()
+                && ignored
+                    .iter()
+                    .zip(path)
+                    .all(|(ignored, key)| ignored == "*" || ignored == key)
+        })
+    }
+
+    fn diff(
+        &self,
+        path: &mut Vec<String>,
+        expected: Option<&Value>,
+        actual: Option<&Value>,
+        counts: &mut Counts,
+    ) {
+        if self.is_ignored(path) {
+            return;
+        }
+        match (expected, actual) {
+            (Some(Value::Object(expected)), Some(Value::Object(actual))) => {
+                let keys = expected
+                    .keys()
+                    .chain(actual.keys())
+                    .collect::<BTreeSet<_>>();
+                for key in keys {
+                    path.push(key.clone());
+                    self.diff(path, expected.get(key), actual.get(key), counts);
+                    path.pop();
+                }
+            }
+            (Some(Value::Array(expected)), Some(Value::Array(actual))) => {
+                for index in 0..expected.len().max(actual.len()) {
+                    path.push(index.to_string());
+                    self.diff(path, expected.get(index), actual.get(index), counts);
+                    path.pop();
+                }
+            }
+            (expected, actual) => {
+                // A value that is missing or of another type on one side differs in all of its values.
+                let values = leaves(expected).max(leaves(actual));
+                counts.total += values;
+                if expected == actual {
+                    counts.matched += values;
+                } else if counts.fields.len() < MAX_FIELDS {
+                    counts.fields.push(path.join("."));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    matched: usize,
+    fields: Vec<String>,
+}
+
+/// Number of scalar values in `value`, counting empty objects and arrays as one.
+fn leaves(value: Option<&Value>) -> usize {
+    match value {
+        None => 0,
+        Some(Value::Object(object)) => object
+            .values()
+            .map(|value| leaves(Some(value)))
+            .sum::<usize>()
+            .max(1),
+        Some(Value::Array(array)) => array
+            .iter()
+            .map(|value| leaves(Some(value)))
+            .sum::<usize>()
+            .max(1),
+        Some(_) => 1,
+    }
+}
+
+/// Share of the bytes of the longer body that are the same in both, at their start and their end.
+fn byte_similarity(expected: &[u8], actual: &[u8]) -> f64 {
+    let longest = expected.len().max(actual.len());
+    if longest == 0 {
+        return 1.;
+    }
+    let prefix = expected
+        .iter()
+        .zip(actual)
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    (prefix + suffix) as f64 / longest as f64
+}
+
+/// How similar the bodies of the responses to every URL are to those they were compared to.
+#[derive(Debug, Default)]
+pub(crate) struct BodyComparison {
+    by_url: HashMap<String, UrlComparison>,
+}
+
+#[derive(Debug, Default)]
+struct UrlComparison {
+    compared: usize,
+    identical: usize,
+    similarity_sum: f64,
+    /// Number of responses every field differed in.
+    fields: BTreeMap<String, usize>,
+}
+
+impl UrlComparison {
+    fn similarity(&self) -> f64 {
+        self.similarity_sum / self.compared as f64
+    }
+}
+
+impl BodyComparison {
+    pub(crate) fn record(&mut self, url: &str, diff: &BodyDiff) {
+        let comparison = self.by_url.entry(url.to_owned()).or_default();
+        comparison.compared += 1;
+        comparison.similarity_sum += diff.similarity;
+        if diff.similarity == 1. {
+            comparison.identical += 1;
+        }
+        for field in &diff.fields {
+            *comparison.fields.entry(field.clone()).or_default() += 1;
+        }
+    }
+
+    /// Write the overall similarity, and the least similar URLs with the fields that differed most often.
+    pub(crate) fn write<W: Write>(&self, mut writer: W, compared_to: &str) -> io::Result<()> {
+        let compared = self.by_url.values().map(|url| url.compared).sum::<usize>();
+        let identical = self.by_url.values().map(|url| url.identical).sum::<usize>();
+        let similarity_sum = self
+            .by_url
+            .values()
+            .map(|url| url.similarity_sum)
+            .sum::<f64>();
+        write!(
+            writer,
+            "Bodies compared to {}: compared={} identical={}",
+            compared_to, compared, identical
+        )?;
+        if compared > 0 {
+            write!(
+                writer,
+                " mean_similarity={:.2}%",
+                similarity_sum / compared as f64 * 100.
+            )?;
+        }
+        writeln!(writer)?;
+
+        let mut differing = self
+            .by_url
+            .iter()
+            .filter(|(_, url)| url.identical < url.compared)
+            .collect::<Vec<_>>();
+        differing.sort_by(|(a_url, a), (b_url, b)| {
+            a.similarity()
+                .total_cmp(&b.similarity())
+                .then_with(|| a_url.cmp(b_url))
+        });
+        for (url, comparison) in differing.into_iter().take(REPORTED_URLS) {
+            let mut fields = comparison.fields.iter().collect::<Vec<_>>();
+            fields
+                .sort_by(|(a_field, a), (b_field, b)| b.cmp(a).then_with(|| a_field.cmp(b_field)));
+            write!(
+                writer,
+                "  {:.2}%: {} ({} of {} differed)",
+                comparison.similarity() * 100.,
+                url,
+                comparison.compared - comparison.identical,
+                comparison.compared
+            )?;
+            if !fields.is_empty() {
+                write!(writer, ":")?;
+                for (index, (field, count)) in fields.into_iter().take(REPORTED_FIELDS).enumerate()
+                {
+                    write!(
+                        writer,
+                        "{} {} ({})",
+                        if index == 0 { "" } else { "," },
+                        field,
+                        count
+                    )?;
+                }
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(ignored: &[&str], expected: &str, actual: &str) -> BodyDiff {
+        let ignored: Vec<String> = ignored.iter().map(|path| path.to_string()).collect();
+        BodyComparer::new(&ignored).compare(expected, actual)
+    }
+
+    #[test]
+    fn identical_json_in_any_formatting() {
+        let diff = compare(
+            &[],
+            r#"{"a": 1, "b": [true, null]}"#,
+            r#"{"b":[true,null],"a":1}"#,
+        );
+        assert_eq!(diff.similarity, 1.);
+        assert!(diff.fields.is_empty());
+    }
+
+    #[test]
+    fn differing_fields_are_listed() {
+        let diff = compare(
+            &[],
+            r#"{"id": 1, "name": "a", "items": [{"price": 1}, {"price": 2}]}"#,
+            r#"{"id": 1, "name": "b", "items": [{"price": 1}, {"price": 3}]}"#,
+        );
+        assert_eq!(diff.similarity, 0.5);
+        assert_eq!(diff.fields, ["items.1.price", "name"]);
+    }
+
+    #[test]
+    fn missing_and_retyped_values_differ_in_all_their_values() {
+        let diff = compare(
+            &[],
+            r#"{"a": 1, "b": {"c": 1, "d": 2}}"#,
+            r#"{"a": 1, "b": "gone", "e": 1}"#,
+        );
+        // `b` has two values on one side, and `e` is missing on the other side.
+        assert_eq!(diff.similarity, 0.25);
+        assert_eq!(diff.fields, ["b", "e"]);
+    }
+
+    #[test]
+    fn ignored_paths_are_skipped() {
+        let expected = r#"{"id": "a1", "at": {"time": 1}, "items": [{"id": 1, "price": 1}, {"id": 2, "price": 2}]}"#;
+        let actual = r#"{"id": "b2", "at": {"time": 2}, "items": [{"id": 3, "price": 1}, {"id": 4, "price": 2}]}"#;
+        let diff = compare(&[], expected, actual);
+        assert_eq!(diff.fields, ["at.time", "id", "items.0.id", "items.1.id"]);
+
+        let diff = compare(&["id", "at.time", "items.*.id"], expected, actual);
+        assert_eq!(diff.similarity, 1.);
+        assert!(diff.fields.is_empty());
+
+        // Paths only match at their full depth.
+        let diff = compare(&["time", "items.id"], expected, actual);
+        assert_eq!(diff.fields, ["at.time", "id", "items.0.id", "items.1.id"]);
+
+        // Ignoring an object skips all of its fields, and fields only present on one side.
+        let diff = compare(&["at"], r#"{"at": {"time": 1}, "x": 1}"#, r#"{"x": 1}"#);
+        assert_eq!(diff.similarity, 1.);
+    }
+
+    #[test]
+    fn arrays_are_compared_by_index() {
+        let diff = compare(&[], "[1, 2, 3]", "[3, 2, 1]");
+        assert!((diff.similarity - 1. / 3.).abs() < 1e-12);
+        assert_eq!(diff.fields, ["0", "2"]);
+
+        let diff = compare(&[], r#"{"items": [1, 2]}"#, r#"{"items": [1, 2, 3, 4]}"#);
+        assert_eq!(diff.similarity, 0.5);
+        assert_eq!(diff.fields, ["items.2", "items.3"]);
+    }
+
+    #[test]
+    fn listed_fields_are_limited() {
+        let expected: Vec<usize> = (0..30).collect();
+        let actual: Vec<usize> = (1..31).collect();
+        let diff = compare(
+            &[],
+            &serde_json::to_string(&expected).unwrap(),
+            &serde_json::to_string(&actual).unwrap(),
+        );
+        assert_eq!(diff.similarity, 0.);
+        assert_eq!(diff.fields.len(), MAX_FIELDS);
+    }
+
+    #[test]
+    fn other_bodies_are_compared_as_bytes() {
+        assert_eq!(compare(&[], "", "").similarity, 1.);
+        assert_eq!(compare(&[], "<p>a</p>", "<p>a</p>").similarity, 1.);
+        // The common prefix `<p>` and suffix `</p>` of the longer body.
+        assert_eq!(compare(&[], "<p>ab</p>", "<p>xy</p>").similarity, 7. / 9.);
+        assert_eq!(compare(&[], "abc", "xyz").similarity, 0.);
+        // A body that is JSON on one side only is compared as bytes.
+        let diff = compare(&[], r#"{"a": 1}"#, "error");
+        assert_eq!(diff.similarity, 0.);
+        assert!(diff.fields.is_empty());
+    }
+
+    #[test]
+    fn summary_lists_least_similar_urls() {
+        let mut comparison = BodyComparison::default();
+        let diff = |similarity, fields: &[&str]| BodyDiff {
+            similarity,
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+        };
+        comparison.record("/same", &diff(1., &[]));
+        comparison.record("/some", &diff(0.5, &["a", "b"]));
+        comparison.record("/some", &diff(1., &[]));
+        comparison.record("/some", &diff(0.75, &["b"]));
+        comparison.record("/most", &diff(0., &["x"]));
+        let mut out = Vec::new();
+        comparison.write(&mut out, "the original").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Bodies compared to the original: compared=5 identical=2 mean_similarity=65.00%\n  0.00%: /most (1 of \
+             1 differed): x (1)\n  75.00%: /some (2 of 3 differed): b (2), a (1)\n"
+        );
+    }
+}
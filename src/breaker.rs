@@ -0,0 +1,55 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+
+/// Trips when the share of failed requests among the most recent responses exceeds a threshold.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    /// Error rate in percent above which the breaker trips.
+    threshold: f64,
+    size: usize,
+    /// Whether each of the most recent responses was an error, oldest first.
+    window: VecDeque<bool>,
+    errors: usize,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: f64, size: usize) -> Self {
+        Self {
+            threshold,
+            size,
+            window: VecDeque::with_capacity(size),
+            errors: 0,
+        }
+    }
+
+    /// Record the outcome of a response, returning the error rate of the window if it exceeds the threshold.
+    ///
+    /// The breaker only trips once the window is full, so that a few early errors don't abort the run.
+    pub(crate) fn record(&mut self, is_error: bool) -> Option<f64> {
+        if self.window.len() == self.size && self.window.pop_front() == Some(true) {
+            self.errors -= 1;
+        }
+        self.window.push_back(is_error);
+        if is_error {
+            self.errors += 1;
+        }
+
+        let error_rate = self.errors as f64 / self.size as f64 * 100.;
+        (self.window.len() == self.size && error_rate > self.threshold).then_some(error_rate)
+    }
+}
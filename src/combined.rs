@@ -0,0 +1,270 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parsing access logs in the common or combined log format of Apache and nginx.
+//!
+//! Example: `127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a.gif?x=1 HTTP/1.0" 200 2326 "-" "curl/8.0"`.
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::{
+    Duration,
+    Epoch,
+};
+use regex::Regex;
+
+/// A line of the log, with `-` placeholders turned into `None`.
+#[derive(Debug)]
+pub(crate) struct Line {
+    pub(crate) remote_addr: String,
+    pub(crate) remote_user: Option<String>,
+    pub(crate) timestamp: Epoch,
+    pub(crate) method: String,
+    /// Path of the request, without the query.
+    pub(crate) path: String,
+    /// Query of the request, including the leading `?`, or empty.
+    pub(crate) query: String,
+    pub(crate) protocol: Option<String>,
+    pub(crate) status: u16,
+    /// Size of the response body, in bytes.
+    pub(crate) body_bytes: Option<u64>,
+    pub(crate) referer: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    /// A number following the combined fields, like nginx's `$request_time`, in the unit it was logged in.
+    pub(crate) latency: Option<f64>,
+}
+
+pub(crate) struct Parser {
+    regex: Regex,
+}
+
+impl Parser {
+    pub(crate) fn new() -> Self {
+        Self {
+            regex: Regex::new(concat!(
+                r#"^(\S+) \S+ (\S+) \[([^\]]+)\] "((?:[^"\\]|\\.)*)" (\d{3}) (\d+|-)"#,
+                r#"(?: "((?:[^"\\]|\\.)*)" "((?:[^"\\]|\\.)*)")?(?: (\d+(?:\.\d+)?))?\s*$"#,
+            ))
+            .unwrap(),
+        }
+    }
+
+    pub(crate) fn parse(&self, line: &str) -> Result<Line> {
+        let captures = self
+            .regex
+            .captures(line)
+            .ok_or_else(|| anyhow::anyhow!("Not in the common or combined log format"))?;
+        let field = |index: usize| {
+            captures
+                .get(index)
+                .map(|value| value.as_str())
+                .filter(|value| *value != "-")
+        };
+        let request_line = unescape(&captures[4]);
+        let mut request = request_line.split(' ');
+        let (Some(method), Some(target)) = (request.next(), request.next()) else {
+            anyhow::bail!("Invalid request line `{}`", request_line);
+        };
+        let (path, query) = match target.find('?') {
+            Some(position) => target.split_at(position),
+            None => (target, ""),
+        };
+
+        Ok(Line {
+            remote_addr: captures[1].to_owned(),
+            remote_user: field(2).map(ToOwned::to_owned),
+            timestamp: parse_timestamp(&captures[3])?,
+            method: method.to_owned(),
+            path: path.to_owned(),
+            query: query.to_owned(),
+            protocol: request.next().map(ToOwned::to_owned),
+            status: captures[5].parse()?,
+            body_bytes: field(6).map(str::parse).transpose()?,
+            referer: field(7).map(unescape),
+            user_agent: field(8).map(unescape),
+            latency: field(9).map(str::parse).transpose()?,
+        })
+    }
+}
+
+/// Parse a timestamp like `10/Oct/2000:13:55:36 -0700`.
+fn parse_timestamp(timestamp: &str) -> Result<Epoch> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parse = || -> Option<Epoch> {
+        let (date_time, zone) = timestamp.split_once(' ')?;
+        let mut parts = date_time.split(['/', ':']);
+        let day = parts.next()?.parse().ok()?;
+        let month = parts.next()?;
+        let month = MONTHS.iter().position(|name| *name == month)? as u8 + 1;
+        let year = parts.next()?.parse().ok()?;
+        let hour = parts.next()?.parse().ok()?;
+        let minute = parts.next()?.parse().ok()?;
+        let second = parts.next()?.parse().ok()?;
+        if parts.next().is_some() || zone.len() != 5 || !zone.is_ascii() {
+            return None;
+        }
+        let zone_hours: f64 = zone[1..3].parse().ok()?;
+        let zone_minutes: f64 = zone[3..].parse().ok()?;
+        let zone_offset = Duration::from_seconds(zone_hours * 3600. + zone_minutes * 60.);
+        // The time is local to the zone, so the zone's offset is undone to get to UTC.
+        let local =
+            Epoch::maybe_from_gregorian_utc(year, month, day, hour, minute, second, 0).ok()?;
+        match &zone[..1] {
+            "+" => Some(local - zone_offset),
+            "-" => Some(local + zone_offset),
+            _ => None,
+        }
+    };
+    parse().with_context(|| {
+        format!(
+            "Invalid timestamp `{}`, expected e.g. `10/Oct/2000:13:55:36 -0700`",
+            timestamp
+        )
+    })
+}
+
+/// Undo the escaping of quotes and backslashes within a quoted field, other escapes are kept as they are.
+fn unescape(value: &str) -> String {
+    value.replace("\\\"", "\"").replace("\\\\", "\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Line> {
+        Parser::new().parse(line)
+    }
+
+    #[test]
+    fn combined_line() {
+        let line = parse(
+            r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a.gif?x=1 HTTP/1.0" 200 2326 "https://www.example.com/" "curl/8.0""#,
+        )
+        .unwrap();
+        assert_eq!(line.remote_addr, "127.0.0.1");
+        assert_eq!(line.remote_user.as_deref(), Some("frank"));
+        assert_eq!(
+            line.timestamp,
+            Epoch::from_gregorian_utc_hms(2000, 10, 10, 20, 55, 36)
+        );
+        assert_eq!(line.method, "GET");
+        assert_eq!(line.path, "/a.gif");
+        assert_eq!(line.query, "?x=1");
+        assert_eq!(line.protocol.as_deref(), Some("HTTP/1.0"));
+        assert_eq!(line.status, 200);
+        assert_eq!(line.body_bytes, Some(2326));
+        assert_eq!(line.referer.as_deref(), Some("https://www.example.com/"));
+        assert_eq!(line.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(line.latency, None);
+    }
+
+    #[test]
+    fn common_line() {
+        let line =
+            parse(r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "POST /items HTTP/1.1" 201 15"#)
+                .unwrap();
+        assert_eq!(line.remote_user, None);
+        assert_eq!(
+            line.timestamp,
+            Epoch::from_gregorian_utc_at_midnight(2024, 1, 1)
+        );
+        assert_eq!(line.method, "POST");
+        assert_eq!(line.path, "/items");
+        assert_eq!(line.query, "");
+        assert_eq!(line.body_bytes, Some(15));
+        assert_eq!(line.referer, None);
+        assert_eq!(line.user_agent, None);
+    }
+
+    #[test]
+    fn placeholders() {
+        let line = parse(
+            r#"10.0.0.1 - - [01/Jan/2024:01:30:00 +0130] "HEAD / HTTP/1.1" 304 - "-" "Mozilla/5.0 (X11)""#,
+        )
+        .unwrap();
+        assert_eq!(
+            line.timestamp,
+            Epoch::from_gregorian_utc_at_midnight(2024, 1, 1)
+        );
+        assert_eq!(line.body_bytes, None);
+        assert_eq!(line.referer, None);
+        assert_eq!(line.user_agent.as_deref(), Some("Mozilla/5.0 (X11)"));
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        let line = parse(
+            r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "GET /search?q=\"a\" HTTP/1.1" 200 5 "-" "agent \"quoted\" \\ end""#,
+        )
+        .unwrap();
+        assert_eq!(line.query, r#"?q="a""#);
+        assert_eq!(line.user_agent.as_deref(), Some(r#"agent "quoted" \ end"#));
+    }
+
+    #[test]
+    fn trailing_latency() {
+        let line = parse(
+            r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "GET / HTTP/2.0" 200 5 "-" "curl/8.0" 0.125"#,
+        )
+        .unwrap();
+        assert_eq!(line.latency, Some(0.125));
+    }
+
+    #[test]
+    fn request_line_without_protocol() {
+        let line = parse(r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "GET /" 200 5"#).unwrap();
+        assert_eq!(line.path, "/");
+        assert_eq!(line.protocol, None);
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for (line, error) in [
+            ("", "Not in the common or combined log format"),
+            (
+                r#"{"path": "/"}"#,
+                "Not in the common or combined log format",
+            ),
+            (
+                r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "GET / HTTP/1.1" 2000 5"#,
+                "Not in the common or combined log format",
+            ),
+            (
+                r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "GET / HTTP/1.1" 200 5 "-""#,
+                "Not in the common or combined log format",
+            ),
+            (
+                r#"10.0.0.1 - - [01/Jan/2024:00:00:00 +0000] "-" 400 0"#,
+                "Invalid request line `-`",
+            ),
+            (
+                r#"10.0.0.1 - - [01/Foo/2024:00:00:00 +0000] "GET / HTTP/1.1" 200 5"#,
+                "Invalid timestamp `01/Foo/2024:00:00:00 +0000`, expected e.g. `10/Oct/2000:13:55:36 -0700`",
+            ),
+            (
+                r#"10.0.0.1 - - [01/Jan/2024:00:00:00 UTC] "GET / HTTP/1.1" 200 5"#,
+                "Invalid timestamp `01/Jan/2024:00:00:00 UTC`, expected e.g. `10/Oct/2000:13:55:36 -0700`",
+            ),
+        ] {
+            assert_eq!(parse(line).unwrap_err().to_string(), error, "{line}");
+        }
+    }
+}
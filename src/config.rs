@@ -0,0 +1,369 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defaults for the options of `r7 run` read from a `--config` file.
+//!
+//! The options of the file are turned into command-line arguments for every option that wasn't already provided on
+//! the command line or through the environment, so they're validated exactly like the command-line arguments are.
+
+use std::{
+    ffi::OsString,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use clap::{
+    parser::ValueSource,
+    ArgMatches,
+    Command,
+    Parser,
+};
+
+/// Parse the command line, with the options of a `--config` file of the `run` subcommand layered underneath.
+pub(crate) fn parse<T: Parser>() -> Result<T> {
+    Ok(T::parse_from(with_config::<T>(
+        std::env::args_os().collect(),
+    )?))
+}
+
+/// Insert the options of the `--config` file of the `run` subcommand into the arguments, right after `run`.
+fn with_config<T: Parser>(mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+    // Parse leniently first, to find the config file and which options were provided before validating anything,
+    // since the config file might provide required options. Invalid command lines are then reported by the final
+    // parse.
+    let command = T::command();
+    if let Ok(matches) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+    {
+        if let Some(("run", run_matches)) = matches.subcommand() {
+            if let Some(path) = run_matches.get_one::<PathBuf>("config") {
+                let run = command
+                    .find_subcommand("run")
+                    .expect("the run subcommand exists");
+                let config_args = arguments(run, run_matches, path)?;
+                let position = args
+                    .iter()
+                    .position(|arg| arg == "run")
+                    .map_or(args.len(), |position| position + 1);
+                args.splice(position..position, config_args);
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Read the config file and turn its options into command-line arguments for `command`, skipping every option that
+/// was already provided on the command line or through the environment.
+fn arguments(command: &Command, matches: &ArgMatches, path: &Path) -> Result<Vec<OsString>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    let table: toml::Table = content
+        .parse()
+        .with_context(|| format!("failed to parse config file {}", path.display()))?;
+
+    let mut arguments = Vec::new();
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+        else {
+            anyhow::bail!("unknown option `{}` in config file {}", key, path.display());
+        };
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Datetime(value) => value.to_string(),
+                toml::Value::Boolean(value) if !arg.get_action().takes_values() => {
+                    if value {
+                        arguments.push(format!("--{}", long).into());
+                    }
+                    continue;
+                }
+                toml::Value::Boolean(value) => value.to_string(),
+                toml::Value::Array(_) | toml::Value::Table(_) => anyhow::bail!(
+                    "option `{}` in config file {} must be a string, number, boolean or a list of them",
+                    key,
+                    path.display()
+                ),
+            };
+            arguments.push(format!("--{}={}", long, value).into());
+        }
+    }
+
+    Ok(arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Subcommand;
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    #[derive(Debug, Subcommand)]
+    enum Commands {
+        Run(Run),
+        Other {
+            #[arg(long)]
+            config: Option<PathBuf>,
+        },
+    }
+
+    #[derive(Debug, clap::Args)]
+    struct Run {
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[arg(long)]
+        scheme_and_host: String,
+        #[arg(long, env = "R7_CONFIG_TEST_TIME_FACTOR", default_value_t = 1.)]
+        time_factor: f64,
+        #[arg(long, env = "R7_CONFIG_TEST_MAX_RATE")]
+        max_rate: Option<f64>,
+        #[arg(long)]
+        header: Vec<String>,
+        #[arg(long, value_delimiter = ',')]
+        status: Vec<u16>,
+        #[arg(long)]
+        quiet: bool,
+        #[arg(long)]
+        capture_body: bool,
+        input: Option<String>,
+    }
+
+    /// A config file with the given content, removed again when dropped.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "r7-config-{}-{}.toml",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+
+        fn run(&self, args: &[&str]) -> Result<Run> {
+            let mut all = vec![
+                "r7".into(),
+                "run".into(),
+                format!("--config={}", self.0.display()).into(),
+            ];
+            all.extend(args.iter().map(OsString::from));
+            match Cli::try_parse_from(with_config::<Cli>(all)?)?.command {
+                Commands::Run(run) => Ok(run),
+                Commands::Other { .. } => unreachable!(),
+            }
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn file_provides_defaults() {
+        let config = ConfigFile::new(
+            "defaults",
+            r#"
+                scheme-and-host = "https://staging.internal"
+                time_factor = 2
+                max-rate = 10.5
+                quiet = true
+                capture-body = false
+            "#,
+        );
+        let run = config.run(&["input.csv"]).unwrap();
+        assert_eq!(run.scheme_and_host, "https://staging.internal");
+        assert_eq!(run.time_factor, 2.);
+        assert_eq!(run.max_rate, Some(10.5));
+        assert!(run.quiet);
+        assert!(!run.capture_body);
+        assert_eq!(run.input.as_deref(), Some("input.csv"));
+    }
+
+    #[test]
+    fn built_in_defaults_apply_without_the_option() {
+        let config = ConfigFile::new("builtin", r#"scheme-and-host = "https://staging.internal""#);
+        let run = config.run(&[]).unwrap();
+        assert_eq!(run.time_factor, 1.);
+        assert_eq!(run.max_rate, None);
+        assert!(run.header.is_empty());
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config = ConfigFile::new(
+            "cli",
+            r#"
+                scheme-and-host = "https://staging.internal"
+                time-factor = 2
+                header = ["X-From: file"]
+            "#,
+        );
+        let run = config
+            .run(&[
+                "--scheme-and-host",
+                "https://www.example.com",
+                "--time-factor=3",
+                "--header",
+                "X-From: cli",
+            ])
+            .unwrap();
+        assert_eq!(run.scheme_and_host, "https://www.example.com");
+        assert_eq!(run.time_factor, 3.);
+        // Lists aren't merged, the command line replaces the list of the file.
+        assert_eq!(run.header, ["X-From: cli"]);
+    }
+
+    #[test]
+    fn environment_takes_precedence_over_file() {
+        std::env::set_var("R7_CONFIG_TEST_TIME_FACTOR", "4");
+        let config = ConfigFile::new(
+            "env",
+            r#"
+                scheme-and-host = "https://staging.internal"
+                time-factor = 2
+                max-rate = 5
+            "#,
+        );
+        let from_env = config.run(&[]).unwrap();
+        let from_cli = config.run(&["--time-factor", "3"]).unwrap();
+        std::env::remove_var("R7_CONFIG_TEST_TIME_FACTOR");
+        assert_eq!(from_env.time_factor, 4.);
+        assert_eq!(from_env.max_rate, Some(5.));
+        assert_eq!(from_cli.time_factor, 3.);
+    }
+
+    #[test]
+    fn lists_repeat_the_option() {
+        let config = ConfigFile::new(
+            "lists",
+            r#"
+                scheme-and-host = "https://staging.internal"
+                header = ["X-A: 1", "X-B: 2"]
+                status = [500, 502]
+            "#,
+        );
+        let run = config.run(&[]).unwrap();
+        assert_eq!(run.header, ["X-A: 1", "X-B: 2"]);
+        assert_eq!(run.status, [500, 502]);
+        // A single value works for options that can be repeated, too.
+        let config = ConfigFile::new(
+            "single",
+            r#"
+                scheme-and-host = "https://staging.internal"
+                header = "X-A: 1"
+                status = "500,503"
+            "#,
+        );
+        let run = config.run(&[]).unwrap();
+        assert_eq!(run.header, ["X-A: 1"]);
+        assert_eq!(run.status, [500, 503]);
+    }
+
+    #[test]
+    fn values_are_validated_like_arguments() {
+        let config = ConfigFile::new(
+            "invalid",
+            r#"
+                scheme-and-host = "https://staging.internal"
+                time-factor = "fast"
+            "#,
+        );
+        assert!(config
+            .run(&[])
+            .unwrap_err()
+            .to_string()
+            .contains("invalid value 'fast' for '--time-factor <TIME_FACTOR>'"));
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        for (name, content, error) in [
+            (
+                "unknown",
+                "unknown-option = 1",
+                "unknown option `unknown-option` in config file",
+            ),
+            (
+                "nested",
+                "config = \"other.toml\"",
+                "unknown option `config` in config file",
+            ),
+            (
+                "table",
+                "[scheme-and-host]\nvalue = 1",
+                "option `scheme-and-host` in config file",
+            ),
+            (
+                "syntax",
+                "scheme-and-host = ",
+                "failed to parse config file",
+            ),
+        ] {
+            let config = ConfigFile::new(name, content);
+            let error_message = config.run(&[]).unwrap_err().to_string();
+            assert!(error_message.starts_with(error), "{name}: {error_message}");
+        }
+        let missing = ConfigFile(std::env::temp_dir().join("r7-config-missing.toml"));
+        assert!(missing
+            .run(&[])
+            .unwrap_err()
+            .to_string()
+            .starts_with("failed to read config file"));
+    }
+
+    #[test]
+    fn other_subcommands_are_untouched() {
+        let args: Vec<OsString> = ["r7", "other", "--config", "/nonexistent.toml"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(with_config::<Cli>(args.clone()).unwrap(), args);
+    }
+}
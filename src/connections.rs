@@ -0,0 +1,109 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Counting the connections a client opens, to tell how often requests reused a connection of an earlier one.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[derive(Debug, Default)]
+struct Counts {
+    attempted: AtomicUsize,
+    opened: AtomicUsize,
+}
+
+/// Layer of the connector of a client that counts the connections that were attempted and established.
+///
+/// Connections that hyper establishes for a request but ends up not using, because a pooled connection became idle
+/// in the meantime, are counted too, so this can slightly overestimate the connections a replay needed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionCounter(Arc<Counts>);
+
+impl ConnectionCounter {
+    /// Number of connections that were attempted, whether or not they could be established, since the counter was
+    /// created or last reset.
+    pub(crate) fn attempted(&self) -> usize {
+        self.0.attempted.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections established since the counter was created or last reset.
+    pub(crate) fn opened(&self) -> usize {
+        self.0.opened.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.attempted.store(0, Ordering::Relaxed);
+        self.0.opened.store(0, Ordering::Relaxed);
+    }
+}
+
+impl<S> Layer<S> for ConnectionCounter {
+    type Service = Counting<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Counting {
+            inner,
+            counts: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Counting<S> {
+    inner: S,
+    counts: Arc<Counts>,
+}
+
+impl<S, R> Service<R> for Counting<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let connecting = self.inner.call(request);
+        let counts = self.counts.clone();
+        counts.attempted.fetch_add(1, Ordering::Relaxed);
+        Box::pin(async move {
+            let connection = connecting.await?;
+            counts.opened.fetch_add(1, Ordering::Relaxed);
+            Ok(connection)
+        })
+    }
+}
@@ -14,14 +14,19 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::Result;
 use hifitime::Epoch;
 use serde::de::{
     self,
     Deserialize,
     Deserializer,
 };
+use serde_json::Value;
 use std::str::FromStr;
 
+/// Parse a timestamp like `2024-06-01 @ 12:00:00.000`, or any ISO 8601 timestamp like `2024-06-01T12:00:00Z`.
+///
+/// Timestamps with an offset, e.g. `+02:00`, are converted to UTC, timestamps without one are taken to be in UTC.
 pub(crate) fn kibana_timestamp_as_epoch<'de, D>(
     deserializer: D,
 ) -> std::result::Result<Epoch, D::Error>
@@ -29,6 +34,97 @@ where
     D: Deserializer<'de>,
 {
     let untouched = String::deserialize(deserializer)?;
-    let iso8601 = format!("{}Z", untouched.replace(" @ ", "T"));
-    Epoch::from_str(&iso8601).map_err(de::Error::custom)
+    let iso8601 = untouched.replace(" @ ", "T");
+    let iso8601 = if has_offset(&iso8601) {
+        iso8601
+    } else {
+        format!("{}Z", iso8601)
+    };
+    Epoch::from_str(&iso8601)
+        .map_err(|err| de::Error::custom(format!("Invalid timestamp `{}`: {}", untouched, err)))
+}
+
+/// Whether the time of an ISO 8601 timestamp is followed by `Z` or an offset like `+02:00`.
+fn has_offset(iso8601: &str) -> bool {
+    iso8601
+        .split_once('T')
+        .is_some_and(|(_, time)| time.ends_with(['Z', 'z']) || time.contains(['+', '-']))
+}
+
+/// Time zone that timestamps without an offset are in, with `--assume-timezone`.
+#[derive(Debug, Clone)]
+pub(crate) struct AssumedTimeZone(jiff::tz::TimeZone);
+
+impl FromStr for AssumedTimeZone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        jiff::tz::TimeZone::get(s)
+            .map(AssumedTimeZone)
+            .map_err(|err| anyhow::anyhow!("{}, expected e.g. `Europe/Berlin`", err))
+    }
+}
+
+impl AssumedTimeZone {
+    /// The timestamp with the offset of this time zone at that time appended, if it has none yet, in a way that
+    /// `kibana_timestamp_as_epoch` parses.
+    ///
+    /// Fails for local times that are ambiguous, because the clocks were set back, or that don't exist, because the
+    /// clocks were set forward.
+    pub(crate) fn apply(&self, timestamp: &str) -> Result<Option<String>> {
+        let iso8601 = timestamp.replace(" @ ", "T");
+        if has_offset(&iso8601) {
+            return Ok(None);
+        }
+        let zoned = iso8601
+            .parse::<jiff::civil::DateTime>()
+            .map_err(anyhow::Error::from)
+            .and_then(|civil| Ok(self.0.to_ambiguous_zoned(civil).unambiguous()?))
+            .map_err(|err| anyhow::anyhow!("Invalid timestamp `{}`: {}", timestamp, err))?;
+        let offset = zoned.offset().seconds();
+        Ok(Some(format!(
+            "{}{}{:02}:{:02}",
+            iso8601,
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 3600,
+            offset.abs() % 3600 / 60
+        )))
+    }
+}
+
+/// Format a timestamp the way `kibana_timestamp_as_epoch` parses it, e.g. `2024-06-01 @ 12:00:00.000000000`.
+pub(crate) fn epoch_as_kibana_timestamp(epoch: Epoch) -> String {
+    let (year, month, day, hour, minute, second, nanos) = epoch.to_gregorian_utc();
+    format!(
+        "{:04}-{:02}-{:02} @ {:02}:{:02}:{:02}.{:09}",
+        year, month, day, hour, minute, second, nanos
+    )
+}
+
+/// Look up a dotted path like `http.response.duration` in nested JSON objects.
+///
+/// A key that contains dots itself is matched as a whole before the path is split.
+pub(crate) fn value_at_path<'a>(value: &'a Value, path: &str) -> Result<&'a Value> {
+    if let Some(value) = value.get(path) {
+        return Ok(value);
+    }
+
+    let keys = path.split('.').collect::<Vec<_>>();
+    let mut current = value;
+    for (index, key) in keys.iter().enumerate() {
+        let parent = keys[..index].join(".");
+        current = match current {
+            Value::Object(object) => object.get(*key).ok_or_else(|| match index {
+                0 => anyhow::anyhow!("Missing field `{}`", key),
+                _ => anyhow::anyhow!("Missing field `{}` in `{}` (of `{}`)", key, parent, path),
+            })?,
+            _ => anyhow::bail!(
+                "Field `{}` is not an object (looking up `{}`)",
+                parent,
+                path
+            ),
+        };
+    }
+
+    Ok(current)
 }
@@ -0,0 +1,78 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Telling apart an input file without records from one whose records were all filtered out, for `--allow-empty`.
+
+use std::{
+    fmt,
+    process::ExitCode,
+};
+
+use anyhow::Result;
+
+/// Why no records were left to process, with an exit code of its own so pipelines can tell it from other failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EmptyInput {
+    /// The input files have no records at all.
+    NoRecords,
+    /// The input files have records, but every one of them was filtered out or dropped, e.g. as a bad record.
+    AllDropped {
+        /// Number of records in the input files, including bad ones.
+        records: usize,
+    },
+}
+
+impl EmptyInput {
+    /// The reason for input files with the given number of records, including bad ones.
+    pub(crate) fn new(records: usize) -> Self {
+        match records {
+            0 => EmptyInput::NoRecords,
+            records => EmptyInput::AllDropped { records },
+        }
+    }
+
+    /// Fail with this reason, or only warn about it with `--allow-empty`.
+    pub(crate) fn fail(self, allow_empty: bool) -> Result<()> {
+        if allow_empty {
+            tracing::warn!("{}, nothing to do", self);
+            Ok(())
+        } else {
+            Err(self.into())
+        }
+    }
+
+    pub(crate) fn exit_code(&self) -> ExitCode {
+        match self {
+            EmptyInput::NoRecords => ExitCode::from(3),
+            EmptyInput::AllDropped { .. } => ExitCode::from(4),
+        }
+    }
+}
+
+impl fmt::Display for EmptyInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmptyInput::NoRecords => write!(f, "No records in provided file"),
+            EmptyInput::AllDropped { records } => write!(
+                f,
+                "All {} records in provided file were filtered out or dropped",
+                records
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmptyInput {}
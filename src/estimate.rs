@@ -0,0 +1,259 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Estimating the traffic a replay will generate, for `--dry-run`.
+
+use std::io::{
+    self,
+    Write,
+};
+
+use hifitime::Duration;
+use indicatif::HumanBytes;
+
+/// Sum of a size field over the records that have it.
+#[derive(Debug, Default)]
+struct Bytes {
+    total: u64,
+    records: usize,
+}
+
+impl Bytes {
+    fn add(&mut self, bytes: Option<u64>) {
+        if let Some(bytes) = bytes {
+            self.total += bytes;
+            self.records += 1;
+        }
+    }
+}
+
+/// Estimate of the traffic of a replay, from the offsets and the original sizes of its requests.
+#[derive(Debug)]
+pub(crate) struct Estimate {
+    requests: usize,
+    duration: Duration,
+    /// Highest number of requests sent within any second.
+    peak_rps: usize,
+    /// Sizes of the original requests, i.e. what the replay sends.
+    sent: Bytes,
+    /// Sizes of the original responses, i.e. what the replay receives.
+    received: Bytes,
+    max_rate: Option<f64>,
+}
+
+impl Estimate {
+    /// Estimate for requests given as their offset, and the `received_bytes` and `sent_bytes` of their records.
+    pub(crate) fn new(
+        requests: impl IntoIterator<Item = (Duration, Option<u64>, Option<u64>)>,
+        max_rate: Option<f64>,
+    ) -> Self {
+        let mut offsets = Vec::new();
+        let mut sent = Bytes::default();
+        let mut received = Bytes::default();
+        for (offset, request_bytes, response_bytes) in requests {
+            offsets.push(offset);
+            sent.add(request_bytes);
+            received.add(response_bytes);
+        }
+        offsets.sort();
+        let mut peak_rps = 0;
+        let mut window_start = 0;
+        for (index, offset) in offsets.iter().enumerate() {
+            while *offset - offsets[window_start] >= Duration::from_seconds(1.) {
+                window_start += 1;
+            }
+            peak_rps = peak_rps.max(index - window_start + 1);
+        }
+        let mut duration = offsets.last().copied().unwrap_or_default();
+        if let Some(max_rate) = max_rate {
+            // The requests can't be sent faster than the rate limit allows, regardless of their offsets. The first
+            // request is sent right away, so only the intervals between the requests are limited.
+            duration = duration.max(Duration::from_seconds(
+                offsets.len().saturating_sub(1) as f64 / max_rate,
+            ));
+        }
+
+        Self {
+            requests: offsets.len(),
+            duration,
+            peak_rps,
+            sent,
+            received,
+            max_rate,
+        }
+    }
+
+    pub(crate) fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "Requests: {}", self.requests)?;
+        writeln!(out, "Duration: at least {}", self.duration)?;
+        if self.requests > 1 {
+            // The last request takes up a slot after the duration, of the second the peak rate is counted in, or of
+            // the interval of --max-rate if that is longer, so that the mean rate is at most either of them.
+            let slot = self.max_rate.map_or(1., |max_rate| (1. / max_rate).max(1.));
+            let mean_rps = self.requests as f64 / (self.duration.to_seconds() + slot);
+            writeln!(out, "Mean rate: {:.2} requests per second", mean_rps)?;
+        }
+        match self.max_rate {
+            Some(max_rate) if max_rate < self.peak_rps as f64 => writeln!(
+                out,
+                "Peak rate: {} requests per second, limited to {} by --max-rate",
+                self.peak_rps, max_rate
+            )?,
+            _ => writeln!(out, "Peak rate: {} requests per second", self.peak_rps)?,
+        }
+        self.write_bytes(out, "Sent", &self.sent, "received_bytes")?;
+        self.write_bytes(out, "Received", &self.received, "sent_bytes")?;
+        Ok(())
+    }
+
+    /// Write the total of a size, noting how many records it is based on if not all of them have the field.
+    fn write_bytes(
+        &self,
+        out: &mut impl Write,
+        label: &str,
+        bytes: &Bytes,
+        field: &str,
+    ) -> io::Result<()> {
+        if bytes.records == 0 {
+            return writeln!(
+                out,
+                "{}: unavailable, the records have no `{}` field",
+                label, field
+            );
+        }
+        write!(out, "{}: {}", label, HumanBytes(bytes.total))?;
+        if bytes.records < self.requests {
+            write!(
+                out,
+                ", from the `{}` of {} of {} records",
+                field, bytes.records, self.requests
+            )?;
+        }
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(offsets: &[f64], max_rate: Option<f64>) -> String {
+        let estimate = Estimate::new(
+            offsets
+                .iter()
+                .map(|offset| (Duration::from_seconds(*offset), None, None)),
+            max_rate,
+        );
+        let mut out = Vec::new();
+        estimate.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn mean_rate_of_requests_a_second_apart() {
+        let report = report(&[0., 1.], None);
+        assert!(
+            report.contains("Mean rate: 1.00 requests per second"),
+            "{report}"
+        );
+        assert!(
+            report.contains("Peak rate: 1 requests per second"),
+            "{report}"
+        );
+    }
+
+    /// Mean rate of the report, which is rounded to two decimals.
+    fn mean_rps(report: &str) -> f64 {
+        let line = report
+            .lines()
+            .find_map(|line| line.strip_prefix("Mean rate: "))
+            .unwrap();
+        line.split(' ').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn mean_rate_is_at_most_peak_rate() {
+        for offsets in [
+            &[0., 0.1][..],
+            &[0., 0.5, 1.],
+            &[0., 1., 1., 1., 0.5, 0., 1.],
+            &[0., 1., 2., 3.],
+            &[0., 0.1, 0.2, 5.],
+            &[0., 0.9, 1.8, 2.7],
+        ] {
+            let estimate = Estimate::new(
+                offsets
+                    .iter()
+                    .map(|offset| (Duration::from_seconds(*offset), None, None)),
+                None,
+            );
+            let mut out = Vec::new();
+            estimate.write(&mut out).unwrap();
+            let report = String::from_utf8(out).unwrap();
+            assert!(mean_rps(&report) <= estimate.peak_rps as f64, "{report}");
+        }
+    }
+
+    #[test]
+    fn mean_rate_is_at_most_max_rate() {
+        for max_rate in [0.5, 2., 100.] {
+            let report = report(&[0.; 10], Some(max_rate));
+            assert!(mean_rps(&report) <= max_rate, "{report}");
+        }
+    }
+
+    #[test]
+    fn max_rate_extends_duration() {
+        let report = report(&[0., 0., 0., 0., 0.], Some(2.));
+        assert!(report.contains("Duration: at least 2 s"), "{report}");
+        assert!(
+            report.contains("Mean rate: 1.67 requests per second"),
+            "{report}"
+        );
+        assert!(
+            report.contains("Peak rate: 5 requests per second, limited to 2 by --max-rate"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn single_request_has_no_mean_rate() {
+        let report = report(&[0.], None);
+        assert!(!report.contains("Mean rate"), "{report}");
+    }
+
+    #[test]
+    fn missing_sizes_are_unavailable() {
+        let estimate = Estimate::new(
+            [
+                (Duration::ZERO, Some(100), None),
+                (Duration::from_seconds(1.), None, None),
+            ],
+            None,
+        );
+        let mut out = Vec::new();
+        estimate.write(&mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(
+            report.contains("Sent: 100 B, from the `received_bytes` of 1 of 2 records"),
+            "{report}"
+        );
+        assert!(
+            report.contains("Received: unavailable, the records have no `sent_bytes` field"),
+            "{report}"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// Lifecycle event of a run. All durations are in seconds.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    Started {
+        requests: usize,
+        minimum_runtime: f64,
+    },
+    RequestSent {
+        url: &'a str,
+        retry: usize,
+    },
+    RequestCompleted {
+        url: &'a str,
+        status: Option<u16>,
+        required_time: Option<f64>,
+        completed: usize,
+        errors: usize,
+    },
+    /// The error rate exceeded `--abort-on-error-rate`, the error rate is in percent.
+    CircuitBreakerTripped {
+        completed: usize,
+        error_rate: f64,
+    },
+    /// The number of failures reached `--max-failures`, or `--fail-fast`.
+    MaxFailuresReached {
+        completed: usize,
+        failures: usize,
+    },
+    /// The replay was paused with `SIGTSTP`.
+    Paused,
+    /// The replay was resumed with `SIGCONT`, after having been paused for the given time.
+    Resumed {
+        paused_for: f64,
+    },
+    Finished {
+        completed: usize,
+        errors: usize,
+        aborted: bool,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    /// Seconds since the start of the run.
+    elapsed: f64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Writes events as newline-delimited JSON, if an events file was requested.
+#[derive(Debug)]
+pub(crate) struct Events {
+    started: Instant,
+    file: Option<Mutex<File>>,
+}
+
+impl Events {
+    pub(crate) fn new(path: Option<&Path>) -> Result<Self> {
+        Ok(Self {
+            started: Instant::now(),
+            file: path.map(File::create).transpose()?.map(Mutex::new),
+        })
+    }
+
+    /// Write the event and flush it right away, so that a consumer sees it in real time.
+    ///
+    /// Failures to write are logged rather than returned, a broken consumer should not abort the replay.
+    pub(crate) fn emit(&self, event: Event<'_>) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let line = Line {
+            elapsed: self.started.elapsed().as_secs_f64(),
+            event: &event,
+        };
+        let mut buffer = serde_json::to_vec(&line).expect("events are always serializable");
+        buffer.push(b'\n');
+        let mut file = file.lock().expect("events file lock shouldn't be poisoned");
+        if let Err(err) = file.write_all(&buffer).and_then(|()| file.flush()) {
+            tracing::warn!("Failed to write event: {}", err);
+        }
+    }
+}
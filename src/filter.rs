@@ -0,0 +1,518 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A small expression language to filter records on arbitrary fields.
+//!
+//! An expression compares fields of a record against literals, e.g. `target_processing_time > 0.5`,
+//! `path =~ "^/api/"` or `status >= 500 && domain_name != "www.example.com"`. Supported are:
+//!
+//! * the comparisons `==`, `!=`, `<`, `<=`, `>`, `>=` and `=~` (regular expression match),
+//! * the logical operators `&&`, `||` and `!`, and parentheses,
+//! * numbers, strings in single or double quotes, `true`, `false` and `null` as literals,
+//! * dotted paths like `http.response.status` to reach into nested JSON objects.
+//!
+//! A field on its own is true if it is present and not `null`, `false`, `0` or the empty string.
+//!
+//! Values of different types are never equal, e.g. `status == "200"` matches no record with a numeric `status`, except
+//! that strings holding a number are compared as numbers with number literals.
+
+use std::{
+    cmp::Ordering,
+    str::FromStr,
+};
+
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Field(String),
+    Literal(Value),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Matches,
+}
+
+/// Split the input into tokens, each with the offset it starts at.
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let two = input.get(start..start + 2).unwrap_or_default();
+        let (token, len) = match (c, two) {
+            (c, _) if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            (_, "==") => (Token::Operator(Operator::Eq), 2),
+            (_, "!=") => (Token::Operator(Operator::Ne), 2),
+            (_, "<=") => (Token::Operator(Operator::Le), 2),
+            (_, ">=") => (Token::Operator(Operator::Ge), 2),
+            (_, "=~") => (Token::Operator(Operator::Matches), 2),
+            (_, "&&") => (Token::And, 2),
+            (_, "||") => (Token::Or, 2),
+            ('<', _) => (Token::Operator(Operator::Lt), 1),
+            ('>', _) => (Token::Operator(Operator::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::OpenParen, 1),
+            (')', _) => (Token::CloseParen, 1),
+            ('"' | '\'', _) => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => anyhow::bail!("unterminated string at {}", start),
+                        },
+                        Some((_, quote)) if quote == c => break,
+                        Some((_, other)) => value.push(other),
+                        None => anyhow::bail!("unterminated string at {}", start),
+                    }
+                }
+                tokens.push((start, Token::Literal(Value::String(value))));
+                continue;
+            }
+            (c, _) if c.is_ascii_digit() || c == '-' => {
+                let end = input[start..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')))
+                    .map_or(input.len(), |len| start + len);
+                let number = input[start..end]
+                    .parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("invalid number `{}`", &input[start..end]))?;
+                (Token::Literal(Value::from(number)), end - start)
+            }
+            (c, _) if c.is_alphabetic() || matches!(c, '_' | '@') => {
+                let end = input[start..]
+                    .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '@' | '.' | '-')))
+                    .map_or(input.len(), |len| start + len);
+                let token = match &input[start..end] {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    field => Token::Field(field.to_owned()),
+                };
+                (token, end - start)
+            }
+            (c, _) => anyhow::bail!("unexpected character `{}` at {}", c, start),
+        };
+        tokens.push((start, token));
+        for _ in 0..len {
+            chars.next();
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Truthy(String),
+    Compare(String, Operator, Value),
+    Matches(String, Regex),
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    /// Offset of the next token in the input.
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(0, |(offset, _)| *offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::OpenParen) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::CloseParen) => Ok(expr),
+                    _ => anyhow::bail!("expected `)`"),
+                }
+            }
+            Some(Token::Field(field)) => match self.peek() {
+                Some(&Token::Operator(operator)) => {
+                    self.next();
+                    let offset = self.offset();
+                    let Some(Token::Literal(literal)) = self.next() else {
+                        anyhow::bail!("expected a literal after the comparison on `{}`", field);
+                    };
+                    if operator == Operator::Matches {
+                        let Value::String(pattern) = literal else {
+                            anyhow::bail!("`=~` expects a string with a regular expression");
+                        };
+                        let regex = Regex::new(&pattern).map_err(|err| {
+                            // Syntax errors point at the pattern over several lines, of which only the last one
+                            // with the error itself fits into the message.
+                            let err = err.to_string();
+                            let err = err.lines().last().unwrap_or_default();
+                            anyhow::anyhow!(
+                                "invalid regular expression `{}` at {}: {}",
+                                pattern,
+                                offset,
+                                err.trim_start_matches("error: ")
+                            )
+                        })?;
+                        Ok(Expr::Matches(field, regex))
+                    } else {
+                        Ok(Expr::Compare(field, operator, literal))
+                    }
+                }
+                _ => Ok(Expr::Truthy(field)),
+            },
+            Some(token) => anyhow::bail!("unexpected {:?}", token),
+            None => anyhow::bail!("unexpected end of expression"),
+        }
+    }
+}
+
+/// Look up a dotted path in nested JSON objects.
+pub(crate) fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    // Keys containing dots, like `@timestamp.keyword`, are tried as a whole first.
+    if let Some(value) = value.get(path) {
+        return Some(value);
+    }
+    let (head, tail) = path.split_once('.')?;
+    lookup(value.get(head)?, tail)
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.),
+        Value::String(value) => !value.is_empty(),
+        Value::Array(_) | Value::Object(_) => true,
+    }
+}
+
+fn compare(value: &Value, literal: &Value) -> Option<Ordering> {
+    match (value, literal) {
+        (Value::Number(value), Value::Number(literal)) => {
+            value.as_f64()?.partial_cmp(&literal.as_f64()?)
+        }
+        // CSV columns, or JSON fields, might contain numbers as strings.
+        (Value::String(value), Value::Number(literal)) => value
+            .trim()
+            .parse::<f64>()
+            .ok()?
+            .partial_cmp(&literal.as_f64()?),
+        (Value::String(value), Value::String(literal)) => Some(value.cmp(literal)),
+        (Value::Bool(value), Value::Bool(literal)) => Some(value.cmp(literal)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+impl Expr {
+    fn matches(&self, record: &Value) -> bool {
+        match self {
+            Expr::Or(lhs, rhs) => lhs.matches(record) || rhs.matches(record),
+            Expr::And(lhs, rhs) => lhs.matches(record) && rhs.matches(record),
+            Expr::Not(expr) => !expr.matches(record),
+            Expr::Truthy(field) => lookup(record, field).is_some_and(is_truthy),
+            Expr::Compare(field, operator, literal) => {
+                let value = lookup(record, field).unwrap_or(&Value::Null);
+                let ordering = compare(value, literal);
+                match operator {
+                    Operator::Eq => ordering == Some(Ordering::Equal),
+                    Operator::Ne => ordering != Some(Ordering::Equal),
+                    Operator::Lt => ordering == Some(Ordering::Less),
+                    Operator::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Operator::Gt => ordering == Some(Ordering::Greater),
+                    Operator::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                    Operator::Matches => {
+                        unreachable!("regular expressions are parsed into Expr::Matches")
+                    }
+                }
+            }
+            Expr::Matches(field, regex) => match lookup(record, field) {
+                Some(Value::String(value)) => regex.is_match(value),
+                Some(Value::Null) | None => false,
+                Some(value) => regex.is_match(&value.to_string()),
+            },
+        }
+    }
+}
+
+/// A parsed filter expression, see the module documentation for its syntax.
+#[derive(Debug, Clone)]
+pub(crate) struct Filter(Expr);
+
+impl Filter {
+    /// Whether the record, represented as a JSON object, satisfies the expression.
+    pub(crate) fn matches(&self, record: &Value) -> bool {
+        self.0.matches(record)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("unexpected {:?} after the end of the expression", token);
+        }
+
+        Ok(Self(expr))
+    }
+}
+
+/// Status code, or class of status codes, of the original response, e.g. `404` or `5xx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StatusClass {
+    Exact(u16),
+    /// All status codes with the given hundreds digit.
+    Class(u16),
+}
+
+impl StatusClass {
+    pub(crate) fn matches(self, status: u16) -> bool {
+        match self {
+            StatusClass::Exact(expected) => status == expected,
+            StatusClass::Class(hundreds) => status / 100 == hundreds,
+        }
+    }
+}
+
+impl FromStr for StatusClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let class = match s.to_ascii_lowercase().strip_suffix("xx") {
+            Some(hundreds) => StatusClass::Class(hundreds.parse()?),
+            None => StatusClass::Exact(s.parse()?),
+        };
+        match class {
+            StatusClass::Class(1..=5) | StatusClass::Exact(100..=599) => Ok(class),
+            _ => anyhow::bail!("expected a status code like `404` or a class like `5xx`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(expr: &str, record: &Value) -> bool {
+        expr.parse::<Filter>().unwrap().matches(record)
+    }
+
+    fn parse_error(expr: &str) -> String {
+        expr.parse::<Filter>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let record = json!({"a": true, "b": false, "c": false});
+        assert!(matches("a || b && c", &record));
+        assert!(!matches("(a || b) && c", &record));
+        assert!(matches("c && b || a", &record));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let record = json!({"a": true, "b": false});
+        assert!(!matches("!a && b", &record));
+        assert!(matches("!(a && b)", &record));
+        assert!(matches("!!a", &record));
+        assert!(matches("!b && a", &record));
+    }
+
+    #[test]
+    fn comparisons() {
+        let record = json!({"status": 503, "path": "/api/items", "time": "0.7"});
+        assert!(matches("status == 503", &record));
+        assert!(matches("status != 500", &record));
+        assert!(matches("status >= 500 && status < 600", &record));
+        assert!(matches("status > 502 && status <= 503", &record));
+        assert!(matches("path == '/api/items'", &record));
+        assert!(matches("path < \"/b\"", &record));
+        // Numbers in strings compare as numbers.
+        assert!(matches("time > 0.5", &record));
+        assert!(!matches("missing == 1", &record));
+        assert!(matches("missing == null", &record));
+    }
+
+    #[test]
+    fn regular_expressions() {
+        let record = json!({"path": "/api/items", "status": 404, "empty": null});
+        assert!(matches("path =~ \"^/api/\"", &record));
+        assert!(!matches("path =~ \"^/items\"", &record));
+        assert!(matches("path =~ 'items$'", &record));
+        // Other values are matched by their JSON representation.
+        assert!(matches("status =~ \"^4\"", &record));
+        assert!(!matches("empty =~ \".*\"", &record));
+        assert!(!matches("missing =~ \".*\"", &record));
+    }
+
+    #[test]
+    fn escaped_quotes_in_strings() {
+        let record = json!({"agent": "say \"hi\"", "name": "it's"});
+        assert!(matches(r#"agent == "say \"hi\"""#, &record));
+        assert!(matches(r"name == 'it\'s'", &record));
+    }
+
+    #[test]
+    fn nested_paths() {
+        let record = json!({
+            "http": {"response": {"status": 200}},
+            "@timestamp.keyword": "2024-01-01",
+        });
+        assert!(matches("http.response.status == 200", &record));
+        assert!(matches("http.response", &record));
+        assert!(!matches("http.request.status == 200", &record));
+        assert!(matches("@timestamp.keyword == \"2024-01-01\"", &record));
+    }
+
+    #[test]
+    fn truthiness_of_fields() {
+        let record = json!({
+            "null": null,
+            "false": false,
+            "zero": 0,
+            "empty": "",
+            "true": true,
+            "one": 1,
+            "text": "x",
+            "array": [],
+            "object": {},
+        });
+        // The fields are nested, as `null`, `false` and `true` on their own are literals.
+        for field in ["null", "false", "zero", "empty", "missing"] {
+            assert!(
+                !matches(&format!("f.{field}"), &json!({"f": record})),
+                "{field}"
+            );
+        }
+        for field in ["true", "one", "text", "array", "object"] {
+            assert!(
+                matches(&format!("f.{field}"), &json!({"f": record})),
+                "{field}"
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_types_match_nothing() {
+        let record = json!({"status": 200, "ok": true});
+        assert!(!matches("status == \"200\"", &record));
+        assert!(!matches("status < \"300\"", &record));
+        assert!(!matches("ok == 1", &record));
+        // Only `!=` holds, as the values aren't equal.
+        assert!(matches("status != \"200\"", &record));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_error("path == \"/api"), "unterminated string at 8");
+        assert_eq!(parse_error("status == 5x0"), "invalid number `5x0`");
+        assert_eq!(parse_error("status # 500"), "unexpected character `#` at 7");
+        assert_eq!(parse_error("(status == 500"), "expected `)`");
+        assert_eq!(
+            parse_error("status == path"),
+            "expected a literal after the comparison on `status`"
+        );
+        assert_eq!(
+            parse_error("path =~ 1"),
+            "`=~` expects a string with a regular expression"
+        );
+        assert_eq!(parse_error("status &&"), "unexpected end of expression");
+        assert_eq!(parse_error("&& status"), "unexpected And");
+        assert_eq!(
+            parse_error("status ok"),
+            "unexpected Field(\"ok\") after the end of the expression"
+        );
+        assert_eq!(
+            parse_error("status == 1 && path =~ \"[\""),
+            "invalid regular expression `[` at 23: unclosed character class"
+        );
+    }
+
+    #[test]
+    fn status_classes() {
+        assert_eq!(
+            "404".parse::<StatusClass>().unwrap(),
+            StatusClass::Exact(404)
+        );
+        assert_eq!("5xx".parse::<StatusClass>().unwrap(), StatusClass::Class(5));
+        assert_eq!("4XX".parse::<StatusClass>().unwrap(), StatusClass::Class(4));
+        assert!(StatusClass::Class(5).matches(503));
+        assert!(!StatusClass::Class(5).matches(404));
+        assert!(StatusClass::Exact(404).matches(404));
+        for invalid in ["600", "99", "6xx", "0xx", "abc"] {
+            assert!(invalid.parse::<StatusClass>().is_err(), "{invalid}");
+        }
+    }
+}
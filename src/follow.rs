@@ -0,0 +1,331 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tailing an input file that is still being written, with `--follow`.
+//!
+//! Only complete lines are read, a line that is still being written is read once it ends. When the file is truncated
+//! it is read again from its start, and when it is replaced, e.g. by log rotation, the complete lines of the previous
+//! file are read before continuing with the new one from its start.
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        Read,
+        Seek,
+        SeekFrom,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+
+/// Interval at which the file is checked for new lines.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) struct Follower {
+    path: PathBuf,
+    file: File,
+    /// Position in the file up to which complete lines have been read.
+    position: u64,
+    /// Bytes after the position that were read, but don't end in a newline yet.
+    partial: Vec<u8>,
+    /// First line of the file, which is prepended to every read after it, to keep the header of CSV files.
+    header: Option<Vec<u8>>,
+    keep_header: bool,
+}
+
+impl Follower {
+    /// Follow the file at `path` from its start, keeping its first line as a header if `keep_header` is set.
+    pub(crate) fn new(path: &Path, keep_header: bool) -> Result<Self> {
+        Ok(Follower {
+            path: path.to_owned(),
+            file: open(path)?,
+            position: 0,
+            partial: Vec::new(),
+            header: None,
+            keep_header,
+        })
+    }
+
+    /// The complete lines written since the previous read, which may be none.
+    pub(crate) fn read(&mut self) -> Result<Vec<u8>> {
+        let lines = self.read_lines()?;
+        if self.replaced()? {
+            tracing::info!(
+                "{} was replaced, following the new file",
+                self.path.display()
+            );
+            self.file = open(&self.path)?;
+            self.rewind();
+        }
+
+        Ok(lines)
+    }
+
+    /// Wait for complete lines to be written, and read them.
+    ///
+    /// This is cancel safe, lines are only read right before they are returned.
+    pub(crate) async fn next(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let lines = self.read()?;
+            if !lines.is_empty() {
+                return Ok(lines);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn read_lines(&mut self) -> Result<Vec<u8>> {
+        let length = self.file.metadata()?.len();
+        if length < self.position + self.partial.len() as u64 {
+            tracing::warn!(
+                "{} was truncated, reading it from its start",
+                self.path.display()
+            );
+            self.rewind();
+        }
+        self.file
+            .seek(SeekFrom::Start(self.position + self.partial.len() as u64))?;
+        let read = self.file.read_to_end(&mut self.partial)?;
+        if read == 0 {
+            return Ok(Vec::new());
+        }
+        let Some(end) = self.partial.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let rest = self.partial.split_off(end + 1);
+        let mut lines = std::mem::replace(&mut self.partial, rest);
+        if self.keep_header {
+            match &self.header {
+                Some(header) => {
+                    lines.splice(0..0, header.iter().copied());
+                }
+                None => {
+                    let end = lines.iter().position(|byte| *byte == b'\n').unwrap_or(0);
+                    self.header = Some(lines[..=end].to_vec());
+                }
+            }
+        }
+        self.position += (end + 1) as u64;
+
+        Ok(lines)
+    }
+
+    fn rewind(&mut self) {
+        self.position = 0;
+        self.partial.clear();
+        self.header = None;
+    }
+
+    /// Whether the path refers to another file than the one being read.
+    #[cfg(unix)]
+    fn replaced(&self) -> Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        let current = match std::fs::metadata(&self.path) {
+            Ok(current) => current,
+            // The file may have been moved away, with the new file not created yet.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let following = self.file.metadata()?;
+        Ok(current.dev() != following.dev() || current.ino() != following.ino())
+    }
+
+    /// Files can't be replaced while open outside of unix, only truncated.
+    #[cfg(not(unix))]
+    fn replaced(&self) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("Failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions,
+        io::Write,
+    };
+
+    use super::*;
+
+    /// A path in the temporary directory, whose files are removed again when dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("r7-follow-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        fn rotated(&self) -> PathBuf {
+            self.0.with_extension("1")
+        }
+
+        fn append(&self, content: &str) {
+            append(&self.0, content);
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(self.rotated());
+        }
+    }
+
+    fn append(path: &Path, content: &str) {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    }
+
+    fn read(follower: &mut Follower) -> String {
+        String::from_utf8(follower.read().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reads_complete_lines() {
+        let path = TempPath::new("complete");
+        path.append("a\nb\n");
+        let mut follower = Follower::new(&path.0, false).unwrap();
+        assert_eq!(read(&mut follower), "a\nb\n");
+        assert_eq!(read(&mut follower), "");
+        path.append("c\n");
+        assert_eq!(read(&mut follower), "c\n");
+    }
+
+    #[test]
+    fn partial_lines_are_read_once_they_end() {
+        let path = TempPath::new("partial");
+        path.append("a\npar");
+        let mut follower = Follower::new(&path.0, false).unwrap();
+        assert_eq!(read(&mut follower), "a\n");
+        path.append("tial");
+        assert_eq!(read(&mut follower), "");
+        path.append("\nb\nc");
+        assert_eq!(read(&mut follower), "partial\nb\n");
+        path.append("\n");
+        assert_eq!(read(&mut follower), "c\n");
+    }
+
+    #[test]
+    fn header_is_kept() {
+        let path = TempPath::new("header");
+        path.append("path,status\n/a,200\n");
+        let mut follower = Follower::new(&path.0, true).unwrap();
+        assert_eq!(read(&mut follower), "path,status\n/a,200\n");
+        path.append("/b,200\n");
+        assert_eq!(read(&mut follower), "path,status\n/b,200\n");
+    }
+
+    #[test]
+    fn truncated_file_is_read_from_its_start() {
+        let path = TempPath::new("truncated");
+        path.append("path,status\n/a,200\n/b,200\n");
+        let mut follower = Follower::new(&path.0, true).unwrap();
+        assert_eq!(read(&mut follower), "path,status\n/a,200\n/b,200\n");
+        File::create(&path.0).unwrap();
+        path.append("path,status\n/c,200\n");
+        assert_eq!(read(&mut follower), "path,status\n/c,200\n");
+        path.append("/d,200\n");
+        assert_eq!(read(&mut follower), "path,status\n/d,200\n");
+    }
+
+    #[test]
+    fn truncation_drops_partial_line() {
+        let path = TempPath::new("truncated-partial");
+        path.append("a\nb\nunfinished");
+        let mut follower = Follower::new(&path.0, false).unwrap();
+        assert_eq!(read(&mut follower), "a\nb\n");
+        File::create(&path.0).unwrap();
+        path.append("c\n");
+        assert_eq!(read(&mut follower), "c\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotated_file_is_finished_before_the_new_one() {
+        let path = TempPath::new("rotated");
+        path.append("path\n/a\n");
+        let mut follower = Follower::new(&path.0, true).unwrap();
+        assert_eq!(read(&mut follower), "path\n/a\n");
+
+        // Lines written to the previous file after it was moved away are still read, with its header.
+        std::fs::rename(&path.0, path.rotated()).unwrap();
+        append(&path.rotated(), "/b\n");
+        assert_eq!(read(&mut follower), "path\n/b\n");
+        // Until the new file is created, the previous one keeps being followed.
+        append(&path.rotated(), "/c\n");
+        assert_eq!(read(&mut follower), "path\n/c\n");
+
+        path.append("path\n/d\n");
+        append(&path.rotated(), "/e\n");
+        assert_eq!(read(&mut follower), "path\n/e\n");
+        // The new file is read from its start, with its own header.
+        assert_eq!(read(&mut follower), "path\n/d\n");
+        append(&path.rotated(), "/ignored\n");
+        path.append("/f\n");
+        assert_eq!(read(&mut follower), "path\n/f\n");
+    }
+
+    #[test]
+    fn missing_file_fails() {
+        let path = TempPath::new("missing");
+        assert!(Follower::new(&path.0, false)
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Failed to open"));
+    }
+
+    #[tokio::test]
+    async fn next_waits_for_lines() {
+        let path = TempPath::new("next");
+        path.append("");
+        let mut follower = Follower::new(&path.0, false).unwrap();
+        let writer = {
+            let path = path.0.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                append(&path, "a\n");
+            })
+        };
+        let lines = tokio::time::timeout(Duration::from_secs(5), follower.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lines, b"a\n");
+        writer.await.unwrap();
+    }
+}
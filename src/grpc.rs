@@ -0,0 +1,194 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replaying unary gRPC calls, with `--grpc`.
+//!
+//! A gRPC call is an HTTP/2 `POST`-request whose body is a sequence of length-prefixed messages. Its outcome is
+//! reported in the `grpc-status` trailer, as the HTTP status is `200 OK` for failed calls as well, or in a header of
+//! the same name for responses that consist of headers only.
+
+use std::fmt;
+
+use anyhow::Result;
+use base64::{
+    engine::general_purpose::STANDARD as BASE64,
+    Engine,
+};
+use reqwest::{
+    header::{
+        HeaderMap,
+        HeaderValue,
+        CONTENT_TYPE,
+        TE,
+    },
+    RequestBuilder,
+    Version,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Length of the prefix of every message: whether it is compressed, and its length as a big-endian `u32`.
+const PREFIX_LENGTH: usize = 5;
+
+/// Names of the status codes, indexed by code, as defined by `google.rpc.Code`.
+const CODES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+/// Message of a call from the value of the body field, which has to be base64-encoded.
+///
+/// Records without a body send an empty message, which is how messages without any set fields are encoded.
+pub(crate) fn message(body: Option<&Value>) -> Result<Vec<u8>> {
+    match body {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(body)) => BASE64.decode(body.trim()).map_err(|err| {
+            anyhow::anyhow!("the body of a gRPC call has to be base64-encoded: {}", err)
+        }),
+        Some(_) => anyhow::bail!("the body of a gRPC call has to be a base64-encoded string"),
+    }
+}
+
+/// Prepare the request as a unary call with the single `message`.
+pub(crate) fn apply(builder: RequestBuilder, message: &[u8]) -> Result<RequestBuilder> {
+    Ok(builder
+        .version(Version::HTTP_2)
+        .header(CONTENT_TYPE, HeaderValue::from_static("application/grpc"))
+        .header(TE, HeaderValue::from_static("trailers"))
+        .body(frame(message)?))
+}
+
+/// Body of a unary call with the single, uncompressed `message`.
+fn frame(message: &[u8]) -> Result<Vec<u8>> {
+    let length = u32::try_from(message.len())
+        .map_err(|_| anyhow::anyhow!("gRPC message of {} bytes is too long", message.len()))?;
+    let mut body = Vec::with_capacity(PREFIX_LENGTH + message.len());
+    body.push(0);
+    body.extend_from_slice(&length.to_be_bytes());
+    body.extend_from_slice(message);
+    Ok(body)
+}
+
+/// Message of the body of a unary response, which is empty if the call failed.
+pub(crate) fn unframe(body: &[u8]) -> Result<&[u8]> {
+    if body.is_empty() {
+        return Ok(body);
+    }
+    let Some((prefix, message)) = body.split_first_chunk::<PREFIX_LENGTH>() else {
+        anyhow::bail!("gRPC response of {} bytes is too short", body.len());
+    };
+    if prefix[0] != 0 {
+        anyhow::bail!("gRPC response is compressed, which isn't supported");
+    }
+    let length = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+    message.get(..length).ok_or_else(|| {
+        anyhow::anyhow!(
+            "gRPC response announces a message of {} bytes, but only has {}",
+            length,
+            message.len()
+        )
+    })
+}
+
+/// Base64 encoding of a message, to output it alongside the other response details.
+pub(crate) fn encode(message: &[u8]) -> String {
+    BASE64.encode(message)
+}
+
+/// Outcome of a gRPC call.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Status {
+    pub(crate) code: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) message: Option<String>,
+}
+
+impl Status {
+    /// Status from the trailers of the response, or from its headers if it consists of headers only.
+    pub(crate) fn from_response(headers: &HeaderMap, trailers: Option<&HeaderMap>) -> Result<Self> {
+        let metadata = trailers
+            .filter(|trailers| trailers.contains_key("grpc-status"))
+            .unwrap_or(headers);
+        let code = metadata
+            .get("grpc-status")
+            .ok_or_else(|| anyhow::anyhow!("gRPC response has no `grpc-status`"))?
+            .to_str()
+            .ok()
+            .and_then(|code| code.trim().parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("gRPC response has an invalid `grpc-status`"))?;
+        let message = metadata
+            .get("grpc-message")
+            .map(|message| percent_decode(message.as_bytes()))
+            .filter(|message| !message.is_empty());
+        Ok(Self { code, message })
+    }
+
+    pub(crate) fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match CODES.get(self.code as usize) {
+            Some(name) => write!(f, "{} ({})", self.code, name)?,
+            None => write!(f, "{}", self.code)?,
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode the percent-encoding of `grpc-message`, keeping invalid escapes as they are.
+fn percent_decode(value: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut index = 0;
+    while index < value.len() {
+        let escaped = value
+            .get(index + 1..index + 3)
+            .filter(|_| value[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(value[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,263 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    str::FromStr,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::Epoch;
+use regex::Regex;
+use reqwest::header::{
+    HeaderMap,
+    HeaderName,
+    HeaderValue,
+    AUTHORIZATION,
+    COOKIE,
+};
+
+/// Request header whose value is taken from a field of every record, in the format `Header-Name=field`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HeaderFromColumn {
+    pub(crate) name: HeaderName,
+    pub(crate) column: String,
+}
+
+impl FromStr for HeaderFromColumn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, column)) = s.split_once('=') else {
+            anyhow::bail!("expected `Header-Name=field`");
+        };
+        if column.is_empty() {
+            anyhow::bail!("field must not be empty");
+        }
+        let name = HeaderName::from_str(name.trim())
+            .with_context(|| format!("invalid header name `{}`", name))?;
+
+        Ok(Self {
+            name,
+            column: column.to_owned(),
+        })
+    }
+}
+
+impl HeaderFromColumn {
+    /// Value of the header for the given value of its field, or `None` if the field is missing or empty, so that no
+    /// empty header is sent.
+    pub(crate) fn value(&self, field: Option<&str>) -> Result<Option<HeaderValue>> {
+        let Some(field) = field.filter(|field| !field.trim().is_empty()) else {
+            return Ok(None);
+        };
+        HeaderValue::from_str(field).map(Some).map_err(|_| {
+            anyhow::anyhow!(
+                "invalid value for the `{}` header in field `{}`",
+                self.name,
+                self.column
+            )
+        })
+    }
+}
+
+/// Credentials of the original request, the `Authorization` or `Cookie` header, taken from a field of every record,
+/// in the format `Header-Name=field`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AuthFromColumn(pub(crate) HeaderFromColumn);
+
+impl FromStr for AuthFromColumn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let header = s.parse::<HeaderFromColumn>()?;
+        if header.name != AUTHORIZATION && header.name != COOKIE {
+            anyhow::bail!("expected the `Authorization` or the `Cookie` header");
+        }
+
+        Ok(Self(header))
+    }
+}
+
+/// Response header that every response is expected to have, with a value matching a regular expression, in the
+/// format `Header-Name: regex`.
+#[derive(Debug, Clone)]
+pub(crate) struct ExpectedHeader {
+    pub(crate) name: HeaderName,
+    pub(crate) pattern: Regex,
+}
+
+impl ExpectedHeader {
+    /// Whether the header is present in `headers` with a matching value. A header that is present multiple times
+    /// matches if any of its values does.
+    pub(crate) fn matches(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(&self.name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| self.pattern.is_match(value))
+    }
+}
+
+impl FromStr for ExpectedHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, pattern)) = s.split_once(':') else {
+            anyhow::bail!("expected `Header-Name: regex`");
+        };
+        let name = HeaderName::from_str(name.trim())
+            .with_context(|| format!("invalid header name `{}`", name))?;
+        let pattern = pattern.trim();
+        let pattern =
+            Regex::new(pattern).with_context(|| format!("invalid regex `{}`", pattern))?;
+
+        Ok(Self { name, pattern })
+    }
+}
+
+/// `Host` header for `--preserve-host` from the domain name of a record, or `None` if it has none.
+pub(crate) fn host(domain_name: Option<&str>) -> Result<Option<HeaderValue>> {
+    let Some(domain_name) = domain_name.filter(|domain_name| !domain_name.trim().is_empty()) else {
+        return Ok(None);
+    };
+    HeaderValue::from_str(domain_name.trim())
+        .map(Some)
+        .map_err(|_| anyhow::anyhow!("invalid domain name `{}`", domain_name))
+}
+
+/// Client IP address in a field of a record, which can include a port, like the `client:port` field of AWS
+/// Application Load Balancer logs.
+pub(crate) fn client_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+/// Stable ID of a record, to correlate a replayed request with the logs of the target.
+///
+/// The ID is the 64-bit FNV-1a hash, as 16 hexadecimal digits, of the timestamp of the record (as RFC3339 in UTC with
+/// nanoseconds, e.g. `2024-06-01T12:00:00.000000000Z`), its path and its parameters, each followed by a newline. The
+/// same record gets the same ID in every run, and records that are identical in these fields share their ID.
+pub(crate) fn correlation_id(timestamp: Epoch, path: &str, parameters: &str) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let (year, month, day, hour, minute, second, nanos) = timestamp.to_gregorian_utc();
+    let input = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z\n{}\n{}\n",
+        year, month, day, hour, minute, second, nanos, path, parameters
+    );
+    let hash = input.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{
+        HOST,
+        REFERER,
+        USER_AGENT,
+    };
+
+    use super::*;
+
+    #[test]
+    fn header_from_column_parses() {
+        let header = "User-Agent=user_agent".parse::<HeaderFromColumn>().unwrap();
+        assert_eq!(header.name, USER_AGENT);
+        assert_eq!(header.column, "user_agent");
+        assert!("User-Agent".parse::<HeaderFromColumn>().is_err());
+        assert!("User-Agent=".parse::<HeaderFromColumn>().is_err());
+    }
+
+    #[test]
+    fn header_from_column_rebuilds_headers() {
+        let user_agent = "User-Agent=user_agent".parse::<HeaderFromColumn>().unwrap();
+        let referer = "Referer=referer".parse::<HeaderFromColumn>().unwrap();
+
+        assert_eq!(
+            user_agent
+                .value(Some("Mozilla/5.0 (X11; Linux x86_64)"))
+                .unwrap(),
+            Some(HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64)"))
+        );
+        assert_eq!(
+            referer
+                .value(Some("https://www.example.com/search?q=boxes"))
+                .unwrap(),
+            Some(HeaderValue::from_static(
+                "https://www.example.com/search?q=boxes"
+            ))
+        );
+        assert_eq!(referer.name, REFERER);
+    }
+
+    #[test]
+    fn header_from_column_skips_absent_and_empty_fields() {
+        let referer = "Referer=referer".parse::<HeaderFromColumn>().unwrap();
+
+        assert_eq!(referer.value(None).unwrap(), None);
+        assert_eq!(referer.value(Some("")).unwrap(), None);
+        assert_eq!(referer.value(Some("  ")).unwrap(), None);
+    }
+
+    #[test]
+    fn header_from_column_rejects_invalid_values() {
+        let user_agent = "User-Agent=user_agent".parse::<HeaderFromColumn>().unwrap();
+
+        assert_eq!(
+            user_agent
+                .value(Some("line\nbreak"))
+                .unwrap_err()
+                .to_string(),
+            "invalid value for the `user-agent` header in field `user_agent`"
+        );
+    }
+
+    #[test]
+    fn host_is_sent_instead_of_the_target() {
+        let request = reqwest::Client::new()
+            .get("http://10.0.0.17:8080/products")
+            .header(HOST, host(Some(" tenant.example.com ")).unwrap().unwrap())
+            .build()
+            .unwrap();
+
+        assert_eq!(request.url().host_str(), Some("10.0.0.17"));
+        assert_eq!(request.headers()[HOST], "tenant.example.com");
+        assert_eq!(request.headers().get_all(HOST).iter().count(), 1);
+    }
+
+    #[test]
+    fn host_requires_a_domain_name() {
+        assert_eq!(host(None).unwrap(), None);
+        assert_eq!(host(Some("")).unwrap(), None);
+        assert_eq!(
+            host(Some("tenant\n.example.com")).unwrap_err().to_string(),
+            "invalid domain name `tenant\n.example.com`"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Probe of the targets before a replay starts, so that a long replay isn't started against a target that is down.
+
+use std::collections::BTreeSet;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::Duration;
+use reqwest::{
+    Client,
+    Url,
+};
+
+use crate::{
+    sigv4::Signer,
+    token::TokenSource,
+};
+
+/// Send a GET-request for `path` to every distinct target of `urls`, failing unless all of them respond with a success
+/// status within `timeout`. With a signer the requests are signed, and with a token source authorized, like those
+/// of the replay.
+pub(crate) async fn check<'a>(
+    client: &Client,
+    signer: Option<&Signer>,
+    token_source: Option<&TokenSource>,
+    urls: impl IntoIterator<Item = &'a Url>,
+    path: &str,
+    timeout: Duration,
+) -> Result<()> {
+    if !path.starts_with('/') {
+        anyhow::bail!(
+            "--healthcheck must be a path starting with `/`, got `{}`",
+            path
+        );
+    }
+    let mut targets = BTreeSet::new();
+    for url in urls {
+        targets.insert(
+            url.join(path)
+                .with_context(|| format!("invalid --healthcheck path `{}`", path))?,
+        );
+    }
+
+    for target in targets {
+        let mut request = client.get(target.clone()).build()?;
+        if let Some(signer) = signer {
+            signer.sign(&mut request, None).await?;
+        }
+        if let Some(token_source) = token_source {
+            token_source.authorize(&mut request).await;
+        }
+        let response = tokio::time::timeout(timeout.into(), client.execute(request))
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Healthcheck of {} got no response within {}, not starting the replay",
+                    target,
+                    timeout
+                )
+            })?
+            .with_context(|| {
+                format!("Healthcheck of {} failed, not starting the replay", target)
+            })?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!(
+                "Healthcheck of {} responded with status {}, not starting the replay",
+                target,
+                status
+            );
+        }
+        tracing::info!("Healthcheck of {} responded with status {}", target, status);
+    }
+
+    Ok(())
+}
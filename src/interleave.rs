@@ -0,0 +1,60 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replaying multiple input files as independent streams, e.g. one per tenant, with `--interleave`.
+
+use std::{
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::Result;
+
+/// Tag that holds the input file a request was read from, when interleaving.
+pub(crate) const STREAM_TAG: &str = "stream";
+
+/// An input file that is replayed alongside the others, optionally against its own target.
+#[derive(Debug, Clone)]
+pub(crate) struct Stream {
+    pub(crate) path: PathBuf,
+    pub(crate) scheme_and_host: Option<String>,
+}
+
+impl FromStr for Stream {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Only a scheme and host after the last `=` is taken as the target, so that file names can contain `=`.
+        let (path, scheme_and_host) = match s.rsplit_once('=') {
+            Some((path, scheme_and_host)) if scheme_and_host.contains("://") => {
+                (path, Some(scheme_and_host))
+            }
+            _ => (s, None),
+        };
+        if path.is_empty() {
+            anyhow::bail!("path must not be empty");
+        }
+        if let Some(scheme_and_host) = scheme_and_host {
+            reqwest::Url::parse(scheme_and_host)
+                .map_err(|err| anyhow::anyhow!("invalid target `{}`: {}", scheme_and_host, err))?;
+        }
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            scheme_and_host: scheme_and_host.map(ToOwned::to_owned),
+        })
+    }
+}
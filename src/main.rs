@@ -14,29 +14,92 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+mod adaptive;
+mod assertion;
+mod baseline;
+mod body;
+mod body_diff;
+mod breaker;
+mod combined;
+mod config;
+mod connections;
 mod de;
+mod empty;
+mod estimate;
+mod events;
+mod filter;
+mod follow;
+mod grpc;
+mod headers;
+mod healthcheck;
+mod interleave;
+mod mapping;
+mod normalize;
+mod openapi;
+mod order;
+mod output;
+mod pause;
+mod profile;
+mod protocol;
+mod proxy;
+mod redirect;
+mod remaining;
+mod replay;
+mod requests;
+mod resolve;
+mod results;
+mod retry;
+mod safe;
+mod schedule;
 mod ser;
+mod sigv4;
+mod sink;
+mod sketch;
+mod summary;
+mod throttle;
+mod tls;
+mod token;
+mod tui;
+mod url_length;
+mod wasm;
+mod websocket;
+mod writer;
 
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
     fs::File,
     io::{
         self,
+        BufRead,
         BufReader,
+        IsTerminal,
         Write,
     },
     path::{
         Path,
         PathBuf,
     },
-    sync::Arc,
-    time::Instant,
+    process::ExitCode,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
 };
 
-use anyhow::Result;
+use anyhow::{
+    Context,
+    Result,
+};
 use clap::{
     Args,
     Parser,
     Subcommand,
+    ValueEnum,
 };
 use hifitime::{
     Duration,
@@ -47,13 +110,17 @@ use indicatif::{
     ProgressStyle,
 };
 use reqwest::{
+    header::{
+        HeaderMap,
+        HeaderValue,
+        ACCEPT_ENCODING,
+        CONNECTION,
+    },
     Client,
     Request,
+    Url,
 };
-use serde::{
-    Deserialize,
-    Serialize,
-};
+use serde::Deserialize;
 use tracing_subscriber::{
     filter::{
         EnvFilter,
@@ -63,7 +130,7 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct AccessLogRecord {
     #[serde(
         rename = "@timestamp",
@@ -75,17 +142,237 @@ struct AccessLogRecord {
     parameters: String,
     #[serde(rename = "target_processing_time")]
     required_time: f64,
+    /// Status code of the original response, used to select records with `--original-status`.
+    #[serde(default)]
+    status: Option<u16>,
+    /// Size of the original response, in bytes, used to pace requests with `--target-bandwidth`.
+    #[serde(default)]
+    sent_bytes: Option<u64>,
+    /// Size of the original request, in bytes, used to pace requests with `--target-bandwidth`.
+    #[serde(default)]
+    received_bytes: Option<u64>,
+    /// Domain name the original request was sent to, used to look up where to replay it with a mapping.
+    #[serde(default)]
+    domain_name: Option<String>,
+    /// Scheme of the original request, `http` or `https`, used to keep it with `--preserve-scheme`.
+    #[serde(default)]
+    original_scheme: Option<String>,
+    /// All other fields of the record, which are only used if requested by an option.
+    #[serde(flatten)]
+    fields: BTreeMap<String, serde_json::Value>,
+    /// The record as it was read from the input file.
+    #[serde(skip)]
+    raw: Option<remaining::RawRecord>,
 }
 
 #[derive(Debug, Deserialize)]
 struct JsonAccessLogRecord {
+    /// The fields of the record, kept untyped until the configured field paths have been resolved.
     #[serde(rename = "_source")]
-    source: AccessLogRecord,
+    source: serde_json::Value,
+}
+
+/// Where the fields that `AccessLogRecord` requires are found in the input file, if not under their default names.
+#[derive(Debug, Args)]
+struct InputFields {
+    /// Field containing the timestamp of the request, instead of `@timestamp`.
+    ///
+    /// In JSON files this can be a dotted path into nested objects, e.g. `event.created`. In CSV files it is the name
+    /// of a column.
+    #[arg(long, value_name = "PATH")]
+    timestamp_field: Option<String>,
+    /// Time zone of the timestamps that have no offset, e.g. `Europe/Berlin`, instead of UTC.
+    ///
+    /// Timestamps with an offset, like `2024-06-01T12:00:00+02:00`, or `Z` for UTC, are read as they are. Records
+    /// whose local time is ambiguous or doesn't exist in the time zone, around a change to or from daylight saving
+    /// time, fail to parse. Timestamps of combined logs always have an offset.
+    #[arg(long, value_name = "TZ")]
+    assume_timezone: Option<de::AssumedTimeZone>,
+    /// Field containing the time the original request took, in seconds, instead of `target_processing_time`.
+    ///
+    /// In JSON files this can be a dotted path into nested objects, e.g. `http.response.duration`. In CSV files it is
+    /// the name of a column.
+    #[arg(long, value_name = "PATH")]
+    latency_field: Option<String>,
+    /// Format of the input file.
+    ///
+    /// By default the format is determined by the extension of the file, `.csv`, `.json` or `.log` (combined).
+    /// Files with another extension, or none at all, are read as JSON if their content starts with `{` or `[`, and
+    /// as CSV otherwise.
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormat>,
+    /// Unit of the time the original request took, in `target_processing_time` or `--latency-field`.
+    ///
+    /// The time is converted to seconds, which are what the replayed requests are compared against in
+    /// `change_percentage`. Filters with `--filter` still compare the value in the input file as it is.
+    #[arg(long, value_enum, default_value_t = LatencyUnit::Seconds)]
+    latency_unit: LatencyUnit,
+}
+
+/// Unit of the latencies in an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LatencyUnit {
+    Seconds,
+    Millis,
+    Micros,
+}
+
+impl LatencyUnit {
+    fn to_seconds(self, latency: f64) -> f64 {
+        match self {
+            LatencyUnit::Seconds => latency,
+            LatencyUnit::Millis => latency / 1e3,
+            LatencyUnit::Micros => latency / 1e6,
+        }
+    }
+}
+
+/// Point of the original requests that the replay reproduces the spacing of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OffsetBasis {
+    /// When the request was received, its timestamp.
+    RequestStart,
+    /// When the response was complete, its timestamp plus the latency of the original request.
+    ResponseEnd,
+}
+
+impl OffsetBasis {
+    fn timestamp(self, record: &AccessLogRecord) -> Epoch {
+        match self {
+            OffsetBasis::RequestStart => record.timestamp,
+            // Load balancers log a latency of -1 for requests that didn't get a response.
+            OffsetBasis::ResponseEnd => {
+                record.timestamp + Duration::from_seconds(record.required_time.max(0f64))
+            }
+        }
+    }
+}
+
+/// How records are weighted by `--weight-column`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum WeightMode {
+    /// Replay every record as many times as its weight, at the same offset.
+    Expand,
+    /// Replay every record once, and count its response as many requests as its weight in the summary.
+    Scale,
+}
+
+/// Format of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Csv,
+    /// Newline-delimited JSON objects.
+    Json,
+    /// Common or combined log format of Apache and nginx.
+    Combined,
+}
+
+impl InputFormat {
+    /// Determine the format by the extension of the file, falling back to the first character of its content.
+    fn of_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => return Ok(InputFormat::Csv),
+            Some("json") => return Ok(InputFormat::Json),
+            Some("log") => return Ok(InputFormat::Combined),
+            _ => {}
+        }
+
+        let mut reader = BufReader::new(
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+        );
+        let first_char = loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break None;
+            }
+            let len = buffer.len();
+            match buffer
+                .iter()
+                .position(|byte| !(byte.is_ascii_whitespace() || b"\xef\xbb\xbf".contains(byte)))
+            {
+                Some(position) => break Some(buffer[position]),
+                None => reader.consume(len),
+            }
+        };
+        let format = match first_char {
+            Some(b'{' | b'[') => InputFormat::Json,
+            _ => InputFormat::Csv,
+        };
+        tracing::debug!("Reading {} as {:?}, by its content", path.display(), format);
+
+        Ok(format)
+    }
+}
+
+/// Which of the records in the input file to keep.
+#[derive(Debug, Args)]
+struct RecordSelection {
+    /// Only keep records matching the given expression.
+    ///
+    /// Example: `target_processing_time > 0.5 && path =~ "^/api/"`.
+    ///
+    /// Fields are referenced by their name in the input file, with dots reaching into nested JSON objects, and
+    /// compared with `==`, `!=`, `<`, `<=`, `>`, `>=` or `=~` (regular expression match) against numbers or quoted
+    /// strings. Comparisons can be combined with `&&`, `||`, `!` and parentheses. A field on its own matches if it is
+    /// present and not `null`, `false`, `0` or empty.
+    #[arg(long, value_name = "EXPR")]
+    filter: Option<filter::Filter>,
+    /// Only keep records whose original response had one of the given status codes or classes, e.g. `5xx,404`.
+    ///
+    /// This selects records by the status recorded in the input file, in its `status` field, which has to be present
+    /// for every record. It is unrelated to the status of the replayed requests.
+    #[arg(long, value_name = "CLASSES", value_delimiter = ',')]
+    original_status: Vec<filter::StatusClass>,
+    /// Drop the first N of the records, after they have been selected and sorted.
+    ///
+    /// This jumps to a later part of the input file, e.g. to the part where a failure occurred, without replaying
+    /// everything before it. The offsets of the remaining records are relative to the first of them. Combined with
+    /// `--limit` this selects any window of records by their index.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip: usize,
+    /// Keep at most N of the records, after they have been selected, sorted and `--skip` has been applied.
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
 }
 
-impl From<JsonAccessLogRecord> for AccessLogRecord {
-    fn from(json_record: JsonAccessLogRecord) -> Self {
-        json_record.source
+impl RecordSelection {
+    fn matches(&self, record: &AccessLogRecord) -> Result<bool> {
+        if !self.original_status.is_empty() {
+            let status = record.status.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Record at {} has no `status` field, which --original-status requires",
+                    record.timestamp
+                )
+            })?;
+            if !self
+                .original_status
+                .iter()
+                .any(|status_class| status_class.matches(status))
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(self
+            .filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(&record.to_value())))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.filter.is_none() && self.original_status.is_empty()
+    }
+}
+
+impl InputFields {
+    /// Pairs of the configured field and the field of `AccessLogRecord` it replaces.
+    fn renames(&self) -> impl Iterator<Item = (&str, &'static str)> {
+        [
+            (self.timestamp_field.as_deref(), "@timestamp"),
+            (self.latency_field.as_deref(), "target_processing_time"),
+        ]
+        .into_iter()
+        .filter_map(|(field, target)| Some((field?, target)))
     }
 }
 
@@ -94,69 +381,473 @@ struct RequestWithOffset {
     offset: Duration,
     request: Request,
     record: AccessLogRecord,
+    /// ID sent in `--correlation-header`, if requested.
+    correlation_id: Option<String>,
+    /// File to stream the body of the request from, with `--body-from-file-column`.
+    body_file: Option<PathBuf>,
+    /// Values of the `--tag-column` fields of the record, by field name.
+    tags: BTreeMap<String, String>,
+    /// Whether the request is a gRPC call, with `--grpc`.
+    grpc: bool,
+    /// Completion of the request this one has to wait for, with `--match-concurrency`.
+    after: Option<tokio::sync::oneshot::Receiver<()>>,
+    /// Dropped once the request completed, to let the request waiting for it proceed.
+    done: Option<tokio::sync::oneshot::Sender<()>>,
+    /// Number of requests the response counts as in the summary, with `--weight-mode scale`.
+    weight: u64,
+}
+
+impl RequestWithOffset {
+    /// Copy of the request at another offset, to send the same record more than once.
+    fn clone_at(&self, offset: Duration) -> Self {
+        Self {
+            offset,
+            request: self
+                .request
+                .try_clone()
+                .expect("requests without a streaming body can always be cloned"),
+            record: self.record.clone(),
+            correlation_id: self.correlation_id.clone(),
+            body_file: self.body_file.clone(),
+            tags: self.tags.clone(),
+            grpc: self.grpc,
+            after: None,
+            done: None,
+            weight: self.weight,
+        }
+    }
 }
 
 impl AccessLogRecord {
-    fn records_from_path<P: AsRef<Path>>(path: P) -> Result<Vec<AccessLogRecord>> {
-        let mut records = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
-            Some("csv") => Self::records_from_csv_path(path),
-            Some("json") => Self::records_from_json_path(path),
-            Some(ext) => anyhow::bail!("Unknown file extension: {}", ext),
-            None => anyhow::bail!("Can't determine file-type"),
-        }?;
-        records.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap());
+    /// Parse the records of the provided file, keeping only those that are selected, sorted by timestamp unless
+    /// `sort` is `false`. Sorting is stable, so records with the same timestamp keep their order.
+    ///
+    /// Fails on the first record that fails to parse, unless bad records should be skipped, in which case the number
+    /// of skipped records is returned alongside the records.
+    fn records_from_path<P: AsRef<Path>>(
+        path: P,
+        input_fields: &InputFields,
+        selection: &RecordSelection,
+        skip_bad_records: bool,
+        sort: bool,
+    ) -> Result<(Vec<AccessLogRecord>, usize)> {
+        Self::select_records(
+            Self::parse_records_from_path(path, input_fields)?,
+            input_fields,
+            selection,
+            skip_bad_records,
+            sort,
+        )
+    }
 
-        Ok(records)
+    /// Keep the parsed records that are selected, like `records_from_path`.
+    fn select_records(
+        parsed: Vec<Result<AccessLogRecord>>,
+        input_fields: &InputFields,
+        selection: &RecordSelection,
+        skip_bad_records: bool,
+        sort: bool,
+    ) -> Result<(Vec<AccessLogRecord>, usize)> {
+        let mut records = Vec::new();
+        let mut skipped = 0usize;
+        for record in parsed {
+            match record {
+                Ok(record) => records.push(record),
+                Err(err) if skip_bad_records => {
+                    skipped += 1;
+                    tracing::warn!("Skipping bad record: {}", err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if skipped > 0 {
+            tracing::warn!("Skipped {} bad records", skipped);
+        }
+        if !selection.is_empty() {
+            let total = records.len();
+            let mut selected = Vec::with_capacity(total);
+            for record in records {
+                if selection.matches(&record)? {
+                    selected.push(record);
+                }
+            }
+            records = selected;
+            tracing::info!("Selected {} of {} records", records.len(), total);
+        }
+        for record in &mut records {
+            record.required_time = input_fields.latency_unit.to_seconds(record.required_time);
+        }
+        if sort {
+            records.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap());
+        }
+        if selection.skip > 0 || selection.limit.is_some() {
+            let total = records.len();
+            records = records
+                .into_iter()
+                .skip(selection.skip)
+                .take(selection.limit.unwrap_or(usize::MAX))
+                .collect();
+            tracing::info!(
+                "Kept {} of {} records, starting at record {}",
+                records.len(),
+                total,
+                selection.skip + 1
+            );
+        }
+
+        Ok((records, skipped))
     }
 
-    fn records_from_csv_path<P: AsRef<Path>>(path: P) -> Result<Vec<AccessLogRecord>> {
-        let reader = csv::Reader::from_path(path)?;
-        reader
-            .into_deserialize::<AccessLogRecord>()
-            .map(|row| row.map_err(Into::into))
-            .collect::<Result<Vec<_>>>()
+    /// Parse the records of the provided file, in file order, without stopping at the first record that fails to
+    /// parse.
+    fn parse_records_from_path<P: AsRef<Path>>(
+        path: P,
+        input_fields: &InputFields,
+    ) -> Result<Vec<Result<AccessLogRecord>>> {
+        let format = match input_fields.input_format {
+            Some(format) => format,
+            None => InputFormat::of_path(path.as_ref())?,
+        };
+        Self::parse_records(BufReader::new(File::open(path)?), format, input_fields)
     }
 
-    fn records_from_json_path<P: AsRef<Path>>(path: P) -> Result<Vec<AccessLogRecord>> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        serde_json::Deserializer::from_reader(reader)
-            .into_iter::<JsonAccessLogRecord>()
-            .map(|item| item.map(Into::<AccessLogRecord>::into).map_err(Into::into))
-            .collect()
+    /// Parse the records read from `reader`, like `parse_records_from_path`.
+    fn parse_records(
+        reader: impl BufRead,
+        format: InputFormat,
+        input_fields: &InputFields,
+    ) -> Result<Vec<Result<AccessLogRecord>>> {
+        match format {
+            InputFormat::Csv => Self::records_from_csv(reader, input_fields),
+            InputFormat::Json => Self::records_from_json(reader, input_fields),
+            InputFormat::Combined => Self::records_from_combined_log(reader, input_fields),
+        }
     }
 
-    fn requests_from_path<P: AsRef<Path>>(
-        path: P,
-        client: &Client,
-        scheme_and_host: &str,
-        time_factor: Option<f64>,
-    ) -> Result<Vec<RequestWithOffset>> {
-        let mut first_timestamp = None;
-        Self::records_from_path(path)?
-            .into_iter()
-            .map(|record| {
-                let time_factor = time_factor.unwrap_or(1f64);
-                let offset = first_timestamp
-                    .map(|first_timestamp| record.timestamp - first_timestamp)
-                    .unwrap_or_default()
-                    * time_factor;
-                first_timestamp.get_or_insert(record.timestamp);
-
-                client
-                    .get(format!(
-                        "{}{}{}",
-                        scheme_and_host, record.path, record.parameters
-                    ))
-                    .build()
-                    .map(|request| RequestWithOffset {
-                        offset,
-                        request,
-                        record,
-                    })
-                    .map_err(Into::into)
+    fn records_from_csv(
+        reader: impl BufRead,
+        input_fields: &InputFields,
+    ) -> Result<Vec<Result<AccessLogRecord>>> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let mut headers = reader
+            .headers()?
+            .iter()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        for (field, target) in input_fields.renames() {
+            let column = headers
+                .iter_mut()
+                .find(|header| *header == field)
+                .ok_or_else(|| anyhow::anyhow!("Missing column `{}`", field))?;
+            *column = target.to_owned();
+        }
+        let timestamp_column = headers.iter().position(|header| header == "@timestamp");
+        let headers = csv::StringRecord::from(headers);
+        Ok(reader
+            .into_records()
+            .map(|row| {
+                let row = row?;
+                let mut record = match (&input_fields.assume_timezone, timestamp_column) {
+                    (Some(zone), Some(column)) => match zone.apply(&row[column])? {
+                        Some(timestamp) => row
+                            .iter()
+                            .enumerate()
+                            .map(|(index, value)| if index == column { &timestamp } else { value })
+                            .collect::<csv::StringRecord>()
+                            .deserialize::<AccessLogRecord>(Some(&headers))?,
+                        None => row.deserialize::<AccessLogRecord>(Some(&headers))?,
+                    },
+                    _ => row.deserialize::<AccessLogRecord>(Some(&headers))?,
+                };
+                record.raw = Some(remaining::RawRecord::Csv(row));
+                Ok(record)
             })
-            .collect::<Result<Vec<_>>>()
+            .collect())
+    }
+
+    /// Parse a stream of JSON values, every one of them a record, or an array of records.
+    ///
+    /// Values can span multiple lines. A value that isn't valid JSON is reported as a bad record, and parsing resumes
+    /// at the line after the error, so that `--skip-bad-records` can skip it. Without it, parsing fails at the first
+    /// bad record, as always.
+    fn records_from_json(
+        mut reader: impl BufRead,
+        input_fields: &InputFields,
+    ) -> Result<Vec<Result<AccessLogRecord>>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut lines = LineCounter::default();
+        let mut records = Vec::new();
+        let mut start = 0;
+        'values: while start < data.len() {
+            let rest = &data[start..];
+            let start_line = lines.line_at(&data, start);
+            let mut values =
+                serde_json::Deserializer::from_slice(rest).into_iter::<serde_json::Value>();
+            let mut end = 0;
+            while let Some(value) = values.next() {
+                let value_start = start
+                    + end
+                    + rest[end..]
+                        .iter()
+                        .take_while(|byte| byte.is_ascii_whitespace())
+                        .count();
+                let line = lines.line_at(&data, value_start);
+                match value {
+                    Ok(serde_json::Value::Array(elements)) => {
+                        for (index, element) in elements.into_iter().enumerate() {
+                            records.push(
+                                Self::record_from_json_value(element, input_fields).map_err(
+                                    |err| {
+                                        anyhow::anyhow!(
+                                            "{} in element {} of the array at line {}",
+                                            err,
+                                            index,
+                                            line
+                                        )
+                                    },
+                                ),
+                            );
+                        }
+                    }
+                    Ok(value) => records.push(
+                        Self::record_from_json_value(value, input_fields)
+                            .map_err(|err| anyhow::anyhow!("{} at line {}", err, line)),
+                    ),
+                    Err(err) => {
+                        // Errors of the JSON parser are positioned relative to where parsing resumed.
+                        let message = err.to_string();
+                        let suffix = format!(" at line {} column {}", err.line(), err.column());
+                        let error_line = start_line + err.line().saturating_sub(1);
+                        records.push(Err(anyhow::anyhow!(
+                            "{} at line {} column {}",
+                            message.strip_suffix(&suffix).unwrap_or(&message),
+                            error_line,
+                            err.column()
+                        )));
+                        let error_offset = offset_of(rest, err.line(), err.column());
+                        match rest[error_offset..].iter().position(|byte| *byte == b'\n') {
+                            Some(newline) => {
+                                start += error_offset + newline + 1;
+                                continue 'values;
+                            }
+                            None => break 'values,
+                        }
+                    }
+                }
+                end = values.byte_offset();
+            }
+            break;
+        }
+
+        Ok(records)
+    }
+
+    /// Parse an access log in the common or combined log format, line by line.
+    ///
+    /// The method, the client address, the referer and the user agent are kept in the fields `method`,
+    /// `remote_addr`, `referer` and `user_agent`. Lines without a latency at their end, as in the formats' basic
+    /// form, have no original time to compare against, so their `change_percentage` is skipped.
+    fn records_from_combined_log(
+        reader: impl BufRead,
+        input_fields: &InputFields,
+    ) -> Result<Vec<Result<AccessLogRecord>>> {
+        if input_fields.renames().next().is_some() {
+            anyhow::bail!("--timestamp-field and --latency-field don't apply to combined logs");
+        }
+        let parser = combined::Parser::new();
+        let mut records = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = parser
+                .parse(&line)
+                .map(|parsed| {
+                    let mut fields = BTreeMap::new();
+                    for (name, value) in [
+                        ("method", Some(parsed.method)),
+                        ("remote_addr", Some(parsed.remote_addr)),
+                        ("remote_user", parsed.remote_user),
+                        ("protocol", parsed.protocol),
+                        ("referer", parsed.referer),
+                        ("user_agent", parsed.user_agent),
+                    ] {
+                        if let Some(value) = value {
+                            fields.insert(name.to_owned(), value.into());
+                        }
+                    }
+                    AccessLogRecord {
+                        timestamp: parsed.timestamp,
+                        path: parsed.path,
+                        parameters: parsed.query,
+                        required_time: parsed.latency.unwrap_or(f64::NAN),
+                        status: Some(parsed.status),
+                        sent_bytes: parsed.body_bytes,
+                        received_bytes: None,
+                        domain_name: None,
+                        original_scheme: None,
+                        fields,
+                        raw: Some(remaining::RawRecord::Line(line)),
+                    }
+                })
+                .map_err(|err| anyhow::anyhow!("{} at line {}", err, index + 1));
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    fn record_from_json_value(
+        raw: serde_json::Value,
+        input_fields: &InputFields,
+    ) -> Result<AccessLogRecord> {
+        let mut source = JsonAccessLogRecord::deserialize(&raw)?.source;
+        for (field, target) in input_fields.renames() {
+            let value = de::value_at_path(&source, field)?.clone();
+            source
+                .as_object_mut()
+                .ok_or_else(|| anyhow::anyhow!("`_source` is not an object"))?
+                .insert(target.to_owned(), value);
+        }
+        if let Some(zone) = &input_fields.assume_timezone {
+            if let Some(serde_json::Value::String(timestamp)) = source.get_mut("@timestamp") {
+                if let Some(zoned) = zone.apply(timestamp)? {
+                    *timestamp = zoned;
+                }
+            }
+        }
+        let mut record = serde_json::from_value::<AccessLogRecord>(source)?;
+        record.raw = Some(remaining::RawRecord::Json(raw));
+        Ok(record)
+    }
+
+    /// The record as a JSON object with the field names of the input file, for evaluating filters against.
+    fn to_value(&self) -> serde_json::Value {
+        let mut value = serde_json::Map::from_iter(self.fields.clone());
+        value.insert("@timestamp".to_owned(), self.timestamp.to_string().into());
+        value.insert("path".to_owned(), self.path.clone().into());
+        value.insert("params".to_owned(), self.parameters.clone().into());
+        value.insert(
+            "target_processing_time".to_owned(),
+            self.required_time.into(),
+        );
+        value.insert("status".to_owned(), self.status.into());
+        value.insert("sent_bytes".to_owned(), self.sent_bytes.into());
+        value.insert("received_bytes".to_owned(), self.received_bytes.into());
+        value.insert("domain_name".to_owned(), self.domain_name.clone().into());
+        value.insert(
+            "original_scheme".to_owned(),
+            self.original_scheme.clone().into(),
+        );
+
+        serde_json::Value::Object(value)
+    }
+
+    /// Whether the original request was a WebSocket upgrade, according to the `type` field of AWS Application Load
+    /// Balancer logs, or because it is requested with a `ws` or `wss` URL.
+    fn is_websocket(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "ws" | "wss")
+            || self
+                .field("type")
+                .is_some_and(|connection_type| matches!(&*connection_type, "ws" | "wss"))
+    }
+
+    /// Whether the original request was a gRPC call, according to the `type` field of AWS Application Load Balancer
+    /// logs.
+    fn is_grpc(&self) -> bool {
+        self.field("type")
+            .is_some_and(|connection_type| matches!(&*connection_type, "grpc" | "grpcs"))
+    }
+
+    /// Replace the scheme of `url` by the scheme of the original request, from the `original_scheme` field.
+    fn preserve_scheme(&self, url: &mut Url) -> Result<()> {
+        let original_scheme = self.original_scheme.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Record at {} has no `original_scheme` field, which --preserve-scheme requires",
+                self.timestamp
+            )
+        })?;
+        let original_scheme = original_scheme.to_ascii_lowercase();
+        if !matches!(&*original_scheme, "http" | "https" | "ws" | "wss") {
+            anyhow::bail!(
+                "Record at {} has an unsupported `original_scheme`: {}",
+                self.timestamp,
+                original_scheme
+            );
+        }
+        url.set_scheme(&original_scheme)
+            .map_err(|()| anyhow::anyhow!("Can't replace the scheme of {}", url))
+    }
+
+    /// Value of one of the additional fields of the record, as a string.
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match self.fields.get(name)? {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(value) => Some(Cow::Borrowed(value)),
+            value => Some(Cow::Owned(value.to_string())),
+        }
+    }
+
+    /// Weight of the record in the given field, with `--weight-column`, unless the field is missing or empty.
+    fn weight(&self, field: &str) -> Result<Option<u64>> {
+        let Some(value) = self.field(field).filter(|value| !value.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let weight = value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|weight| weight.is_finite() && *weight > 0.)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Record at {} has an invalid weight `{}`, expected a positive number",
+                    self.timestamp,
+                    value
+                )
+            })?;
+        Ok(Some((weight.round() as u64).max(1)))
+    }
+
+    /// URL to replay this record against.
+    ///
+    /// Without a URL field the URL is composed of the scheme and host, the path and the parameters. With a URL field
+    /// the URL is taken from that field instead, and only its scheme, host and port are replaced by the scheme and
+    /// host, if provided.
+    fn url(&self, scheme_and_host: Option<&str>, url_field: Option<&str>) -> Result<Url> {
+        let Some(url_field) = url_field else {
+            return Ok(Url::parse(&format!(
+                "{}{}{}",
+                scheme_and_host.unwrap_or_default(),
+                self.path,
+                self.parameters
+            ))?);
+        };
+
+        let original = self
+            .field(url_field)
+            .ok_or_else(|| anyhow::anyhow!("Record has no field `{}`", url_field))?;
+        let mut url = Url::parse(&original)
+            .ok()
+            .filter(Url::has_host)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Field `{}` does not contain an absolute URL: {}",
+                    url_field,
+                    original
+                )
+            })?;
+        if let Some(scheme_and_host) = scheme_and_host {
+            let target = Url::parse(scheme_and_host)?;
+            url.set_scheme(target.scheme())
+                .map_err(|()| anyhow::anyhow!("Can't replace the scheme of {}", url))?;
+            url.set_host(target.host_str())?;
+            url.set_port(target.port())
+                .map_err(|()| anyhow::anyhow!("Can't replace the port of {}", url))?;
+        }
+
+        Ok(url)
     }
 }
 
@@ -165,12 +856,20 @@ impl AccessLogRecord {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Only log warnings and errors.
+    ///
+    /// The level can still be set in detail with the `RUST_LOG` environment variable, e.g. `RUST_LOG=r7=debug`.
+    #[arg(short, long, global = true)]
+    quiet: bool,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
+    Convert(Convert),
     Print(Print),
-    Run(Run),
+    Record(Record),
+    Run(Box<Run>),
+    Validate(Validate),
 }
 
 /// Parse the provided file containing at least the fields `@timestamp', `path` and `params`, and print every
@@ -179,191 +878,2372 @@ enum Commands {
 struct Print {
     /// File to parse and print.
     input_file: PathBuf,
+    #[command(flatten)]
+    input_fields: InputFields,
+    /// Which offsets to print for every record.
+    ///
+    /// The offset to the previous record is prefixed with `+`, the offset from the first record with `T+`.
+    #[arg(long, value_enum, default_value_t = Relative::Both)]
+    relative: Relative,
+    #[command(flatten)]
+    selection: RecordSelection,
+    /// Skip records that fail to parse, instead of failing.
+    ///
+    /// Every skipped record is logged, and their number is reported.
+    #[arg(long)]
+    skip_bad_records: bool,
+    /// Succeed without printing anything if the file has no records, or none that are left after filtering.
+    ///
+    /// By default this fails, with exit code 3 for a file without records and 4 for a file whose records were all
+    /// filtered out or skipped.
+    #[arg(long)]
+    allow_empty: bool,
+}
+
+/// Parse the provided file and write its records in the given format, with only the fields `r7` uses, under their
+/// default names, in order (by timestamp).
+///
+/// The optional fields `status`, `sent_bytes`, `received_bytes`, `domain_name` and `original_scheme` are written if
+/// any record has them, other fields only if they are kept with `--keep-field`. Latencies are written in seconds.
+#[derive(Debug, Args)]
+struct Convert {
+    /// File to parse and convert.
+    input_file: PathBuf,
+    #[command(flatten)]
+    input_fields: InputFields,
+    #[command(flatten)]
+    selection: RecordSelection,
+    /// Skip records that fail to parse, instead of failing.
+    ///
+    /// Every skipped record is logged, and their number is reported.
+    #[arg(long)]
+    skip_bad_records: bool,
+    /// Format to write the records in.
+    ///
+    /// Defaults to the format given by the extension of `--output`, `.csv` or `.json`.
+    #[arg(long, value_enum)]
+    output_format: Option<InputFormat>,
+    /// File to write the records to, instead of standard output.
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Additional fields to keep, as a comma-separated list.
+    ///
+    /// In JSON files these can be dotted paths into nested objects, which are written under the path as their name.
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+    keep_field: Vec<String>,
 }
 
-impl Print {
+impl Convert {
     fn run(&self) -> Result<()> {
-        let mut last_timestamp = None;
-        for record in AccessLogRecord::records_from_path(&self.input_file)? {
-            let offset = match last_timestamp {
-                Some(last_timestamp) => record.timestamp - last_timestamp,
-                None => Duration::from_seconds(0.0),
-            };
+        let (records, skipped) = AccessLogRecord::records_from_path(
+            &self.input_file,
+            &self.input_fields,
+            &self.selection,
+            self.skip_bad_records,
+            true,
+        )?;
 
-            println!(
-                "{} {} {}{}",
-                record.timestamp,
-                format_args!("+{:>12}", offset),
-                record.path,
-                record.parameters
-            );
-            last_timestamp = Some(record.timestamp);
+        let mut columns = vec!["@timestamp", "path", "params", "target_processing_time"];
+        for (column, present) in [
+            (
+                "status",
+                records.iter().any(|record| record.status.is_some()),
+            ),
+            (
+                "sent_bytes",
+                records.iter().any(|record| record.sent_bytes.is_some()),
+            ),
+            (
+                "received_bytes",
+                records.iter().any(|record| record.received_bytes.is_some()),
+            ),
+            (
+                "domain_name",
+                records.iter().any(|record| record.domain_name.is_some()),
+            ),
+            (
+                "original_scheme",
+                records
+                    .iter()
+                    .any(|record| record.original_scheme.is_some()),
+            ),
+        ] {
+            if present {
+                columns.push(column);
+            }
+        }
+        columns.extend(self.keep_field.iter().map(String::as_str));
+
+        let mut writer =
+            writer::RecordWriter::new(self.output_format, self.output.as_deref(), &columns)?;
+        for record in &records {
+            let mut value = record.to_value();
+            value["@timestamp"] = de::epoch_as_kibana_timestamp(record.timestamp).into();
+            writer.write(
+                columns
+                    .iter()
+                    .map(|column| {
+                        let field = filter::lookup(&value, column).cloned();
+                        (*column, field.unwrap_or_default())
+                    })
+                    .collect(),
+            )?;
+        }
+        writer.flush()?;
+        tracing::info!("Converted {} records", records.len());
+        if skipped > 0 {
+            tracing::warn!("Skipped bad records: {}", skipped);
         }
 
         Ok(())
     }
 }
 
-/// Replay GET-requests for provided URLs, with accurate relative timing.
+/// Record live traffic as a reverse proxy: forward every request to an upstream, and write it as a record that `run`
+/// replays.
 ///
-/// The command parses the provided file and runs the discovered requests, with accurate relative timing, against the
-/// provided host.
+/// Every record has the fields `@timestamp` (when the request was received), `path`, `params`,
+/// `target_processing_time` (how long the upstream took to respond, in seconds, or `-1` if it didn't), `status`,
+/// `sent_bytes` and `received_bytes` (the size of the response and of the request), `domain_name` (the host the client
+/// requested) and `method`, which `run` replays with `--method-field method`. Records are written as soon as their
+/// response is complete, `run` sorts them by timestamp. Clients have to speak HTTP/1.x, bodies are buffered rather than
+/// streamed, and upgrades like WebSockets aren't supported. Recording stops on CTRL-C.
 #[derive(Debug, Args)]
-struct Run {
-    /// Scheme and host to run the GET-requests against.
-    ///
-    /// Example: `https://my-alternative-service.internal`.
+struct Record {
+    /// Address to listen for requests on.
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+    listen: std::net::SocketAddr,
+    /// Scheme and host of the upstream to forward requests to, e.g. `http://localhost:3000`.
+    #[arg(long, value_name = "URL")]
+    upstream: Url,
+    /// Format to write the records in.
     ///
-    /// The intention of this parameter is to enable replays against a different host than the one the requests were
-    /// originally run against. The most common use-case is taking production traffic and running it against a
-    /// non-production host.
-    #[arg(short, long)]
-    scheme_and_host: String,
-    /// File to parse the GET-requests from.
-    input_file: PathBuf,
-    /// Time in which the requests should be fulfilled, as a factor of the original runtime
-    ///
-    /// A factor smaller than 1 means the requests will finish sooner, e.g. with a factor of 0.5 in half the time
-    /// (double the load), whereas a factor higher than 1 means the requests will finish later, e.g. with a factor of 2
-    /// in double the time (half the load).
-    #[arg(long)]
-    time_factor: Option<f64>,
+    /// Defaults to the format given by the extension of `--output`, `.csv` or `.json`.
+    #[arg(long, value_enum)]
+    output_format: Option<InputFormat>,
+    /// File to write the records to, instead of standard output.
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
 }
 
-impl Run {
+impl Record {
     async fn run(&self) -> Result<()> {
-        let client = Arc::new(Client::new());
-        let requests = AccessLogRecord::requests_from_path(
-            &self.input_file,
-            &client,
-            &self.scheme_and_host,
-            self.time_factor,
-        )?;
-        if requests.is_empty() {
-            anyhow::bail!("No records in provided file");
-        }
-        let last = requests
-            .last()
-            .expect("Vec should be non-empty at this point!");
-        let minimum_expected_runtime = last.offset;
-
+        let mut writer =
+            writer::RecordWriter::new(self.output_format, self.output.as_deref(), &proxy::COLUMNS)?;
+        let listener = tokio::net::TcpListener::bind(self.listen)
+            .await
+            .with_context(|| format!("Failed to listen on {}", self.listen))?;
+        // Responses are passed through as they are, rather than decompressed or followed on redirects.
+        let client = tls::add_root_certificates(Client::builder(), &[])?
+            .no_gzip()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let (exchanges, mut recorded_exchanges) = tokio::sync::mpsc::unbounded_channel();
+        let server = tokio::spawn(proxy::serve(
+            listener,
+            self.upstream.clone(),
+            client,
+            exchanges,
+        ));
         tracing::info!(
-            "Starting to execute {} requests, minimum runtime is: {}",
-            requests.len(),
-            minimum_expected_runtime
+            "Recording requests on {}, forwarding them to {}, stop with CTRL-C",
+            self.listen,
+            self.upstream
         );
 
-        let pb = ProgressBar::new(requests.len() as u64).with_style(ProgressStyle::with_template(
-            "[{elapsed}] {wide_bar} {pos:>7}/{len:7}",
-        )?);
-
-        let mut join_set = tokio::task::JoinSet::new();
-        for request_with_offset in requests {
-            join_set.spawn({
-                let client = client.clone();
-                let pb = pb.clone();
-                async move {
-                    let result = Self::get(&client, request_with_offset).await;
-                    pb.inc(1);
-                    result
-                }
-            });
-        }
-
-        let mut responses: Vec<Result<ResponseDetails>> = Vec::new();
-        let clean_exit = loop {
+        let mut recorded = 0usize;
+        loop {
             tokio::select! {
-                response = join_set.join_next() => {
-                    match response {
-                        Some(response) => responses.push(response?),
-                        None => {
-                            break true
-                        }
-                    }
-                }
-                _ = tokio::signal::ctrl_c() => {
-                    break false
+                exchange = recorded_exchanges.recv() => {
+                    let Some(exchange) = exchange else { break };
+                    writer.write(exchange.fields())?;
+                    writer.flush()?;
+                    recorded += 1;
                 }
-            }
-        };
-
-        let mut stdout = io::stdout().lock();
-        for response_details in responses {
-            match response_details {
-                Ok(response_details) => {
-                    serde_json::to_writer(&mut stdout, &response_details)?;
-                    writeln!(stdout)?;
+                result = tokio::signal::ctrl_c() => {
+                    result?;
+                    break;
                 }
-                Err(err) => eprintln!("{}", err),
             }
         }
-
-        if clean_exit {
-            Ok(())
-        } else {
-            anyhow::bail!("Aborted with CTRL-C")
+        server.abort();
+        while let Ok(exchange) = recorded_exchanges.try_recv() {
+            writer.write(exchange.fields())?;
+            recorded += 1;
         }
-    }
+        writer.flush()?;
+        tracing::info!("Recorded {} requests", recorded);
 
-    async fn get(
-        client: &Client,
-        RequestWithOffset {
-            request,
-            offset,
-            record,
-        }: RequestWithOffset,
-    ) -> Result<ResponseDetails> {
-        tokio::time::sleep(offset.into()).await;
-        let url = request.url().as_str().to_owned();
-        let start = Instant::now();
-        let response = client.execute(request).await?.error_for_status()?;
-        let required_time = Duration::from(start.elapsed());
-        tracing::debug!(
-            "Request={}..., waited_for={}, status={}, required_time={}",
-            &url[..64],
-            offset,
-            response.status(),
-            required_time
-        );
-        let original_time = record.required_time;
-        let change_percentage =
-            ((required_time.to_seconds() - original_time) / original_time) * 100.;
-        Ok(ResponseDetails {
-            url,
-            status: response.status(),
-            required_time,
-            original_time,
-            change_percentage,
-        })
+        Ok(())
     }
 }
 
-#[derive(Debug, Serialize)]
-struct ResponseDetails {
-    url: String,
-    #[serde(serialize_with = "crate::ser::statuscode_as_u16")]
-    status: reqwest::StatusCode,
-    #[serde(serialize_with = "crate::ser::duration_to_seconds")]
-    required_time: Duration,
-    original_time: f64,
-    change_percentage: f64,
+/// Reference point of the offsets printed by `Print`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Relative {
+    /// Offset to the previous record.
+    Previous,
+    /// Offset from the first record.
+    Start,
+    /// Both of the above.
+    Both,
 }
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 64)]
-async fn main() -> Result<()> {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
-        .with(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
+impl Print {
+    fn run(&self) -> Result<()> {
+        let (records, _) = AccessLogRecord::records_from_path(
+            &self.input_file,
+            &self.input_fields,
+            &self.selection,
+            self.skip_bad_records,
+            true,
+        )?;
+        if records.is_empty() {
+            let records =
+                AccessLogRecord::parse_records_from_path(&self.input_file, &self.input_fields)?
+                    .len();
+            return empty::EmptyInput::new(records).fail(self.allow_empty);
+        }
+        let first_timestamp = records.first().map(|record| record.timestamp);
+        let mut last_timestamp = None;
+        // The durations are formatted up front, so that the columns can be as wide as their widest value.
+        let columns = records
+            .iter()
+            .map(|record| {
+                let since_previous = match last_timestamp {
+                    Some(last_timestamp) => record.timestamp - last_timestamp,
+                    None => Duration::from_seconds(0.0),
+                };
+                let since_start = first_timestamp
+                    .map(|first_timestamp| record.timestamp - first_timestamp)
+                    .unwrap_or_default();
+                last_timestamp = Some(record.timestamp);
 
-    let cli = Cli::parse();
+                (
+                    record.timestamp.to_string(),
+                    format!("+{}", since_previous),
+                    format!("T+{}", since_start),
+                )
+            })
+            .collect::<Vec<_>>();
+        let width = |column: fn(&(String, String, String)) -> &String| {
+            columns
+                .iter()
+                .map(|row| column(row).chars().count())
+                .max()
+                .unwrap_or_default()
+        };
+        let timestamp_width = width(|row| &row.0);
+        let previous_width = width(|row| &row.1);
+        let start_width = width(|row| &row.2);
 
-    match &cli.command {
-        Commands::Print(args) => args.run(),
-        Commands::Run(args) => args.run().await,
+        for (record, (timestamp, since_previous, since_start)) in records.iter().zip(&columns) {
+            let offsets = match self.relative {
+                Relative::Previous => format!("{:>previous_width$}", since_previous),
+                Relative::Start => format!("{:>start_width$}", since_start),
+                Relative::Both => format!(
+                    "{:>previous_width$} {:>start_width$}",
+                    since_previous, since_start
+                ),
+            };
+            println!(
+                "{:<timestamp_width$} {} {}{}",
+                timestamp, offsets, record.path, record.parameters
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the provided file and report on its health, without running any requests.
+///
+/// Reports the number of records, the range of their timestamps, how many of them have a domain name, how many of
+/// them the selection would drop, how many of the rest have no entry in the mapping, which fails `run`, and every
+/// record that failed to parse. Exits with a non-zero status if any record failed to parse.
+#[derive(Debug, Args)]
+struct Validate {
+    /// File to parse and validate.
+    input_file: PathBuf,
+    #[command(flatten)]
+    input_fields: InputFields,
+    #[command(flatten)]
+    selection: RecordSelection,
+    /// JSON file mapping domain names to hosts, as for `run`, to report the records without a mapping.
+    #[arg(long, value_name = "PATH")]
+    mapping: Option<PathBuf>,
+}
+
+impl Validate {
+    fn run(&self) -> Result<()> {
+        let mapping = self
+            .mapping
+            .as_ref()
+            .map(mapping::Mapping::from_path)
+            .transpose()?;
+        let mut records = Vec::new();
+        let mut errors = 0usize;
+        for record in
+            AccessLogRecord::parse_records_from_path(&self.input_file, &self.input_fields)?
+        {
+            match record {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    errors += 1;
+                    println!("Parse error: {}", err);
+                }
+            }
+        }
+
+        println!("Records: {}", records.len());
+        let first_timestamp = records.iter().map(|record| record.timestamp).min();
+        let last_timestamp = records.iter().map(|record| record.timestamp).max();
+        if let (Some(first_timestamp), Some(last_timestamp)) = (first_timestamp, last_timestamp) {
+            println!(
+                "Timestamps: {} to {} (spanning {})",
+                first_timestamp,
+                last_timestamp,
+                last_timestamp - first_timestamp
+            );
+        }
+        let with_domain_name = records
+            .iter()
+            .filter(|record| {
+                record
+                    .domain_name
+                    .as_deref()
+                    .is_some_and(|domain_name| !domain_name.trim().is_empty())
+            })
+            .count();
+        println!(
+            "Domain names: {} records with one, {} without",
+            with_domain_name,
+            records.len() - with_domain_name
+        );
+        let total = records.len();
+        let (selected, _) = AccessLogRecord::select_records(
+            records.into_iter().map(Ok).collect(),
+            &self.input_fields,
+            &self.selection,
+            false,
+            true,
+        )?;
+        println!("Dropped by the selection: {}", total - selected.len());
+        if let Some(mapping) = &mapping {
+            let unmapped = selected
+                .iter()
+                .filter(|record| {
+                    mapping
+                        .get_scheme_and_host(record.domain_name.as_deref())
+                        .is_err()
+                })
+                .count();
+            println!("Without a mapping, failing `run`: {}", unmapped);
+        }
+        println!("Parse errors: {}", errors);
+
+        if errors > 0 {
+            anyhow::bail!("{} records failed to parse", errors);
+        }
+
+        Ok(())
+    }
+}
+
+/// Replay GET-requests for provided URLs, with accurate relative timing.
+///
+/// The command parses the provided file and runs the discovered requests, with accurate relative timing, against the
+/// provided host.
+#[derive(Debug, Args)]
+struct Run {
+    /// Read defaults for any of the options from this TOML file.
+    ///
+    /// Keys are the long names of the options, e.g. `scheme-and-host = "https://staging.internal"`, and options that
+    /// can be repeated take a list. Options on the command line take precedence over environment variables, which take
+    /// precedence over the config file, which takes precedence over the built-in defaults.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Scheme and host to run the GET-requests against.
+    ///
+    /// Example: `https://my-alternative-service.internal`.
+    ///
+    /// The intention of this parameter is to enable replays against a different host than the one the requests were
+    /// originally run against. The most common use-case is taking production traffic and running it against a
+    /// non-production host.
+    ///
+    /// Together with `--url-field` this is optional, and only replaces the scheme, host and port of the recorded URLs.
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["url_field", "mapping"],
+        conflicts_with = "mapping"
+    )]
+    scheme_and_host: Option<String>,
+    /// JSON file mapping the domain names of the original requests to the scheme and host to replay them against.
+    ///
+    /// Example: `{"www.example.com": "https://www.staging.internal", "*": "https://fallback.staging.internal"}`.
+    ///
+    /// The domain name of a record is taken from its `domain_name` field. An entry for the exact domain name takes
+    /// precedence over wildcard entries like `*.example.com` for its subdomains, of which the most specific one wins.
+    /// Both take precedence over the catch-all entry `*`, which applies to every domain name without an entry of its
+    /// own. Records that match none of them fail the run before any request is sent.
+    #[arg(long, value_name = "PATH")]
+    mapping: Option<PathBuf>,
+    /// Scheme and host to send every request to as well, at the same time, to compare its responses to those of the
+    /// primary target.
+    ///
+    /// Example: `https://my-alternative-service.internal`.
+    ///
+    /// This doubles the traffic of the replay, and the shadow requests count towards `--max-rate`. The response of
+    /// the shadow target, and whether its status and, with `--capture-body`, body match the primary response, are
+    /// part of the output of every request that the primary target responded to successfully, and summarized at the
+    /// end of the run. Requests that fail on the primary target are reported as usual, without a comparison.
+    #[arg(long, value_name = "SCHEME_AND_HOST")]
+    shadow_target: Option<Url>,
+    /// Compare the captured bodies to those of the `--shadow-target` or the `--baseline` by their content, rather
+    /// than byte for byte.
+    ///
+    /// JSON bodies are compared value by value, other bodies as bytes. Every comparison reports the `similarity` of
+    /// the bodies, the share of the values or bytes that are the same, and for JSON bodies the `fields` that differ,
+    /// which are summarized by URL at the end of the run. Against the baseline, the body of the first of its
+    /// responses to the same path and query is compared against, which the baseline run has to have captured.
+    #[arg(long, requires = "capture_body")]
+    compare_bodies: bool,
+    /// Dotted path of a JSON field that `--compare-bodies` ignores, e.g. `meta.request_id`.
+    ///
+    /// Array elements are addressed by their index, and `*` matches any key or index, e.g. `items.*.updated_at`. Can
+    /// be given multiple times.
+    #[arg(long, value_name = "PATH", requires = "compare_bodies")]
+    ignore_json_path: Vec<String>,
+    /// Maximum length of the URLs requests are sent to, in bytes, e.g. `8192`.
+    ///
+    /// Targets usually reject longer URLs with `414 URI Too Long`. Records with a longer URL are skipped, or
+    /// truncated with `--over-length truncate`, before any request is sent, and counted in the summary.
+    #[arg(long, value_name = "BYTES")]
+    max_url_length: Option<usize>,
+    /// What to do with records whose URL is longer than `--max-url-length`.
+    #[arg(long, value_enum, default_value_t = url_length::OverLength::Skip, requires = "max_url_length")]
+    over_length: url_length::OverLength,
+    /// Keep the scheme of the original request, from the `original_scheme` field of every record.
+    ///
+    /// Only the host and port are then taken from `--scheme-and-host` or the mapping, and their scheme is ignored.
+    /// This allows testing scheme-dependent behaviour, like redirects from `http` to `https`.
+    #[arg(long)]
+    preserve_scheme: bool,
+    /// Send every request with the HTTP version of the original request, from the `protocol` field of every
+    /// record, e.g. `HTTP/1.0`.
+    ///
+    /// Combined logs have this field for every line, records without it are sent with the version the connection
+    /// negotiates. HTTP/2 can only be used against `https` targets, which negotiate it during the TLS handshake, and
+    /// for gRPC calls with `--grpc`, which in turn can't use HTTP/1. HTTP/3 can't be used at all. What happens with
+    /// these requests, and with HTTP/2 requests to targets that turn out not to negotiate it, is decided by
+    /// `--on-protocol-mismatch`.
+    #[arg(long)]
+    preserve_protocol: bool,
+    /// What to do with requests whose original HTTP version can't be used, with `--preserve-protocol`.
+    ///
+    /// Every such request is logged, and their number is reported.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = protocol::OnMismatch::Downgrade,
+        requires = "preserve_protocol"
+    )]
+    on_protocol_mismatch: protocol::OnMismatch,
+    /// Name of a field that contains the full, absolute URL of the original request.
+    ///
+    /// When set, requests are sent to the URL in this field instead of one composed of `--scheme-and-host`, `path`
+    /// and `params`. Without `--scheme-and-host` the URL is used verbatim.
+    #[arg(long, value_name = "NAME")]
+    url_field: Option<String>,
+    /// Name of a field that contains the method of the original request, e.g. `POST`.
+    ///
+    /// Without it, or for records where the field is empty, requests are sent as `GET`-requests.
+    #[arg(long, value_name = "NAME")]
+    method_field: Option<String>,
+    /// Name of a field that contains the body of the original request, which is sent along with the request.
+    #[arg(long, value_name = "NAME")]
+    body_field: Option<String>,
+    /// Name of a field that contains the path of a file to send as the body of the request.
+    ///
+    /// The file is streamed rather than read into memory, which allows replaying large uploads. Relative paths are
+    /// relative to the directory of the input file, and every file has to exist before the replay starts. Records
+    /// where the field is missing or empty are sent without a body.
+    #[arg(long, value_name = "NAME", conflicts_with = "body_field")]
+    body_from_file_column: Option<String>,
+    /// Neutralize requests that could have side effects, by sending them with a safe method and without a body.
+    ///
+    /// Requests whose method isn't safe, i.e. everything but `GET`, `HEAD`, `OPTIONS` and `TRACE`, are sent as
+    /// `--safe-method` instead, and no request is sent with a body. This allows replaying write traffic against a
+    /// target without changing its data, to measure routing and latency. The fidelity suffers accordingly: the
+    /// target handles these requests as reads, so their responses and latencies don't reflect the original ones.
+    #[arg(long)]
+    safe_mode: bool,
+    /// Method to send requests with an unsafe method as, with `--safe-mode`.
+    #[arg(long, value_enum, default_value_t = safe::SafeMethod::Get, requires = "safe_mode")]
+    safe_method: safe::SafeMethod,
+    /// Send every request with this method, and without a body, keeping its URL and headers.
+    ///
+    /// Unlike `--safe-mode`, which only changes requests that could have side effects, this probes every URL, e.g.
+    /// with `head` to warm up a cache or test the routing without transferring bodies. With `head` or `options` the
+    /// target usually does less work than for the original request, so `change_percentage` compares different
+    /// things and is of limited use.
+    #[arg(long, value_enum, conflicts_with = "safe_mode")]
+    override_method: Option<safe::SafeMethod>,
+    /// How the request body is reconstructed from `--body-field`.
+    ///
+    /// With `form` or `multipart` the field has to contain a JSON object of form fields (in CSV files as a string),
+    /// whose values are strings, numbers or booleans, or arrays of them for repeated fields. The `Content-Type` is
+    /// set accordingly. Records whose body field doesn't have this shape fail the run before any request is sent.
+    #[arg(long, value_enum, default_value_t = body::BodyFormat::Raw, requires = "body_field")]
+    body_format: body::BodyFormat,
+    /// Send a header with the value of a field of every record, in the format `Header-Name=field`.
+    ///
+    /// Example: `User-Agent=user_agent`.
+    ///
+    /// Useful for logs that keep individual headers in fields of their own, like the `user_agent` of AWS
+    /// Application Load Balancer logs. Records where the field is missing or empty are sent without the header. Can
+    /// be given multiple times.
+    #[arg(long, value_name = "HEADER=FIELD")]
+    header_from_column: Vec<headers::HeaderFromColumn>,
+    /// Send the credentials of the original request, the `Authorization` or `Cookie` header, with the value of a
+    /// field of every record, in the format `Header-Name=field`.
+    ///
+    /// Example: `Cookie=request_cookie`.
+    ///
+    /// Like `--header-from-column`, but for replaying the sessions of individual users: the values are marked as
+    /// sensitive, so that they don't end up in logs, and the run fails before any request is sent if none of the
+    /// records has the field, e.g. because of a typo. Records where the field is missing or empty are sent without
+    /// the header, and counted. Can be given once per header.
+    #[arg(
+        long,
+        value_name = "HEADER=FIELD",
+        conflicts_with_all = ["sigv4", "token_command"]
+    )]
+    auth_from_column: Vec<headers::AuthFromColumn>,
+    /// Send the `Host` header of the original request, from the `domain_name` field of every record, instead of the
+    /// host of the URL.
+    ///
+    /// Requests are still sent to the host of `--scheme-and-host`, the mapping or `--resolve`, which allows testing
+    /// services that route by virtual host, like multi-tenant services behind a shared ingress. The TLS server name
+    /// remains the host of the URL. Records without a `domain_name` are sent with the host of their URL.
+    #[arg(long)]
+    preserve_host: bool,
+    /// Sign every request with AWS Signature Version 4 for this region and service, e.g. `eu-central-1:execute-api`
+    /// for API Gateway with IAM authorization.
+    ///
+    /// The credentials are taken from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, for temporary credentials,
+    /// `AWS_SESSION_TOKEN`. If these aren't set, the credentials of the instance profile are fetched from the
+    /// instance metadata service, and fetched anew before they expire. Every attempt of a request is signed right
+    /// before it is sent, as is the `--healthcheck`.
+    #[arg(long, value_name = "REGION:SERVICE")]
+    sigv4: Option<sigv4::Scope>,
+    /// Authorize every request with the bearer token printed by this command, which is run with the shell.
+    ///
+    /// Example: `vault read -field=token secret/staging/replay`.
+    ///
+    /// The first line the command prints is sent as `Authorization: Bearer <token>`, replacing any `Authorization`
+    /// header of the request. The command is run before the replay starts, failing the run if it doesn't print a
+    /// token, and again once the token is older than `--token-ttl` or after the target responded with `401
+    /// Unauthorized` to a request sent with it. If it fails during the replay, the previous token is used until it
+    /// is due to be fetched anew again. The request that got the `401` is not retried with the new token. The
+    /// command runs with the permissions of `r7`, so only pass commands from trusted sources.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "sigv4")]
+    token_command: Option<String>,
+    /// Fetch a new token with `--token-command` once the current one is older than this.
+    ///
+    /// Example: `50 min`, for tokens that are valid for an hour.
+    ///
+    /// Without it, a new token is only fetched after the target rejected the current one.
+    #[arg(long, value_name = "DURATION", requires = "token_command")]
+    token_ttl: Option<Duration>,
+    /// WebAssembly module that transforms every request after it has been built, e.g. to rewrite its URL or
+    /// headers.
+    ///
+    /// The module, in the binary or the text format, exports its `memory`, an `alloc` function and a `transform`
+    /// function, which gets the method, URL, headers and body of a request as JSON and returns them transformed, see
+    /// the README. It is instantiated once for every input file, or with `--follow` for every batch of records read
+    /// from it, and runs without any imports, so it can't access the network or the file system. Bodies streamed from
+    /// `--body-from-file-column` aren't passed to it, and requests are signed with `--sigv4` or authorized with
+    /// `--token-command` after they have been transformed.
+    #[arg(long, value_name = "MODULE")]
+    transform_wasm: Option<PathBuf>,
+    /// Copy the value of this field of every record into the `tags` of its response, e.g. `experiment`.
+    ///
+    /// This allows analyzing the results by the field, with `--group-by tags` or afterwards, without joining them
+    /// against the input file again. Records where the field is missing or empty get no tag for it. Can be given
+    /// multiple times.
+    #[arg(long, value_name = "NAME")]
+    tag_column: Vec<String>,
+    /// Weight every record by the number of original requests it stands for, in this field, e.g. `sample_rate`.
+    ///
+    /// This replays sampled logs at their original volume. The weight is rounded to a whole number, records without
+    /// the field, or with an empty one, have a weight of 1. How a record is weighted is set with `--weight-mode`.
+    #[arg(long, value_name = "NAME")]
+    weight_column: Option<String>,
+    /// How to weight the records by `--weight-column`.
+    ///
+    /// `expand` sends the original volume to the target: every record is held in memory as many times as its weight,
+    /// which multiplies the memory needed for the replay by the mean weight. `scale` sends the sampled volume, and
+    /// only scales the summary, including `--stats-out`, and the limits that are based on it, like `--max-failures`.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = WeightMode::Expand,
+        requires = "weight_column"
+    )]
+    weight_mode: WeightMode,
+    /// Send the IP address of the original client, from this field of every record, as `X-Forwarded-For`.
+    ///
+    /// The field can contain the address with a port, like the `client:port` field of AWS Application Load Balancer
+    /// logs. Records where the field doesn't contain an IP address are sent without the header. Note that the
+    /// target has to trust the header, e.g. by being configured to take the client IP from it, for this to have any
+    /// effect.
+    #[arg(long, value_name = "NAME")]
+    spoof_client_ip_from_column: Option<String>,
+    /// Also send the IP address of the original client as `X-Real-IP`.
+    #[arg(long, requires = "spoof_client_ip_from_column")]
+    spoof_real_ip: bool,
+    /// Send an ID of the record every request was replayed from in this header, `X-Repeater-Id` if no name is given.
+    ///
+    /// The ID is also included in the output as `correlation_id`, to join it with the logs of the target. It is
+    /// taken from `--correlation-id-field` if given, or derived from the timestamp, path and parameters of the record
+    /// otherwise, as the 64-bit FNV-1a hash of these fields. Either way it is the same for a record in every run.
+    #[arg(
+        long,
+        value_name = "NAME",
+        num_args = 0..=1,
+        default_missing_value = "X-Repeater-Id"
+    )]
+    correlation_header: Option<reqwest::header::HeaderName>,
+    /// Field with an ID of the original request, like the `request_id` of AWS Application Load Balancer logs, to use
+    /// as the ID of `--correlation-header`.
+    ///
+    /// Records without the field get a derived ID instead.
+    #[arg(long, value_name = "NAME", requires = "correlation_header")]
+    correlation_id_field: Option<String>,
+    /// How to replay requests that originally were WebSocket upgrades.
+    ///
+    /// Upgrade requests are recognized by the `type` field of AWS Application Load Balancer logs being `ws` or `wss`,
+    /// or by a `ws` or `wss` URL in `--url-field`, as the headers of the original requests aren't logged. Replaying
+    /// them as plain requests can stall on targets that wait for the upgrade. With `handshake`, `101 Switching
+    /// Protocols` counts as a successful response.
+    #[arg(long, value_enum, default_value_t = websocket::WebSocketMode::Skip)]
+    websocket: websocket::WebSocketMode,
+    /// Replay the records of gRPC calls as unary gRPC calls, instead of as plain requests.
+    ///
+    /// gRPC calls are recognized by the `type` field of AWS Application Load Balancer logs being `grpcs`. They are
+    /// sent as HTTP/2 `POST`-requests with the `application/grpc` content type, over cleartext HTTP/2 to `http`
+    /// targets. The message is taken base64-encoded from `--body-field`, or as is from the file of
+    /// `--body-from-file-column`, and records without one send an empty message. Whether a call succeeded is decided
+    /// by its `grpc-status` rather than by the HTTP status, and calls that failed count as errors. With
+    /// `--capture-body` the response message is output base64-encoded. Streaming calls aren't supported.
+    #[arg(long, conflicts_with_all = ["safe_mode", "override_method"])]
+    grpc: bool,
+    /// File to parse the GET-requests from.
+    input_file: PathBuf,
+    /// Replay the records of this file alongside those of the input file, as a separate stream.
+    ///
+    /// Example: `tenant-b.json=https://tenant-b.staging.internal`.
+    ///
+    /// Every stream is scheduled independently from its own first record, rather than merged with the others by
+    /// timestamp, so that logs of different tenants or time periods are replayed concurrently as if they had started
+    /// at the same time. A scheme and host after `=` replaces the target for the records of this file, otherwise
+    /// they are sent to the target of the input file. Every response is tagged with the file its record was read
+    /// from, as `stream`, and the summary is broken down by stream unless `--group-by` is given. Can be given
+    /// multiple times, once per additional file.
+    #[arg(
+        long,
+        value_name = "FILE[=SCHEME_AND_HOST]",
+        conflicts_with_all = ["anchor", "remaining_out"]
+    )]
+    interleave: Vec<interleave::Stream>,
+    /// Keep reading the input file as it grows, like `tail -f`, and replay the records written to it until CTRL-C.
+    ///
+    /// The records are scheduled by their timestamps relative to the first record of the file, like those that were
+    /// there when the replay started, so a file that is written as traffic happens is replayed with the traffic's
+    /// timing. Records written late, timestamped before where the replay is at, are sent immediately. Lines are
+    /// only read once they are complete. When the file is truncated it is read from its start again, and when it is
+    /// replaced, e.g. by log rotation, the new file is followed from its start.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "interleave",
+            "rps_profile",
+            "match_concurrency",
+            "ordered_output",
+            "no_sort",
+            "time_factor_schedule",
+            "preserve_time_of_day",
+            "target_bandwidth",
+            "openapi",
+            "spread_start",
+            "soft_start",
+            "skip",
+            "limit",
+            "dry_run",
+            "deterministic_check",
+        ]
+    )]
+    follow: bool,
+    #[command(flatten)]
+    input_fields: InputFields,
+    /// Time in which the requests should be fulfilled, as a factor of the original runtime
+    ///
+    /// A factor smaller than 1 means the requests will finish sooner, e.g. with a factor of 0.5 in half the time
+    /// (double the load), whereas a factor higher than 1 means the requests will finish later, e.g. with a factor of 2
+    /// in double the time (half the load).
+    #[arg(long)]
+    time_factor: Option<f64>,
+    /// Time factors for consecutive segments of the replay, as a comma-separated list of `<until>=<factor>`.
+    ///
+    /// Example: `40%=1,60%=0.5` replays the first 40% of the log at the original speed, the next 20% at double the
+    /// speed, and the rest at `--time-factor`.
+    ///
+    /// Every segment starts where the previous one ends and ends at a percentage of the time spanned by the log
+    /// (from the first record, or `--anchor`, to the last record) or at a point in time as RFC3339, e.g.
+    /// `2024-06-01T12:30:00Z=0.5`. Records are offset by the factor of every segment they span, so the replay speeds
+    /// up or slows down at segment boundaries without gaps or overlaps.
+    #[arg(long, value_name = "SEGMENTS", value_delimiter = ',')]
+    time_factor_schedule: Vec<schedule::TimeFactorSegment>,
+    /// Pace the replay by the rates of requests over time in this CSV file, instead of by the timestamps of the
+    /// records.
+    ///
+    /// The file has a `timestamp` column, as RFC3339, and an `rps` column, e.g. the per-minute rate of requests of a
+    /// metrics system, which allows reconstructing the shape of the load when there is no log of every request.
+    /// Every row starts a bucket that lasts until the next row, and the last bucket lasts as long as the one before
+    /// it. The requests of every bucket are spread evenly across it, and are the records of the input file in their
+    /// order, starting over from the first record once all of them have been sent, so that the mix of requests of
+    /// the log is kept whether the profile needs fewer or more requests than there are records.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "time_factor",
+            "time_factor_schedule",
+            "target_bandwidth",
+            "preserve_time_of_day",
+            "anchor",
+            "match_concurrency",
+        ]
+    )]
+    rps_profile: Option<PathBuf>,
+    /// Pace the requests to transfer approximately this many bytes per second, instead of following their timing.
+    ///
+    /// Every request is sent once the requests before it had their share of time at this rate, as determined by the
+    /// sizes of the original request and response in the `received_bytes` and `sent_bytes` fields, at least one of
+    /// which has to be present for every record. This is approximate: the sizes are the ones of the original run, and
+    /// the responses of the replay can differ in size.
+    #[arg(
+        long,
+        value_name = "BYTES_PER_SEC",
+        conflicts_with_all = ["time_factor", "time_factor_schedule", "anchor"]
+    )]
+    target_bandwidth: Option<f64>,
+    /// Send every request at today's occurrence of its original time of day, in UTC, instead of relative to the
+    /// start of the replay.
+    ///
+    /// A request originally sent at 2024-06-01T14:05:00Z is sent at 14:05:00 UTC today, regardless of the time at
+    /// which the replay is started. Whether requests whose time of day has already passed are sent immediately or
+    /// tomorrow is determined by `--past-time-of-day`.
+    #[arg(
+        long,
+        conflicts_with_all = ["time_factor", "time_factor_schedule", "target_bandwidth", "anchor"]
+    )]
+    preserve_time_of_day: bool,
+    /// When to send requests whose time of day has already passed today, with `--preserve-time-of-day`.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = schedule::PastTimeOfDay::Immediately,
+        requires = "preserve_time_of_day"
+    )]
+    past_time_of_day: schedule::PastTimeOfDay,
+    /// Absolute point in time, as RFC3339, that the start of the replay corresponds to.
+    ///
+    /// Example: `2024-06-01T12:00:00Z`.
+    ///
+    /// By default the offsets of all requests are relative to the first record in the input file. With an anchor
+    /// the offsets are relative to the anchor instead, which allows multiple instances of `r7`, replaying disjoint
+    /// parts of the same log and started at the same time, to interleave their requests as they originally did.
+    ///
+    /// Records timestamped before the anchor are sent immediately.
+    #[arg(long)]
+    anchor: Option<Epoch>,
+    /// Spread the requests due within this window from the start of the replay evenly across it.
+    ///
+    /// Example: `10 s`.
+    ///
+    /// Logs often contain many records with the same, or nearly the same, timestamp at their very start, which
+    /// would otherwise all be sent at once. Spreading them smooths this initial burst, at the cost of slightly
+    /// distorting the timing of the earliest requests. Their order is kept.
+    #[arg(long, value_name = "DURATION")]
+    spread_start: Option<Duration>,
+    /// Ramp the rate up linearly from zero to the full rate over this window from the start of the replay.
+    ///
+    /// Example: `5 min`.
+    ///
+    /// Sending at the full rate right away can trip the autoscaling of a target, which then responds with errors
+    /// until it has scaled up. With a soft start the requests of the first half of the window are stretched across
+    /// the whole window, at a rate that increases steadily, and every request after it keeps its original timing,
+    /// delayed by half the window. The time at which the full rate is reached is logged.
+    #[arg(long, value_name = "DURATION")]
+    soft_start: Option<Duration>,
+    /// Reproduce how many requests were in flight at the same time originally, rather than only when they started.
+    ///
+    /// The original concurrency is reconstructed from the timestamp and the latency of every record: a request that
+    /// originally started after an earlier one completed is sent after the replay of that one completed, in addition
+    /// to not before its own offset. When the target responds as fast as the original one did, the requests are sent
+    /// at their offsets as usual. When it is slower, requests are delayed rather than piling up beyond the original
+    /// concurrency, which caps the throughput. This is an approximation, as the latencies of the replay differ from
+    /// the original ones, and requests that originally overlapped are still sent at their offsets.
+    #[arg(long)]
+    match_concurrency: bool,
+    #[command(flatten)]
+    selection: RecordSelection,
+    /// Read the response bodies and include them in the output.
+    ///
+    /// Bodies are decoded according to their `Content-Encoding` (`gzip`, `deflate` and `br` are supported) before
+    /// they are recorded, alongside both the size as transferred and the decoded size. A body that decodes to more
+    /// than 1 MiB and 100 times its size as transferred fails the request, to not run out of memory.
+    #[arg(long)]
+    capture_body: bool,
+    /// Include up to this many of the redirects that were followed for every request in the output, as `redirects`.
+    ///
+    /// Redirects are followed up to 10 times whether or not they are recorded. Every redirect is recorded as the
+    /// status of the redirect response and the URL it redirected to, in the order they were followed.
+    #[arg(long, value_name = "N")]
+    max_redirects_recorded: Option<usize>,
+    /// Value of the `Accept-Encoding` header sent with every request.
+    ///
+    /// Defaults to `gzip, deflate, br` when capturing bodies, and `gzip` otherwise.
+    #[arg(long)]
+    accept_encoding: Option<String>,
+    /// Maximum number of captured body bytes to hold in memory before holding back new requests.
+    ///
+    /// Only has an effect together with `--capture-body`. Captured bodies are held in memory until they have been
+    /// written to the output. When they exceed this limit, requests that are due are delayed until enough bodies
+    /// have been written. Requests that are already in flight are not affected, so the limit can be overshot by the
+    /// bodies of requests that were sent before it was reached.
+    #[arg(long, value_name = "N")]
+    max_in_flight_bytes: Option<usize>,
+    /// Break the summary printed at the end of the run down by the given dimension.
+    ///
+    /// For every group the number of requests, the error rate and the latency percentiles of the successful requests
+    /// are reported, the same as for the overall summary.
+    #[arg(long, value_enum)]
+    group_by: Option<summary::GroupBy>,
+    /// Compute the latency percentiles of the summary from every latency, rather than estimating them.
+    ///
+    /// By default the percentiles, also those of `--assert`, are estimated to within 1% of the actual latency, in
+    /// memory that stays bounded however many requests are replayed. With this flag every latency is kept in memory
+    /// until the end of the run, which is only advisable for smaller runs.
+    #[arg(long)]
+    exact_percentiles: bool,
+    /// Assert a condition on the summary of the run, failing the run if it doesn't hold.
+    ///
+    /// Examples: `p99<500ms`, `error_rate<1%`, `mean_change<=10%`.
+    ///
+    /// The metrics `p<N>` and `mean_latency` (of the successful requests, with a unit of `us`, `ms`, `s` or `min`),
+    /// `error_rate` and `mean_change` (in percent), and `requests` and `errors` can be compared with `<`, `<=`,
+    /// `>` or `>=`. All assertions are evaluated and reported after the summary, and the run fails if any of them
+    /// doesn't hold. Can be given multiple times.
+    #[arg(long, value_name = "ASSERTION")]
+    assert: Vec<assertion::Assertion>,
+    /// Write the summary of the run as a single JSON object to this file.
+    ///
+    /// The object contains the counts, latency percentiles (in seconds) and kinds of failures, overall and for every
+    /// group of `--group-by`. It is written even if the run is aborted, with `complete` set to `false`, and has a
+    /// `schema_version` that is incremented whenever its format changes incompatibly.
+    #[arg(long, value_name = "PATH")]
+    stats_out: Option<PathBuf>,
+    /// Output of a previous run, as JSON lines, to compare the latencies of this run against instead of the original
+    /// latencies of the records.
+    ///
+    /// `original_time` and `change_percentage` then refer to the mean latency of the responses to the same path and
+    /// query in the previous run, regardless of the host. Requests that are missing from the baseline aren't
+    /// compared, and have neither.
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+    /// Flag successful requests whose latency increased by more than this percentage compared to the original one.
+    ///
+    /// Regressed requests are marked with `regressed` in the output, and the worst of them are listed after the
+    /// summary. Requests without an original latency, where it isn't positive, are skipped.
+    #[arg(long, value_name = "PERCENT")]
+    regression_threshold: Option<f64>,
+    /// Don't compare the latency of a response to the original one if both are shorter than this.
+    ///
+    /// Example: `10 ms`.
+    ///
+    /// Small absolute differences of fast requests are large relative ones, e.g. 1 ms instead of 2 ms is a change of
+    /// 100%, which would otherwise dominate `mean_change` and `--regression-threshold`. The `change_percentage` of
+    /// these responses is `null`, and they are left out of both. This only affects the comparison: `required_time`
+    /// and the latency percentiles still cover every response.
+    #[arg(long, value_name = "DURATION")]
+    min_latency_for_comparison: Option<Duration>,
+    /// Fail the run if any request regressed beyond `--regression-threshold`.
+    #[arg(long, requires = "regression_threshold")]
+    fail_on_regression: bool,
+    /// Compare the status of every response to the original one, from the `status` field of the record, and fail
+    /// the run if any of them differ.
+    ///
+    /// Responses are marked with `status_matches` in the output, alongside their `original_status`, and the
+    /// mismatches are listed with their URLs after the summary, e.g. a `404` of the target where the original
+    /// response was a `200`. Requests that got no response count as mismatches. Records without an original status
+    /// are excluded.
+    #[arg(long)]
+    assert_status_match: bool,
+    /// Consider these statuses to match each other with `--assert-status-match`, in the format `301,302`.
+    ///
+    /// This tolerates benign differences between the target and the original one, e.g. a `301` where the original
+    /// response was a `302`. A status only matches the original status if both are in the same group, statuses of
+    /// different groups still mismatch. Can be given multiple times, once per group.
+    #[arg(long, value_name = "STATUS,STATUS", requires = "assert_status_match")]
+    status_equivalent: Vec<summary::StatusGroup>,
+    /// Expect every response to have this header, with a value matching the regular expression, in the format
+    /// `Header-Name: regex`, and fail the run if any response doesn't.
+    ///
+    /// Example: `Cache-Control: max-age=\d+`.
+    ///
+    /// The regular expression matches anywhere in the value, unless anchored with `^` and `$`, and a header that is
+    /// present multiple times matches if any of its values does. Responses are marked with the names of the headers
+    /// they violated in `header_violations` in the output, and the violations are counted by header after the
+    /// summary, with example URLs. Only responses that were received are checked, which excludes error statuses
+    /// unless `--only-errors` is given. Can be given multiple times, once per header.
+    #[arg(long, value_name = "HEADER: REGEX")]
+    expect_header: Vec<headers::ExpectedHeader>,
+    /// Refuse to start a replay whose last request is sent later than this after the start, unless `--yes` is given.
+    ///
+    /// A record with a bad timestamp, far away from the others, would otherwise make the replay silently wait for
+    /// it, e.g. for days. The record responsible is reported.
+    #[arg(long, value_name = "DURATION", default_value = "1 day")]
+    max_offset: Duration,
+    /// Replay the records in the order of the file, instead of sorted by timestamp.
+    ///
+    /// This assumes the file is already in the order the requests should be sent in, e.g. a curated sequence. Every
+    /// record is sent after the previous one by the time between their timestamps, or together with it if it is
+    /// timestamped earlier. Without this option records with the same timestamp keep their order in the file, too.
+    /// Either way, requests that are due at the same time are sent concurrently, in no particular order.
+    #[arg(long, conflicts_with = "preserve_time_of_day")]
+    no_sort: bool,
+    /// Schedule the requests by when the original requests started, or by when their responses were complete.
+    ///
+    /// With `response-end` a request is scheduled at its timestamp plus its latency in the input file, e.g.
+    /// `target_processing_time`, so that the replay reproduces the spacing between the original responses. Slow
+    /// requests are sent later than they originally were, which changes the shape of the load on slow endpoints.
+    /// Records that didn't get a response, with a negative latency, are scheduled at their timestamp.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "BASIS",
+        default_value_t = OffsetBasis::RequestStart,
+        conflicts_with = "preserve_time_of_day"
+    )]
+    offset_basis: OffsetBasis,
+    /// Send a GET-request for this path, e.g. `/health`, to every target before starting the replay, and abort unless
+    /// they all respond with a success status.
+    ///
+    /// This avoids starting a long replay against a target that is down. The healthcheck isn't part of the output or
+    /// the summary.
+    #[arg(long, value_name = "PATH")]
+    healthcheck: Option<String>,
+    /// How long to wait for the response to `--healthcheck`.
+    #[arg(long, value_name = "DURATION", default_value = "10 s")]
+    healthcheck_timeout: Duration,
+    /// Warn, but start anyway, if the last request of the replay is sent later than this after the start.
+    #[arg(long, value_name = "DURATION")]
+    max_duration_warn: Option<Duration>,
+    /// Start the replay even if it takes longer than `--max-offset`.
+    #[arg(long)]
+    yes: bool,
+    /// Only report an estimate of the traffic the replay would generate, without sending any requests.
+    ///
+    /// The estimate consists of the number of requests, how long the replay takes at least, its mean and peak rate
+    /// of requests per second, and how many bytes are sent and received. The sizes are those of the original
+    /// requests and responses, from the `received_bytes` and `sent_bytes` fields of the records, and are reported as
+    /// unavailable if the records don't have them.
+    #[arg(long)]
+    dry_run: bool,
+    /// Verify that the schedule of the replay has this fingerprint before sending any request, and fail otherwise.
+    ///
+    /// The fingerprint is a hash of the offset, method and URL of every request, in the order they are scheduled,
+    /// and is reported by `--dry-run`. Passing the fingerprint of an earlier run ensures that a load test replays
+    /// exactly the same requests at the same times as before, e.g. after changing its options or upgrading `r7`.
+    /// With `--preserve-time-of-day` the offsets depend on when the replay starts, so that the fingerprint differs
+    /// from run to run.
+    #[arg(long, value_name = "FINGERPRINT")]
+    deterministic_check: Option<String>,
+    /// Collapse IDs and other high-cardinality segments of the paths used as groups by `--group-by`.
+    ///
+    /// For example, `/users/12345` is grouped as `/users/:id`. By default, segments that are numbers, UUIDs or
+    /// hexadecimal hashes of at least 16 characters are replaced. Only the grouping is affected, requests are
+    /// sent to their actual URL.
+    #[arg(long)]
+    normalize_paths: bool,
+    /// Rule for `--normalize-paths` in the format `REGEX=PLACEHOLDER`, replacing the default rules.
+    ///
+    /// Example: `[a-z]{2}-[A-Z]{2}=:locale`.
+    ///
+    /// Every path segment that the regular expression matches in full is replaced by the placeholder. Can be given
+    /// multiple times, the first matching rule applies.
+    #[arg(long, value_name = "REGEX=PLACEHOLDER", requires = "normalize_paths")]
+    normalize_rule: Vec<normalize::NormalizeRule>,
+    /// OpenAPI specification, in JSON, to check the paths of the records against.
+    ///
+    /// The number of records whose path matches a path of the specification, with any value for its parameters, is
+    /// reported. Paths are matched including the paths of the `servers` of the specification, if any.
+    #[arg(long, value_name = "SPEC")]
+    openapi: Option<PathBuf>,
+    /// Only replay the records whose path is documented in the `--openapi` specification, or only those whose path
+    /// isn't, e.g. to find endpoints that aren't documented.
+    #[arg(long, value_enum, requires = "openapi")]
+    openapi_filter: Option<openapi::OpenApiFilter>,
+    /// Number of times a failed request is retried.
+    ///
+    /// Requests are retried if they failed without a response, or with a response that doesn't have a client error
+    /// status (4xx). Only requests with an idempotent method are retried, unless `--retry-all-methods` is given.
+    #[arg(long, default_value_t = 0)]
+    retries: usize,
+    /// Retry requests regardless of their method, including `POST` and `PATCH`.
+    ///
+    /// By default only requests with an idempotent method are retried: `GET`, `HEAD`, `OPTIONS`, `TRACE`, `PUT` and
+    /// `DELETE`. This prevents duplicate writes by retrying e.g. a `POST` whose first attempt did reach the target.
+    /// gRPC calls are sent as `POST` and are therefore not retried without it either.
+    #[arg(long)]
+    retry_all_methods: bool,
+    /// How long to wait between retries: `none`, `fixed:<DURATION>` (e.g. `fixed:500 ms`) or `exponential`.
+    ///
+    /// Exponential backoff waits 100 ms before the first retry, doubling with every further retry, up to
+    /// `--retry-max-backoff`.
+    #[arg(long, default_value = "exponential", value_name = "BACKOFF")]
+    retry_backoff: retry::Backoff,
+    /// Upper bound of the wait between retries with exponential backoff.
+    #[arg(long, default_value = "10 s", value_name = "DURATION")]
+    retry_max_backoff: Duration,
+    /// Maximum random delay added to every wait between retries, so that requests that failed at the same time are
+    /// not retried in lockstep.
+    #[arg(long, value_name = "DURATION")]
+    retry_jitter: Option<Duration>,
+    /// Maximum time a single request may spend being retried, measured from its first attempt.
+    ///
+    /// A retry that would start after this time has passed is not attempted.
+    #[arg(long, value_name = "DURATION")]
+    max_retry_duration: Option<Duration>,
+    /// File to write lifecycle events of the run to, as newline-delimited JSON.
+    ///
+    /// The events `started`, `request_sent` (for every attempt), `request_completed` and `finished` are written, each
+    /// with the seconds elapsed since the start of the run and running counts where applicable. Every event is
+    /// flushed immediately, so the file can be monitored live. To write to an inherited file descriptor, pass e.g.
+    /// `/dev/fd/3`.
+    #[arg(long, value_name = "PATH")]
+    events_file: Option<PathBuf>,
+    /// Connect to the given IP address whenever a request is sent to the given host, in the format `host:port:ip`.
+    ///
+    /// Example: `my-alternative-service.internal:443:10.0.0.17`.
+    ///
+    /// The URL, and therefore the `Host` header and the TLS server name, are unaffected, which allows targeting a
+    /// specific backend instance behind a load balancer. The host is matched against the host of the URLs that are
+    /// requested, i.e. the host of `--scheme-and-host` (or of `--url-field`). The port is accepted for compatibility
+    /// with curl, but the port of the URL is always used to connect. Can be given multiple times, also for the same
+    /// host to connect to any of the addresses.
+    #[arg(long, value_name = "HOST:PORT:IP")]
+    resolve: Vec<resolve::ResolveOverride>,
+    /// Address family to connect with.
+    ///
+    /// `auto` uses every address a host resolves to, as the operating system orders them. `v4` and `v6` only use
+    /// the IPv4 or IPv6 addresses respectively, and requests to a host without an address of that family fail. This
+    /// applies to host names that are resolved, and to the addresses given with `--resolve`, but not to URLs that
+    /// contain an IP address.
+    #[arg(long, value_enum, default_value_t = resolve::IpFamily::Auto)]
+    ip_family: resolve::IpFamily,
+    /// Resolve every host again once its addresses are older than this, e.g. `30 s`, logging when they change.
+    ///
+    /// This lets a long replay follow a load balancer whose addresses change, e.g. during a rolling deployment. It
+    /// only affects new connections: connections that are kept alive stay connected to the address they were
+    /// established with, use `--no-keepalive` to open a new connection for every request.
+    #[arg(long, value_name = "DURATION")]
+    dns_refresh: Option<Duration>,
+    /// Maximum number of requests per second, across all requests of the run.
+    ///
+    /// This is a protective ceiling on top of the timing of the replay: requests that would exceed it, for example
+    /// because of a small `--time-factor` or a burst in the original traffic, are delayed rather than dropped. Every
+    /// attempt, including retries, counts against the limit. Rates below one request per day are rejected.
+    #[arg(long, value_name = "REQ_PER_SEC", value_parser = throttle::parse_rate)]
+    max_rate: Option<f64>,
+    /// Maximum number of requests per second to a single host, in the format `host=rps`.
+    ///
+    /// Example: `fragile-backend.internal=20`.
+    ///
+    /// Like `--max-rate`, but only for the requests whose URL has the given host, which allows protecting a specific
+    /// backend in a replay against multiple hosts without slowing down the others. Requests to hosts that are not
+    /// listed are not limited, other than by `--max-rate`. Can be given multiple times, once per host.
+    #[arg(long, value_name = "HOST=REQ_PER_SEC")]
+    per_host_rate: Vec<throttle::HostRate>,
+    /// Adapt the number of requests in flight to the latency of the target, starting at this many.
+    ///
+    /// Requests that are due while the limit is reached wait for another request to complete first, so the replay
+    /// falls behind the original timing rather than overloading the target. After every window of completed requests,
+    /// at least 20 and at least as many as the current limit, the limit grows by one as long as the p99 latency of the
+    /// successful responses in the window stays within `--adaptive-latency-threshold` of the lowest p99 of a window so
+    /// far, and is cut to three quarters otherwise. The summary reports how the limit evolved over the run.
+    #[arg(long, value_name = "INITIAL")]
+    adaptive_concurrency: Option<usize>,
+    /// Percentage the p99 latency of a window may exceed the lowest one so far by before `--adaptive-concurrency`
+    /// decreases the limit.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 50.,
+        requires = "adaptive_concurrency"
+    )]
+    adaptive_latency_threshold: f64,
+    /// Maximum number of requests to send in total, after which no further requests are sent.
+    ///
+    /// Unlike `--limit`, which selects the records up front, the budget counts every request that is actually sent,
+    /// including retries and the requests to `--shadow-target`, which caps the cost of a replay against a metered
+    /// target. Once it is used up the replay stops like with CTRL-C, except that it ends successfully: the requests
+    /// in flight complete, retries that weren't counted yet are dropped, and the number of requests that were not
+    /// sent is reported.
+    #[arg(long, value_name = "N")]
+    request_budget: Option<usize>,
+    /// Open a new connection for every request, instead of reusing connections of previous requests.
+    ///
+    /// Requests are sent with `Connection: close` and no connections are kept for reuse. This matches clients that
+    /// don't keep connections alive, but considerably changes the load on the target: every request pays for
+    /// establishing a connection, including the TLS handshake, which is also included in its measured
+    /// `required_time`. Compare latencies only between runs with the same setting.
+    #[arg(long, conflicts_with = "connections_per_host")]
+    no_keepalive: bool,
+    /// Maximum number of idle connections per host that are kept open for reuse by later requests.
+    ///
+    /// Requests that are due while all kept connections are busy still open additional connections, which are closed
+    /// afterwards if the limit is reached. A lower limit therefore causes more requests to pay for establishing a
+    /// connection, which is included in their measured `required_time`. By default the number is unlimited.
+    #[arg(long, value_name = "N")]
+    connections_per_host: Option<usize>,
+    /// Maximum time to establish a connection, including resolving the host, e.g. `2 s`.
+    ///
+    /// Requests that fail because of it are reported as `connect_timeout` in the summary, separately from requests
+    /// that time out after connecting. By default there is no limit.
+    #[arg(long, value_name = "DURATION")]
+    connect_timeout: Option<Duration>,
+    /// PEM file with additional root certificates to trust, e.g. of an internal certificate authority.
+    ///
+    /// The file may contain several certificates, and the option can be given multiple times. The certificates are
+    /// trusted in addition to the built-in roots. Without it, the certificates that the `SSL_CERT_FILE` and
+    /// `SSL_CERT_DIR` environment variables point to are used instead, if they are set: `--ca-cert` takes
+    /// precedence over them.
+    #[arg(long, value_name = "FILE")]
+    ca_cert: Vec<PathBuf>,
+    /// Maximum time for a request, from establishing the connection until the response body has been received,
+    /// e.g. `30 s`.
+    ///
+    /// Applies to every attempt separately when retrying. By default there is no limit.
+    #[arg(long, value_name = "DURATION")]
+    request_timeout: Option<Duration>,
+    /// Skip records that fail to parse, instead of failing.
+    ///
+    /// Every skipped record is logged, and their number is reported in the summary.
+    #[arg(long)]
+    skip_bad_records: bool,
+    /// Exit with a non-zero status if any bad records were skipped.
+    ///
+    /// The replay still runs without the skipped records, but is reported as failed.
+    #[arg(long, requires = "skip_bad_records")]
+    strict: bool,
+    /// Succeed without sending any requests if the input files have no records, or none that are left after
+    /// filtering.
+    ///
+    /// By default this fails, with exit code 3 for files without records and 4 for files whose records were all
+    /// filtered out or dropped, e.g. as bad records or because of their URLs.
+    #[arg(long)]
+    allow_empty: bool,
+    /// Stop the run once more than this percentage of the last `--error-rate-window` responses were errors.
+    ///
+    /// When the circuit breaker trips no further requests are sent, requests that are already in flight are
+    /// completed without retries, and the run exits with a non-zero status. Errors are counted the same as in the
+    /// summary: every request that failed or didn't receive a successful (2xx) response.
+    #[arg(long, value_name = "PERCENT")]
+    abort_on_error_rate: Option<f64>,
+    /// Number of most recent responses the error rate for `--abort-on-error-rate` is computed over.
+    ///
+    /// The circuit breaker only trips once at least this many responses have been received.
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    error_rate_window: usize,
+    /// Stop the run once this many requests have failed.
+    ///
+    /// Like the circuit breaker of `--abort-on-error-rate`, no further requests are sent once the limit is reached,
+    /// requests that are already in flight are completed without retries, and the run exits with a non-zero status.
+    /// Failures are counted the same as errors in the summary. Being an absolute number regardless of the rate, this
+    /// is simpler to reason about for small runs. Both can be given, whichever is reached first stops the run.
+    #[arg(long, value_name = "N")]
+    max_failures: Option<usize>,
+    /// Stop the run at the first failure, the same as `--max-failures 1`.
+    ///
+    /// Takes precedence over `--max-failures`, whose limit can only be higher.
+    #[arg(long)]
+    fail_fast: bool,
+    /// Buffer the responses written to standard output and flush them at this interval, e.g. `1 s`.
+    ///
+    /// Responses are written in the order they complete, unless `--ordered-output` is given. By default every response
+    /// is flushed as soon as it has been written, which makes them appear live, but is comparatively expensive for runs
+    /// with many requests per second. Buffered responses are flushed at the end of the run, including when it's
+    /// aborted.
+    #[arg(long, value_name = "DURATION")]
+    flush_interval: Option<Duration>,
+    /// Write the responses in the order their requests were scheduled, instead of the order they complete in.
+    ///
+    /// This makes it practical to diff the output against the input, or against the output of another run. Requests
+    /// are still sent concurrently: the responses are held in memory until those of all requests scheduled before
+    /// them have been written, so a single slow request holds back, and buffers, all responses after it. Requests
+    /// that fail or aren't sent are skipped in the output.
+    #[arg(long)]
+    ordered_output: bool,
+    /// File to write the responses to, instead of standard output.
+    ///
+    /// Files ending in `.gz` are compressed with gzip.
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// Compress the responses written to `--output` with gzip, regardless of its extension.
+    ///
+    /// The compressed file is completed when the run ends, including when it's stopped with CTRL-C or aborted.
+    #[arg(long, requires = "output")]
+    output_gzip: bool,
+    /// Only write the requests that failed to standard output, instead of every response.
+    ///
+    /// Responses with a status other than 2xx are written like any other response, as `http_error`, including their
+    /// body with `--capture-body`, once retrying them is exhausted. Requests that failed without a response, e.g.
+    /// because the connection was refused or timed out, are written as usual. The summary still covers all requests.
+    #[arg(long)]
+    only_errors: bool,
+    /// Log the first N bytes of the body of every response with a status other than 2xx, e.g. `512`.
+    ///
+    /// Only as much of the body is read as is logged, without `--capture-body`. Bodies that aren't text by their
+    /// `Content-Type`, e.g. images, aren't shown, nor are bodies that are still compressed because `--capture-body`
+    /// asked the target for a `Content-Encoding`. Requests that are retried only log the body of their last attempt.
+    #[arg(long, value_name = "BYTES")]
+    echo_error_body: Option<usize>,
+    /// Also send the responses to this HTTP collector or Kafka topic as they complete, e.g.
+    /// `https://collector.example.com/ingest` or `kafka://broker:9092/topic`.
+    ///
+    /// The lines written to the output are sent in batches, at least every second: POSTed as newline-delimited JSON,
+    /// or produced as one record per line to partition 0 of the topic, or the one given by `?partition=N`. If the sink
+    /// can't keep up, lines are dropped instead of holding back the replay, and failing to send a batch is logged
+    /// without aborting the replay.
+    #[arg(long, value_name = "URL")]
+    sink: Option<sink::SinkUrl>,
+    /// Log the progress of the replay at this interval, e.g. `10 s`: the number of completed requests and errors, the
+    /// requests in flight, and the completed requests per second since the previous line.
+    ///
+    /// This is logged along with the progress bar, and gives a time series of the run in the logs. It isn't logged
+    /// while `--tui` shows the dashboard, which would be drawn over.
+    #[arg(long, value_name = "DURATION")]
+    progress_log_interval: Option<Duration>,
+    /// Show a live dashboard instead of the progress bar.
+    ///
+    /// The dashboard shows the current requests per second, the number of requests in flight, the distribution of
+    /// status codes, a graph of the latencies and the most recent errors. It is drawn on standard error, so the
+    /// responses written to standard output have to be redirected, e.g. to a file. Otherwise, or if standard error
+    /// isn't a terminal, the progress bar is shown instead. The summary is printed after the dashboard is closed.
+    #[arg(long)]
+    tui: bool,
+    /// File to write the records that didn't complete to, if the run is aborted.
+    ///
+    /// When the run is aborted, with CTRL-C or by the circuit breaker, every record that wasn't sent or whose
+    /// response wasn't received is written to this file, in the same format as the input file, so that it can be
+    /// passed to another run to pick up where this one left off. The file is not written when the run completes.
+    #[arg(long, value_name = "PATH")]
+    remaining_out: Option<PathBuf>,
+    /// File to write the records of the requests that failed to, to replay only those in another run.
+    ///
+    /// Every record whose request got an error status or no response at all is written to this file, in the same
+    /// format and order as in the input file, once the run has ended, including when it is aborted. The file is
+    /// only written if any request failed.
+    #[arg(long, value_name = "PATH", conflicts_with = "interleave")]
+    failures_out: Option<PathBuf>,
+    /// How long to wait for the requests in flight to complete after CTRL-C.
+    ///
+    /// On CTRL-C no further requests are sent, and those in flight are given this long to complete, so that their
+    /// responses are part of the output and the summary. Requests that are still in flight afterwards, or when
+    /// CTRL-C is pressed again, are aborted and counted in the summary.
+    #[arg(long, value_name = "DURATION", default_value = "30 s")]
+    drain_timeout: Duration,
+}
+
+impl Run {
+    /// Client to send the requests with. With `grpc`, it speaks HTTP/2 right away, as gRPC requires, rather than
+    /// only after negotiating it during the TLS handshake.
+    fn build_client(
+        &self,
+        grpc: bool,
+        connections: &connections::ConnectionCounter,
+    ) -> Result<Client> {
+        let mut builder = tls::add_root_certificates(Client::builder(), &self.ca_cert)?
+            .connector_layer(connections.clone());
+        if grpc {
+            builder = builder.http2_prior_knowledge();
+        }
+        let mut headers = HeaderMap::new();
+        for resolve_override in &self.resolve {
+            if !self.ip_family.allows(resolve_override.addr.ip()) {
+                anyhow::bail!(
+                    "--resolve maps `{}` to {}, which --ip-family {} doesn't allow",
+                    resolve_override.host,
+                    resolve_override.addr.ip(),
+                    self.ip_family
+                );
+            }
+        }
+        for (host, addrs) in resolve::overrides(&self.resolve) {
+            builder = builder.resolve_to_addrs(host, &addrs);
+        }
+        match self.dns_refresh {
+            Some(dns_refresh) => {
+                builder = builder.dns_resolver(Arc::new(resolve::RefreshingResolver::new(
+                    resolve::FamilyResolver(self.ip_family),
+                    dns_refresh.into(),
+                )));
+            }
+            None if self.ip_family != resolve::IpFamily::Auto => {
+                builder = builder.dns_resolver(Arc::new(resolve::FamilyResolver(self.ip_family)));
+            }
+            None => {}
+        }
+        if self.no_keepalive {
+            builder = builder.pool_max_idle_per_host(0);
+            headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        if let Some(connections_per_host) = self.connections_per_host {
+            builder = builder.pool_max_idle_per_host(connections_per_host);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout.into());
+        }
+        if let Some(request_timeout) = self.request_timeout {
+            builder = builder.timeout(request_timeout.into());
+        }
+        if let Some(max_redirects_recorded) = self.max_redirects_recorded {
+            builder = builder.redirect(redirect::policy(max_redirects_recorded));
+        }
+        if self.capture_body {
+            // Bodies are decoded by us rather than by reqwest, otherwise the transferred size would be unknown.
+            builder = builder.no_gzip();
+        }
+        let accept_encoding = self
+            .accept_encoding
+            .as_deref()
+            .or(self.capture_body.then_some(body::DEFAULT_ACCEPT_ENCODING));
+        if let Some(accept_encoding) = accept_encoding {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(accept_encoding)?);
+        }
+
+        Ok(builder.default_headers(headers).build()?)
+    }
+
+    /// Requests for the records of the input file, and of every `--interleave` file, and the number of bad records
+    /// that were skipped.
+    fn requests(
+        &self,
+        client: &Client,
+        mapping: Option<&mapping::Mapping>,
+    ) -> Result<(Vec<RequestWithOffset>, usize, url_length::OverLengthUrls)> {
+        let (mut requests, mut skipped, mut over_length) =
+            self.stream_requests(client, mapping, &self.input_file, None)?;
+        if !self.interleave.is_empty() {
+            let tag = |requests: &mut Vec<RequestWithOffset>, path: &Path| {
+                for request in requests {
+                    request.tags.insert(
+                        interleave::STREAM_TAG.to_owned(),
+                        path.display().to_string(),
+                    );
+                }
+            };
+            tag(&mut requests, &self.input_file);
+            for stream in &self.interleave {
+                let (mut stream_requests, stream_skipped, stream_over_length) = self
+                    .stream_requests(
+                        client,
+                        mapping,
+                        &stream.path,
+                        stream.scheme_and_host.as_deref(),
+                    )?;
+                tag(&mut stream_requests, &stream.path);
+                requests.append(&mut stream_requests);
+                skipped += stream_skipped;
+                over_length.skipped += stream_over_length.skipped;
+                over_length.truncated += stream_over_length.truncated;
+            }
+            // The sort is stable, so the requests of every stream stay in their order.
+            requests.sort_by_key(|request| request.offset);
+            tracing::info!(
+                "Interleaving {} streams of {} requests in total",
+                self.interleave.len() + 1,
+                requests.len()
+            );
+        }
+        if let Some(rps_profile) = &self.rps_profile {
+            let profile = profile::RpsProfile::from_path(rps_profile)?;
+            let records = requests.len();
+            if records > 0 {
+                requests = profile
+                    .offsets()
+                    .into_iter()
+                    .zip((0..records).cycle())
+                    .map(|(offset, index)| requests[index].clone_at(offset))
+                    .collect();
+            }
+            tracing::info!(
+                "Pacing the replay by the RPS profile, with {} requests from {} records over {}",
+                requests.len(),
+                records,
+                profile.duration()
+            );
+        }
+        if let Some(window) = self.spread_start {
+            let spread = schedule::spread_start(
+                requests.iter_mut().map(|request| &mut request.offset),
+                window,
+            );
+            tracing::info!(
+                "Spreading the {} requests due within the first {} of the replay across it",
+                spread,
+                window
+            );
+        }
+        if let Some(window) = self.soft_start {
+            for request in &mut requests {
+                request.offset = schedule::soft_start(request.offset, window);
+            }
+            tracing::info!(
+                "Ramping up to the full rate over the first {} of the replay, delaying the requests after it by {}",
+                window,
+                window * 0.5
+            );
+        }
+
+        Ok((requests, skipped, over_length))
+    }
+
+    /// Requests for the records of a single input file, scheduled from its first record, sent to `scheme_and_host`
+    /// if given.
+    fn stream_requests(
+        &self,
+        client: &Client,
+        mapping: Option<&mapping::Mapping>,
+        input_file: &Path,
+        scheme_and_host: Option<&str>,
+    ) -> Result<(Vec<RequestWithOffset>, usize, url_length::OverLengthUrls)> {
+        let (records, skipped) = AccessLogRecord::records_from_path(
+            input_file,
+            &self.input_fields,
+            &self.selection,
+            self.skip_bad_records,
+            !self.no_sort,
+        )?;
+        let mut records = records;
+        for header in &self.auth_from_column {
+            if !records.is_empty()
+                && !records
+                    .iter()
+                    .any(|record| record.fields.contains_key(&header.0.column))
+            {
+                anyhow::bail!(
+                    "None of the records of {} has the field `{}` of --auth-from-column",
+                    input_file.display(),
+                    header.0.column
+                );
+            }
+        }
+        if let Some(openapi) = &self.openapi {
+            let spec = openapi::Spec::from_path(openapi)?;
+            let documented = records
+                .iter()
+                .filter(|record| spec.documents(&record.path))
+                .count();
+            tracing::info!(
+                "{} of {} records have a path documented in the OpenAPI specification, {} are undocumented",
+                documented,
+                records.len(),
+                records.len() - documented
+            );
+            if let Some(openapi_filter) = self.openapi_filter {
+                records.retain(|record| openapi_filter.keeps(spec.documents(&record.path)));
+            }
+        }
+        let (requests, over_length) = self.requests_from_records(
+            client,
+            mapping,
+            input_file,
+            scheme_and_host,
+            records,
+            None,
+        )?;
+
+        Ok((requests, skipped, over_length))
+    }
+
+    /// The requests to replay the records as, scheduled relative to `start`, or to `--anchor` or the first of the
+    /// records if not given.
+    fn requests_from_records(
+        &self,
+        client: &Client,
+        mapping: Option<&mapping::Mapping>,
+        input_file: &Path,
+        scheme_and_host: Option<&str>,
+        mut records: Vec<AccessLogRecord>,
+        start: Option<Epoch>,
+    ) -> Result<(Vec<RequestWithOffset>, url_length::OverLengthUrls)> {
+        if self.offset_basis != OffsetBasis::RequestStart && !self.no_sort {
+            records.sort_by(|a, b| {
+                self.offset_basis
+                    .timestamp(a)
+                    .partial_cmp(&self.offset_basis.timestamp(b))
+                    .unwrap()
+            });
+        }
+        // Without sorting, every record is scheduled after the previous one in the file by the time between their
+        // timestamps, or at the same time if it is timestamped earlier.
+        let mut previous: Option<(Epoch, Epoch)> = None;
+        let timestamps = records
+            .iter()
+            .map(|record| {
+                let record_timestamp = self.offset_basis.timestamp(record);
+                let timestamp = match previous {
+                    Some((previous_record, previous_timestamp)) if self.no_sort => {
+                        previous_timestamp
+                            + (record_timestamp - previous_record).max(Duration::ZERO)
+                    }
+                    _ => record_timestamp,
+                };
+                previous = Some((record_timestamp, timestamp));
+                timestamp
+            })
+            .collect::<Vec<_>>();
+        let Some(start) = start
+            .or(self.anchor)
+            .or_else(|| timestamps.first().copied())
+        else {
+            return Ok((Vec::new(), url_length::OverLengthUrls::default()));
+        };
+        let end = timestamps.last().copied().unwrap_or(start);
+        let schedule = schedule::Schedule::new(
+            &self.time_factor_schedule,
+            start,
+            end.max(start),
+            self.time_factor.unwrap_or(1f64),
+        )?;
+        let mut builder = requests::Builder::new(
+            self,
+            client,
+            mapping,
+            input_file,
+            scheme_and_host,
+            schedule,
+            start,
+        )?;
+        let mut requests = Vec::new();
+        for (record, timestamp) in records.into_iter().zip(timestamps) {
+            requests.extend(builder.request(record, timestamp)?);
+        }
+        builder.deviations.log(self);
+        if let Some(weight_column) = &self.weight_column {
+            requests = requests::weigh(requests, weight_column, self.weight_mode)?;
+        }
+        if self.match_concurrency {
+            requests::match_concurrency(&mut requests);
+        }
+
+        Ok((requests, builder.deviations.over_length))
+    }
+
+    /// The requests of the records in `lines`, read from the input file with `--follow`.
+    ///
+    /// They are scheduled relative to `start`, which is set to `--anchor` or the first of the records if not known
+    /// yet.
+    fn followed_requests(
+        &self,
+        client: &Client,
+        mapping: Option<&mapping::Mapping>,
+        format: InputFormat,
+        lines: Vec<u8>,
+        start: &mut Option<Epoch>,
+    ) -> Result<(Vec<RequestWithOffset>, usize, url_length::OverLengthUrls)> {
+        // An empty file doesn't even have the header of a CSV file yet.
+        if lines.is_empty() {
+            return Ok(Default::default());
+        }
+        let (records, skipped) = AccessLogRecord::select_records(
+            AccessLogRecord::parse_records(io::Cursor::new(lines), format, &self.input_fields)?,
+            &self.input_fields,
+            &self.selection,
+            self.skip_bad_records,
+            true,
+        )?;
+        if start.is_none() {
+            *start = self.anchor.or_else(|| {
+                records
+                    .iter()
+                    .map(|record| self.offset_basis.timestamp(record))
+                    .min()
+            });
+        }
+        let (requests, over_length) =
+            self.requests_from_records(client, mapping, &self.input_file, None, records, *start)?;
+
+        Ok((requests, skipped, over_length))
+    }
+
+    async fn run(&self) -> Result<()> {
+        self.validate()?;
+        let host_rate_limiters = self.host_rate_limiters()?;
+        let connections = connections::ConnectionCounter::default();
+        let client = self.build_client(false, &connections)?;
+        let mapping = self
+            .mapping
+            .as_ref()
+            .map(mapping::Mapping::from_path)
+            .transpose()?;
+        // With `--follow`, the file being followed and its format, and the timestamp the requests are scheduled
+        // relative to.
+        let mut follower = None;
+        let mut follow_start = None;
+        let (requests, mut skipped_records, mut over_length) = if self.follow {
+            let format = match self.input_fields.input_format {
+                Some(format) => format,
+                None => InputFormat::of_path(&self.input_file)?,
+            };
+            let mut file = follow::Follower::new(&self.input_file, format == InputFormat::Csv)?;
+            let lines = file.read()?;
+            follower = Some((file, format));
+            self.followed_requests(&client, mapping.as_ref(), format, lines, &mut follow_start)?
+        } else {
+            self.requests(&client, mapping.as_ref())?
+        };
+        if requests.is_empty() && follower.is_none() {
+            let mut records = 0;
+            for path in std::iter::once(&self.input_file)
+                .chain(self.interleave.iter().map(|stream| &stream.path))
+            {
+                records +=
+                    AccessLogRecord::parse_records_from_path(path, &self.input_fields)?.len();
+            }
+            return empty::EmptyInput::new(records).fail(self.allow_empty);
+        }
+        let fingerprint = self.fingerprint(&requests)?;
+        if self.dry_run {
+            return self.dry_run(&requests, &fingerprint);
+        }
+        let minimum_expected_runtime = self.minimum_runtime(&requests)?;
+
+        let baseline = self.baseline(&requests)?;
+        self.log_not_retried(&requests);
+        let signer = match &self.sigv4 {
+            Some(scope) => Some(sigv4::Signer::new(scope.clone()).await?),
+            None => None,
+        };
+        let token_source = match &self.token_command {
+            Some(command) => Some(token::TokenSource::new(command.clone(), self.token_ttl).await?),
+            None => None,
+        };
+        if let Some(path) = &self.healthcheck {
+            healthcheck::check(
+                &client,
+                signer.as_ref(),
+                token_source.as_ref(),
+                requests.iter().map(|request| request.request.url()),
+                path,
+                self.healthcheck_timeout,
+            )
+            .await?;
+        }
+
+        tracing::info!(
+            "Starting to execute {} requests, minimum runtime is: {}",
+            requests.len(),
+            minimum_expected_runtime
+        );
+
+        // Created before any request is sent, so that a path that can't be written to fails the run right away.
+        let offsets = requests
+            .iter()
+            .map(|request| request.offset)
+            .collect::<Vec<_>>();
+        let mut lines = results::Lines::new(self, &offsets)?;
+        let mut dashboard = self.dashboard(requests.len())?;
+        let pb = if dashboard.is_some() {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(requests.len() as u64).with_style(ProgressStyle::with_template(
+                "[{elapsed}] {wide_bar} {pos:>7}/{len:7}",
+            )?)
+        };
+
+        let replay = Arc::new(self.replay(
+            client,
+            &connections,
+            host_rate_limiters,
+            baseline,
+            signer,
+            token_source,
+        )?);
+        replay.events.emit(events::Event::Started {
+            requests: requests.len(),
+            minimum_runtime: minimum_expected_runtime.to_seconds(),
+        });
+
+        // Offsets, and the raw records if they have to be written out, of the requests that didn't complete yet.
+        let mut unfinished = BTreeMap::new();
+        // Connections opened by `--healthcheck` are reused by the replay, rather than opened by it.
+        connections.reset();
+        let mut join_set = tokio::task::JoinSet::new();
+        let spawn = |join_set: &mut tokio::task::JoinSet<_>,
+                     unfinished: &mut BTreeMap<_, _>,
+                     index: usize,
+                     mut request_with_offset: RequestWithOffset| {
+            let raw = request_with_offset.record.raw.take();
+            unfinished.insert(
+                index,
+                (
+                    request_with_offset.offset,
+                    raw.filter(|_| self.remaining_out.is_some() || self.failures_out.is_some()),
+                ),
+            );
+            join_set.spawn({
+                let replay = replay.clone();
+                let pb = pb.clone();
+                async move {
+                    let completed = results::Completed {
+                        index,
+                        url: request_with_offset.request.url().clone(),
+                        tags: std::mem::take(&mut request_with_offset.tags),
+                        original_status: request_with_offset.record.status,
+                        weight: request_with_offset.weight,
+                    };
+                    let result = replay.get(request_with_offset).await;
+                    pb.inc(1);
+                    (completed, result)
+                }
+            });
+        };
+        let mut next_index = requests.len();
+        for (index, request_with_offset) in requests.into_iter().enumerate() {
+            spawn(&mut join_set, &mut unfinished, index, request_with_offset);
+        }
+
+        // Responses are written as soon as they complete, so that captured bodies don't pile up in memory, unless
+        // `--ordered-output` holds them back.
+        let mut flush = tokio::time::interval(
+            self.flush_interval
+                .map_or(std::time::Duration::MAX, Into::into),
+        );
+        let mut results = results::Results::new(self);
+        let mut redraw = tokio::time::interval(std::time::Duration::from_millis(250));
+        let progress_log_interval = self
+            .progress_log_interval
+            .map_or(std::time::Duration::MAX, Into::into);
+        let mut progress_log = tokio::time::interval_at(
+            tokio::time::Instant::now()
+                .checked_add(progress_log_interval)
+                .unwrap_or_else(tokio::time::Instant::now),
+            progress_log_interval,
+        );
+        // Completed requests and time of the previous progress log line, to report the rate since then.
+        let mut last_progress = (0, std::time::Instant::now());
+        let mut signals = pause::Signals::new()?;
+        let mut paused_at = None;
+        // Until when the requests in flight may complete after CTRL-C, before they are aborted.
+        let mut drain_deadline = None;
+        // Reached once the rate has been ramped up to the full rate with `--soft-start`.
+        let full_rate = replay
+            .clock
+            .sleep_until(self.soft_start.unwrap_or_default().into());
+        tokio::pin!(full_rate);
+        let mut ramping_up = self.soft_start.is_some();
+        let clean_exit = loop {
+            // While following the input file, the replay continues without requests to wait for.
+            let following = follower.is_some() && !*replay.stopping.borrow();
+            tokio::select! {
+                response = join_set.join_next(), if !following || !join_set.is_empty() => {
+                    // Following the input file only ends with CTRL-C, which is clean once the requests in flight
+                    // have completed.
+                    let Some(response) = response else {
+                        break drain_deadline.is_none() || follower.is_some()
+                    };
+                    let (completed, result) = response?;
+                    let raw = if matches!(result, Ok(None)) {
+                        None
+                    } else {
+                        unfinished.remove(&completed.index).and_then(|(_, raw)| raw)
+                    };
+                    results.record(
+                        completed,
+                        result,
+                        raw,
+                        &mut lines,
+                        &replay,
+                        dashboard.as_mut(),
+                    )?;
+                }
+                (followed, format) = async {
+                    match &mut follower {
+                        Some((file, format)) => (file.next().await, *format),
+                        None => std::future::pending().await,
+                    }
+                }, if following => {
+                    let (requests, skipped, followed_over_length) = self.followed_requests(
+                        &replay.client,
+                        mapping.as_ref(),
+                        format,
+                        followed?,
+                        &mut follow_start,
+                    )?;
+                    skipped_records += skipped;
+                    over_length.skipped += followed_over_length.skipped;
+                    over_length.truncated += followed_over_length.truncated;
+                    pb.inc_length(requests.len() as u64);
+                    for request_with_offset in requests {
+                        spawn(&mut join_set, &mut unfinished, next_index, request_with_offset);
+                        next_index += 1;
+                    }
+                }
+                _ = flush.tick(), if self.flush_interval.is_some() => {
+                    lines.flush()?;
+                }
+                _ = progress_log.tick(),
+                    if self.progress_log_interval.is_some() && dashboard.is_none() =>
+                {
+                    let (last_completed, last_logged) = last_progress;
+                    let completed = results.summary.requests();
+                    let rate =
+                        (completed - last_completed) as f64 / last_logged.elapsed().as_secs_f64();
+                    tracing::info!(
+                        "Progress: completed={} errors={} in_flight={} rps={:.2}",
+                        completed,
+                        results.summary.errors(),
+                        replay.in_flight.load(Ordering::Relaxed),
+                        rate
+                    );
+                    last_progress = (completed, std::time::Instant::now());
+                }
+                () = &mut full_rate, if ramping_up => {
+                    ramping_up = false;
+                    if dashboard.is_none() {
+                        tracing::info!(
+                            "Reached the full rate after the soft start, {} requests have completed",
+                            results.summary.requests()
+                        );
+                    }
+                }
+                _ = redraw.tick(), if dashboard.is_some() => {
+                    if let Some(dashboard) = &mut dashboard {
+                        dashboard.draw(
+                            replay.in_flight.load(Ordering::Relaxed),
+                            replay.clock.is_paused(),
+                        )?;
+                    }
+                }
+                signal = signals.recv() => {
+                    toggle_pause(&replay, signal, &mut paused_at, dashboard.is_none());
+                }
+                () = async {
+                    match drain_deadline {
+                        Some(drain_deadline) => tokio::time::sleep_until(drain_deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    break false
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    if drain_deadline.is_some() {
+                        break false
+                    }
+                    replay.stop();
+                    drain_deadline = Some(
+                        tokio::time::Instant::now()
+                            .checked_add(self.drain_timeout.into())
+                            .unwrap_or_else(tokio::time::Instant::now),
+                    );
+                    if dashboard.is_none() {
+                        tracing::warn!(
+                            "Stopping, waiting up to {} for the requests in flight to complete, press CTRL-C again \
+                             to abort them",
+                            self.drain_timeout
+                        );
+                    }
+                }
+            }
+        };
+        // Requests that are still in flight after the drain timeout, or a second CTRL-C, are aborted.
+        let aborted = replay.in_flight.load(Ordering::Relaxed);
+        let budget_exhausted = replay.budget_exhausted.load(Ordering::Relaxed);
+        let stopped_early = results.stopped() || budget_exhausted;
+        join_set.abort_all();
+        lines.finish().await?;
+        // Restore the terminal before printing the summary.
+        drop(dashboard);
+
+        pb.finish();
+        results.summary.record_connections(
+            replay.attempts.load(Ordering::Relaxed),
+            connections.attempted(),
+            connections.opened(),
+        );
+        let mut stderr = io::stderr().lock();
+        results.summary.write(&mut stderr)?;
+        if let Some(stats_out) = &self.stats_out {
+            let run_summary = results.summary.to_run_summary(clean_exit && !stopped_early);
+            let file = File::create(stats_out)
+                .with_context(|| format!("Failed to create {}", stats_out.display()))?;
+            serde_json::to_writer_pretty(io::BufWriter::new(file), &run_summary)?;
+        }
+        replay.write_throttling(&mut stderr)?;
+        if skipped_records > 0 {
+            writeln!(stderr, "Skipped bad records: {}", skipped_records)?;
+        }
+        if let Some(max_url_length) = self.max_url_length.filter(|_| !over_length.is_empty()) {
+            over_length.write(&mut stderr, max_url_length)?;
+        }
+        results.write_comparisons(&mut stderr, self)?;
+        let failed_assertions = results.write_assertions(&mut stderr, &self.assert)?;
+        results.write_stop(
+            &mut stderr,
+            budget_exhausted.then(|| self.request_budget.unwrap_or_default()),
+        )?;
+        if aborted > 0 {
+            writeln!(
+                stderr,
+                "Aborted {} requests that were still in flight",
+                aborted
+            )?;
+        }
+        if !clean_exit || stopped_early {
+            self.write_unfinished(
+                &mut stderr,
+                &unfinished,
+                Duration::from(replay.clock.elapsed()),
+            )?;
+        }
+        results.write_failures(&mut stderr, self)?;
+        replay.events.emit(events::Event::Finished {
+            completed: results.summary.requests(),
+            errors: results.summary.errors(),
+            aborted: !clean_exit || stopped_early,
+        });
+
+        results.check(self, failed_assertions)?;
+        if self.strict && skipped_records > 0 && clean_exit {
+            anyhow::bail!("{} bad records were skipped", skipped_records);
+        }
+        if clean_exit {
+            Ok(())
+        } else {
+            anyhow::bail!("Aborted with CTRL-C")
+        }
+    }
+
+    /// Fail on options that are out of range, or that require another option.
+    fn validate(&self) -> Result<()> {
+        if self.max_failures == Some(0) {
+            anyhow::bail!("--max-failures must be positive");
+        }
+        if self.compare_bodies && self.shadow_target.is_none() && self.baseline.is_none() {
+            anyhow::bail!("--compare-bodies requires --shadow-target or --baseline");
+        }
+        if self.adaptive_concurrency == Some(0) {
+            anyhow::bail!("--adaptive-concurrency must be positive");
+        }
+        if self.adaptive_latency_threshold <= 0. {
+            anyhow::bail!("--adaptive-latency-threshold must be positive");
+        }
+        if self
+            .target_bandwidth
+            .is_some_and(|target_bandwidth| target_bandwidth <= 0.)
+        {
+            anyhow::bail!("--target-bandwidth must be positive");
+        }
+        if self.group_by == Some(summary::GroupBy::Tags)
+            && self.tag_column.is_empty()
+            && self.interleave.is_empty()
+        {
+            anyhow::bail!("--group-by tags requires --tag-column or --interleave");
+        }
+        if self.group_by == Some(summary::GroupBy::Stream) && self.interleave.is_empty() {
+            anyhow::bail!("--group-by stream requires --interleave");
+        }
+        if self.error_rate_window == 0 {
+            anyhow::bail!("--error-rate-window must be positive");
+        }
+
+        Ok(())
+    }
+
+    /// Rate limiters of the hosts with a `--per-host-rate`, by host name.
+    fn host_rate_limiters(&self) -> Result<BTreeMap<String, throttle::RateLimiter>> {
+        let mut host_rate_limiters = BTreeMap::new();
+        for host_rate in &self.per_host_rate {
+            let rate_limiter = throttle::RateLimiter::new(host_rate.requests_per_second)
+                .with_context(|| format!("Invalid --per-host-rate for {}", host_rate.host))?;
+            if host_rate_limiters
+                .insert(host_rate.host.clone(), rate_limiter)
+                .is_some()
+            {
+                anyhow::bail!(
+                    "--per-host-rate given more than once for {}",
+                    host_rate.host
+                );
+            }
+        }
+
+        Ok(host_rate_limiters)
+    }
+
+    /// Fingerprint of the schedule of the requests, which has to be the one of `--deterministic-check` if given.
+    fn fingerprint(&self, requests: &[RequestWithOffset]) -> Result<String> {
+        let mut scheduled = requests.iter().collect::<Vec<_>>();
+        scheduled.sort_by_key(|request| request.offset);
+        let fingerprint = schedule::fingerprint(scheduled.into_iter().map(|request| {
+            (
+                request.offset,
+                request.request.method(),
+                request.request.url(),
+            )
+        }));
+        if let Some(expected) = &self.deterministic_check {
+            if !fingerprint.eq_ignore_ascii_case(expected.trim()) {
+                anyhow::bail!(
+                    "The schedule has the fingerprint {}, not {} as expected",
+                    fingerprint,
+                    expected
+                );
+            }
+            tracing::info!("The schedule has the expected fingerprint {}", fingerprint);
+        }
+
+        Ok(fingerprint)
+    }
+
+    /// Print the estimate of the replay of the requests, and the fingerprint of their schedule, for `--dry-run`.
+    fn dry_run(&self, requests: &[RequestWithOffset], fingerprint: &str) -> Result<()> {
+        let estimate = estimate::Estimate::new(
+            requests.iter().map(|request| {
+                (
+                    request.offset,
+                    request.record.received_bytes,
+                    request.record.sent_bytes,
+                )
+            }),
+            self.max_rate,
+        );
+        let mut stdout = io::stdout().lock();
+        estimate.write(&mut stdout)?;
+        writeln!(stdout, "Fingerprint: {}", fingerprint)?;
+
+        Ok(())
+    }
+
+    /// Time it takes until the last of the requests is due, which has to be within `--max-offset` unless `--yes` is
+    /// given.
+    fn minimum_runtime(&self, requests: &[RequestWithOffset]) -> Result<Duration> {
+        // With `--preserve-time-of-day` the last record isn't necessarily the last request to be sent.
+        let last = requests.iter().max_by_key(|request| request.offset);
+        let minimum_expected_runtime = last.map_or(Duration::ZERO, |last| last.offset);
+        if let Some(last) = last.filter(|_| minimum_expected_runtime > self.max_offset && !self.yes)
+        {
+            anyhow::bail!(
+                "The replay would take at least {}, longer than --max-offset {}, because of the record at {}. \
+                 Check its timestamp, or pass --yes to start anyway",
+                minimum_expected_runtime,
+                self.max_offset,
+                last.record.timestamp
+            );
+        }
+        if let Some((last, max_duration_warn)) = last.zip(
+            self.max_duration_warn
+                .filter(|max_duration_warn| minimum_expected_runtime > *max_duration_warn),
+        ) {
+            tracing::warn!(
+                "The replay will take at least {}, longer than --max-duration-warn {}, because of the record at {}",
+                minimum_expected_runtime,
+                max_duration_warn,
+                last.record.timestamp
+            );
+        }
+
+        Ok(minimum_expected_runtime)
+    }
+
+    /// The `--baseline` to compare the responses to, if given, logging how many of the requests it doesn't have.
+    fn baseline(&self, requests: &[RequestWithOffset]) -> Result<Option<baseline::Baseline>> {
+        let baseline = self
+            .baseline
+            .as_ref()
+            .map(|baseline| baseline::Baseline::from_path(baseline, self.compare_bodies))
+            .transpose()?;
+        if let Some(baseline) = &baseline {
+            let missing = requests
+                .iter()
+                .filter(|request| baseline.latency(request.request.url()).is_none())
+                .count();
+            tracing::info!(
+                "Comparing against the latencies of {} URLs from the baseline",
+                baseline.len()
+            );
+            if missing > 0 {
+                tracing::warn!(
+                    "{} requests have no latency in the baseline, they aren't compared",
+                    missing
+                );
+            }
+        }
+
+        Ok(baseline)
+    }
+
+    /// Log how many of the requests won't be retried because of their method.
+    fn log_not_retried(&self, requests: &[RequestWithOffset]) {
+        if self.retries == 0 || self.retry_all_methods {
+            return;
+        }
+        let not_retried = requests
+            .iter()
+            .filter(|request| !retry::is_idempotent(request.request.method()))
+            .count();
+        if not_retried > 0 {
+            tracing::info!(
+                "{} requests have a method that isn't idempotent and won't be retried, see --retry-all-methods",
+                not_retried
+            );
+        }
+    }
+
+    /// The dashboard of `--tui`, unless it can't be shown in this terminal and the progress bar is shown instead.
+    fn dashboard(&self, requests: usize) -> Result<Option<tui::Dashboard>> {
+        match (
+            self.tui,
+            io::stderr().is_terminal(),
+            io::stdout().is_terminal() && self.output.is_none(),
+        ) {
+            (false, _, _) => Ok(None),
+            (true, true, false) => Ok(Some(tui::Dashboard::new(requests)?)),
+            (true, false, _) => {
+                tracing::warn!(
+                    "Standard error is not a terminal, showing the progress bar instead of --tui"
+                );
+                Ok(None)
+            }
+            (true, true, true) => {
+                tracing::warn!(
+                    "Standard output is a terminal, redirect it or use --output to use --tui, showing the progress bar \
+                     instead"
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// State of the replay that is shared by all its requests.
+    fn replay(
+        &self,
+        client: Client,
+        connections: &connections::ConnectionCounter,
+        host_rate_limiters: BTreeMap<String, throttle::RateLimiter>,
+        baseline: Option<baseline::Baseline>,
+        signer: Option<sigv4::Signer>,
+        token_source: Option<token::TokenSource>,
+    ) -> Result<replay::Replay> {
+        Ok(replay::Replay {
+            client,
+            grpc_client: self
+                .grpc
+                .then(|| self.build_client(true, connections))
+                .transpose()?,
+            capture_body: self.capture_body,
+            keep_error_responses: self.only_errors,
+            record_redirects: self.max_redirects_recorded.is_some(),
+            buffered_bytes: body::BufferedBytes::new(self.max_in_flight_bytes),
+            retry_policy: retry::RetryPolicy {
+                retries: self.retries,
+                backoff: self.retry_backoff,
+                max_backoff: self.retry_max_backoff,
+                jitter: self.retry_jitter,
+                max_retry_duration: self.max_retry_duration,
+                all_methods: self.retry_all_methods,
+            },
+            events: events::Events::new(self.events_file.as_deref())?,
+            rate_limiter: self
+                .max_rate
+                .map(throttle::RateLimiter::new)
+                .transpose()
+                .context("Invalid --max-rate")?,
+            host_rate_limiters,
+            on_protocol_mismatch: self.on_protocol_mismatch,
+            protocol_mismatches: AtomicUsize::new(0),
+            adaptive_concurrency: self.adaptive_concurrency.map(|initial| {
+                adaptive::AdaptiveConcurrency::new(initial, self.adaptive_latency_threshold)
+            }),
+            stopping: tokio::sync::watch::Sender::new(false),
+            in_flight: AtomicUsize::new(0),
+            attempts: AtomicUsize::new(0),
+            request_budget: self.request_budget,
+            budget_exhausted: AtomicBool::new(false),
+            clock: pause::Clock::new(),
+            shadow_target: self.shadow_target.clone(),
+            baseline,
+            body_comparer: self
+                .compare_bodies
+                .then(|| body_diff::BodyComparer::new(&self.ignore_json_path)),
+            min_latency_for_comparison: self.min_latency_for_comparison,
+            signer,
+            token_source,
+            expected_headers: self.expect_header.clone(),
+            echo_error_body: self.echo_error_body,
+        })
+    }
+
+    /// Write how many requests did not complete, and were due by `elapsed`, and write their records to
+    /// `--remaining-out`.
+    fn write_unfinished(
+        &self,
+        mut writer: impl Write,
+        unfinished: &BTreeMap<usize, (Duration, Option<remaining::RawRecord>)>,
+        elapsed: Duration,
+    ) -> Result<()> {
+        if unfinished.is_empty() {
+            return Ok(());
+        }
+        let due = unfinished
+            .values()
+            .filter(|(offset, _)| *offset <= elapsed)
+            .count();
+        writeln!(
+            writer,
+            "{} requests did not complete: {} were due, {} were not yet due",
+            unfinished.len(),
+            due,
+            unfinished.len() - due
+        )?;
+        if let Some(remaining_out) = &self.remaining_out {
+            remaining::write(
+                &self.input_file,
+                remaining_out,
+                unfinished.values().filter_map(|(_, raw)| raw.as_ref()),
+            )?;
+            writeln!(
+                writer,
+                "Wrote the records that did not complete to {}",
+                remaining_out.display()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pause or resume the replay on `signal`, logging it unless the dashboard is shown.
+fn toggle_pause(
+    replay: &replay::Replay,
+    signal: pause::Signal,
+    paused_at: &mut Option<std::time::Instant>,
+    log: bool,
+) {
+    match signal {
+        pause::Signal::Pause => {
+            if replay.clock.pause() {
+                if log {
+                    tracing::warn!(
+                        "Paused the replay, send SIGCONT to resume (e.g. `kill -CONT {}`)",
+                        std::process::id()
+                    );
+                }
+                replay.events.emit(events::Event::Paused);
+                *paused_at = Some(std::time::Instant::now());
+            }
+        }
+        pause::Signal::Resume => {
+            if replay.clock.resume() {
+                let paused_for = paused_at.take().map_or(Duration::ZERO, |paused_at| {
+                    Duration::from(paused_at.elapsed())
+                });
+                if log {
+                    tracing::warn!("Resumed the replay after {}", paused_for.approx());
+                }
+                replay.events.emit(events::Event::Resumed {
+                    paused_for: paused_for.to_seconds(),
+                });
+            }
+        }
+    }
+}
+
+/// Line numbers of byte offsets in a file, for offsets that only ever increase.
+#[derive(Debug, Default)]
+struct LineCounter {
+    offset: usize,
+    line: usize,
+}
+
+impl LineCounter {
+    /// The 1-based line of `offset` in `data`.
+    fn line_at(&mut self, data: &[u8], offset: usize) -> usize {
+        let offset = offset.min(data.len());
+        if offset > self.offset {
+            self.line += data[self.offset..offset]
+                .iter()
+                .filter(|byte| **byte == b'\n')
+                .count();
+            self.offset = offset;
+        }
+        self.line + 1
+    }
+}
+
+/// Byte offset of the 1-based `line` and `column` in `data`, as reported by the JSON parser.
+fn offset_of(data: &[u8], line: usize, column: usize) -> usize {
+    let line_start = match line {
+        0 | 1 => 0,
+        line => data
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == b'\n')
+            .nth(line - 2)
+            .map_or(data.len(), |(index, _)| index + 1),
+    };
+    (line_start + column.saturating_sub(1)).min(data.len())
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 64)]
+async fn main() -> Result<ExitCode> {
+    let cli: Cli = config::parse()?;
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(
+            EnvFilter::builder()
+                .with_default_directive(if cli.quiet {
+                    LevelFilter::WARN.into()
+                } else {
+                    LevelFilter::INFO.into()
+                })
+                .from_env_lossy(),
+        )
+        .init();
+
+    let result = match &cli.command {
+        Commands::Convert(args) => args.run(),
+        Commands::Print(args) => args.run(),
+        Commands::Record(args) => args.run().await,
+        Commands::Run(args) => args.run().await,
+        Commands::Validate(args) => args.run(),
+    };
+    match result {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(err) => match err.downcast_ref::<empty::EmptyInput>() {
+            // Reported like any other error, but with an exit code of its own.
+            Some(empty) => {
+                eprintln!("Error: {:?}", err);
+                Ok(empty.exit_code())
+            }
+            None => Err(err),
+        },
     }
 }
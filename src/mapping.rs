@@ -0,0 +1,156 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::Deserialize;
+
+/// Key of the entry that applies to every domain name without an entry of its own.
+const CATCH_ALL: &str = "*";
+
+/// Maps the domain names of the original requests to the scheme and host they are replayed against.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Mapping(HashMap<String, String>);
+
+impl Mapping {
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open mapping file {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to parse mapping file {}", path.display()))
+    }
+
+    /// Scheme and host for the given domain name.
+    ///
+    /// An entry for the exact domain name takes precedence over wildcard entries like `*.example.com`, which apply to
+    /// every subdomain of `example.com`, with the most specific one winning. These in turn take precedence over the
+    /// catch-all entry `*`.
+    pub(crate) fn get_scheme_and_host(&self, domain_name: Option<&str>) -> Result<&str> {
+        domain_name
+            .and_then(|domain_name| {
+                self.0.get(domain_name).or_else(|| {
+                    domain_name
+                        .match_indices('.')
+                        .find_map(|(index, _)| self.0.get(&format!("*{}", &domain_name[index..])))
+                })
+            })
+            .or_else(|| self.0.get(CATCH_ALL))
+            .map(String::as_str)
+            .ok_or_else(|| match domain_name {
+                Some(domain_name) => {
+                    anyhow::anyhow!("No mapping for domain name `{}`", domain_name)
+                }
+                None => anyhow::anyhow!("Record has no domain name and there is no `*` mapping"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping() -> Mapping {
+        Mapping(HashMap::from(
+            [
+                ("api.example.com", "https://api.staging.internal"),
+                ("*.example.com", "https://wildcard.staging.internal"),
+                ("*.eu.example.com", "https://eu.staging.internal"),
+                ("*", "https://fallback.staging.internal"),
+            ]
+            .map(|(domain_name, scheme_and_host)| {
+                (domain_name.to_owned(), scheme_and_host.to_owned())
+            }),
+        ))
+    }
+
+    #[test]
+    fn exact_match_wins_over_wildcard() {
+        assert_eq!(
+            mapping()
+                .get_scheme_and_host(Some("api.example.com"))
+                .unwrap(),
+            "https://api.staging.internal"
+        );
+    }
+
+    #[test]
+    fn most_specific_wildcard_wins_over_catch_all() {
+        let mapping = mapping();
+        assert_eq!(
+            mapping
+                .get_scheme_and_host(Some("www.example.com"))
+                .unwrap(),
+            "https://wildcard.staging.internal"
+        );
+        assert_eq!(
+            mapping
+                .get_scheme_and_host(Some("shop.de.example.com"))
+                .unwrap(),
+            "https://wildcard.staging.internal"
+        );
+        assert_eq!(
+            mapping
+                .get_scheme_and_host(Some("shop.eu.example.com"))
+                .unwrap(),
+            "https://eu.staging.internal"
+        );
+    }
+
+    #[test]
+    fn catch_all_applies_without_match() {
+        let mapping = mapping();
+        // A wildcard only applies to subdomains, not to the domain itself.
+        assert_eq!(
+            mapping.get_scheme_and_host(Some("example.com")).unwrap(),
+            "https://fallback.staging.internal"
+        );
+        assert_eq!(
+            mapping
+                .get_scheme_and_host(Some("www.example.org"))
+                .unwrap(),
+            "https://fallback.staging.internal"
+        );
+        assert_eq!(
+            mapping.get_scheme_and_host(None).unwrap(),
+            "https://fallback.staging.internal"
+        );
+    }
+
+    #[test]
+    fn fails_without_catch_all() {
+        let mut mapping = mapping();
+        mapping.0.remove(CATCH_ALL);
+        assert_eq!(
+            mapping
+                .get_scheme_and_host(Some("www.example.org"))
+                .unwrap_err()
+                .to_string(),
+            "No mapping for domain name `www.example.org`"
+        );
+        assert!(mapping.get_scheme_and_host(None).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::str::FromStr;
+
+use anyhow::Result;
+use regex::Regex;
+
+/// Rules applied by `--normalize-paths`, unless replaced by `--normalize-rule`.
+const DEFAULT_RULES: [(&str, &str); 3] = [
+    (r"[0-9]+", ":id"),
+    (
+        r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}",
+        ":uuid",
+    ),
+    (r"[0-9a-fA-F]{16,}", ":hash"),
+];
+
+/// Rule replacing every path segment that matches a regular expression in full with a placeholder, in the format
+/// `REGEX=PLACEHOLDER`.
+#[derive(Debug, Clone)]
+pub(crate) struct NormalizeRule {
+    regex: Regex,
+    placeholder: String,
+}
+
+impl NormalizeRule {
+    fn new(regex: &str, placeholder: &str) -> Result<Self> {
+        Ok(Self {
+            regex: Regex::new(&format!("^(?:{})$", regex))?,
+            placeholder: placeholder.to_owned(),
+        })
+    }
+}
+
+impl FromStr for NormalizeRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((regex, placeholder)) = s.rsplit_once('=') else {
+            anyhow::bail!("expected `REGEX=PLACEHOLDER`");
+        };
+        Self::new(regex, placeholder)
+    }
+}
+
+/// Collapses high-cardinality path segments, like IDs, into placeholders, e.g. `/users/12345` into `/users/:id`.
+#[derive(Debug, Clone)]
+pub(crate) struct PathNormalizer {
+    rules: Vec<NormalizeRule>,
+}
+
+impl PathNormalizer {
+    /// Normalizer with the given rules, or the default rules if there are none.
+    pub(crate) fn new(rules: &[NormalizeRule]) -> Self {
+        let rules = if rules.is_empty() {
+            DEFAULT_RULES
+                .iter()
+                .map(|(regex, placeholder)| {
+                    NormalizeRule::new(regex, placeholder).expect("default rules are valid")
+                })
+                .collect()
+        } else {
+            rules.to_vec()
+        };
+
+        Self { rules }
+    }
+
+    /// Replace every segment of the path with the placeholder of the first rule that matches it.
+    pub(crate) fn normalize(&self, path: &str) -> String {
+        path.split('/')
+            .map(|segment| {
+                self.rules
+                    .iter()
+                    .find(|rule| !segment.is_empty() && rule.regex.is_match(segment))
+                    .map_or(segment, |rule| rule.placeholder.as_str())
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
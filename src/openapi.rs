@@ -0,0 +1,134 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The paths of an OpenAPI specification, to select records by whether their path is documented in it.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use regex::RegexSet;
+use serde::{
+    de::IgnoredAny,
+    Deserialize,
+};
+
+/// Which records to replay, by whether their path is documented in the OpenAPI specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OpenApiFilter {
+    /// Only records whose path matches a path of the specification.
+    Documented,
+    /// Only records whose path doesn't match any path of the specification.
+    Undocumented,
+}
+
+impl OpenApiFilter {
+    pub(crate) fn keeps(self, documented: bool) -> bool {
+        match self {
+            OpenApiFilter::Documented => documented,
+            OpenApiFilter::Undocumented => !documented,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Document {
+    #[serde(default)]
+    servers: Vec<Server>,
+    paths: BTreeMap<String, IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Server {
+    url: String,
+}
+
+/// The paths of an OpenAPI specification, prefixed by the paths of its servers.
+#[derive(Debug)]
+pub(crate) struct Spec(RegexSet);
+
+impl Spec {
+    /// Read an OpenAPI specification in JSON.
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open OpenAPI specification {}", path.display()))?;
+        let document: Document =
+            serde_json::from_reader(BufReader::new(file)).with_context(|| {
+                match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("yaml" | "yml") => format!(
+                        "Failed to parse OpenAPI specification {}, only JSON is supported: convert it first, e.g. \
+                         with `yq -o json`",
+                        path.display()
+                    ),
+                    _ => format!("Failed to parse OpenAPI specification {}", path.display()),
+                }
+            })?;
+
+        let mut base_paths = document
+            .servers
+            .iter()
+            .map(|server| base_path(&server.url))
+            .collect::<Vec<_>>();
+        if base_paths.is_empty() {
+            base_paths.push("");
+        }
+        let patterns = base_paths.iter().flat_map(|base_path| {
+            document
+                .paths
+                .keys()
+                .map(move |path| format!("^{}{}$", template(base_path), template(path)))
+        });
+        Ok(Self(RegexSet::new(patterns)?))
+    }
+
+    /// Whether the path matches one of the paths of the specification, with any value for their parameters.
+    pub(crate) fn documents(&self, path: &str) -> bool {
+        self.0.is_match(path)
+    }
+}
+
+/// Path of the URL of a server, without a trailing slash, e.g. `/v1` for `https://api.example.com/v1/`.
+fn base_path(url: &str) -> &str {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |start| &rest[start..]),
+        None => url,
+    };
+    path.trim_end_matches('/')
+}
+
+/// Regular expression matching a path template, where every parameter like `{id}` matches a non-empty segment.
+fn template(template: &str) -> String {
+    let mut pattern = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        pattern.push_str(&regex::escape(&rest[..start]));
+        pattern.push_str("[^/]+");
+        rest = &rest[start + end + 1..];
+    }
+    pattern.push_str(&regex::escape(rest));
+    pattern
+}
@@ -0,0 +1,82 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writing responses in the order their requests were scheduled, rather than the order they complete in.
+
+use std::{
+    collections::BTreeMap,
+    io::{
+        self,
+        Write,
+    },
+};
+
+use hifitime::Duration;
+
+/// Output lines of completed requests, held back until those of all requests scheduled before them are written.
+pub(crate) struct OrderedOutput {
+    /// Position in the schedule of every request, by its index.
+    ranks: Vec<usize>,
+    /// Completed requests by their position, with their line if they have one to write.
+    completed: BTreeMap<usize, Option<Vec<u8>>>,
+    /// Position of the next request to write.
+    next: usize,
+}
+
+impl OrderedOutput {
+    /// Output ordered by the offsets of the requests, those with the same offset in the order of their indices.
+    pub(crate) fn new(offsets: &[Duration]) -> Self {
+        let mut indices: Vec<usize> = (0..offsets.len()).collect();
+        indices.sort_by_key(|&index| offsets[index]);
+        let mut ranks = vec![0; offsets.len()];
+        for (rank, index) in indices.into_iter().enumerate() {
+            ranks[index] = rank;
+        }
+        Self {
+            ranks,
+            completed: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    /// Record that the request with `index` completed, with the line to write for it if any, and write all lines
+    /// that no longer wait on an earlier request.
+    pub(crate) fn complete(
+        &mut self,
+        index: usize,
+        line: Option<Vec<u8>>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        self.completed.insert(self.ranks[index], line);
+        while let Some(line) = self.completed.remove(&self.next) {
+            if let Some(line) = line {
+                out.write_all(&line)?;
+                writeln!(out)?;
+            }
+            self.next += 1;
+        }
+        Ok(())
+    }
+
+    /// Write the lines that are still held back, in order, because an earlier request never completed.
+    pub(crate) fn finish(self, out: &mut impl Write) -> io::Result<()> {
+        for line in self.completed.into_values().flatten() {
+            out.write_all(&line)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Where `run` writes the responses to: standard output, or a file that is optionally gzip-compressed.
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        BufWriter,
+        StdoutLock,
+        Write,
+    },
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use flate2::{
+    write::GzEncoder,
+    Compression,
+};
+
+pub(crate) enum Output {
+    Stdout(BufWriter<StdoutLock<'static>>),
+    File(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    /// Output to `path`, compressed with `gzip` or if it ends in `.gz`, or to standard output.
+    pub(crate) fn new(path: Option<&Path>, gzip: bool) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Output::Stdout(BufWriter::new(io::stdout().lock())));
+        };
+        let file = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        if gzip || path.extension().is_some_and(|extension| extension == "gz") {
+            Ok(Output::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Output::File(file))
+        }
+    }
+
+    /// Flush everything written so far, and for gzip write the footer that completes the file.
+    ///
+    /// Dropping the output without finishing it also writes the footer, but ignores any error doing so.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut writer) => writer.flush(),
+            Output::File(mut writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(writer) => writer.write(buf),
+            Output::File(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    /// Flushing gzip compresses what was written so far into a complete block, so that it can already be read.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(writer) => writer.flush(),
+            Output::File(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pausing and resuming a replay, with `SIGTSTP` and `SIGCONT`.
+//!
+//! While a replay is paused no further requests are sent, and the clock the offsets of the requests are measured
+//! against stands still. Once resumed, the remaining requests keep their timing relative to each other, they are
+//! merely shifted by the time the replay was paused.
+
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use tokio::sync::watch;
+
+#[derive(Debug, Default)]
+struct State {
+    paused_since: Option<Instant>,
+    /// Total time the replay was paused for, excluding an ongoing pause.
+    paused_for: Duration,
+}
+
+/// Clock the offsets of the requests are measured against, which stands still while the replay is paused.
+#[derive(Debug)]
+pub(crate) struct Clock {
+    started: Instant,
+    state: watch::Sender<State>,
+}
+
+impl Clock {
+    /// Create a clock starting now.
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            state: watch::Sender::new(State::default()),
+        }
+    }
+
+    /// Time elapsed since the clock started, excluding the time it was paused.
+    pub(crate) fn elapsed(&self) -> Duration {
+        let state = self.state.borrow();
+        let until = state.paused_since.unwrap_or_else(Instant::now);
+        until.duration_since(self.started) - state.paused_for
+    }
+
+    /// Pause the clock, returning whether it was running.
+    pub(crate) fn pause(&self) -> bool {
+        self.state.send_if_modified(|state| {
+            if state.paused_since.is_some() {
+                return false;
+            }
+            state.paused_since = Some(Instant::now());
+            true
+        })
+    }
+
+    /// Resume the clock, returning whether it was paused.
+    pub(crate) fn resume(&self) -> bool {
+        self.state
+            .send_if_modified(|state| match state.paused_since.take() {
+                Some(paused_since) => {
+                    state.paused_for += paused_since.elapsed();
+                    true
+                }
+                None => false,
+            })
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.state.borrow().paused_since.is_some()
+    }
+
+    /// Wait until the clock is running.
+    pub(crate) async fn running(&self) {
+        // The sender is owned by `self`, so it can't be dropped while waiting.
+        let _ = self
+            .state
+            .subscribe()
+            .wait_for(|state| state.paused_since.is_none())
+            .await;
+    }
+
+    /// Wait until the clock reaches the offset, taking into account pauses that happen while waiting.
+    pub(crate) async fn sleep_until(&self, offset: Duration) {
+        let mut state = self.state.subscribe();
+        loop {
+            let deadline = {
+                let state = state.borrow_and_update();
+                match state.paused_since {
+                    Some(_) => None,
+                    None => Some(self.started + offset + state.paused_for),
+                }
+            };
+            match deadline {
+                Some(deadline) => tokio::select! {
+                    () = tokio::time::sleep_until(deadline.into()) => return,
+                    _ = state.changed() => {}
+                },
+                None => {
+                    let _ = state.changed().await;
+                }
+            }
+        }
+    }
+}
+
+/// Signals to pause and resume the replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Signal {
+    Pause,
+    Resume,
+}
+
+/// Receives `SIGTSTP` and `SIGCONT`.
+///
+/// Listening for `SIGTSTP` replaces its default behavior, so `r7` keeps running when it receives it, e.g. when
+/// pressing Ctrl-Z, and has to be resumed with `SIGCONT`. On platforms other than Unix no signals are ever received.
+#[derive(Debug)]
+pub(crate) struct Signals {
+    #[cfg(unix)]
+    pause: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    resume: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    #[cfg(unix)]
+    pub(crate) fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{
+            signal,
+            SignalKind,
+        };
+
+        Ok(Self {
+            pause: signal(SignalKind::from_raw(libc::SIGTSTP))?,
+            resume: signal(SignalKind::from_raw(libc::SIGCONT))?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn new() -> std::io::Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Wait for the next signal.
+    #[cfg(unix)]
+    pub(crate) async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.pause.recv() => Signal::Pause,
+            _ = self.resume.recv() => Signal::Resume,
+        }
+    }
+
+    /// Wait for the next signal.
+    #[cfg(not(unix))]
+    pub(crate) async fn recv(&mut self) -> Signal {
+        std::future::pending().await
+    }
+}
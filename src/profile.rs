@@ -0,0 +1,268 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pacing a replay by a time series of request rates, with `--rps-profile`, rather than by the timestamps of the
+//! records.
+//!
+//! The profile is a CSV file with a `timestamp` column, as RFC3339, and an `rps` column, e.g. the per-minute rate
+//! of requests exported from a metrics system. Every row starts a bucket that lasts until the timestamp of the next
+//! row, the last bucket lasts as long as the one before it, and the replay starts at the first timestamp.
+
+use std::path::Path;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::{
+    Duration,
+    Epoch,
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    timestamp: String,
+    rps: f64,
+}
+
+/// Bucket of the profile, starting at an offset from the start of the replay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bucket {
+    start: Duration,
+    length: Duration,
+    rps: f64,
+}
+
+/// Rates of requests over time that a replay is paced by.
+#[derive(Debug)]
+pub(crate) struct RpsProfile {
+    buckets: Vec<Bucket>,
+}
+
+impl RpsProfile {
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = csv::Reader::from_path(path)
+            .with_context(|| format!("Failed to open RPS profile {}", path.display()))?;
+        Self::from_reader(reader, path)
+    }
+
+    /// Read the profile from a CSV reader, with `path` naming it in errors.
+    fn from_reader<R: std::io::Read>(mut reader: csv::Reader<R>, path: &Path) -> Result<Self> {
+        let mut rows = Vec::new();
+        for (index, row) in reader.deserialize::<Row>().enumerate() {
+            // The header is line 1.
+            let line = index + 2;
+            let row =
+                row.with_context(|| format!("Failed to parse RPS profile {}", path.display()))?;
+            let timestamp = row.timestamp.trim().parse::<Epoch>().map_err(|err| {
+                anyhow::anyhow!(
+                    "Invalid timestamp `{}` at line {} of RPS profile {}: {}",
+                    row.timestamp,
+                    line,
+                    path.display(),
+                    err
+                )
+            })?;
+            if !row.rps.is_finite() || row.rps < 0. {
+                anyhow::bail!(
+                    "Invalid rate {} at line {} of RPS profile {}, it must not be negative",
+                    row.rps,
+                    line,
+                    path.display()
+                );
+            }
+            if rows
+                .last()
+                .is_some_and(|(previous, _)| *previous >= timestamp)
+            {
+                anyhow::bail!(
+                    "Timestamp {} at line {} of RPS profile {} is not after the one before it",
+                    timestamp,
+                    line,
+                    path.display()
+                );
+            }
+            rows.push((timestamp, row.rps));
+        }
+        if rows.len() < 2 {
+            anyhow::bail!(
+                "RPS profile {} needs at least two rows, to know how long its buckets are",
+                path.display()
+            );
+        }
+
+        let start = rows[0].0;
+        let mut buckets: Vec<Bucket> = rows
+            .windows(2)
+            .map(|window| Bucket {
+                start: window[0].0 - start,
+                length: window[1].0 - window[0].0,
+                rps: window[0].1,
+            })
+            .collect();
+        let (last, rps) = rows[rows.len() - 1];
+        let length = buckets[buckets.len() - 1].length;
+        buckets.push(Bucket {
+            start: last - start,
+            length,
+            rps,
+        });
+
+        Ok(Self { buckets })
+    }
+
+    /// Offsets of the requests that reproduce the rates of the profile, spread evenly within every bucket.
+    ///
+    /// Fractions of a request are carried over to the next bucket, so that the total number of requests matches the
+    /// profile, however short its buckets are.
+    pub(crate) fn offsets(&self) -> Vec<Duration> {
+        let mut offsets = Vec::new();
+        let mut expected = 0f64;
+        for bucket in &self.buckets {
+            let before = expected.floor();
+            expected += bucket.rps * bucket.length.to_seconds();
+            let count = (expected.floor() - before) as usize;
+            offsets.extend(
+                (0..count)
+                    .map(|index| bucket.start + bucket.length * (index as f64 / count as f64)),
+            );
+        }
+        offsets
+    }
+
+    /// Duration of the profile, from the start of its first bucket to the end of its last one.
+    pub(crate) fn duration(&self) -> Duration {
+        self.buckets
+            .last()
+            .map(|bucket| bucket.start + bucket.length)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(content: &str) -> Result<RpsProfile> {
+        RpsProfile::from_reader(
+            csv::Reader::from_reader(content.as_bytes()),
+            Path::new("profile.csv"),
+        )
+    }
+
+    fn seconds(seconds: f64) -> Duration {
+        Duration::from_seconds(seconds)
+    }
+
+    #[test]
+    fn buckets_last_until_the_next_row() {
+        let profile = profile(
+            "timestamp,rps\n\
+             2024-06-01T12:00:00Z,2\n\
+             2024-06-01T12:00:10Z,0.5\n\
+             2024-06-01T12:00:30Z,1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            profile.buckets,
+            [
+                Bucket {
+                    start: seconds(0.),
+                    length: seconds(10.),
+                    rps: 2.,
+                },
+                Bucket {
+                    start: seconds(10.),
+                    length: seconds(20.),
+                    rps: 0.5,
+                },
+                // The last bucket lasts as long as the one before it.
+                Bucket {
+                    start: seconds(30.),
+                    length: seconds(20.),
+                    rps: 1.,
+                },
+            ]
+        );
+        assert_eq!(profile.duration(), seconds(50.));
+    }
+
+    #[test]
+    fn offsets_are_spread_within_buckets() {
+        let profile = profile(
+            "timestamp,rps\n\
+             2024-06-01T12:00:00Z,0.4\n\
+             2024-06-01T12:00:10Z,0\n\
+             2024-06-01T12:00:20Z,0.2\n",
+        )
+        .unwrap();
+        let offsets = profile.offsets();
+        assert_eq!(
+            offsets,
+            [
+                seconds(0.),
+                seconds(2.5),
+                seconds(5.),
+                seconds(7.5),
+                seconds(20.),
+                seconds(25.),
+            ]
+        );
+    }
+
+    #[test]
+    fn fractions_are_carried_over() {
+        // Every bucket of a second expects a third of a request, which adds up to one request every three buckets.
+        let mut content = "timestamp,rps\n".to_owned();
+        for second in 0..9 {
+            content.push_str(&format!("2024-06-01T12:00:0{second}Z,0.33334\n"));
+        }
+        let offsets = profile(&content).unwrap().offsets();
+        assert_eq!(offsets, [seconds(2.), seconds(5.), seconds(8.)]);
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        for (content, error) in [
+            (
+                "timestamp,rps\n2024-06-01T12:00:00Z,1\n",
+                "RPS profile profile.csv needs at least two rows, to know how long its buckets are",
+            ),
+            (
+                "timestamp,rps\n2024-06-01T12:00:00Z,1\n2024-06-01T12:00:00Z,1\n",
+                "Timestamp 2024-06-01T12:00:00 UTC at line 3 of RPS profile profile.csv is not after the one \
+                 before it",
+            ),
+            (
+                "timestamp,rps\n2024-06-01T12:00:00Z,-1\n2024-06-01T12:01:00Z,1\n",
+                "Invalid rate -1 at line 2 of RPS profile profile.csv, it must not be negative",
+            ),
+            (
+                "timestamp,rps\nyesterday,1\n2024-06-01T12:01:00Z,1\n",
+                "Invalid timestamp `yesterday` at line 2 of RPS profile profile.csv",
+            ),
+            (
+                "timestamp,rate\n2024-06-01T12:00:00Z,1\n2024-06-01T12:01:00Z,1\n",
+                "Failed to parse RPS profile profile.csv",
+            ),
+        ] {
+            let message = profile(content).unwrap_err().to_string();
+            assert!(message.starts_with(error), "{message}");
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sending requests with the HTTP version of the original request, with `--preserve-protocol`.
+
+use anyhow::Result;
+use reqwest::{
+    Url,
+    Version,
+};
+
+/// What to do with requests whose original HTTP version can't be used, with `--on-protocol-mismatch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OnMismatch {
+    /// Send the request with the version the connection supports instead.
+    Downgrade,
+    /// Don't send the request.
+    Skip,
+}
+
+/// The HTTP version to send a request to `url` with, for a `protocol` field like `HTTP/1.0`, or `None` if it can't
+/// be used.
+///
+/// Only HTTP/1.0, HTTP/1.1 and HTTP/2 can be sent. HTTP/2 needs TLS to be negotiated, unless the client speaks it
+/// right away with `http2_only`, in which case only HTTP/2 can be sent.
+pub(crate) fn version(protocol: &str, url: &Url, http2_only: bool) -> Result<Option<Version>> {
+    let version = match &*protocol.trim().to_ascii_uppercase() {
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/1.1" => Version::HTTP_11,
+        "HTTP/2" | "HTTP/2.0" => Version::HTTP_2,
+        "HTTP/0.9" | "HTTP/3" | "HTTP/3.0" => return Ok(None),
+        _ => anyhow::bail!("Unknown protocol `{}`", protocol),
+    };
+    let usable = if version == Version::HTTP_2 {
+        http2_only || matches!(url.scheme(), "https" | "wss")
+    } else {
+        !http2_only
+    };
+    Ok(usable.then_some(version))
+}
+
+/// Whether the request failed because it requires HTTP/2, but the connection is HTTP/1, because the target didn't
+/// negotiate HTTP/2 during the TLS handshake.
+pub(crate) fn is_unsupported_version(err: &reqwest::Error) -> bool {
+    // The client only exposes the kind of this error in its message.
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if err.to_string().contains("UserUnsupportedVersion") {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
@@ -0,0 +1,483 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reverse proxy for `record`, which forwards requests to an upstream and records every one of them in the format
+//! `run` replays.
+//!
+//! Clients have to speak HTTP/1.x. Bodies are buffered rather than streamed, and upgrades like WebSockets aren't
+//! supported.
+
+use std::{
+    net::SocketAddr,
+    time::Instant,
+};
+
+use anyhow::Result;
+use hifitime::{
+    Duration,
+    Epoch,
+};
+use reqwest::{
+    header::{
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+        CONNECTION,
+        CONTENT_LENGTH,
+        EXPECT,
+        HOST,
+        TRANSFER_ENCODING,
+    },
+    Client,
+    Method,
+    StatusCode,
+    Url,
+};
+use serde_json::Value;
+use tokio::{
+    io::{
+        AsyncBufReadExt,
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::mpsc::UnboundedSender,
+};
+
+/// Columns of the records, in the order they are written.
+pub(crate) const COLUMNS: [&str; 9] = [
+    "@timestamp",
+    "path",
+    "params",
+    "target_processing_time",
+    "status",
+    "sent_bytes",
+    "received_bytes",
+    "domain_name",
+    "method",
+];
+
+/// Upper limit for the request line and the headers of a request, to not buffer arbitrary amounts of garbage.
+const MAX_HEAD_LENGTH: usize = 64 * 1024;
+
+/// Headers that only apply to a single connection, and aren't forwarded, as listed in RFC 9110, section 7.6.1.
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// A request that was forwarded, with the response of the upstream.
+#[derive(Debug)]
+pub(crate) struct Exchange {
+    timestamp: Epoch,
+    method: Method,
+    path: String,
+    /// Query of the request, including the leading `?`, or empty.
+    params: String,
+    /// Time until the upstream responded, `None` if it didn't.
+    latency: Option<Duration>,
+    status: StatusCode,
+    request_bytes: usize,
+    response_bytes: usize,
+    /// Host the client sent the request to, without the port.
+    host: Option<String>,
+}
+
+impl Exchange {
+    /// Fields of the record, in the order of `COLUMNS`.
+    pub(crate) fn fields(&self) -> Vec<(&'static str, Value)> {
+        // Like AWS Application Load Balancers, requests the upstream didn't respond to are logged with a latency of -1.
+        let target_processing_time = self.latency.map_or(-1., |latency| latency.to_seconds());
+        COLUMNS
+            .into_iter()
+            .zip([
+                crate::de::epoch_as_kibana_timestamp(self.timestamp).into(),
+                self.path.clone().into(),
+                self.params.clone().into(),
+                target_processing_time.into(),
+                self.status.as_u16().into(),
+                self.response_bytes.into(),
+                self.request_bytes.into(),
+                self.host.clone().into(),
+                self.method.as_str().into(),
+            ])
+            .collect()
+    }
+}
+
+/// Request as received from a client.
+struct IncomingRequest {
+    method: Method,
+    /// Path and query of the request.
+    target: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    /// Whether the client keeps the connection open for further requests.
+    keep_alive: bool,
+    /// Size of the request on the wire.
+    bytes: usize,
+}
+
+/// Accept connections until the task is aborted, sending every exchange to `exchanges`.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    upstream: Url,
+    client: Client,
+    exchanges: UnboundedSender<Exchange>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                tracing::warn!("Failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+        let upstream = upstream.clone();
+        let client = client.clone();
+        let exchanges = exchanges.clone();
+        tokio::spawn(async move {
+            if let Err(err) = connection(stream, peer, &upstream, &client, &exchanges).await {
+                tracing::debug!("Connection of {} failed: {}", peer, err);
+            }
+        });
+    }
+}
+
+async fn connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    upstream: &Url,
+    client: &Client,
+    exchanges: &UnboundedSender<Exchange>,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let request = match read_request(&mut reader, &mut writer).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                let _ = writer
+                    .write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .await;
+                return Err(err);
+            }
+        };
+        let timestamp = Epoch::now()?;
+        let (status, headers, body, latency) = match forward(upstream, client, peer, &request).await
+        {
+            Ok((status, headers, body, latency)) => (status, headers, body, Some(latency)),
+            Err(err) => {
+                tracing::warn!(
+                    "Forwarding {} {} failed: {}",
+                    request.method,
+                    request.target,
+                    err
+                );
+                let status = StatusCode::BAD_GATEWAY;
+                (status, HeaderMap::new(), Vec::new(), None)
+            }
+        };
+        let response_bytes = write_response(
+            &mut writer,
+            &request.method,
+            status,
+            &headers,
+            &body,
+            request.keep_alive,
+        )
+        .await?;
+
+        let (path, params) = match request.target.split_once('?') {
+            Some((path, query)) => (path.to_owned(), format!("?{}", query)),
+            None => (request.target.clone(), String::new()),
+        };
+        let host = request
+            .headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| Url::parse(&format!("http://{}", host)).ok())
+            .and_then(|url| url.host_str().map(ToOwned::to_owned));
+        // The receiver only goes away once recording stops.
+        let _ = exchanges.send(Exchange {
+            timestamp,
+            method: request.method,
+            path,
+            params,
+            latency,
+            status,
+            request_bytes: request.bytes,
+            response_bytes,
+            host,
+        });
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Read the next request of the connection, `None` once the client closed it.
+async fn read_request<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    writer: &mut (impl AsyncWriteExt + Unpin),
+) -> Result<Option<IncomingRequest>> {
+    let mut bytes = 0;
+    let mut line = Vec::new();
+    if read_line(reader, &mut line, &mut bytes).await? == 0 {
+        return Ok(None);
+    }
+    let request_line = String::from_utf8_lossy(&line);
+    let mut parts = request_line.split_ascii_whitespace();
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("invalid request line `{}`", request_line.trim_end());
+    };
+    let method = Method::from_bytes(method.as_bytes())?;
+    let target = match target.strip_prefix('/') {
+        Some(_) => target.to_owned(),
+        // Requests in absolute form, as sent to forward proxies, are recorded by their path and query.
+        None => {
+            let url = Url::parse(target)?;
+            match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_owned(),
+            }
+        }
+    };
+    let http_1_0 = match version {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => anyhow::bail!("unsupported version `{}`", version),
+    };
+
+    let mut headers = HeaderMap::new();
+    loop {
+        line.clear();
+        if read_line(reader, &mut line, &mut bytes).await? == 0 {
+            anyhow::bail!("connection closed in the headers");
+        }
+        let header = line.strip_suffix(b"\n").unwrap_or(&line);
+        let header = header.strip_suffix(b"\r").unwrap_or(header);
+        if header.is_empty() {
+            break;
+        }
+        let Some(colon) = header.iter().position(|byte| *byte == b':') else {
+            anyhow::bail!("invalid header `{}`", String::from_utf8_lossy(header));
+        };
+        headers.append(
+            HeaderName::from_bytes(&header[..colon])?,
+            HeaderValue::from_bytes(header[colon + 1..].trim_ascii())?,
+        );
+    }
+    let connection = headers
+        .get(CONNECTION)
+        .and_then(|connection| connection.to_str().ok())
+        .map(str::to_ascii_lowercase);
+    let keep_alive = match connection.as_deref() {
+        Some(connection) if connection.contains("close") => false,
+        Some(connection) if connection.contains("keep-alive") => true,
+        _ => !http_1_0,
+    };
+
+    if headers
+        .get(EXPECT)
+        .is_some_and(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+    {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+    let chunked = headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    let body = if chunked {
+        read_chunked(reader, &mut bytes).await?
+    } else {
+        let length = match headers.get(CONTENT_LENGTH) {
+            Some(length) => length.to_str()?.trim().parse::<usize>()?,
+            None => 0,
+        };
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await?;
+        bytes += length;
+        body
+    };
+
+    Ok(Some(IncomingRequest {
+        method,
+        target,
+        headers,
+        body,
+        keep_alive,
+        bytes,
+    }))
+}
+
+/// Read a line of the request head, including the newline, failing once the head gets too long.
+async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    line: &mut Vec<u8>,
+    bytes: &mut usize,
+) -> Result<usize> {
+    let read = reader
+        .take((MAX_HEAD_LENGTH.saturating_sub(*bytes) + 1) as u64)
+        .read_until(b'\n', line)
+        .await?;
+    *bytes += read;
+    if *bytes > MAX_HEAD_LENGTH {
+        anyhow::bail!("request head is longer than {} bytes", MAX_HEAD_LENGTH);
+    }
+    Ok(read)
+}
+
+/// Read a body with `Transfer-Encoding: chunked`, as described in RFC 9112, section 7.1, dropping any trailers.
+async fn read_chunked<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    bytes: &mut usize,
+) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        *bytes += reader.read_until(b'\n', &mut line).await?;
+        let size = String::from_utf8_lossy(&line);
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| anyhow::anyhow!("invalid chunk size `{}`", size))?;
+        if size == 0 {
+            break;
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        line.clear();
+        *bytes += size + reader.read_until(b'\n', &mut line).await?;
+    }
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line).await?;
+        *bytes += read;
+        if read == 0 || line.trim_ascii().is_empty() {
+            return Ok(body);
+        }
+    }
+}
+
+/// Forward the request to the upstream, returning its response and the time until it started to respond.
+async fn forward(
+    upstream: &Url,
+    client: &Client,
+    peer: SocketAddr,
+    request: &IncomingRequest,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>, Duration)> {
+    let url = upstream.join(&request.target)?;
+    let mut headers = request.headers.clone();
+    for header in HOP_BY_HOP_HEADERS.iter().copied().chain([
+        "host",
+        "content-length",
+        "transfer-encoding",
+        "expect",
+    ]) {
+        headers.remove(header);
+    }
+    if let Some(host) = request.headers.get(HOST) {
+        headers.insert("x-forwarded-host", host.clone());
+    }
+    headers.append(
+        "x-forwarded-for",
+        HeaderValue::from_str(&peer.ip().to_string())?,
+    );
+
+    let start = Instant::now();
+    let response = client
+        .request(request.method.clone(), url)
+        .headers(headers)
+        .body(request.body.clone())
+        .send()
+        .await?;
+    let latency = Duration::from(start.elapsed());
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes().await?.to_vec();
+    Ok((status, headers, body, latency))
+}
+
+/// Write the response to the client, returning its size on the wire.
+async fn write_response(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    method: &Method,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    keep_alive: bool,
+) -> Result<usize> {
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+    .into_bytes();
+    for (name, value) in headers {
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str())
+            || name == CONTENT_LENGTH
+            || name == TRANSFER_ENCODING
+        {
+            continue;
+        }
+        response.extend_from_slice(name.as_str().as_bytes());
+        response.extend_from_slice(b": ");
+        response.extend_from_slice(value.as_bytes());
+        response.extend_from_slice(b"\r\n");
+    }
+    // Responses to HEAD-requests keep the length of the body they would have had.
+    let has_body = *method != Method::HEAD
+        && !status.is_informational()
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::NOT_MODIFIED;
+    let content_length = if has_body {
+        Some(body.len().to_string())
+    } else {
+        headers
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .map(ToOwned::to_owned)
+    };
+    if let Some(content_length) = content_length {
+        response.extend_from_slice(format!("content-length: {}\r\n", content_length).as_bytes());
+    }
+    if !keep_alive {
+        response.extend_from_slice(b"connection: close\r\n");
+    }
+    response.extend_from_slice(b"\r\n");
+    if has_body {
+        response.extend_from_slice(body);
+    }
+    writer.write_all(&response).await?;
+    writer.flush().await?;
+    Ok(response.len())
+}
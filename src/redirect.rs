@@ -0,0 +1,65 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording the redirects that are followed for a request, with `--max-redirects-recorded`.
+//!
+//! The redirect policy is shared by all requests of the client, so the hops are recorded into a task-local that is
+//! set for the duration of every request that records them.
+
+use std::{
+    future::Future,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use reqwest::redirect::Policy;
+
+/// Maximum number of redirects that are followed, the same as the default policy of reqwest.
+const MAX_REDIRECTS: usize = 10;
+
+/// A redirect that was followed: the status of the redirect response, and the URL it redirected to.
+pub(crate) type Hop = (u16, String);
+
+tokio::task_local! {
+    static HOPS: Arc<Mutex<Vec<Hop>>>;
+}
+
+/// Redirect policy that follows redirects like the default policy, recording up to `max_recorded` of them.
+pub(crate) fn policy(max_recorded: usize) -> Policy {
+    Policy::custom(move |attempt| {
+        let _ = HOPS.try_with(|hops| {
+            let mut hops = hops.lock().expect("lock shouldn't be poisoned");
+            if hops.len() < max_recorded {
+                hops.push((attempt.status().as_u16(), attempt.url().to_string()));
+            }
+        });
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Run the request, returning the redirects that were recorded by `policy` alongside its result.
+pub(crate) async fn recording<F: Future>(request: F) -> (F::Output, Vec<Hop>) {
+    let hops = Arc::new(Mutex::new(Vec::new()));
+    let result = HOPS.scope(hops.clone(), request).await;
+    let hops = std::mem::take(&mut *hops.lock().expect("lock shouldn't be poisoned"));
+    (result, hops)
+}
@@ -0,0 +1,79 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::File,
+    io::{
+        BufWriter,
+        Write,
+    },
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+
+/// A record as it was read from the input file, so that it can be written back unchanged.
+#[derive(Debug, Clone)]
+pub(crate) enum RawRecord {
+    Csv(csv::StringRecord),
+    /// The whole line, including the `_source` wrapper.
+    Json(serde_json::Value),
+    /// A line of a combined log.
+    Line(String),
+}
+
+/// Write the records to a new file in the format of the input file, so that it can be used as input of another run.
+pub(crate) fn write<'a>(
+    input_file: &Path,
+    path: &Path,
+    records: impl IntoIterator<Item = &'a RawRecord>,
+) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create records file {}", path.display()))?;
+    let mut csv_writer = None;
+    let mut json_writer = BufWriter::new(&file);
+    for record in records {
+        match record {
+            RawRecord::Csv(record) => {
+                let csv_writer = match &mut csv_writer {
+                    Some(csv_writer) => csv_writer,
+                    None => {
+                        // The original headers, as the ones of the parsed records might be renamed by `InputFields`.
+                        let headers = csv::Reader::from_path(input_file)?.headers()?.clone();
+                        let mut writer = csv::Writer::from_writer(&file);
+                        writer.write_record(&headers)?;
+                        csv_writer.insert(writer)
+                    }
+                };
+                csv_writer.write_record(record)?;
+            }
+            RawRecord::Json(line) => {
+                serde_json::to_writer(&mut json_writer, line)?;
+                writeln!(json_writer)?;
+            }
+            RawRecord::Line(line) => writeln!(json_writer, "{}", line)?,
+        }
+    }
+    if let Some(mut csv_writer) = csv_writer {
+        csv_writer.flush()?;
+    }
+    json_writer.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,825 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::BTreeMap,
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+    sync::atomic::{
+        AtomicBool,
+        AtomicUsize,
+        Ordering,
+    },
+    time::Instant,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::Duration;
+use http_body_util::BodyExt;
+use reqwest::{
+    header::CONTENT_LENGTH,
+    Client,
+    Request,
+    Url,
+    Version,
+};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{
+    adaptive::AdaptiveConcurrency,
+    baseline::Baseline,
+    body::BufferedBytes,
+    body_diff::{
+        BodyComparer,
+        BodyDiff,
+    },
+    events::{
+        Event,
+        Events,
+    },
+    grpc,
+    headers::ExpectedHeader,
+    pause::Clock,
+    protocol,
+    redirect,
+    retry::{
+        self,
+        RetryPolicy,
+    },
+    sigv4::Signer,
+    summary::Outcome,
+    throttle::RateLimiter,
+    token::TokenSource,
+    RequestWithOffset,
+};
+
+/// State shared by all requests of a replay.
+#[derive(Debug)]
+pub(crate) struct Replay {
+    pub(crate) client: Client,
+    /// Client for gRPC calls, with `--grpc`.
+    pub(crate) grpc_client: Option<Client>,
+    pub(crate) capture_body: bool,
+    /// Whether responses with an error status are returned as responses rather than as errors, with
+    /// `--only-errors`.
+    pub(crate) keep_error_responses: bool,
+    /// Whether to record the redirects that are followed, see `crate::redirect`.
+    pub(crate) record_redirects: bool,
+    pub(crate) buffered_bytes: BufferedBytes,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) events: Events,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    /// Rate limiters for individual hosts, by host name. Requests to other hosts are only limited by `rate_limiter`.
+    pub(crate) host_rate_limiters: BTreeMap<String, RateLimiter>,
+    /// What to do with requests that require HTTP/2 from a target that doesn't negotiate it, with
+    /// `--preserve-protocol`.
+    pub(crate) on_protocol_mismatch: protocol::OnMismatch,
+    /// Number of requests that required HTTP/2 from a target that didn't negotiate it.
+    pub(crate) protocol_mismatches: AtomicUsize,
+    /// Limit on the requests in flight that adapts to their latency, with `--adaptive-concurrency`.
+    pub(crate) adaptive_concurrency: Option<AdaptiveConcurrency>,
+    /// Set once the replay should stop sending requests.
+    pub(crate) stopping: watch::Sender<bool>,
+    /// Number of requests that have been sent, but haven't completed yet.
+    pub(crate) in_flight: AtomicUsize,
+    /// Number of attempts of requests that have been sent, including retries.
+    pub(crate) attempts: AtomicUsize,
+    /// Maximum number of attempts, including retries and shadow requests, with `--request-budget`.
+    pub(crate) request_budget: Option<usize>,
+    /// Set once a request wasn't sent because the request budget was used up.
+    pub(crate) budget_exhausted: AtomicBool,
+    /// Clock the offsets are measured against, paused while the replay is paused.
+    pub(crate) clock: Clock,
+    /// Scheme and host every request is also sent to, with `--shadow-target`.
+    pub(crate) shadow_target: Option<Url>,
+    /// Latencies of a previous run that responses are compared to instead of the original latencies, with
+    /// `--baseline`.
+    pub(crate) baseline: Option<Baseline>,
+    /// Compares the bodies to those of the shadow target or the baseline, with `--compare-bodies`.
+    pub(crate) body_comparer: Option<BodyComparer>,
+    /// Latency below which a response isn't compared to the original one, if the original latency is below it too,
+    /// with `--min-latency-for-comparison`.
+    pub(crate) min_latency_for_comparison: Option<Duration>,
+    /// Signs every attempt of a request, with `--sigv4`.
+    pub(crate) signer: Option<Signer>,
+    /// Authorizes every attempt of a request with a bearer token, with `--token-command`.
+    pub(crate) token_source: Option<TokenSource>,
+    /// Headers every response is expected to have, with `--expect-header`.
+    pub(crate) expected_headers: Vec<ExpectedHeader>,
+    /// Number of bytes of the bodies of failed responses to log, with `--echo-error-body`.
+    pub(crate) echo_error_body: Option<usize>,
+}
+
+/// Counts a request as in flight for as long as it is held.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(in_flight: &'a AtomicUsize) -> Self {
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Replay {
+    /// Stop sending requests. Requests that have already been sent are completed, but not retried.
+    pub(crate) fn stop(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// Write how many requests were throttled or didn't get their protocol version, and how the concurrency
+    /// adapted, for the summary of the replay.
+    pub(crate) fn write_throttling(&self, mut writer: impl Write) -> io::Result<()> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            writeln!(
+                writer,
+                "Throttled by --max-rate: {} requests",
+                rate_limiter.throttled()
+            )?;
+        }
+        for (host, rate_limiter) in &self.host_rate_limiters {
+            writeln!(
+                writer,
+                "Throttled by --per-host-rate for {}: {} requests",
+                host,
+                rate_limiter.throttled()
+            )?;
+        }
+        let protocol_mismatches = self.protocol_mismatches.load(Ordering::Relaxed);
+        if protocol_mismatches > 0 {
+            writeln!(
+                writer,
+                "HTTP/2 not negotiated by the target: {} requests {}",
+                protocol_mismatches,
+                match self.on_protocol_mismatch {
+                    protocol::OnMismatch::Downgrade => "sent with HTTP/1.1",
+                    protocol::OnMismatch::Skip => "skipped",
+                }
+            )?;
+        }
+        if let Some(adaptive_concurrency) = &self.adaptive_concurrency {
+            adaptive_concurrency.write(&mut writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Count an attempt in `attempts`, returning `false` without counting it if that would exceed the request budget.
+    fn count_attempt(&self) -> bool {
+        match self.request_budget {
+            Some(budget) => self
+                .attempts
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |attempts| {
+                    (attempts < budget).then_some(attempts + 1)
+                })
+                .is_ok(),
+            None => {
+                self.attempts.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    async fn stopped(&self) {
+        // The sender is owned by `self`, so it can't be dropped while waiting.
+        let _ = self
+            .stopping
+            .subscribe()
+            .wait_for(|stopping| *stopping)
+            .await;
+    }
+
+    /// Send the request once its offset has passed on the clock of the replay, returning `None` if the replay was
+    /// stopped before it was sent.
+    pub(crate) async fn get(
+        &self,
+        RequestWithOffset {
+            request,
+            offset,
+            record,
+            correlation_id,
+            body_file,
+            tags: _,
+            grpc,
+            after,
+            done: _done,
+            weight: _,
+        }: RequestWithOffset,
+    ) -> Result<Option<ResponseDetails>> {
+        let permit = tokio::select! {
+            permit = async {
+                if let Some(after) = after {
+                    // The sender is dropped rather than used, once the previous request completed.
+                    let _ = after.await;
+                }
+                self.clock.sleep_until(offset.into()).await;
+                self.buffered_bytes.wait_for_capacity().await;
+                match &self.adaptive_concurrency {
+                    Some(adaptive_concurrency) => Some(adaptive_concurrency.acquire().await),
+                    None => None,
+                }
+            } => permit,
+            () = self.stopped() => return Ok(None),
+        };
+        let _in_flight = InFlight::new(&self.in_flight);
+        // Requests that are missing from the baseline aren't compared, their original time isn't a number.
+        let original_time = match &self.baseline {
+            Some(baseline) => baseline.latency(request.url()).unwrap_or(f64::NAN),
+            None => record.required_time,
+        };
+        let baseline_body = self
+            .baseline
+            .as_ref()
+            .filter(|_| self.body_comparer.is_some())
+            .and_then(|baseline| baseline.body(request.url()))
+            .map(ToOwned::to_owned);
+        let mut response = self
+            .send_with_shadow(
+                request,
+                body_file.as_deref(),
+                grpc,
+                offset,
+                original_time,
+                correlation_id,
+            )
+            .await;
+        if let (Some(body_comparer), Some(expected), Ok(Some(details))) =
+            (&self.body_comparer, &baseline_body, &mut response)
+        {
+            if let Some(body) = &details.body {
+                details.baseline_body_diff = Some(body_comparer.compare(expected, body));
+            }
+        }
+        // Only successful responses are fed back, fast failures would otherwise drive the limit up.
+        if let (Some(permit), Ok(Some(details))) = (&permit, &response) {
+            if details.status.is_success() {
+                permit.record(details.required_time);
+            }
+        }
+        response
+    }
+
+    /// Send the request, and to the shadow target as well with `--shadow-target`.
+    async fn send_with_shadow(
+        &self,
+        request: Request,
+        body_file: Option<&Path>,
+        grpc: bool,
+        offset: Duration,
+        original_time: f64,
+        correlation_id: Option<String>,
+    ) -> Result<Option<ResponseDetails>> {
+        let Some(shadow_target) = &self.shadow_target else {
+            return self
+                .send(
+                    request,
+                    body_file,
+                    grpc,
+                    offset,
+                    original_time,
+                    correlation_id,
+                )
+                .await;
+        };
+
+        let mut shadow_request = request
+            .try_clone()
+            .expect("requests without a streaming body can always be cloned");
+        *shadow_request.url_mut() = shadow_url(request.url(), shadow_target)?;
+        let (primary, shadow) = tokio::join!(
+            self.send(
+                request,
+                body_file,
+                grpc,
+                offset,
+                original_time,
+                correlation_id,
+            ),
+            self.send(shadow_request, body_file, grpc, offset, original_time, None,),
+        );
+        let mut primary = match primary {
+            Ok(Some(primary)) => primary,
+            primary => {
+                // The shadow response is only reported alongside a primary one, so its captured body is released
+                // here rather than once it has been written.
+                if let Ok(Some(shadow)) = &shadow {
+                    self.buffered_bytes.release(shadow.body_size.unwrap_or(0));
+                }
+                return primary;
+            }
+        };
+        primary.shadow = Some(Box::new(Shadow::compare(
+            &primary,
+            shadow,
+            self.body_comparer.as_ref(),
+        )));
+        Ok(Some(primary))
+    }
+
+    /// Send the request, retrying it according to the retry policy, returning `None` if the replay was stopped before
+    /// it was sent.
+    async fn send(
+        &self,
+        mut request: Request,
+        body_file: Option<&Path>,
+        grpc: bool,
+        offset: Duration,
+        original_time: f64,
+        correlation_id: Option<String>,
+    ) -> Result<Option<ResponseDetails>> {
+        let url = request.url().as_str().to_owned();
+        let host_rate_limiter = request
+            .url()
+            .host_str()
+            .and_then(|host| self.host_rate_limiters.get(host));
+        let client = self
+            .grpc_client
+            .as_ref()
+            .filter(|_| grpc)
+            .unwrap_or(&self.client);
+        let first_attempt = Instant::now();
+        let mut retries = 0;
+        // Whether the request is sent again with HTTP/1.1, which is still its first attempt.
+        let mut downgraded = false;
+        let (response, required_time, redirects) = loop {
+            let first_attempt_of_request = retries == 0 && !downgraded;
+            let mut attempt = request
+                .try_clone()
+                .expect("requests without a streaming body can always be cloned");
+            // Bodies from files are streamed, so they are opened anew for every attempt.
+            if let Some(body_file) = body_file {
+                let (body, size) = crate::body::file_body(body_file).await?;
+                attempt.headers_mut().insert(CONTENT_LENGTH, size.into());
+                *attempt.body_mut() = Some(body);
+            }
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire().await;
+            }
+            if let Some(host_rate_limiter) = host_rate_limiter {
+                host_rate_limiter.acquire().await;
+            }
+            // Requests, or retries, that became due just before the replay was paused wait for it to be resumed.
+            tokio::select! {
+                () = self.clock.running() => {}
+                () = self.stopped() => {}
+            }
+            if first_attempt_of_request && self.is_stopping() {
+                return Ok(None);
+            }
+            if let Some(signer) = &self.signer {
+                signer.sign(&mut attempt, body_file).await?;
+            }
+            let token_generation = match &self.token_source {
+                Some(token_source) => Some(token_source.authorize(&mut attempt).await),
+                None => None,
+            };
+            // Retries have been counted when they were decided on, and a downgraded request with its first attempt.
+            if first_attempt_of_request && !self.count_attempt() {
+                if !self.budget_exhausted.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(
+                        "Used up the request budget of {}, no further requests will be sent",
+                        self.request_budget.unwrap_or_default()
+                    );
+                }
+                self.stop();
+                return Ok(None);
+            }
+            self.events.emit(Event::RequestSent {
+                url: &url,
+                retry: retries,
+            });
+            let start = Instant::now();
+            let (result, hops) = if self.record_redirects {
+                let (result, hops) = redirect::recording(client.execute(attempt)).await;
+                (result, Some(hops))
+            } else {
+                (client.execute(attempt).await, None)
+            };
+            let (err, response) = match result {
+                Ok(response) => match response.error_for_status_ref() {
+                    Ok(_) => break (response, Duration::from(start.elapsed()), hops),
+                    Err(err) => (err, Some(response)),
+                },
+                Err(err)
+                    if request.version() == Version::HTTP_2
+                        && protocol::is_unsupported_version(&err) =>
+                {
+                    tracing::debug!("The target of {} doesn't negotiate HTTP/2", url);
+                    self.protocol_mismatches.fetch_add(1, Ordering::Relaxed);
+                    match self.on_protocol_mismatch {
+                        protocol::OnMismatch::Downgrade => {
+                            *request.version_mut() = Version::HTTP_11;
+                            downgraded = true;
+                            continue;
+                        }
+                        protocol::OnMismatch::Skip => return Ok(None),
+                    }
+                }
+                Err(err) => (err, None),
+            };
+            if let (Some(token_source), Some(token_generation)) =
+                (&self.token_source, token_generation)
+            {
+                if err.status() == Some(reqwest::StatusCode::UNAUTHORIZED) {
+                    token_source.rejected(token_generation).await;
+                }
+            }
+            let delay = (retry::is_retryable(&err)
+                && self.retry_policy.allows(request.method())
+                && !self.is_stopping())
+            .then(|| {
+                self.retry_policy
+                    .delay(retries + 1, Duration::from(first_attempt.elapsed()))
+            })
+            .flatten()
+            .filter(|_| self.count_attempt());
+            match (delay, response) {
+                (Some(delay), _) => {
+                    retries += 1;
+                    tracing::debug!("Retrying {} in {}: {}", url, delay, err);
+                    tokio::time::sleep(delay.into()).await;
+                }
+                // Kept for its status, and body, to be reported like any other response.
+                (None, Some(response)) if self.keep_error_responses => {
+                    break (response, Duration::from(start.elapsed()), hops)
+                }
+                (None, Some(response)) => {
+                    self.echo_error_body(&url, response).await;
+                    return Err(err.into());
+                }
+                (None, None) => return Err(err.into()),
+            }
+        };
+        tracing::debug!(
+            "Request={}..., waited_for={}, status={}, required_time={}",
+            url.get(..64).unwrap_or(&url),
+            offset,
+            response.status(),
+            required_time
+        );
+        let status = response.status();
+        let header_violations = self
+            .expected_headers
+            .iter()
+            .filter(|expected| !expected.matches(response.headers()))
+            .map(|expected| expected.name.to_string())
+            .collect();
+        let mut grpc_status = None;
+        let (body, body_size, compressed_body_size) = if grpc {
+            // The outcome of the call is only known from the trailers, after the whole body has been received.
+            let headers = response.headers().clone();
+            let collected = reqwest::Body::from(response).collect().await?;
+            let status = grpc::Status::from_response(&headers, collected.trailers())
+                .with_context(|| format!("gRPC call to {}", url))?;
+            if !status.is_ok() {
+                anyhow::bail!("gRPC call to {} failed with status {}", url, status);
+            }
+            grpc_status = Some(status);
+            if self.capture_body {
+                let raw = collected.to_bytes();
+                let message = grpc::unframe(&raw)?;
+                self.buffered_bytes.add(message.len());
+                (Some(grpc::encode(message)), Some(message.len()), None)
+            } else {
+                (None, None, None)
+            }
+        } else if self.capture_body {
+            let content_encoding = response
+                .headers()
+                .get(reqwest::header::CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned);
+            let content_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned);
+            let raw = response.bytes().await?;
+            let decoded = crate::body::decode(content_encoding.as_deref(), &raw)?;
+            self.buffered_bytes.add(decoded.len());
+            if let Some(limit) = self
+                .echo_error_body
+                .filter(|_| !crate::summary::is_success(status))
+            {
+                echo_body(&url, status, content_type.as_deref(), &decoded, limit);
+            }
+            (
+                Some(String::from_utf8_lossy(&decoded).into_owned()),
+                Some(decoded.len()),
+                Some(raw.len()),
+            )
+        } else {
+            if !crate::summary::is_success(status) {
+                self.echo_error_body(&url, response).await;
+            }
+            (None, None, None)
+        };
+        // Not a number for requests that are too fast on both sides to be compared meaningfully.
+        let change_percentage = match self.min_latency_for_comparison {
+            Some(min_latency)
+                if required_time < min_latency && original_time < min_latency.to_seconds() =>
+            {
+                f64::NAN
+            }
+            _ => ((required_time.to_seconds() - original_time) / original_time) * 100.,
+        };
+        Ok(Some(ResponseDetails {
+            outcome: Outcome::of_status(status),
+            url,
+            status,
+            required_time,
+            original_time,
+            change_percentage,
+            body,
+            body_size,
+            compressed_body_size,
+            retries,
+            correlation_id,
+            redirects,
+            grpc_status,
+            original_status: None,
+            status_matches: None,
+            regressed: false,
+            header_violations,
+            tags: BTreeMap::new(),
+            shadow: None,
+            baseline_body_diff: None,
+        }))
+    }
+
+    /// Log the start of the body of a response that failed, with `--echo-error-body`.
+    ///
+    /// Only as much of the body is read as is logged. Bodies that are still encoded, because `--capture-body` asked
+    /// the target for a particular encoding, aren't shown.
+    async fn echo_error_body(&self, url: &str, mut response: reqwest::Response) {
+        let Some(limit) = self.echo_error_body else {
+            return;
+        };
+        let status = response.status();
+        let headers = response.headers();
+        if let Some(content_encoding) = headers
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.eq_ignore_ascii_case("identity"))
+        {
+            tracing::warn!(
+                "{} responded with {}, its body isn't shown because it is encoded with {}",
+                url,
+                status,
+                content_encoding
+            );
+            return;
+        }
+        let content_type = headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+        let mut body = Vec::new();
+        if crate::body::is_text(content_type.as_deref()) {
+            // One byte more than is logged, to tell whether the body is longer.
+            while body.len() <= limit {
+                match response.chunk().await {
+                    Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                    Ok(None) => break,
+                    Err(err) => {
+                        tracing::debug!("Failed to read the body of {}: {}", url, err);
+                        break;
+                    }
+                }
+            }
+        }
+        echo_body(url, status, content_type.as_deref(), &body, limit);
+    }
+}
+
+/// Log up to `limit` bytes of the body of a response that failed, unless it isn't text.
+fn echo_body(
+    url: &str,
+    status: reqwest::StatusCode,
+    content_type: Option<&str>,
+    body: &[u8],
+    limit: usize,
+) {
+    if !crate::body::is_text(content_type) {
+        tracing::warn!(
+            "{} responded with {}, its body isn't shown because it is {}",
+            url,
+            status,
+            content_type.unwrap_or_default()
+        );
+        return;
+    }
+    // Line breaks and other control characters are escaped, to keep the body on the line it is logged on.
+    let shown = String::from_utf8_lossy(&body[..body.len().min(limit)])
+        .chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect::<String>();
+    tracing::warn!(
+        "{} responded with {}: {}{}",
+        url,
+        status,
+        shown,
+        if body.len() > limit { "…" } else { "" }
+    );
+}
+
+/// URL of the request to the shadow target, with the scheme, host and port of `url` replaced by those of the target.
+fn shadow_url(url: &Url, shadow_target: &Url) -> Result<Url> {
+    let mut shadow_url = url.clone();
+    shadow_url
+        .set_scheme(shadow_target.scheme())
+        .map_err(|()| anyhow::anyhow!("Can't replace the scheme of {}", url))?;
+    shadow_url.set_host(shadow_target.host_str())?;
+    shadow_url
+        .set_port(shadow_target.port())
+        .map_err(|()| anyhow::anyhow!("Can't replace the port of {}", url))?;
+    Ok(shadow_url)
+}
+
+/// A request that failed, as written to the output in place of its response.
+#[derive(Debug, Serialize)]
+pub(crate) struct FailureDetails<'a> {
+    pub(crate) outcome: Outcome,
+    pub(crate) url: &'a str,
+    /// Status of the response, for requests that failed because of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) status: Option<u16>,
+    pub(crate) error: String,
+    /// Values of the `--tag-column` fields of the record, by field name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) tags: &'a BTreeMap<String, String>,
+}
+
+/// Response of the shadow target to a request, compared to the response of the primary target.
+#[derive(Debug, Serialize)]
+pub(crate) struct Shadow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) response: Option<ResponseDetails>,
+    /// Why the request to the shadow target failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    /// How the response of the shadow target differs from the primary one, unless the request to the shadow target
+    /// wasn't sent at all because the replay was stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) diff: Option<Diff>,
+}
+
+/// Differences between the responses of the primary and the shadow target to a request.
+#[derive(Debug, Serialize)]
+pub(crate) struct Diff {
+    /// Whether the shadow target responded with the same status, `false` if it didn't respond at all.
+    pub(crate) status_matches: bool,
+    /// How much longer the shadow target took to respond, in seconds, negative if it was faster.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) latency_difference: Option<f64>,
+    /// Whether the bodies of both responses are identical, with `--capture-body`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body_matches: Option<bool>,
+    /// How similar the body of the shadow target is to the primary one, with `--compare-bodies`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body_diff: Option<BodyDiff>,
+}
+
+impl Shadow {
+    fn compare(
+        primary: &ResponseDetails,
+        shadow: Result<Option<ResponseDetails>>,
+        body_comparer: Option<&BodyComparer>,
+    ) -> Self {
+        match shadow {
+            Ok(Some(response)) => {
+                let diff = Diff {
+                    status_matches: response.status == primary.status,
+                    latency_difference: Some(
+                        (response.required_time - primary.required_time).to_seconds(),
+                    ),
+                    body_matches: primary
+                        .body
+                        .as_ref()
+                        .zip(response.body.as_ref())
+                        .map(|(primary, shadow)| primary == shadow),
+                    body_diff: body_comparer
+                        .zip(primary.body.as_ref())
+                        .zip(response.body.as_ref())
+                        .map(|((body_comparer, primary), shadow)| {
+                            body_comparer.compare(primary, shadow)
+                        }),
+                };
+                Self {
+                    response: Some(response),
+                    error: None,
+                    diff: Some(diff),
+                }
+            }
+            Ok(None) => Self {
+                response: None,
+                error: None,
+                diff: None,
+            },
+            Err(err) => {
+                let status = err
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(reqwest::Error::status);
+                Self {
+                    response: None,
+                    error: Some(err.to_string()),
+                    diff: Some(Diff {
+                        status_matches: status == Some(primary.status),
+                        latency_difference: None,
+                        body_matches: None,
+                        body_diff: None,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ResponseDetails {
+    /// `success`, or `http_error` for responses with an error status that are kept with `--only-errors`.
+    pub(crate) outcome: Outcome,
+    pub(crate) url: String,
+    #[serde(serialize_with = "crate::ser::statuscode_as_u16")]
+    pub(crate) status: reqwest::StatusCode,
+    #[serde(serialize_with = "crate::ser::duration_to_seconds")]
+    pub(crate) required_time: Duration,
+    /// Latency of the original request, or of the baseline with `--baseline`, in seconds. `null` for requests that
+    /// are missing from the baseline, as is `change_percentage`.
+    pub(crate) original_time: f64,
+    pub(crate) change_percentage: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) compressed_body_size: Option<usize>,
+    #[serde(skip_serializing_if = "crate::ser::is_zero")]
+    pub(crate) retries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) correlation_id: Option<String>,
+    /// Redirects that were followed, as pairs of the status and the URL redirected to, with
+    /// `--max-redirects-recorded`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) redirects: Option<Vec<redirect::Hop>>,
+    /// Outcome of the call according to its `grpc-status`, for gRPC calls with `--grpc`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) grpc_status: Option<grpc::Status>,
+    /// Status of the original response, with `--assert-status-match`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) original_status: Option<u16>,
+    /// Whether the status matches the original one, with `--assert-status-match` for records with an original
+    /// status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) status_matches: Option<bool>,
+    /// Whether the latency regressed beyond `--regression-threshold` compared to the original one.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) regressed: bool,
+    /// Names of the `--expect-header` headers that were missing from the response, or didn't match.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) header_violations: Vec<String>,
+    /// Values of the `--tag-column` fields of the record, by field name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) tags: BTreeMap<String, String>,
+    /// Response of the `--shadow-target` to the same request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) shadow: Option<Box<Shadow>>,
+    /// How similar the body is to the one of the baseline, with `--compare-bodies`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) baseline_body_diff: Option<BodyDiff>,
+}
@@ -0,0 +1,903 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Building the requests that the records of an input file are replayed as.
+//!
+//! Every record is turned into a request by a [`Builder`], which counts the records whose request deviates from the
+//! original one, or that aren't replayed at all, to report them once all records are built. Weights and the original
+//! concurrency are applied to all requests of a file at once, afterwards.
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::{
+    Duration,
+    Epoch,
+};
+use reqwest::{
+    header::{
+        HeaderValue,
+        HOST,
+    },
+    Client,
+    Method,
+    RequestBuilder,
+    Url,
+    Version,
+};
+
+use crate::{
+    grpc,
+    headers,
+    mapping::Mapping,
+    protocol,
+    schedule::{
+        self,
+        Schedule,
+    },
+    url_length::{
+        self,
+        OverLengthUrls,
+    },
+    wasm,
+    websocket,
+    AccessLogRecord,
+    RequestWithOffset,
+    Run,
+    WeightMode,
+};
+
+/// Number of records whose request deviates from the original one, or that aren't replayed, by reason.
+#[derive(Debug, Default)]
+pub(crate) struct Deviations {
+    grpc_as_plain: usize,
+    protocol_mismatches: usize,
+    websockets_skipped: usize,
+    pub(crate) over_length: OverLengthUrls,
+    neutralized: usize,
+    invalid_client_ips: usize,
+    without_credentials: usize,
+    missing_domain_names: usize,
+    records_past_time_of_day: usize,
+    records_before_anchor: usize,
+}
+
+impl Deviations {
+    /// Log the deviations there were, with the option that caused them.
+    pub(crate) fn log(&self, run: &Run) {
+        if self.grpc_as_plain > 0 {
+            tracing::info!(
+                "Replaying {} gRPC calls as plain requests, see --grpc",
+                self.grpc_as_plain
+            );
+        }
+        if self.protocol_mismatches > 0 {
+            tracing::warn!(
+                "{} {} requests that can't be sent with their original protocol version, see --preserve-protocol",
+                match run.on_protocol_mismatch {
+                    protocol::OnMismatch::Downgrade => "Downgrading",
+                    protocol::OnMismatch::Skip => "Skipping",
+                },
+                self.protocol_mismatches
+            );
+        }
+        if self.websockets_skipped > 0 {
+            tracing::info!(
+                "Skipping {} WebSocket upgrade requests, see --websocket",
+                self.websockets_skipped
+            );
+        }
+        if self.over_length.skipped > 0 {
+            tracing::warn!(
+                "Skipping {} requests whose URL is longer than --max-url-length",
+                self.over_length.skipped
+            );
+        }
+        if self.over_length.truncated > 0 {
+            tracing::warn!(
+                "Truncated the query of {} requests whose URL is longer than --max-url-length",
+                self.over_length.truncated
+            );
+        }
+        if self.neutralized > 0 {
+            tracing::info!(
+                "--safe-mode changed the method, or removed the body, of {} requests",
+                self.neutralized
+            );
+        }
+        if self.invalid_client_ips > 0 {
+            tracing::warn!(
+                "{} records have no IP address in field `{}`, they are sent without X-Forwarded-For",
+                self.invalid_client_ips,
+                run.spoof_client_ip_from_column.as_deref().unwrap_or_default()
+            );
+        }
+        if self.without_credentials > 0 {
+            tracing::warn!(
+                "{} records have no value in a field of --auth-from-column, they are sent without that header",
+                self.without_credentials
+            );
+        }
+        if self.missing_domain_names > 0 {
+            tracing::warn!(
+                "{} records have no `domain_name`, they are sent with the Host of their URL despite --preserve-host",
+                self.missing_domain_names
+            );
+        }
+        if self.records_past_time_of_day > 0 {
+            tracing::warn!(
+                "The time of day of {} records has already passed today, they will be sent immediately",
+                self.records_past_time_of_day
+            );
+        }
+        if self.records_before_anchor > 0 {
+            tracing::warn!(
+                "{} records are timestamped before the anchor and will be sent immediately",
+                self.records_before_anchor
+            );
+        }
+    }
+}
+
+/// Builds the requests of the records of a single input file, in the order they are scheduled in.
+pub(crate) struct Builder<'a> {
+    run: &'a Run,
+    client: &'a Client,
+    mapping: Option<&'a Mapping>,
+    /// Target of the requests instead of `--scheme-and-host` and the mapping, for an `--interleave` file.
+    scheme_and_host: Option<&'a str>,
+    /// Directory that relative paths of body files are relative to, the one of the input file.
+    body_files_dir: &'a Path,
+    schedule: Schedule,
+    start: Epoch,
+    now: Epoch,
+    transform: Option<wasm::Transform>,
+    /// Bytes of the records so far, which pace the next one with `--target-bandwidth`.
+    bytes_before: u64,
+    pub(crate) deviations: Deviations,
+}
+
+impl<'a> Builder<'a> {
+    /// Builder for the records of `input_file`, scheduled from `start`.
+    pub(crate) fn new(
+        run: &'a Run,
+        client: &'a Client,
+        mapping: Option<&'a Mapping>,
+        input_file: &'a Path,
+        scheme_and_host: Option<&'a str>,
+        schedule: Schedule,
+        start: Epoch,
+    ) -> Result<Self> {
+        Ok(Self {
+            run,
+            client,
+            mapping,
+            scheme_and_host,
+            body_files_dir: input_file.parent().unwrap_or(Path::new("")),
+            schedule,
+            start,
+            now: Epoch::now()?,
+            transform: run
+                .transform_wasm
+                .as_deref()
+                .map(wasm::Transform::from_path)
+                .transpose()?,
+            bytes_before: 0,
+            deviations: Deviations::default(),
+        })
+    }
+
+    /// The request to replay `record` as, scheduled by its `timestamp`, or `None` if it is skipped.
+    pub(crate) fn request(
+        &mut self,
+        record: AccessLogRecord,
+        timestamp: Epoch,
+    ) -> Result<Option<RequestWithOffset>> {
+        let Some(url) = self.url(&record)? else {
+            return Ok(None);
+        };
+        let is_websocket = record.is_websocket(&url);
+        if is_websocket && self.run.websocket == websocket::WebSocketMode::Skip {
+            self.deviations.websockets_skipped += 1;
+            return Ok(None);
+        }
+        let Some(version) = self.version(&record, &url)? else {
+            return Ok(None);
+        };
+        let is_grpc = record.is_grpc();
+        if is_grpc && !self.run.grpc {
+            self.deviations.grpc_as_plain += 1;
+        }
+        let is_grpc = is_grpc && self.run.grpc;
+        let offset = self.offset(&record, timestamp)?;
+
+        let method = match self
+            .run
+            .method_field
+            .as_deref()
+            .and_then(|method_field| record.field(method_field))
+        {
+            Some(method) if !method.trim().is_empty() => {
+                Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes())
+                    .map_err(|_| anyhow::anyhow!("Invalid method `{}`", method))?
+            }
+            _ => Method::GET,
+        };
+        let has_body = self
+            .run
+            .body_field
+            .as_deref()
+            .and_then(|body_field| record.fields.get(body_field))
+            .is_some_and(|body| !body.is_null() && body.as_str() != Some(""));
+        let body_file = self.body_file(&record);
+        let has_body = has_body || body_file.is_some();
+        let sends_body = !self.run.safe_mode && self.run.override_method.is_none();
+        let body_file = match body_file {
+            Some(body_file) if !body_file.is_file() => anyhow::bail!(
+                "Record at {}: body file {} doesn't exist",
+                record.timestamp,
+                body_file.display()
+            ),
+            body_file => body_file.filter(|_| sends_body),
+        };
+        let method = if is_grpc {
+            Method::POST
+        } else if let Some(override_method) = self.run.override_method {
+            override_method.method()
+        } else if self.run.safe_mode {
+            let safe = self.run.safe_method.neutralize(method.clone());
+            if safe != method || has_body {
+                self.deviations.neutralized += 1;
+            }
+            safe
+        } else {
+            method
+        };
+
+        let mut builder = self.client.request(method, websocket::http_url(url)?);
+        if let Some(version) = version {
+            builder = builder.version(version);
+        }
+        if is_websocket {
+            builder = self.run.websocket.apply(builder);
+        }
+        builder = self.headers(builder, &record)?;
+        builder = self.body(
+            builder,
+            &record,
+            is_grpc,
+            body_file.as_deref(),
+            has_body && sends_body,
+        )?;
+        let correlation_id = self.correlation_id(&record);
+        if let (Some(header), Some(correlation_id)) =
+            (&self.run.correlation_header, &correlation_id)
+        {
+            let value = HeaderValue::from_str(correlation_id).map_err(|_| {
+                anyhow::anyhow!(
+                    "Record at {}: invalid value for the `{}` header",
+                    record.timestamp,
+                    header
+                )
+            })?;
+            builder = builder.header(header.clone(), value);
+        }
+        let tags = self
+            .run
+            .tag_column
+            .iter()
+            .filter_map(|column| {
+                let value = record.field(column).filter(|value| !value.is_empty())?;
+                Some((column.clone(), value.into_owned()))
+            })
+            .collect();
+        let mut request = builder.build()?;
+        if let Some(transform) = &mut self.transform {
+            transform.apply(&mut request).with_context(|| {
+                format!(
+                    "Failed to transform the request of the record at {}",
+                    record.timestamp
+                )
+            })?;
+        }
+
+        Ok(Some(RequestWithOffset {
+            offset,
+            request,
+            record,
+            correlation_id,
+            body_file: body_file.filter(|_| !is_grpc),
+            tags,
+            grpc: is_grpc,
+            after: None,
+            done: None,
+            weight: 1,
+        }))
+    }
+
+    /// URL to send the request of `record` to, or `None` if it is too long and skipped.
+    fn url(&mut self, record: &AccessLogRecord) -> Result<Option<Url>> {
+        let scheme_and_host = match (self.scheme_and_host, self.mapping) {
+            (Some(scheme_and_host), _) => Some(scheme_and_host),
+            (None, Some(mapping)) => {
+                Some(mapping.get_scheme_and_host(record.domain_name.as_deref())?)
+            }
+            (None, None) => self.run.scheme_and_host.as_deref(),
+        };
+        let mut url = record.url(scheme_and_host, self.run.url_field.as_deref())?;
+        if self.run.preserve_scheme {
+            record.preserve_scheme(&mut url)?;
+        }
+        if let Some(max_url_length) = self
+            .run
+            .max_url_length
+            .filter(|max_url_length| url.as_str().len() > *max_url_length)
+        {
+            let length = url.as_str().len();
+            if self.run.over_length == url_length::OverLength::Truncate
+                && url_length::truncate(&mut url, max_url_length)
+            {
+                tracing::debug!(
+                    "Truncated the query of the record at {}, its URL was {} bytes long",
+                    record.timestamp,
+                    length
+                );
+                self.deviations.over_length.truncated += 1;
+            } else {
+                tracing::debug!(
+                    "Skipping the record at {}, its URL is {} bytes long",
+                    record.timestamp,
+                    length
+                );
+                self.deviations.over_length.skipped += 1;
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(url))
+    }
+
+    /// Protocol version to send the request of `record` with, if any in particular, or `None` if it can't be sent
+    /// with its original protocol version and is skipped.
+    fn version(&mut self, record: &AccessLogRecord, url: &Url) -> Result<Option<Option<Version>>> {
+        let Some(protocol) = record
+            .field("protocol")
+            .filter(|_| self.run.preserve_protocol)
+        else {
+            return Ok(Some(None));
+        };
+        let http2_only = record.is_grpc() && self.run.grpc;
+        match protocol::version(&protocol, url, http2_only) {
+            Ok(Some(usable)) => Ok(Some(Some(usable))),
+            Ok(None) => {
+                tracing::debug!(
+                    "The record at {} can't be sent with its original protocol {}",
+                    record.timestamp,
+                    protocol
+                );
+                self.deviations.protocol_mismatches += 1;
+                Ok((self.run.on_protocol_mismatch != protocol::OnMismatch::Skip).then_some(None))
+            }
+            Err(err) => Err(anyhow::anyhow!("Record at {}: {}", record.timestamp, err)),
+        }
+    }
+
+    /// Offset from the start of the replay to send the request of `record` at.
+    fn offset(&mut self, record: &AccessLogRecord, timestamp: Epoch) -> Result<Duration> {
+        if let Some(target_bandwidth) = self.run.target_bandwidth {
+            let bytes = match (record.received_bytes, record.sent_bytes) {
+                (None, None) => anyhow::bail!(
+                    "Record at {} has neither a `received_bytes` nor a `sent_bytes` field, which \
+                     --target-bandwidth requires",
+                    record.timestamp
+                ),
+                (received_bytes, sent_bytes) => {
+                    received_bytes.unwrap_or(0) + sent_bytes.unwrap_or(0)
+                }
+            };
+            let offset = Duration::from_seconds(self.bytes_before as f64 / target_bandwidth);
+            self.bytes_before += bytes;
+            Ok(offset)
+        } else if self.run.preserve_time_of_day {
+            Ok(
+                schedule::time_of_day_offset(record.timestamp, self.now, self.run.past_time_of_day)
+                    .unwrap_or_else(|| {
+                        self.deviations.records_past_time_of_day += 1;
+                        Duration::ZERO
+                    }),
+            )
+        } else if timestamp < self.start {
+            self.deviations.records_before_anchor += 1;
+            Ok(Duration::ZERO)
+        } else {
+            Ok(self.schedule.scale(timestamp - self.start))
+        }
+    }
+
+    /// File to stream the body of the request of `record` from, with `--body-from-file-column`.
+    fn body_file(&self, record: &AccessLogRecord) -> Option<PathBuf> {
+        self.run
+            .body_from_file_column
+            .as_deref()
+            .and_then(|column| record.field(column))
+            .filter(|body_file| !body_file.trim().is_empty())
+            .map(|body_file| self.body_files_dir.join(&*body_file))
+    }
+
+    /// Add the headers taken from the fields of `record` to the request.
+    fn headers(
+        &mut self,
+        mut builder: RequestBuilder,
+        record: &AccessLogRecord,
+    ) -> Result<RequestBuilder> {
+        let headers_from_columns = self
+            .run
+            .header_from_column
+            .iter()
+            .map(|header| (header, false))
+            .chain(
+                self.run
+                    .auth_from_column
+                    .iter()
+                    .map(|header| (&header.0, true)),
+            );
+        let mut has_credentials = true;
+        for (header, sensitive) in headers_from_columns {
+            let Some(mut value) = header
+                .value(record.field(&header.column).as_deref())
+                .map_err(|error| anyhow::anyhow!("Record at {}: {}", record.timestamp, error))?
+            else {
+                has_credentials &= !sensitive;
+                continue;
+            };
+            value.set_sensitive(sensitive);
+            builder = builder.header(header.name.clone(), value);
+        }
+        if !has_credentials {
+            self.deviations.without_credentials += 1;
+        }
+        if self.run.preserve_host {
+            match headers::host(record.domain_name.as_deref())
+                .map_err(|error| anyhow::anyhow!("Record at {}: {}", record.timestamp, error))?
+            {
+                Some(host) => builder = builder.header(HOST, host),
+                None => self.deviations.missing_domain_names += 1,
+            }
+        }
+        if let Some(column) = &self.run.spoof_client_ip_from_column {
+            match record.field(column).as_deref().and_then(headers::client_ip) {
+                Some(client_ip) => {
+                    let client_ip = HeaderValue::from_str(&client_ip.to_string())?;
+                    if self.run.spoof_real_ip {
+                        builder = builder.header("x-real-ip", client_ip.clone());
+                    }
+                    builder = builder.header("x-forwarded-for", client_ip);
+                }
+                None => self.deviations.invalid_client_ips += 1,
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Add the body of `record` to the request: the framed message of a gRPC call, or the body field if it is sent.
+    ///
+    /// Bodies of other requests that are read from a file are streamed when the request is sent instead.
+    fn body(
+        &self,
+        builder: RequestBuilder,
+        record: &AccessLogRecord,
+        is_grpc: bool,
+        body_file: Option<&Path>,
+        sends_body: bool,
+    ) -> Result<RequestBuilder> {
+        let body = self
+            .run
+            .body_field
+            .as_deref()
+            .and_then(|body_field| record.fields.get(body_field));
+        if is_grpc {
+            // Messages are small enough to be read into memory, unlike uploads they need to be framed.
+            let message = match body_file {
+                Some(body_file) => std::fs::read(body_file)
+                    .with_context(|| format!("Failed to read body file {}", body_file.display()))?,
+                None => grpc::message(body)
+                    .map_err(|err| anyhow::anyhow!("Record at {}: {}", record.timestamp, err))?,
+            };
+            grpc::apply(builder, &message)
+        } else if let Some(body) = body.filter(|_| sends_body) {
+            self.run
+                .body_format
+                .apply(builder, body)
+                .map_err(|err| anyhow::anyhow!("Record at {}: {}", record.timestamp, err))
+        } else {
+            Ok(builder)
+        }
+    }
+
+    /// ID to send in `--correlation-header`, from `--correlation-id-field` or derived from the record.
+    fn correlation_id(&self, record: &AccessLogRecord) -> Option<String> {
+        self.run.correlation_header.as_ref()?;
+        Some(
+            match self
+                .run
+                .correlation_id_field
+                .as_deref()
+                .and_then(|field| record.field(field))
+                .filter(|id| !id.trim().is_empty())
+            {
+                Some(id) => id.into_owned(),
+                None => headers::correlation_id(record.timestamp, &record.path, &record.parameters),
+            },
+        )
+    }
+}
+
+/// Weigh the requests by the `weight_column` of their record, expanding every request into as many as its weight
+/// with [`WeightMode::Expand`].
+pub(crate) fn weigh(
+    mut requests: Vec<RequestWithOffset>,
+    weight_column: &str,
+    mode: WeightMode,
+) -> Result<Vec<RequestWithOffset>> {
+    let mut unweighted = 0usize;
+    for request in &mut requests {
+        match request.record.weight(weight_column)? {
+            Some(weight) => request.weight = weight,
+            None => unweighted += 1,
+        }
+    }
+    if unweighted > 0 {
+        tracing::warn!(
+            "{} records have no weight in field `{}`, they have a weight of 1",
+            unweighted,
+            weight_column
+        );
+    }
+    if mode == WeightMode::Expand {
+        let records = requests.len();
+        let mut expanded =
+            Vec::with_capacity(requests.iter().map(|request| request.weight as usize).sum());
+        for mut request in requests {
+            let weight = std::mem::replace(&mut request.weight, 1);
+            for _ in 1..weight {
+                expanded.push(request.clone_at(request.offset));
+            }
+            expanded.push(request);
+        }
+        requests = expanded;
+        tracing::info!(
+            "Expanded {} records into {} requests by their weight",
+            records,
+            requests.len()
+        );
+    }
+
+    Ok(requests)
+}
+
+/// Let every request wait for the completion of its predecessor in the lanes of the original requests, to replay
+/// them with their original concurrency, with `--match-concurrency`.
+pub(crate) fn match_concurrency(requests: &mut [RequestWithOffset]) {
+    let start = requests
+        .iter()
+        .map(|request| request.record.timestamp)
+        .min()
+        .unwrap_or_default();
+    // Records without a latency took no time, as far as is known.
+    let original = requests
+        .iter()
+        .map(|request| {
+            let required_time = request.record.required_time;
+            (
+                request.record.timestamp - start,
+                Duration::from_seconds(if required_time > 0. {
+                    required_time
+                } else {
+                    0.
+                }),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (predecessors, concurrency) = schedule::concurrency_lanes(&original);
+    for (index, predecessor) in predecessors.into_iter().enumerate() {
+        if let Some(predecessor) = predecessor {
+            let (done, after) = tokio::sync::oneshot::channel();
+            requests[predecessor].done = Some(done);
+            requests[index].after = Some(after);
+        }
+    }
+    tracing::info!(
+        "Matching the original concurrency of at most {} requests in flight",
+        concurrency
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        Cli,
+        Commands,
+        InputFormat,
+    };
+
+    const RECORDS: &str = "\
+@timestamp,path,params,target_processing_time,method,body,user,id,weight
+2024-06-01 @ 12:00:00.000,/v1/items,?x=1,0.5,POST,\"{\"\"a\"\":1}\",alice,first,3
+2024-06-01 @ 12:00:00.100,/v1/items/1,,0.2,DELETE,,bob,,
+2024-06-01 @ 12:00:00.600,/other,,0.1,,,,third,2
+";
+
+    fn run(args: &[&str]) -> Run {
+        let args = ["r7", "run", "--scheme-and-host", "http://localhost:8080"]
+            .into_iter()
+            .chain(args.iter().copied())
+            .chain(["input.csv"]);
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Run(run) => *run,
+            _ => unreachable!(),
+        }
+    }
+
+    fn records(run: &Run) -> Vec<AccessLogRecord> {
+        let parsed =
+            AccessLogRecord::parse_records(RECORDS.as_bytes(), InputFormat::Csv, &run.input_fields)
+                .unwrap();
+        AccessLogRecord::select_records(parsed, &run.input_fields, &run.selection, false, true)
+            .unwrap()
+            .0
+    }
+
+    /// The requests of `RECORDS` with the given options, scheduled from the first record, with the deviations.
+    fn build(args: &[&str]) -> Result<(Vec<RequestWithOffset>, Deviations)> {
+        let run = run(args);
+        let records = records(&run);
+        let start = records[0].timestamp;
+        let end = records[records.len() - 1].timestamp;
+        let schedule = Schedule::new(
+            &run.time_factor_schedule,
+            start,
+            end,
+            run.time_factor.unwrap_or(1f64),
+        )?;
+        let client = Client::new();
+        let mut builder = Builder::new(
+            &run,
+            &client,
+            None,
+            Path::new("input.csv"),
+            None,
+            schedule,
+            start,
+        )?;
+        let mut requests = Vec::new();
+        for record in records {
+            let timestamp = record.timestamp;
+            requests.extend(builder.request(record, timestamp)?);
+        }
+
+        Ok((requests, builder.deviations))
+    }
+
+    fn urls(requests: &[RequestWithOffset]) -> Vec<&str> {
+        requests
+            .iter()
+            .map(|request| request.request.url().as_str())
+            .collect()
+    }
+
+    fn offsets(requests: &[RequestWithOffset]) -> Vec<Duration> {
+        requests.iter().map(|request| request.offset).collect()
+    }
+
+    fn methods(requests: &[RequestWithOffset]) -> Vec<&Method> {
+        requests
+            .iter()
+            .map(|request| request.request.method())
+            .collect()
+    }
+
+    fn header<'r>(request: &'r RequestWithOffset, name: &str) -> Option<&'r str> {
+        request
+            .request
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn requests_are_sent_to_the_target_at_the_original_offsets() {
+        let (requests, _) = build(&[]).unwrap();
+        assert_eq!(
+            urls(&requests),
+            [
+                "http://localhost:8080/v1/items?x=1",
+                "http://localhost:8080/v1/items/1",
+                "http://localhost:8080/other",
+            ]
+        );
+        assert_eq!(
+            offsets(&requests),
+            [
+                Duration::ZERO,
+                Duration::from_milliseconds(100.),
+                Duration::from_milliseconds(600.),
+            ]
+        );
+        assert_eq!(methods(&requests), [Method::GET, Method::GET, Method::GET]);
+        assert!(requests.iter().all(|request| request.weight == 1));
+    }
+
+    #[test]
+    fn offsets_are_scaled_by_the_time_factor() {
+        let (requests, _) = build(&["--time-factor", "2"]).unwrap();
+        assert_eq!(
+            offsets(&requests),
+            [
+                Duration::ZERO,
+                Duration::from_milliseconds(200.),
+                Duration::from_milliseconds(1200.),
+            ]
+        );
+    }
+
+    #[test]
+    fn methods_and_bodies_are_taken_from_their_fields() {
+        let (requests, deviations) =
+            build(&["--method-field", "method", "--body-field", "body"]).unwrap();
+        assert_eq!(
+            methods(&requests),
+            [Method::POST, Method::DELETE, Method::GET]
+        );
+        assert_eq!(
+            requests[0].request.body().and_then(|body| body.as_bytes()),
+            Some(&b"{\"a\":1}"[..])
+        );
+        assert!(requests[1].request.body().is_none());
+        assert_eq!(deviations.neutralized, 0);
+    }
+
+    #[test]
+    fn safe_mode_neutralizes_methods_and_drops_bodies() {
+        let (requests, deviations) = build(&[
+            "--method-field",
+            "method",
+            "--body-field",
+            "body",
+            "--safe-mode",
+            "--safe-method",
+            "head",
+        ])
+        .unwrap();
+        assert_eq!(
+            methods(&requests),
+            [Method::HEAD, Method::HEAD, Method::GET]
+        );
+        assert!(requests[0].request.body().is_none());
+        assert_eq!(deviations.neutralized, 2);
+    }
+
+    #[test]
+    fn invalid_methods_fail() {
+        let err = build(&["--method-field", "body"]).err().unwrap();
+        assert_eq!(err.to_string(), "Invalid method `{\"a\":1}`");
+    }
+
+    #[test]
+    fn over_length_urls_are_skipped_or_truncated() {
+        let (requests, deviations) = build(&["--max-url-length", "32"]).unwrap();
+        assert_eq!(
+            urls(&requests),
+            [
+                "http://localhost:8080/v1/items/1",
+                "http://localhost:8080/other"
+            ]
+        );
+        assert_eq!(deviations.over_length.skipped, 1);
+        assert_eq!(deviations.over_length.truncated, 0);
+
+        let (requests, deviations) =
+            build(&["--max-url-length", "32", "--over-length", "truncate"]).unwrap();
+        assert_eq!(
+            urls(&requests),
+            [
+                "http://localhost:8080/v1/items",
+                "http://localhost:8080/v1/items/1",
+                "http://localhost:8080/other",
+            ]
+        );
+        assert_eq!(deviations.over_length.skipped, 0);
+        assert_eq!(deviations.over_length.truncated, 1);
+    }
+
+    #[test]
+    fn headers_are_taken_from_their_fields() {
+        let (requests, deviations) = build(&[
+            "--header-from-column",
+            "x-user=user",
+            "--correlation-header",
+            "--correlation-id-field",
+            "id",
+        ])
+        .unwrap();
+        assert_eq!(header(&requests[0], "x-user"), Some("alice"));
+        assert_eq!(header(&requests[2], "x-user"), None);
+        assert_eq!(header(&requests[0], "x-repeater-id"), Some("first"));
+        assert_eq!(header(&requests[2], "x-repeater-id"), Some("third"));
+        // Records without an ID of their own get one derived from the record.
+        let derived = header(&requests[1], "x-repeater-id").unwrap();
+        assert_eq!(requests[1].correlation_id.as_deref(), Some(derived));
+        assert!(!derived.is_empty());
+        assert_eq!(deviations.without_credentials, 0);
+    }
+
+    #[test]
+    fn tags_are_taken_from_non_empty_fields() {
+        let (requests, _) = build(&["--tag-column", "user"]).unwrap();
+        assert_eq!(
+            requests[1].tags.get("user").map(String::as_str),
+            Some("bob")
+        );
+        assert!(requests[2].tags.is_empty());
+    }
+
+    #[test]
+    fn weights_expand_or_scale_requests() {
+        let (requests, _) = build(&[]).unwrap();
+        let scaled = weigh(requests, "weight", WeightMode::Scale).unwrap();
+        assert_eq!(
+            scaled
+                .iter()
+                .map(|request| request.weight)
+                .collect::<Vec<_>>(),
+            [3, 1, 2]
+        );
+
+        let (requests, _) = build(&[]).unwrap();
+        let expanded = weigh(requests, "weight", WeightMode::Expand).unwrap();
+        assert_eq!(
+            urls(&expanded),
+            [
+                "http://localhost:8080/v1/items?x=1",
+                "http://localhost:8080/v1/items?x=1",
+                "http://localhost:8080/v1/items?x=1",
+                "http://localhost:8080/v1/items/1",
+                "http://localhost:8080/other",
+                "http://localhost:8080/other",
+            ]
+        );
+        assert!(expanded.iter().all(|request| request.weight == 1));
+    }
+
+    #[test]
+    fn requests_wait_for_their_predecessor_with_the_original_concurrency() {
+        let (mut requests, _) = build(&[]).unwrap();
+        match_concurrency(&mut requests);
+        // The second request overlapped the first, the third started after both completed, the second first.
+        assert!(requests[0].after.is_none() && requests[0].done.is_none());
+        assert!(requests[1].after.is_none() && requests[1].done.is_some());
+        assert!(requests[2].after.is_some() && requests[2].done.is_none());
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    str::FromStr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use reqwest::dns::{
+    Addrs,
+    Name,
+    Resolve,
+    Resolving,
+};
+
+/// Override of the address a host resolves to, in the `host:port:ip` format of curl's `--resolve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResolveOverride {
+    pub(crate) host: String,
+    pub(crate) addr: SocketAddr,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(ip)) = (parts.next(), parts.next(), parts.next()) else {
+            anyhow::bail!("expected `host:port:ip`");
+        };
+        if host.is_empty() {
+            anyhow::bail!("host must not be empty");
+        }
+        let port = port
+            .parse::<u16>()
+            .with_context(|| format!("invalid port `{}`", port))?;
+        let ip = ip
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .with_context(|| format!("invalid IP address `{}`", ip))?;
+
+        Ok(Self {
+            host: host.to_owned(),
+            addr: SocketAddr::new(ip, port),
+        })
+    }
+}
+
+/// The addresses `--resolve` maps hosts to, where a host given multiple times resolves to all of its addresses, in
+/// the order they were given.
+pub(crate) fn overrides(overrides: &[ResolveOverride]) -> Vec<(&str, Vec<SocketAddr>)> {
+    let mut hosts: Vec<(&str, Vec<SocketAddr>)> = Vec::new();
+    for resolve_override in overrides {
+        match hosts
+            .iter_mut()
+            .find(|(host, _)| *host == resolve_override.host)
+        {
+            Some((_, addrs)) => addrs.push(resolve_override.addr),
+            None => hosts.push((&resolve_override.host, vec![resolve_override.addr])),
+        }
+    }
+    hosts
+}
+
+/// Address family of the addresses requests are sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum IpFamily {
+    /// Use every address a host resolves to.
+    Auto,
+    /// Only use IPv4 addresses.
+    V4,
+    /// Only use IPv6 addresses.
+    V6,
+}
+
+impl IpFamily {
+    pub(crate) fn allows(self, ip: IpAddr) -> bool {
+        match self {
+            IpFamily::Auto => true,
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpFamily::Auto => f.write_str("auto"),
+            IpFamily::V4 => f.write_str("v4"),
+            IpFamily::V6 => f.write_str("v6"),
+        }
+    }
+}
+
+/// Resolves host names with the system resolver, keeping only the addresses of one family.
+#[derive(Debug)]
+pub(crate) struct FamilyResolver(pub(crate) IpFamily);
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.0;
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs = tokio::net::lookup_host((host, 0))
+                .await?
+                .collect::<Vec<_>>();
+            let (allowed, other): (Vec<_>, Vec<_>) =
+                addrs.into_iter().partition(|addr| family.allows(addr.ip()));
+            if allowed.is_empty() {
+                let other = other
+                    .iter()
+                    .map(|addr| addr.ip().to_string())
+                    .collect::<Vec<_>>();
+                return Err(format!(
+                    "`{}` has no addresses allowed by --ip-family {}, only {}",
+                    host,
+                    family,
+                    other.join(", ")
+                )
+                .into());
+            }
+            Ok(Box::new(allowed.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// When a host was last resolved, and to which addresses.
+type Resolved = (Instant, Vec<SocketAddr>);
+
+/// Caches the addresses another resolver returns for a limited time, after which hosts are resolved again.
+///
+/// Changes of the addresses a host resolves to are logged, to follow e.g. a rolling deployment behind a load balancer.
+#[derive(Debug)]
+pub(crate) struct RefreshingResolver<R> {
+    inner: R,
+    refresh: Duration,
+    cache: Arc<Mutex<HashMap<String, Resolved>>>,
+}
+
+impl<R> RefreshingResolver<R> {
+    pub(crate) fn new(inner: R, refresh: Duration) -> Self {
+        Self {
+            inner,
+            refresh,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for RefreshingResolver<R> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&host)
+            .filter(|(resolved, _)| resolved.elapsed() < self.refresh)
+            .map(|(_, addrs)| addrs.clone());
+        if let Some(addrs) = cached {
+            return Box::pin(async move { Ok(Box::new(addrs.into_iter()) as Addrs) });
+        }
+
+        let resolving = self.inner.resolve(name);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let addrs = resolving.await?.collect::<Vec<_>>();
+            let previous = cache
+                .lock()
+                .unwrap()
+                .insert(host.clone(), (Instant::now(), addrs.clone()));
+            if previous.is_some_and(|(_, previous)| previous != addrs) {
+                tracing::info!(
+                    "`{}` now resolves to {}",
+                    host,
+                    addrs
+                        .iter()
+                        .map(|addr| addr.ip().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn overrides_group_addresses_by_host() {
+        let overrides = [
+            "api.internal:443:10.0.0.17"
+                .parse::<ResolveOverride>()
+                .unwrap(),
+            "other.internal:443:[::1]".parse().unwrap(),
+            "api.internal:443:10.0.0.18".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            super::overrides(&overrides),
+            vec![
+                (
+                    "api.internal",
+                    vec![
+                        "10.0.0.17:443".parse().unwrap(),
+                        "10.0.0.18:443".parse().unwrap()
+                    ]
+                ),
+                ("other.internal", vec!["[::1]:443".parse().unwrap()]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn overridden_host_connects_to_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let overrides = [format!("backend.invalid:{}:127.0.0.1", port)
+            .parse::<ResolveOverride>()
+            .unwrap()];
+        let mut builder = reqwest::Client::builder();
+        for (host, addrs) in super::overrides(&overrides) {
+            builder = builder.resolve_to_addrs(host, &addrs);
+        }
+        let client = builder.build().unwrap();
+
+        // `.invalid` never resolves, so the request only reaches the listener through the override.
+        let request = tokio::spawn(
+            client
+                .get(format!("http://backend.invalid:{}/", port))
+                .timeout(Duration::from_secs(1))
+                .send(),
+        );
+        let accepted = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await;
+        assert!(accepted.is_ok_and(|accepted| accepted.is_ok()));
+        request.abort();
+    }
+}
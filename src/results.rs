@@ -0,0 +1,824 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording the results of the requests of a replay as they complete.
+//!
+//! Every completed request is written out as a line, counted in the summary and the comparisons that were asked for,
+//! and stops the replay once it trips the circuit breaker of `--abort-on-error-rate` or reaches `--max-failures`.
+//! Once the replay is over, the comparisons are reported and decide whether the run failed.
+
+use std::{
+    collections::BTreeMap,
+    io::{
+        self,
+        Write,
+    },
+};
+
+use anyhow::Result;
+use hifitime::Duration;
+use reqwest::Url;
+
+use crate::{
+    assertion,
+    body_diff,
+    breaker,
+    events,
+    normalize,
+    order,
+    output,
+    remaining::{
+        self,
+        RawRecord,
+    },
+    replay::{
+        self,
+        Replay,
+    },
+    sink,
+    summary::{
+        self,
+        Summary,
+    },
+    tui,
+    Run,
+};
+
+/// A request of the replay that completed, or that wasn't sent because the replay was stopped.
+#[derive(Debug)]
+pub(crate) struct Completed {
+    pub(crate) index: usize,
+    pub(crate) url: Url,
+    pub(crate) tags: BTreeMap<String, String>,
+    /// Status the original request was responded to with, if the record has one.
+    pub(crate) original_status: Option<u16>,
+    pub(crate) weight: u64,
+}
+
+/// Where the lines of the completed requests are written to: the output, in order with `--ordered-output`, and the
+/// `--sink`.
+pub(crate) struct Lines {
+    output: output::Output,
+    ordered_output: Option<order::OrderedOutput>,
+    sink: Option<sink::Sink>,
+    /// Whether every line is flushed right away, rather than every `--flush-interval`.
+    flush_every_line: bool,
+}
+
+impl Lines {
+    /// Lines of the requests with the given offsets, which are ordered by them with `--ordered-output`.
+    pub(crate) fn new(run: &Run, offsets: &[Duration]) -> Result<Self> {
+        Ok(Self {
+            output: output::Output::new(run.output.as_deref(), run.output_gzip)?,
+            ordered_output: run
+                .ordered_output
+                .then(|| order::OrderedOutput::new(offsets)),
+            sink: run.sink.clone().map(sink::Sink::new).transpose()?,
+            flush_every_line: run.flush_interval.is_none(),
+        })
+    }
+
+    /// Write the line of the request with `index`, or record that it has none.
+    fn write(&mut self, index: usize, line: Option<Vec<u8>>) -> io::Result<()> {
+        write_line(
+            &mut self.output,
+            self.ordered_output.as_mut(),
+            self.sink.as_mut(),
+            index,
+            line,
+        )?;
+        if self.flush_every_line {
+            self.output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    /// Write the lines still held back by `--ordered-output`, and everything that hasn't reached the output or the
+    /// sink yet.
+    pub(crate) async fn finish(mut self) -> io::Result<()> {
+        if let Some(ordered_output) = self.ordered_output {
+            ordered_output.finish(&mut self.output)?;
+        }
+        self.output.finish()?;
+        if let Some(sink) = self.sink {
+            sink.finish().await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the output line of the request with `index`, if it has one, right away or in order with `--ordered-output`.
+fn write_line(
+    out: &mut impl Write,
+    ordered_output: Option<&mut order::OrderedOutput>,
+    sink: Option<&mut sink::Sink>,
+    index: usize,
+    line: Option<Vec<u8>>,
+) -> io::Result<()> {
+    if let (Some(sink), Some(line)) = (sink, &line) {
+        sink.send(line);
+    }
+    match (ordered_output, line) {
+        (Some(ordered_output), line) => ordered_output.complete(index, line, out),
+        (None, Some(line)) => {
+            out.write_all(&line)?;
+            writeln!(out)
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+/// The error, followed by its root cause if it has one, e.g. the reason a connection couldn't be established.
+pub(crate) fn describe_error(err: &anyhow::Error) -> String {
+    if err.chain().count() > 1 {
+        format!("{}: {}", err, err.root_cause())
+    } else {
+        err.to_string()
+    }
+}
+
+/// The results of the requests of a replay so far.
+pub(crate) struct Results {
+    /// Whether only the lines of requests that failed or responded with an error status are written.
+    only_errors: bool,
+    /// Whether the raw records of the requests that failed are kept, for `--failures-out`.
+    keep_failed: bool,
+    pub(crate) summary: Summary,
+    regressions: Option<summary::Regressions>,
+    header_violations: Option<summary::HeaderViolations>,
+    status_matches: Option<summary::StatusMatches>,
+    shadow_comparison: summary::ShadowComparison,
+    shadow_bodies: body_diff::BodyComparison,
+    baseline_bodies: body_diff::BodyComparison,
+    circuit_breaker: Option<breaker::CircuitBreaker>,
+    error_rate_window: usize,
+    max_failures: Option<usize>,
+    /// Number of responses and the error rate when the circuit breaker tripped.
+    tripped: Option<(usize, f64)>,
+    /// Number of responses when `--max-failures` was reached.
+    max_failures_reached: Option<usize>,
+    not_sent: usize,
+    /// Raw records of the requests that failed, by index, with `--failures-out`.
+    failed: BTreeMap<usize, RawRecord>,
+}
+
+impl Results {
+    pub(crate) fn new(run: &Run) -> Self {
+        Self {
+            only_errors: run.only_errors,
+            keep_failed: run.failures_out.is_some(),
+            summary: Summary::new(
+                run.group_by
+                    .or((!run.interleave.is_empty()).then_some(summary::GroupBy::Stream)),
+                run.normalize_paths
+                    .then(|| normalize::PathNormalizer::new(&run.normalize_rule)),
+                run.exact_percentiles,
+            ),
+            regressions: run.regression_threshold.map(summary::Regressions::new),
+            header_violations: (!run.expect_header.is_empty())
+                .then(summary::HeaderViolations::default),
+            status_matches: run
+                .assert_status_match
+                .then(|| summary::StatusMatches::new(run.status_equivalent.clone())),
+            shadow_comparison: summary::ShadowComparison::default(),
+            shadow_bodies: body_diff::BodyComparison::default(),
+            baseline_bodies: body_diff::BodyComparison::default(),
+            circuit_breaker: run
+                .abort_on_error_rate
+                .map(|threshold| breaker::CircuitBreaker::new(threshold, run.error_rate_window)),
+            error_rate_window: run.error_rate_window,
+            max_failures: if run.fail_fast {
+                Some(1)
+            } else {
+                run.max_failures
+            },
+            tripped: None,
+            max_failures_reached: None,
+            not_sent: 0,
+            failed: BTreeMap::new(),
+        }
+    }
+
+    /// Record the completed request with its response, `None` if it wasn't sent, and the raw record it was built
+    /// from if it is kept. Stops the replay if that trips the circuit breaker or reaches `--max-failures`.
+    pub(crate) fn record(
+        &mut self,
+        completed: Completed,
+        result: Result<Option<replay::ResponseDetails>>,
+        raw: Option<RawRecord>,
+        lines: &mut Lines,
+        replay: &Replay,
+        dashboard: Option<&mut tui::Dashboard>,
+    ) -> Result<()> {
+        let index = completed.index;
+        let is_error = match result {
+            Ok(Some(response_details)) => {
+                self.record_response(completed, response_details, lines, replay, dashboard)?
+            }
+            Ok(None) => {
+                self.not_sent += 1;
+                lines.write(index, None)?;
+                return Ok(());
+            }
+            Err(err) => {
+                let Completed {
+                    url,
+                    tags,
+                    original_status,
+                    weight,
+                    ..
+                } = completed;
+                let status = err
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(reqwest::Error::status);
+                let outcome = summary::Outcome::of_error(&err);
+                let line = serde_json::to_vec(&replay::FailureDetails {
+                    outcome,
+                    url: url.as_str(),
+                    status: status.map(|status| status.as_u16()),
+                    error: describe_error(&err),
+                    tags: &tags,
+                })?;
+                lines.write(index, Some(line))?;
+                self.summary.record(&url, &tags, status, None, weight);
+                if let Some(status_matches) = &mut self.status_matches {
+                    status_matches.record(url.as_str(), original_status, status);
+                }
+                self.summary.record_failure(outcome, weight);
+                replay.events.emit(events::Event::RequestCompleted {
+                    url: url.as_str(),
+                    status: status.map(|status| status.as_u16()),
+                    required_time: None,
+                    completed: self.summary.requests(),
+                    errors: self.summary.errors(),
+                });
+                match dashboard {
+                    Some(dashboard) => {
+                        dashboard.record(status, None);
+                        dashboard.record_error(describe_error(&err));
+                    }
+                    None => eprintln!("{}", describe_error(&err)),
+                }
+                true
+            }
+        };
+        if let Some(raw) = raw.filter(|_| is_error && self.keep_failed) {
+            self.failed.insert(index, raw);
+        }
+        self.check_limits(is_error, replay);
+
+        Ok(())
+    }
+
+    /// Record a response, returning whether it has an error status.
+    fn record_response(
+        &mut self,
+        Completed {
+            index,
+            url,
+            tags,
+            original_status,
+            weight,
+        }: Completed,
+        mut response_details: replay::ResponseDetails,
+        lines: &mut Lines,
+        replay: &Replay,
+        dashboard: Option<&mut tui::Dashboard>,
+    ) -> Result<bool> {
+        let is_success = summary::is_success(response_details.status);
+        self.summary.record(
+            &url,
+            &tags,
+            Some(response_details.status),
+            Some(response_details.required_time),
+            weight,
+        );
+        if is_success {
+            self.summary
+                .record_change(response_details.change_percentage, weight);
+            if let Some(regressions) = &mut self.regressions {
+                response_details.regressed = regressions.record(
+                    url.as_str(),
+                    response_details.original_time,
+                    response_details.change_percentage,
+                );
+            }
+        }
+        if let Some(dashboard) = dashboard {
+            dashboard.record(
+                Some(response_details.status),
+                Some(response_details.required_time),
+            );
+        }
+        replay.events.emit(events::Event::RequestCompleted {
+            url: url.as_str(),
+            status: Some(response_details.status.as_u16()),
+            required_time: Some(response_details.required_time.to_seconds()),
+            completed: self.summary.requests(),
+            errors: self.summary.errors(),
+        });
+        if let Some(status_matches) = &mut self.status_matches {
+            response_details.original_status = original_status;
+            response_details.status_matches =
+                status_matches.record(url.as_str(), original_status, Some(response_details.status));
+        }
+        if let Some(header_violations) = &mut self.header_violations {
+            header_violations.record(url.as_str(), &response_details.header_violations);
+        }
+        response_details.tags = tags;
+        let line = (!self.only_errors || !is_success)
+            .then(|| serde_json::to_vec(&response_details))
+            .transpose()?;
+        lines.write(index, line)?;
+        replay
+            .buffered_bytes
+            .release(response_details.body_size.unwrap_or(0));
+        if let Some(body_diff) = &response_details.baseline_body_diff {
+            self.baseline_bodies
+                .record(&response_details.url, body_diff);
+        }
+        if let Some(shadow) = &response_details.shadow {
+            if let Some(diff) = &shadow.diff {
+                self.shadow_comparison.record(diff);
+                if let Some(body_diff) = &diff.body_diff {
+                    self.shadow_bodies.record(&response_details.url, body_diff);
+                }
+            }
+            if let Some(response) = &shadow.response {
+                replay
+                    .buffered_bytes
+                    .release(response.body_size.unwrap_or(0));
+            }
+        }
+
+        Ok(!is_success)
+    }
+
+    /// Stop the replay if the circuit breaker trips, or the failure reaches `--max-failures`.
+    fn check_limits(&mut self, is_error: bool, replay: &Replay) {
+        if let Some(circuit_breaker) = self
+            .circuit_breaker
+            .as_mut()
+            .filter(|_| self.tripped.is_none())
+        {
+            if let Some(error_rate) = circuit_breaker.record(is_error) {
+                tracing::error!(
+                    "Circuit breaker tripped after {} responses, error rate of the last {} was {:.2}%, no further \
+                     requests will be sent",
+                    self.summary.requests(),
+                    self.error_rate_window,
+                    error_rate
+                );
+                replay.events.emit(events::Event::CircuitBreakerTripped {
+                    completed: self.summary.requests(),
+                    error_rate,
+                });
+                self.tripped = Some((self.summary.requests(), error_rate));
+                replay.stop();
+            }
+        }
+        if let Some(max_failures) = self
+            .max_failures
+            .filter(|_| is_error && self.tripped.is_none() && self.max_failures_reached.is_none())
+        {
+            if self.summary.errors() >= max_failures {
+                tracing::error!(
+                    "Reached {} failures after {} responses, no further requests will be sent",
+                    self.summary.errors(),
+                    self.summary.requests()
+                );
+                replay.events.emit(events::Event::MaxFailuresReached {
+                    completed: self.summary.requests(),
+                    failures: self.summary.errors(),
+                });
+                self.max_failures_reached = Some(self.summary.requests());
+                replay.stop();
+            }
+        }
+    }
+
+    /// Whether the replay was stopped by the circuit breaker or `--max-failures`.
+    pub(crate) fn stopped(&self) -> bool {
+        self.tripped.is_some() || self.max_failures_reached.is_some()
+    }
+
+    /// Write the comparisons against the original requests, the shadow target and the baseline.
+    pub(crate) fn write_comparisons<W: Write>(&self, mut writer: W, run: &Run) -> io::Result<()> {
+        if let Some(regressions) = &self.regressions {
+            regressions.write(&mut writer)?;
+        }
+        if let Some(header_violations) = &self.header_violations {
+            header_violations.write(&mut writer)?;
+        }
+        if let Some(status_matches) = &self.status_matches {
+            status_matches.write(&mut writer)?;
+        }
+        if run.shadow_target.is_some() {
+            self.shadow_comparison.write(&mut writer)?;
+            if run.compare_bodies {
+                self.shadow_bodies.write(&mut writer, "the shadow target")?;
+            }
+        }
+        if run.compare_bodies && run.baseline.is_some() {
+            self.baseline_bodies.write(&mut writer, "the baseline")?;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate and write the assertions, returning how many of them failed.
+    pub(crate) fn write_assertions<W: Write>(
+        &self,
+        mut writer: W,
+        assertions: &[assertion::Assertion],
+    ) -> io::Result<usize> {
+        if assertions.is_empty() {
+            return Ok(0);
+        }
+        writeln!(writer, "Assertions:")?;
+        let mut failed = 0;
+        for assertion in assertions {
+            let outcome = assertion.evaluate(&self.summary);
+            if !outcome.passed {
+                failed += 1;
+            }
+            writeln!(writer, "  {}", outcome)?;
+        }
+
+        Ok(failed)
+    }
+
+    /// Write why the replay was stopped early, if it was, with the number of requests that weren't sent because of
+    /// it. `request_budget` is the budget that was used up, if any.
+    pub(crate) fn write_stop<W: Write>(
+        &self,
+        mut writer: W,
+        request_budget: Option<usize>,
+    ) -> io::Result<()> {
+        if let Some((completed, error_rate)) = self.tripped {
+            writeln!(
+                writer,
+                "Circuit breaker tripped after {} responses at an error rate of {:.2}%, {} requests were not sent",
+                completed, error_rate, self.not_sent
+            )?;
+        }
+        if let Some(completed) = self.max_failures_reached {
+            writeln!(
+                writer,
+                "Stopped at --max-failures after {} responses, {} requests were not sent",
+                completed, self.not_sent
+            )?;
+        }
+        if let Some(request_budget) = request_budget {
+            writeln!(
+                writer,
+                "Used up the request budget of {} requests, {} requests were not sent",
+                request_budget, self.not_sent
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the raw records of the requests that failed to `--failures-out`, if there were any.
+    pub(crate) fn write_failures<W: Write>(&self, mut writer: W, run: &Run) -> Result<()> {
+        if let Some(failures_out) = run
+            .failures_out
+            .as_ref()
+            .filter(|_| !self.failed.is_empty())
+        {
+            remaining::write(&run.input_file, failures_out, self.failed.values())?;
+            writeln!(
+                writer,
+                "Wrote the records of the {} requests that failed to {}",
+                self.failed.len(),
+                failures_out.display()
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Fail if the replay was stopped early, or the responses didn't hold up to the comparisons and assertions;
+    /// `failed_assertions` is the number of assertions that failed.
+    pub(crate) fn check(&self, run: &Run, failed_assertions: usize) -> Result<()> {
+        if self.tripped.is_some() {
+            anyhow::bail!("Aborted by the circuit breaker");
+        }
+        if self.max_failures_reached.is_some() {
+            anyhow::bail!("Aborted after {} failures", self.summary.errors());
+        }
+        if let Some(regressions) = self
+            .regressions
+            .as_ref()
+            .filter(|regressions| run.fail_on_regression && regressions.regressed() > 0)
+        {
+            anyhow::bail!(
+                "{} requests regressed beyond {}%",
+                regressions.regressed(),
+                regressions.threshold()
+            );
+        }
+        if let Some(status_matches) = self
+            .status_matches
+            .as_ref()
+            .filter(|status_matches| status_matches.mismatched() > 0)
+        {
+            anyhow::bail!(
+                "{} requests responded with a different status than originally",
+                status_matches.mismatched()
+            );
+        }
+        if let Some(header_violations) = self
+            .header_violations
+            .as_ref()
+            .filter(|header_violations| header_violations.violating() > 0)
+        {
+            anyhow::bail!(
+                "{} responses violated the expected headers",
+                header_violations.violating()
+            );
+        }
+        if failed_assertions > 0 {
+            anyhow::bail!(
+                "{} of {} assertions failed",
+                failed_assertions,
+                run.assert.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        connections::ConnectionCounter,
+        Cli,
+        Commands,
+    };
+
+    /// Output file of a test, removed once it is dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("r7-results-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn run(output: &TempPath, args: &[&str]) -> Run {
+        let args = [
+            "r7",
+            "run",
+            "--scheme-and-host",
+            "http://localhost",
+            "--output",
+            output.0.to_str().unwrap(),
+        ]
+        .into_iter()
+        .chain(args.iter().copied())
+        .chain(["input.csv"]);
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Run(run) => *run,
+            _ => unreachable!(),
+        }
+    }
+
+    fn replay(run: &Run) -> Replay {
+        run.replay(
+            reqwest::Client::new(),
+            &ConnectionCounter::default(),
+            BTreeMap::new(),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn completed(index: usize) -> Completed {
+        Completed {
+            index,
+            url: Url::parse(&format!("http://localhost/{}", index)).unwrap(),
+            tags: BTreeMap::new(),
+            original_status: Some(200),
+            weight: 1,
+        }
+    }
+
+    fn raw(index: usize) -> Option<RawRecord> {
+        Some(RawRecord::Line(format!("record {}", index)))
+    }
+
+    /// Record a failure of the request with `index` for every outcome, `None` for a request that wasn't sent.
+    fn record(
+        results: &mut Results,
+        lines: &mut Lines,
+        replay: &Replay,
+        outcomes: &[(usize, Option<&str>)],
+    ) {
+        for &(index, error) in outcomes {
+            let result = match error {
+                Some(error) => Err(anyhow::anyhow!(error.to_owned())),
+                None => Ok(None),
+            };
+            results
+                .record(completed(index), result, raw(index), lines, replay, None)
+                .unwrap();
+        }
+    }
+
+    fn output(output: &TempPath) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(&output.0)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn failures_are_written_and_counted() {
+        let path = TempPath::new("failures");
+        let run = run(&path, &[]);
+        let replay = replay(&run);
+        let mut lines = Lines::new(&run, &[Duration::ZERO; 2]).unwrap();
+        let mut results = Results::new(&run);
+        record(
+            &mut results,
+            &mut lines,
+            &replay,
+            &[(0, Some("connection refused")), (1, None)],
+        );
+        lines.finish().await.unwrap();
+
+        assert_eq!(
+            output(&path),
+            [serde_json::json!({
+                "outcome": "request_error",
+                "url": "http://localhost/0",
+                "error": "connection refused",
+            })]
+        );
+        assert_eq!(results.summary.requests(), 1);
+        assert_eq!(results.summary.errors(), 1);
+        assert_eq!(results.not_sent, 1);
+        assert!(!results.stopped());
+        assert!(!*replay.stopping.borrow());
+        assert!(results.failed.is_empty());
+        results.check(&run, 0).unwrap();
+    }
+
+    #[tokio::test]
+    async fn lines_are_written_in_order_with_ordered_output() {
+        let path = TempPath::new("ordered");
+        let run = run(&path, &["--ordered-output"]);
+        let replay = replay(&run);
+        let offsets = [
+            Duration::from_seconds(1.),
+            Duration::ZERO,
+            Duration::from_seconds(2.),
+        ];
+        let mut lines = Lines::new(&run, &offsets).unwrap();
+        let mut results = Results::new(&run);
+        record(
+            &mut results,
+            &mut lines,
+            &replay,
+            &[(2, Some("third")), (0, Some("second")), (1, Some("first"))],
+        );
+        lines.finish().await.unwrap();
+
+        assert_eq!(
+            output(&path)
+                .iter()
+                .map(|line| line["error"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["first", "second", "third"]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_failures_stop_the_replay() {
+        let path = TempPath::new("max-failures");
+        let run = run(&path, &["--max-failures", "2"]);
+        let replay = replay(&run);
+        let mut lines = Lines::new(&run, &[Duration::ZERO; 4]).unwrap();
+        let mut results = Results::new(&run);
+        record(
+            &mut results,
+            &mut lines,
+            &replay,
+            &[(0, Some("timeout")), (1, Some("timeout"))],
+        );
+        assert!(results.stopped());
+        assert!(*replay.stopping.borrow());
+        record(&mut results, &mut lines, &replay, &[(2, None), (3, None)]);
+
+        let mut stop = Vec::new();
+        results.write_stop(&mut stop, None).unwrap();
+        assert_eq!(
+            String::from_utf8(stop).unwrap(),
+            "Stopped at --max-failures after 2 responses, 2 requests were not sent\n"
+        );
+        assert_eq!(
+            results.check(&run, 0).unwrap_err().to_string(),
+            "Aborted after 2 failures"
+        );
+    }
+
+    #[tokio::test]
+    async fn the_circuit_breaker_trips_once_the_window_is_full() {
+        let path = TempPath::new("breaker");
+        let run = run(
+            &path,
+            &["--abort-on-error-rate", "50", "--error-rate-window", "3"],
+        );
+        let replay = replay(&run);
+        let mut lines = Lines::new(&run, &[Duration::ZERO; 3]).unwrap();
+        let mut results = Results::new(&run);
+        record(
+            &mut results,
+            &mut lines,
+            &replay,
+            &[(0, Some("timeout")), (1, Some("timeout"))],
+        );
+        assert!(!results.stopped());
+        record(&mut results, &mut lines, &replay, &[(2, Some("timeout"))]);
+        assert!(results.stopped());
+        assert!(*replay.stopping.borrow());
+
+        let mut stop = Vec::new();
+        results.write_stop(&mut stop, Some(10)).unwrap();
+        assert_eq!(
+            String::from_utf8(stop).unwrap(),
+            "Circuit breaker tripped after 3 responses at an error rate of 100.00%, 0 requests were not sent\n\
+             Used up the request budget of 10 requests, 0 requests were not sent\n"
+        );
+        assert_eq!(
+            results.check(&run, 0).unwrap_err().to_string(),
+            "Aborted by the circuit breaker"
+        );
+    }
+
+    #[tokio::test]
+    async fn records_of_failed_requests_are_kept_for_failures_out() {
+        let path = TempPath::new("failures-out");
+        let run = run(&path, &["--failures-out", "failures.csv"]);
+        let replay = replay(&run);
+        let mut lines = Lines::new(&run, &[Duration::ZERO; 3]).unwrap();
+        let mut results = Results::new(&run);
+        record(
+            &mut results,
+            &mut lines,
+            &replay,
+            &[(0, Some("timeout")), (1, None), (2, Some("reset"))],
+        );
+
+        assert_eq!(results.failed.keys().copied().collect::<Vec<_>>(), [0, 2]);
+    }
+
+    #[test]
+    fn errors_are_described_with_their_root_cause() {
+        let err = anyhow::anyhow!("connection refused").context("error sending request");
+        assert_eq!(
+            describe_error(&err),
+            "error sending request: connection refused"
+        );
+        assert_eq!(describe_error(&anyhow::anyhow!("timeout")), "timeout");
+    }
+}
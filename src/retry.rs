@@ -0,0 +1,166 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{
+        BuildHasher,
+        Hasher,
+    },
+    str::FromStr,
+};
+
+use anyhow::Result;
+use hifitime::Duration;
+use reqwest::Method;
+
+/// Delay of the first retry with exponential backoff, in milliseconds, which doubles with every further retry.
+const EXPONENTIAL_BASE_MILLISECONDS: f64 = 100.;
+
+/// How long to wait between consecutive attempts of the same request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Backoff {
+    /// Retry immediately.
+    None,
+    /// Wait the same duration before every retry.
+    Fixed(Duration),
+    /// Wait 100 ms before the first retry, doubling with every further retry.
+    Exponential,
+}
+
+impl FromStr for Backoff {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Backoff::None),
+            "exponential" => Ok(Backoff::Exponential),
+            _ => match s.strip_prefix("fixed:") {
+                Some(duration) => Ok(Backoff::Fixed(Duration::from_str(duration)?)),
+                None => anyhow::bail!("expected `none`, `fixed:<DURATION>` or `exponential`"),
+            },
+        }
+    }
+}
+
+/// Determines whether and when a failed request is attempted again.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub(crate) retries: usize,
+    pub(crate) backoff: Backoff,
+    pub(crate) max_backoff: Duration,
+    pub(crate) jitter: Option<Duration>,
+    pub(crate) max_retry_duration: Option<Duration>,
+    /// Whether requests with a method that isn't idempotent are retried too.
+    pub(crate) all_methods: bool,
+}
+
+impl RetryPolicy {
+    /// Whether requests with `method` may be retried at all.
+    pub(crate) fn allows(&self, method: &Method) -> bool {
+        self.all_methods || is_idempotent(method)
+    }
+
+    /// Delay before the given retry, starting at 1, or `None` if the request should not be retried anymore.
+    ///
+    /// `elapsed` is the time since the first attempt of the request was sent.
+    pub(crate) fn delay(&self, retry: usize, elapsed: Duration) -> Option<Duration> {
+        if retry > self.retries {
+            return None;
+        }
+
+        let backoff = match self.backoff {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(duration) => duration,
+            Backoff::Exponential => {
+                let exponent = i32::try_from(retry - 1).unwrap_or(i32::MAX);
+                Duration::from_milliseconds(EXPONENTIAL_BASE_MILLISECONDS * 2f64.powi(exponent))
+                    .min(self.max_backoff)
+            }
+        };
+        let jitter = self
+            .jitter
+            .map(|jitter| jitter * random_fraction())
+            .unwrap_or_default();
+        let delay = backoff + jitter;
+
+        match self.max_retry_duration {
+            Some(max_retry_duration) if elapsed + delay > max_retry_duration => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+/// Whether a failed request is worth retrying: everything but responses with a client error status is.
+pub(crate) fn is_retryable(err: &reqwest::Error) -> bool {
+    !err.status().is_some_and(|status| status.is_client_error())
+}
+
+/// Whether sending a request with `method` more than once has the same effect as sending it once (RFC 9110, section
+/// 9.2.2), which makes retrying it safe.
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Random number in `[0, 1)`, good enough to spread retries but not for anything that needs to be unpredictable.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idempotent_methods() {
+        for (method, idempotent) in [
+            (Method::GET, true),
+            (Method::HEAD, true),
+            (Method::PUT, true),
+            (Method::DELETE, true),
+            (Method::OPTIONS, true),
+            (Method::TRACE, true),
+            (Method::POST, false),
+            (Method::PATCH, false),
+            (Method::CONNECT, false),
+        ] {
+            assert_eq!(is_idempotent(&method), idempotent, "{}", method);
+        }
+    }
+
+    #[test]
+    fn policy_allows_methods() {
+        let mut policy = RetryPolicy {
+            retries: 1,
+            backoff: Backoff::None,
+            max_backoff: Duration::from_seconds(1.),
+            jitter: None,
+            max_retry_duration: None,
+            all_methods: false,
+        };
+        assert!(policy.allows(&Method::GET));
+        assert!(!policy.allows(&Method::POST));
+        assert!(!policy.allows(&Method::PATCH));
+
+        policy.all_methods = true;
+        assert!(policy.allows(&Method::POST));
+        assert!(policy.allows(&Method::PATCH));
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use reqwest::Method;
+
+/// Method that requests with an unsafe method are sent as with `--safe-mode`, or every request with
+/// `--override-method`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SafeMethod {
+    Get,
+    Head,
+    Options,
+}
+
+impl SafeMethod {
+    /// The method to send a request with the given method as, which is unchanged if it is already safe.
+    pub(crate) fn neutralize(self, method: Method) -> Method {
+        if is_safe(&method) {
+            return method;
+        }
+        self.method()
+    }
+
+    pub(crate) fn method(self) -> Method {
+        match self {
+            SafeMethod::Get => Method::GET,
+            SafeMethod::Head => Method::HEAD,
+            SafeMethod::Options => Method::OPTIONS,
+        }
+    }
+}
+
+/// Whether the method is safe as defined by RFC 9110, i.e. doesn't request any change on the target.
+fn is_safe(method: &Method) -> bool {
+    [Method::GET, Method::HEAD, Method::OPTIONS, Method::TRACE].contains(method)
+}
@@ -0,0 +1,479 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    str::FromStr,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::{
+    Duration,
+    Epoch,
+};
+use reqwest::{
+    Method,
+    Url,
+};
+
+/// End of a segment of the time-factor schedule, in the time of the original log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Until {
+    /// Fraction of the time between the start of the replay and the last record.
+    Fraction(f64),
+    Time(Epoch),
+}
+
+/// Segment of the replay that runs at its own time factor, in the format `<until>=<factor>`.
+///
+/// A segment starts where the previous one ends, or at the start of the replay, and ends at a percentage of the
+/// replay, e.g. `40%=1`, or at a point in time as RFC3339, e.g. `2024-06-01T12:30:00Z=0.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TimeFactorSegment {
+    until: Until,
+    factor: f64,
+}
+
+impl FromStr for TimeFactorSegment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((until, factor)) = s.rsplit_once('=') else {
+            anyhow::bail!("expected `<until>=<factor>`");
+        };
+        let until = match until.strip_suffix('%') {
+            Some(percentage) => {
+                let percentage = percentage
+                    .trim()
+                    .parse::<f64>()
+                    .with_context(|| format!("invalid percentage `{}`", until))?;
+                if !(0. ..=100.).contains(&percentage) {
+                    anyhow::bail!("percentage must be between 0% and 100%");
+                }
+                Until::Fraction(percentage / 100.)
+            }
+            None => Until::Time(
+                Epoch::from_str(until.trim())
+                    .with_context(|| format!("invalid point in time `{}`", until))?,
+            ),
+        };
+        let factor = factor
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("invalid factor `{}`", factor))?;
+        if factor < 0. || !factor.is_finite() {
+            anyhow::bail!("factor must not be negative");
+        }
+
+        Ok(Self { until, factor })
+    }
+}
+
+/// Time factors for consecutive segments of the replay, resolved to offsets from its start.
+#[derive(Debug)]
+pub(crate) struct Schedule {
+    /// End of every segment, as the offset from the start in the time of the original log, and its factor.
+    segments: Vec<(Duration, f64)>,
+    /// Factor after the last segment.
+    factor: f64,
+}
+
+impl Schedule {
+    /// Resolve the segments for a replay from `start` to `end`, with `factor` applying after the last segment.
+    pub(crate) fn new(
+        segments: &[TimeFactorSegment],
+        start: Epoch,
+        end: Epoch,
+        factor: f64,
+    ) -> Result<Self> {
+        let span = end - start;
+        let mut previous = Duration::ZERO;
+        let segments = segments
+            .iter()
+            .map(|segment| {
+                let until = match segment.until {
+                    Until::Fraction(fraction) => span * fraction,
+                    Until::Time(time) => time - start,
+                };
+                if until < previous {
+                    anyhow::bail!(
+                        "--time-factor-schedule segments must be in ascending order, a segment ends at {} after the \
+                         start but the one before it ends at {}",
+                        until,
+                        previous
+                    );
+                }
+                previous = until;
+                Ok((until, segment.factor))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { segments, factor })
+    }
+
+    /// Offset in the replay for the given offset in the original log, scaled by the factors of all segments it
+    /// spans.
+    pub(crate) fn scale(&self, offset: Duration) -> Duration {
+        let mut scaled = Duration::ZERO;
+        let mut start = Duration::ZERO;
+        for &(until, factor) in &self.segments {
+            if offset <= until {
+                return scaled + (offset - start) * factor;
+            }
+            scaled += (until - start) * factor;
+            start = until;
+        }
+
+        scaled + (offset - start.min(offset)) * self.factor
+    }
+}
+
+/// What to do with requests whose time of day has already passed today, with `--preserve-time-of-day`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum PastTimeOfDay {
+    /// Send them immediately.
+    Immediately,
+    /// Send them at their time of day tomorrow.
+    Tomorrow,
+}
+
+/// Offset from `now` to the next occurrence of the time of day of `timestamp`, both in UTC.
+///
+/// Returns `None` if the time of day has already passed today and `past` is `Immediately`.
+pub(crate) fn time_of_day_offset(
+    timestamp: Epoch,
+    now: Epoch,
+    past: PastTimeOfDay,
+) -> Option<Duration> {
+    let (_, _, _, hour, minute, second, nanos) = timestamp.to_gregorian_utc();
+    let (year, month, day, _, _, _, _) = now.to_gregorian_utc();
+    let today = Epoch::from_gregorian_utc(year, month, day, hour, minute, second, nanos);
+    if today >= now {
+        return Some(today - now);
+    }
+    match past {
+        PastTimeOfDay::Immediately => None,
+        PastTimeOfDay::Tomorrow => Some(today + Duration::from_days(1.) - now),
+    }
+}
+
+/// Spread the offsets below `window` evenly across it, keeping their order, and return how many were spread.
+///
+/// All of them keep being sent before any later request, since they all end up before `window`.
+pub(crate) fn spread_start<'a>(
+    offsets: impl IntoIterator<Item = &'a mut Duration>,
+    window: Duration,
+) -> usize {
+    let mut early: Vec<&mut Duration> = offsets
+        .into_iter()
+        .filter(|offset| **offset < window)
+        .collect();
+    early.sort_by_key(|offset| **offset);
+    let count = early.len();
+    for (index, offset) in early.into_iter().enumerate() {
+        *offset = window * (index as f64 / count as f64);
+    }
+    count
+}
+
+/// Offset of a request with the rate ramped up linearly from zero to the full rate over the first `window` of the
+/// replay.
+///
+/// This compresses the first half of the window of original time into the whole window, after which every request
+/// keeps its original spacing, delayed by half the window.
+pub(crate) fn soft_start(offset: Duration, window: Duration) -> Duration {
+    if offset * 2 < window {
+        Duration::from_seconds((2. * window.to_seconds() * offset.to_seconds()).sqrt())
+    } else {
+        offset + window * 0.5
+    }
+}
+
+/// Predecessors of the requests, given by when they started and how long they took originally, so that sending
+/// every request only after its predecessor completed reproduces the original concurrency, alongside the highest
+/// concurrency.
+///
+/// The requests are assigned to as few lanes as possible, each of which only ever had one request in flight: a
+/// request continues the lane whose last request completed first, if that happened before it started, and opens a
+/// new lane otherwise. The number of lanes is the highest number of requests that were in flight at the same time.
+pub(crate) fn concurrency_lanes(requests: &[(Duration, Duration)]) -> (Vec<Option<usize>>, usize) {
+    let mut indices: Vec<usize> = (0..requests.len()).collect();
+    indices.sort_by_key(|&index| requests[index].0);
+    let mut predecessors = vec![None; requests.len()];
+    // The end of the last request of every lane, and its index, the lane that became free first on top.
+    let mut lanes = BinaryHeap::new();
+    for index in indices {
+        let (start, duration) = requests[index];
+        if let Some(Reverse((end, previous))) = lanes.peek().copied() {
+            if end <= start {
+                lanes.pop();
+                predecessors[index] = Some(previous);
+            }
+        }
+        lanes.push(Reverse((start + duration, index)));
+    }
+    let concurrency = lanes.len();
+    (predecessors, concurrency)
+}
+
+/// Fingerprint of a schedule, to verify that the same input and options result in the same replay.
+///
+/// The fingerprint is the 64-bit FNV-1a hash, as 16 hexadecimal digits, of the offset in nanoseconds, the method and
+/// the URL of every request, in the order they are given, separated by spaces and each followed by a newline.
+pub(crate) fn fingerprint<'a>(
+    requests: impl IntoIterator<Item = (Duration, &'a Method, &'a Url)>,
+) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = requests
+        .into_iter()
+        .fold(OFFSET_BASIS, |hash, (offset, method, url)| {
+            let input = format!("{} {} {}\n", offset.total_nanoseconds(), method, url);
+            input.bytes().fold(hash, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+            })
+        });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: f64) -> Duration {
+        Duration::from_seconds(seconds)
+    }
+
+    fn segments(s: &str) -> Result<Vec<TimeFactorSegment>> {
+        s.split(',').map(str::parse).collect()
+    }
+
+    /// Schedule for a replay of 100 s in the time of the original log.
+    fn schedule(s: &str, factor: f64) -> Result<Schedule> {
+        let start = Epoch::from_gregorian_utc_at_midnight(2024, 6, 1);
+        Schedule::new(&segments(s)?, start, start + seconds(100.), factor)
+    }
+
+    #[test]
+    fn segments_parse() {
+        assert_eq!(
+            segments("40%=1,60%=0.5").unwrap(),
+            [
+                TimeFactorSegment {
+                    until: Until::Fraction(0.4),
+                    factor: 1.,
+                },
+                TimeFactorSegment {
+                    until: Until::Fraction(0.6),
+                    factor: 0.5,
+                },
+            ]
+        );
+        assert_eq!(
+            "2024-06-01T12:30:00Z = 2"
+                .parse::<TimeFactorSegment>()
+                .unwrap(),
+            TimeFactorSegment {
+                until: Until::Time(Epoch::from_gregorian_utc_hms(2024, 6, 1, 12, 30, 0)),
+                factor: 2.,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        for (s, error) in [
+            ("40%", "expected `<until>=<factor>`"),
+            ("forty%=1", "invalid percentage `forty%`"),
+            ("120%=1", "percentage must be between 0% and 100%"),
+            ("-10%=1", "percentage must be between 0% and 100%"),
+            ("yesterday=1", "invalid point in time `yesterday`"),
+            ("40%=fast", "invalid factor `fast`"),
+            ("40%=-1", "factor must not be negative"),
+            ("40%=inf", "factor must not be negative"),
+        ] {
+            assert_eq!(
+                s.parse::<TimeFactorSegment>().unwrap_err().to_string(),
+                error,
+                "{s}"
+            );
+        }
+    }
+
+    #[test]
+    fn segments_must_ascend() {
+        assert_eq!(
+            schedule("60%=1,40%=0.5", 1.).unwrap_err().to_string(),
+            "--time-factor-schedule segments must be in ascending order, a segment ends at 40 s after the start but \
+             the one before it ends at 1 min"
+        );
+        assert_eq!(
+            schedule("2024-06-01T00:01:00Z=1,50%=2", 1.).unwrap_err().to_string(),
+            "--time-factor-schedule segments must be in ascending order, a segment ends at 50 s after the start but \
+             the one before it ends at 1 min"
+        );
+        assert!(schedule("40%=1,40%=2", 1.).is_ok());
+    }
+
+    #[test]
+    fn scale_across_segments() {
+        let schedule = schedule("40%=1,60%=0.5", 2.).unwrap();
+        assert_eq!(schedule.scale(seconds(0.)), seconds(0.));
+        assert_eq!(schedule.scale(seconds(20.)), seconds(20.));
+        // The boundaries belong to the segment they end.
+        assert_eq!(schedule.scale(seconds(40.)), seconds(40.));
+        assert_eq!(schedule.scale(seconds(50.)), seconds(45.));
+        assert_eq!(schedule.scale(seconds(60.)), seconds(50.));
+        // After the last segment, the factor of the replay applies.
+        assert_eq!(schedule.scale(seconds(70.)), seconds(70.));
+        assert_eq!(schedule.scale(seconds(100.)), seconds(130.));
+    }
+
+    #[test]
+    fn scale_with_absolute_times() {
+        let schedule = schedule("2024-06-01T00:00:10Z=0,50%=3", 1.).unwrap();
+        assert_eq!(schedule.scale(seconds(5.)), seconds(0.));
+        assert_eq!(schedule.scale(seconds(10.)), seconds(0.));
+        assert_eq!(schedule.scale(seconds(20.)), seconds(30.));
+        assert_eq!(schedule.scale(seconds(60.)), seconds(130.));
+    }
+
+    #[test]
+    fn time_of_day() {
+        let now = Epoch::from_gregorian_utc_hms(2024, 6, 1, 12, 0, 0);
+        let later = Epoch::from_gregorian_utc_hms(2023, 1, 15, 13, 30, 0);
+        let earlier = Epoch::from_gregorian_utc_hms(2023, 1, 15, 11, 0, 0);
+        for past in [PastTimeOfDay::Immediately, PastTimeOfDay::Tomorrow] {
+            assert_eq!(time_of_day_offset(later, now, past), Some(seconds(5400.)));
+            assert_eq!(time_of_day_offset(now, now, past), Some(Duration::ZERO));
+        }
+        assert_eq!(
+            time_of_day_offset(earlier, now, PastTimeOfDay::Immediately),
+            None
+        );
+        assert_eq!(
+            time_of_day_offset(earlier, now, PastTimeOfDay::Tomorrow),
+            Some(seconds(23. * 3600.))
+        );
+    }
+
+    #[test]
+    fn spread_start_keeps_order() {
+        let mut offsets = [
+            seconds(0.),
+            seconds(1.),
+            seconds(0.),
+            seconds(5.),
+            seconds(0.5),
+        ];
+        assert_eq!(spread_start(&mut offsets, seconds(2.)), 4);
+        assert_eq!(
+            offsets,
+            [
+                seconds(0.),
+                seconds(1.5),
+                seconds(0.5),
+                seconds(5.),
+                seconds(1.)
+            ]
+        );
+    }
+
+    #[test]
+    fn soft_start_ramps_up() {
+        let window = seconds(10.);
+        assert_eq!(soft_start(seconds(0.), window), seconds(0.));
+        // Half the window of original time is stretched to the whole window.
+        assert_eq!(soft_start(seconds(5.), window), seconds(10.));
+        assert_eq!(soft_start(seconds(20.), window), seconds(25.));
+        // The spacing of the requests grows towards the full rate.
+        let first = soft_start(seconds(1.), window) - soft_start(seconds(0.), window);
+        let last = soft_start(seconds(5.), window) - soft_start(seconds(4.), window);
+        assert!(first > last);
+        assert!(last >= seconds(1.));
+    }
+
+    #[test]
+    fn concurrency_lanes_reproduce_overlap() {
+        // Requests as (start, duration): 0 and 1 overlap, 2 starts when 0 ends, 3 overlaps all others, and 4 starts
+        // once everything before it is done.
+        let requests = [
+            (seconds(0.), seconds(2.)),
+            (seconds(1.), seconds(3.)),
+            (seconds(2.), seconds(1.)),
+            (seconds(1.5), seconds(2.)),
+            (seconds(10.), seconds(1.)),
+        ];
+        let (predecessors, concurrency) = concurrency_lanes(&requests);
+        assert_eq!(concurrency, 3);
+        assert_eq!(predecessors, [None, None, Some(0), None, Some(2)]);
+    }
+
+    #[test]
+    fn concurrency_lanes_have_one_successor_per_predecessor() {
+        // All of these start after the first one ended, but only one of them can continue its lane.
+        let requests = [
+            (seconds(0.), seconds(1.)),
+            (seconds(2.), seconds(1.)),
+            (seconds(2.), seconds(1.)),
+            (seconds(2.), seconds(1.)),
+        ];
+        let (predecessors, concurrency) = concurrency_lanes(&requests);
+        assert_eq!(concurrency, 3);
+        assert_eq!(predecessors, [None, Some(0), None, None]);
+
+        let requests: Vec<_> = (0..100)
+            .map(|index| {
+                (
+                    seconds(index as f64 * 0.3 % 7.),
+                    seconds(index as f64 % 3. + 0.5),
+                )
+            })
+            .collect();
+        let (predecessors, _) = concurrency_lanes(&requests);
+        let mut successors = vec![0; requests.len()];
+        for (index, predecessor) in predecessors.iter().enumerate() {
+            if let Some(predecessor) = *predecessor {
+                successors[predecessor] += 1;
+                let (start, duration) = requests[predecessor];
+                assert!(start + duration <= requests[index].0);
+            }
+        }
+        assert!(successors.iter().all(|&successors| successors <= 1));
+    }
+
+    #[test]
+    fn fingerprint_is_fnv1a() {
+        let get = Method::GET;
+        let post = Method::POST;
+        let a = Url::parse("https://www.example.com/").unwrap();
+        let b = Url::parse("https://www.example.com/a?b=1").unwrap();
+        assert_eq!(
+            fingerprint([(seconds(0.), &get, &a), (seconds(1.), &post, &b)]),
+            "a919fc04fbd1d0a4"
+        );
+        assert_eq!(fingerprint([]), "cbf29ce484222325");
+        assert_ne!(
+            fingerprint([(seconds(1.), &post, &b), (seconds(0.), &get, &a)]),
+            "a919fc04fbd1d0a4"
+        );
+    }
+}
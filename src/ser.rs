@@ -35,3 +35,7 @@ where
 {
     serializer.serialize_f64(value.to_seconds())
 }
+
+pub(crate) fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
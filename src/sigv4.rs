@@ -0,0 +1,558 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signing requests with AWS Signature Version 4, with `--sigv4`, for targets behind e.g. API Gateway with IAM
+//! authorization.
+//!
+//! Every attempt of a request is signed right before it is sent, as the signature covers the time of the request.
+
+use std::{
+    fs::File,
+    path::Path,
+    str::FromStr,
+    time::SystemTime,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use aws_credential_types::Credentials;
+use aws_sigv4::{
+    http_request::{
+        PayloadChecksumKind,
+        PercentEncodingMode,
+        SignableBody,
+        SignableRequest,
+        SigningSettings,
+        UriPathNormalizationMode,
+    },
+    sign::v4,
+};
+use reqwest::{
+    header::{
+        HeaderName,
+        HeaderValue,
+    },
+    Client,
+    Request,
+};
+use serde::Deserialize;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use tokio::sync::Mutex;
+
+/// Endpoint of the instance metadata service, unless overridden by `AWS_EC2_METADATA_SERVICE_ENDPOINT`.
+const INSTANCE_METADATA_ENDPOINT: &str = "http://169.254.169.254";
+/// How long before they expire the credentials of the instance profile are fetched anew.
+const REFRESH_BEFORE_EXPIRY: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Region and service requests are signed for, in the format `region:service`, e.g. `eu-central-1:execute-api`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Scope {
+    region: String,
+    service: String,
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((region, service)) = s.split_once(':') else {
+            anyhow::bail!("expected `region:service`, e.g. `eu-central-1:execute-api`");
+        };
+        if region.is_empty() || service.is_empty() {
+            anyhow::bail!("neither the region nor the service must be empty");
+        }
+
+        Ok(Self {
+            region: region.to_owned(),
+            service: service.to_owned(),
+        })
+    }
+}
+
+impl Scope {
+    /// How requests to the service are signed: S3 signs paths as they are sent, and the hash of the body in the
+    /// `x-amz-content-sha256` header, while every other service signs normalized, doubly encoded paths.
+    fn settings(&self) -> SigningSettings {
+        let mut settings = SigningSettings::default();
+        if self.service == "s3" {
+            settings.percent_encoding_mode = PercentEncodingMode::Single;
+            settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+            settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+        }
+        settings
+    }
+}
+
+/// Credentials of an instance profile, as returned by the instance metadata service.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InstanceProfileCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: String,
+}
+
+/// Signs requests with the credentials from the environment or the instance profile.
+#[derive(Debug)]
+pub(crate) struct Signer {
+    scope: Scope,
+    credentials: Mutex<Credentials>,
+    /// Client to fetch the credentials of the instance profile with, when they come from there.
+    instance_metadata: Option<Client>,
+}
+
+impl Signer {
+    /// Signer with the credentials from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`, or,
+    /// if these aren't set, with those of the instance profile.
+    pub(crate) async fn new(scope: Scope) -> Result<Self> {
+        if let Some(credentials) = credentials_from_environment()? {
+            tracing::info!(
+                "Signing requests for {} in {} with the credentials from the environment",
+                scope.service,
+                scope.region
+            );
+            return Ok(Self {
+                scope,
+                credentials: Mutex::new(credentials),
+                instance_metadata: None,
+            });
+        }
+
+        let client = Client::builder()
+            .no_proxy()
+            .connect_timeout(std::time::Duration::from_secs(1))
+            .timeout(std::time::Duration::from_secs(5))
+            .build()?;
+        let credentials = credentials_from_instance_profile(&client).await.context(
+            "No AWS credentials for --sigv4: AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY aren't set, and the \
+             credentials of the instance profile couldn't be fetched",
+        )?;
+        tracing::info!(
+            "Signing requests for {} in {} with the credentials of the instance profile",
+            scope.service,
+            scope.region
+        );
+        Ok(Self {
+            scope,
+            credentials: Mutex::new(credentials),
+            instance_metadata: Some(client),
+        })
+    }
+
+    /// Sign the request, whose body is streamed from `body_file` if given.
+    pub(crate) async fn sign(&self, request: &mut Request, body_file: Option<&Path>) -> Result<()> {
+        let payload_hash = match body_file {
+            Some(body_file) => {
+                let body_file = body_file.to_owned();
+                Some(tokio::task::spawn_blocking(move || file_hash(&body_file)).await??)
+            }
+            None => None,
+        };
+        let credentials = self.credentials().await?;
+        sign(
+            request,
+            &credentials,
+            &self.scope,
+            self.scope.settings(),
+            SystemTime::now(),
+            payload_hash,
+        )
+    }
+
+    /// Current credentials, fetching those of the instance profile anew shortly before they expire.
+    async fn credentials(&self) -> Result<Credentials> {
+        let mut credentials = self.credentials.lock().await;
+        if let (Some(client), Some(expiry)) = (&self.instance_metadata, credentials.expiry()) {
+            if expiry < SystemTime::now() + REFRESH_BEFORE_EXPIRY {
+                *credentials = credentials_from_instance_profile(client)
+                    .await
+                    .context("Failed to refresh the credentials of the instance profile")?;
+                tracing::debug!("Refreshed the credentials of the instance profile");
+            }
+        }
+        Ok(credentials.clone())
+    }
+}
+
+/// Sign the request at the given time, with the hash of its body if it is streamed from a file.
+///
+/// Every header the request has so far is signed, including its `Host` header if it is set, while headers that are
+/// only added when it is sent, like `Accept`, aren't.
+fn sign(
+    request: &mut Request,
+    credentials: &Credentials,
+    scope: &Scope,
+    settings: SigningSettings,
+    time: SystemTime,
+    payload_hash: Option<String>,
+) -> Result<()> {
+    let identity = credentials.clone().into();
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(&scope.region)
+        .name(&scope.service)
+        .time(time)
+        .settings(settings)
+        .build()?
+        .into();
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = value.to_str().with_context(|| {
+                format!("Can't sign the `{}` header, its value isn't ASCII", name)
+            })?;
+            Ok((name.as_str(), value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let body = match payload_hash {
+        Some(payload_hash) => SignableBody::Precomputed(payload_hash),
+        None => SignableBody::Bytes(
+            request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .unwrap_or_default(),
+        ),
+    };
+    let signable = SignableRequest::new(
+        request.method().as_str(),
+        request.url().as_str(),
+        headers.into_iter(),
+        body,
+    )?;
+    let (instructions, _) = aws_sigv4::http_request::sign(signable, &params)?.into_parts();
+
+    let (headers, _) = instructions.into_parts();
+    for header in headers {
+        let mut value = HeaderValue::from_str(header.value())?;
+        // The signature and the session token stay hidden from the logs.
+        value.set_sensitive(
+            header.sensitive()
+                || header.name() == "authorization"
+                || header.name() == "x-amz-security-token",
+        );
+        request
+            .headers_mut()
+            .insert(HeaderName::from_static(header.name()), value);
+    }
+    Ok(())
+}
+
+fn credentials_from_environment() -> Result<Option<Credentials>> {
+    let variable = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    match (
+        variable("AWS_ACCESS_KEY_ID"),
+        variable("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        (Some(access_key_id), Some(secret_access_key)) => Ok(Some(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            variable("AWS_SESSION_TOKEN"),
+            None,
+            "environment",
+        ))),
+        (None, None) => Ok(None),
+        (Some(_), None) => {
+            anyhow::bail!("AWS_ACCESS_KEY_ID is set, but AWS_SECRET_ACCESS_KEY isn't")
+        }
+        (None, Some(_)) => {
+            anyhow::bail!("AWS_SECRET_ACCESS_KEY is set, but AWS_ACCESS_KEY_ID isn't")
+        }
+    }
+}
+
+/// Fetch the credentials of the instance profile, through version 2 of the instance metadata service.
+async fn credentials_from_instance_profile(client: &Client) -> Result<Credentials> {
+    let endpoint = std::env::var("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+        .unwrap_or_else(|_| INSTANCE_METADATA_ENDPOINT.to_owned());
+    let endpoint = endpoint.trim_end_matches('/');
+    let token = client
+        .put(format!("{}/latest/api/token", endpoint))
+        .header("x-aws-ec2-metadata-token-ttl-seconds", "21600")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let get = |path: String| {
+        let request = client
+            .get(format!(
+                "{}/latest/meta-data/iam/security-credentials/{}",
+                endpoint, path
+            ))
+            .header("x-aws-ec2-metadata-token", &token);
+        async move { request.send().await?.error_for_status()?.bytes().await }
+    };
+    let roles = get(String::new()).await?;
+    let role = String::from_utf8_lossy(&roles)
+        .lines()
+        .next()
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| anyhow::anyhow!("the instance has no instance profile"))?;
+    let credentials = serde_json::from_slice::<InstanceProfileCredentials>(&get(role).await?)
+        .context("unexpected response of the instance metadata service")?;
+
+    let expiration = credentials
+        .expiration
+        .parse::<jiff::Timestamp>()
+        .with_context(|| {
+            format!(
+                "invalid expiration of the instance profile credentials `{}`",
+                credentials.expiration
+            )
+        })?;
+
+    Ok(Credentials::new(
+        credentials.access_key_id,
+        credentials.secret_access_key,
+        Some(credentials.token),
+        Some(SystemTime::from(expiration)),
+        "instance-profile",
+    ))
+}
+
+/// SHA-256 of a body file, as hexadecimal digits, read in chunks rather than into memory.
+fn file_hash(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open body file {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{
+        AUTHORIZATION,
+        CONTENT_LENGTH,
+        CONTENT_TYPE,
+    };
+
+    use super::*;
+
+    /// Time of the requests of the AWS SigV4 test suite, `2015-08-30T12:36:00Z`.
+    fn time() -> SystemTime {
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_440_938_160)
+    }
+
+    fn credentials(session_token: Option<&str>) -> Credentials {
+        Credentials::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            session_token.map(ToOwned::to_owned),
+            None,
+            "test",
+        )
+    }
+
+    fn scope() -> Scope {
+        "us-east-1:service".parse().unwrap()
+    }
+
+    fn authorization(request: &Request) -> &str {
+        request.headers()[AUTHORIZATION].to_str().unwrap()
+    }
+
+    fn get(url: &str) -> Request {
+        Client::new().get(url).build().unwrap()
+    }
+
+    #[test]
+    fn scope_parses() {
+        assert_eq!(
+            "eu-central-1:execute-api".parse::<Scope>().unwrap(),
+            Scope {
+                region: "eu-central-1".to_owned(),
+                service: "execute-api".to_owned(),
+            }
+        );
+        assert!("eu-central-1".parse::<Scope>().is_err());
+        assert!(":execute-api".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn get_vanilla() {
+        let mut request = get("https://example.amazonaws.com/");
+        sign(
+            &mut request,
+            &credentials(None),
+            &scope(),
+            scope().settings(),
+            time(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(request.headers()["x-amz-date"], "20150830T123600Z");
+        assert_eq!(
+            authorization(&request),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+        assert!(request.headers()[AUTHORIZATION].is_sensitive());
+    }
+
+    #[test]
+    fn get_vanilla_query_order_key_case() {
+        let mut request = get("https://example.amazonaws.com/?Param2=value2&Param1=value1");
+        sign(
+            &mut request,
+            &credentials(None),
+            &scope(),
+            scope().settings(),
+            time(),
+            None,
+        )
+        .unwrap();
+
+        assert!(authorization(&request).ends_with(
+            "Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        ));
+    }
+
+    #[test]
+    fn get_utf8() {
+        // The test suite encodes the path once, which the URL already is, while other services than S3 would encode
+        // it again.
+        let mut settings = scope().settings();
+        settings.percent_encoding_mode = PercentEncodingMode::Single;
+        let mut request = get("https://example.amazonaws.com/ሴ");
+        sign(
+            &mut request,
+            &credentials(None),
+            &scope(),
+            settings,
+            time(),
+            None,
+        )
+        .unwrap();
+
+        assert!(authorization(&request).ends_with(
+            "Signature=8318018e0b0f223aa2bbf98705b62bb787dc9c0e678f255a891fd03141be5d85"
+        ));
+    }
+
+    #[test]
+    fn post_x_www_form_urlencoded() {
+        // The test suite signs the hash of the body in `x-amz-content-sha256`, like requests to S3 are.
+        let mut settings = scope().settings();
+        settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+        let mut request = Client::new()
+            .post("https://example.amazonaws.com/")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(CONTENT_LENGTH, "13")
+            .body("Param1=value1")
+            .build()
+            .unwrap();
+        sign(
+            &mut request,
+            &credentials(None),
+            &scope(),
+            settings,
+            time(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.headers()["x-amz-content-sha256"],
+            "9095672bbd1f56dfc5b65f3e153adc8731a4a654192329106275f4c7b24d0b6e"
+        );
+        assert_eq!(
+            authorization(&request),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=content-length;content-type;host;x-amz-content-sha256;x-amz-date, \
+             Signature=d3875051da38690788ef43de4db0d8f280229d82040bfac253562e56c3f20e0b"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_with_session_token() {
+        let token = "6e86291e8372ff2a2260956d9b8aae1d763fbf315fa00fa31553b73ebf194267";
+        let mut request = get("https://example.amazonaws.com/");
+        sign(
+            &mut request,
+            &credentials(Some(token)),
+            &scope(),
+            scope().settings(),
+            time(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(request.headers()["x-amz-security-token"], token);
+        assert!(request.headers()["x-amz-security-token"].is_sensitive());
+        assert!(authorization(&request).ends_with(
+            "SignedHeaders=host;x-amz-date;x-amz-security-token, \
+             Signature=07ec1639c89043aa0e3e2de82b96708f198cceab042d4a97044c66dd9f74e7f8"
+        ));
+    }
+
+    #[test]
+    fn body_file_hash_is_signed() {
+        // The hash of the empty body, which signs the same as get-vanilla.
+        let mut request = get("https://example.amazonaws.com/");
+        sign(
+            &mut request,
+            &credentials(None),
+            &scope(),
+            scope().settings(),
+            time(),
+            Some("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_owned()),
+        )
+        .unwrap();
+
+        assert!(authorization(&request).ends_with(
+            "Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        ));
+    }
+
+    #[test]
+    fn s3_signs_the_payload_hash() {
+        let scope = "us-east-1:s3".parse::<Scope>().unwrap();
+        let mut request = get("https://examplebucket.s3.amazonaws.com/a%20b");
+        sign(
+            &mut request,
+            &credentials(None),
+            &scope,
+            scope.settings(),
+            time(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.headers()["x-amz-content-sha256"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert!(
+            authorization(&request).contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date")
+        );
+    }
+}
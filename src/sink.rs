@@ -0,0 +1,344 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Streams the lines written to the output to an HTTP collector or a Kafka topic, with `--sink`.
+
+use std::{
+    mem,
+    time::Duration,
+};
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use reqwest::{
+    header,
+    Url,
+};
+use rskafka::{
+    chrono::DateTime,
+    client::{
+        partition::{
+            Compression,
+            PartitionClient,
+            UnknownTopicHandling,
+        },
+        ClientBuilder,
+    },
+    record::Record,
+    BackoffConfig,
+};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{
+        self,
+        MissedTickBehavior,
+    },
+};
+
+/// Lines sent to the collector at most in one batch.
+const BATCH_SIZE: usize = 500;
+/// Interval after which a batch that isn't full is sent anyway.
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// Lines held while the collector is busy with previous batches. Lines beyond this are dropped, instead of holding
+/// back the replay.
+const CAPACITY: usize = 10 * BATCH_SIZE;
+/// Time after which sending a batch is given up.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Port of the Kafka broker if the URL doesn't have one.
+const KAFKA_PORT: u16 = 9092;
+
+/// Where the sink sends to, e.g. `https://collector.example.com/ingest` or `kafka://broker:9092/topic`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SinkUrl {
+    Http(Url),
+    Kafka {
+        /// The broker the rest of the cluster is discovered from.
+        broker: String,
+        topic: String,
+        partition: i32,
+    },
+}
+
+impl std::str::FromStr for SinkUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let url = Url::parse(s)?;
+        match url.scheme() {
+            "http" | "https" => Ok(SinkUrl::Http(url)),
+            "kafka" => {
+                let host = url
+                    .host_str()
+                    .context("The Kafka sink needs a broker, e.g. kafka://broker:9092/topic")?;
+                let topic = url.path().trim_start_matches('/');
+                if topic.is_empty() || topic.contains('/') {
+                    bail!("The Kafka sink needs a single topic as its path, e.g. kafka://broker:9092/topic");
+                }
+                let mut partition = 0;
+                for (key, value) in url.query_pairs() {
+                    match &*key {
+                        "partition" => {
+                            partition = value
+                                .parse()
+                                .with_context(|| format!("Invalid Kafka partition '{value}'"))?;
+                        }
+                        _ => bail!("Unsupported Kafka sink parameter '{key}', expected partition"),
+                    }
+                }
+                Ok(SinkUrl::Kafka {
+                    broker: format!("{host}:{}", url.port().unwrap_or(KAFKA_PORT)),
+                    topic: topic.to_owned(),
+                    partition,
+                })
+            }
+            scheme => bail!("Unsupported sink scheme '{scheme}', expected http, https or kafka"),
+        }
+    }
+}
+
+/// Sends lines in batches from a background task: as newline-delimited JSON to an HTTP collector, or as one record
+/// per line to a Kafka topic.
+///
+/// Failing to send a batch is logged, but doesn't affect the replay.
+pub(crate) struct Sink {
+    sender: mpsc::Sender<Vec<u8>>,
+    task: JoinHandle<()>,
+    dropped: usize,
+}
+
+impl Sink {
+    pub(crate) fn new(url: SinkUrl) -> Result<Self> {
+        let destination = match url {
+            SinkUrl::Http(url) => Destination::Http {
+                client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+                url,
+            },
+            SinkUrl::Kafka {
+                broker,
+                topic,
+                partition,
+            } => Destination::Kafka {
+                broker,
+                topic,
+                partition,
+                client: None,
+            },
+        };
+        let (sender, receiver) = mpsc::channel(CAPACITY);
+        let task = tokio::spawn(run(destination, receiver));
+        Ok(Sink {
+            sender,
+            task,
+            dropped: 0,
+        })
+    }
+
+    /// Queue a line, or drop it if the collector can't keep up.
+    pub(crate) fn send(&mut self, line: &[u8]) {
+        if self.sender.try_send(line.to_vec()).is_err() {
+            if self.dropped == 0 {
+                tracing::warn!("The sink can't keep up, dropping lines until it catches up");
+            }
+            self.dropped += 1;
+        }
+    }
+
+    /// Send the lines that are still queued, and wait for the collector to receive them.
+    pub(crate) async fn finish(self) {
+        drop(self.sender);
+        if let Err(err) = self.task.await {
+            tracing::warn!("The sink failed: {err}");
+        }
+        if self.dropped > 0 {
+            tracing::warn!(
+                "Dropped {} lines that the sink couldn't keep up with",
+                self.dropped
+            );
+        }
+    }
+}
+
+async fn run(mut destination: Destination, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    let mut interval = time::interval(BATCH_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut batch = Vec::new();
+    loop {
+        let closed = tokio::select! {
+            line = receiver.recv() => match line {
+                Some(line) => {
+                    batch.push(line);
+                    if batch.len() < BATCH_SIZE {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = interval.tick() => false,
+        };
+        if !batch.is_empty() {
+            let lines = batch.len();
+            if let Err(err) = destination.send(mem::take(&mut batch)).await {
+                tracing::warn!("Failed to send {lines} lines to the sink: {err:#}");
+            }
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+enum Destination {
+    Http {
+        client: reqwest::Client,
+        url: Url,
+    },
+    Kafka {
+        broker: String,
+        topic: String,
+        partition: i32,
+        /// Connected on the first batch, and again after a batch failed.
+        client: Option<PartitionClient>,
+    },
+}
+
+impl Destination {
+    async fn send(&mut self, batch: Vec<Vec<u8>>) -> Result<()> {
+        match self {
+            Destination::Http { client, url } => {
+                let mut body = Vec::new();
+                for line in batch {
+                    body.extend_from_slice(&line);
+                    body.push(b'\n');
+                }
+                client
+                    .post(url.clone())
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)?;
+            }
+            Destination::Kafka {
+                broker,
+                topic,
+                partition,
+                client,
+            } => {
+                let records = batch
+                    .into_iter()
+                    .map(|line| Record {
+                        key: None,
+                        value: Some(line),
+                        headers: Default::default(),
+                        timestamp: DateTime::from_timestamp_millis(
+                            jiff::Timestamp::now().as_millisecond(),
+                        )
+                        .unwrap_or_default(),
+                    })
+                    .collect();
+                let result = async {
+                    let partition_client = match client {
+                        Some(partition_client) => partition_client,
+                        None => client.insert(connect(broker, topic, *partition).await?),
+                    };
+                    partition_client
+                        .produce(records, Compression::NoCompression)
+                        .await
+                        .context("Failed to produce to Kafka")
+                };
+                if let Err(err) = time::timeout(TIMEOUT, result)
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out producing to Kafka")))
+                {
+                    *client = None;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn connect(broker: &str, topic: &str, partition: i32) -> Result<PartitionClient> {
+    let client = ClientBuilder::new(vec![broker.to_owned()])
+        .backoff_config(BackoffConfig {
+            deadline: Some(TIMEOUT),
+            ..Default::default()
+        })
+        .build()
+        .await
+        .with_context(|| format!("Failed to connect to the Kafka broker {broker}"))?;
+    client
+        .partition_client(topic, partition, UnknownTopicHandling::Error)
+        .await
+        .with_context(|| {
+            format!("Failed to find partition {partition} of the Kafka topic '{topic}'")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_urls_parse() {
+        let url: SinkUrl = "https://collector.example.com/ingest".parse().unwrap();
+        assert_eq!(
+            url,
+            SinkUrl::Http(Url::parse("https://collector.example.com/ingest").unwrap())
+        );
+    }
+
+    #[test]
+    fn kafka_urls_parse() {
+        assert_eq!(
+            "kafka://broker:9093/responses".parse::<SinkUrl>().unwrap(),
+            SinkUrl::Kafka {
+                broker: "broker:9093".to_owned(),
+                topic: "responses".to_owned(),
+                partition: 0,
+            }
+        );
+        assert_eq!(
+            "kafka://broker/responses?partition=3"
+                .parse::<SinkUrl>()
+                .unwrap(),
+            SinkUrl::Kafka {
+                broker: "broker:9092".to_owned(),
+                topic: "responses".to_owned(),
+                partition: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_urls_are_rejected() {
+        for url in [
+            "kafka://broker:9092",
+            "kafka://broker:9092/a/b",
+            "kafka://broker:9092/responses?partition=x",
+            "kafka://broker:9092/responses?key=id",
+            "ftp://collector.example.com/ingest",
+        ] {
+            assert!(url.parse::<SinkUrl>().is_err(), "{url}");
+        }
+    }
+}
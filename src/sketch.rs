@@ -0,0 +1,215 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Quantile sketch for the latency percentiles of the summary, which needs bounded memory however many requests are
+//! replayed.
+//!
+//! This is a DDSketch (Masson et al., "DDSketch: A Fast and Fully-Mergeable Quantile Sketch with Relative-Error
+//! Guarantees", 2019): values are counted in buckets whose bounds grow exponentially, so that every value in a bucket
+//! is within `RELATIVE_ACCURACY` of the value the bucket stands for. Latencies between a microsecond and an hour fit
+//! into about 1100 buckets.
+
+use std::collections::BTreeMap;
+
+/// Maximum error of an estimated percentile relative to the actual value.
+pub(crate) const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Values up to this small are counted as zero, as their logarithm isn't meaningful.
+const MIN_VALUE: f64 = 1e-9;
+
+#[derive(Debug)]
+pub(crate) struct Sketch {
+    /// Ratio between the upper and the lower bound of every bucket.
+    gamma: f64,
+    /// Number of values in every bucket, by the index of the bucket, `ceil(log_gamma(value))`.
+    buckets: BTreeMap<i32, u64>,
+    zeros: u64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Sketch {
+    fn default() -> Self {
+        Self {
+            gamma: (1. + RELATIVE_ACCURACY) / (1. - RELATIVE_ACCURACY),
+            buckets: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Sketch {
+    /// Add a non-negative value, e.g. a latency in seconds, `count` times.
+    pub(crate) fn add(&mut self, value: f64, count: u64) {
+        if !value.is_finite() {
+            return;
+        }
+        if value <= MIN_VALUE {
+            self.zeros += count;
+        } else {
+            *self.buckets.entry(self.index(value)).or_default() += count;
+        }
+        self.count += count;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Nearest-rank percentile of the values added so far, within `RELATIVE_ACCURACY` of the actual one.
+    pub(crate) fn percentile(&self, percentile: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100. * self.count as f64).ceil() as u64).clamp(1, self.count);
+        if rank <= self.zeros.max(1) {
+            return Some(self.min);
+        }
+        if rank == self.count {
+            return Some(self.max);
+        }
+        let mut seen = self.zeros;
+        for (index, count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                // The extremes are known exactly, and no estimate can be beyond them.
+                return Some(self.value(*index).clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    fn index(&self, value: f64) -> i32 {
+        (value.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    /// Value the bucket stands for, which has the same relative distance to both of its bounds.
+    fn value(&self, index: i32) -> f64 {
+        2. * self.gamma.powi(index) / (self.gamma + 1.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nearest-rank percentile of sorted values.
+    fn exact(sorted: &[f64], percentile: f64) -> f64 {
+        let rank =
+            ((percentile / 100. * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+        sorted[rank - 1]
+    }
+
+    fn assert_within_accuracy(estimate: f64, exact: f64) {
+        assert!(
+            (estimate - exact).abs() <= RELATIVE_ACCURACY * exact,
+            "{estimate} isn't within {RELATIVE_ACCURACY} of {exact}"
+        );
+    }
+
+    #[test]
+    fn empty_sketch_has_no_percentiles() {
+        assert_eq!(Sketch::default().percentile(50.), None);
+    }
+
+    #[test]
+    fn percentiles_are_within_relative_accuracy() {
+        // Latencies from 10 µs to about 20 s, spread unevenly by a linear congruential generator.
+        let mut state = 42u64;
+        let mut values = Vec::new();
+        for _ in 0..10_000 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let uniform = (state >> 11) as f64 / (1u64 << 53) as f64;
+            values.push(1e-5 * 2e6f64.powf(uniform * uniform));
+        }
+        let mut sketch = Sketch::default();
+        for value in &values {
+            sketch.add(*value, 1);
+        }
+        values.sort_by(f64::total_cmp);
+        for percentile in [1., 10., 50., 90., 95., 99., 99.9] {
+            assert_within_accuracy(
+                sketch.percentile(percentile).unwrap(),
+                exact(&values, percentile),
+            );
+        }
+    }
+
+    #[test]
+    fn extremes_are_exact() {
+        let mut sketch = Sketch::default();
+        for value in [0.0123, 0.5, 1.234, 7.89] {
+            sketch.add(value, 1);
+        }
+        assert_eq!(sketch.percentile(0.), Some(0.0123));
+        assert_eq!(sketch.percentile(100.), Some(7.89));
+        // A single value is both extremes.
+        let mut sketch = Sketch::default();
+        sketch.add(0.4567, 1);
+        assert_eq!(sketch.percentile(50.), Some(0.4567));
+    }
+
+    #[test]
+    fn zeros_and_tiny_values() {
+        let mut sketch = Sketch::default();
+        sketch.add(0., 3);
+        sketch.add(MIN_VALUE, 1);
+        sketch.add(1., 6);
+        assert_eq!(sketch.percentile(10.), Some(0.));
+        assert_eq!(sketch.percentile(40.), Some(0.));
+        assert_within_accuracy(sketch.percentile(50.).unwrap(), 1.);
+        assert_eq!(sketch.percentile(100.), Some(1.));
+
+        let mut sketch = Sketch::default();
+        sketch.add(0., 1);
+        assert_eq!(sketch.percentile(50.), Some(0.));
+        assert_eq!(sketch.percentile(100.), Some(0.));
+    }
+
+    #[test]
+    fn non_finite_values_are_ignored() {
+        let mut sketch = Sketch::default();
+        sketch.add(f64::NAN, 1);
+        sketch.add(f64::INFINITY, 1);
+        assert_eq!(sketch.percentile(50.), None);
+    }
+
+    #[test]
+    fn counts_weight_values() {
+        let mut weighted = Sketch::default();
+        weighted.add(0.1, 90);
+        weighted.add(2., 10);
+        let mut repeated = Sketch::default();
+        for _ in 0..90 {
+            repeated.add(0.1, 1);
+        }
+        for _ in 0..10 {
+            repeated.add(2., 1);
+        }
+        for percentile in [50., 90., 91., 99.] {
+            assert_eq!(
+                weighted.percentile(percentile),
+                repeated.percentile(percentile)
+            );
+        }
+        assert_within_accuracy(weighted.percentile(90.).unwrap(), 0.1);
+        assert_within_accuracy(weighted.percentile(91.).unwrap(), 2.);
+    }
+}
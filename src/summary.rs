@@ -0,0 +1,840 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    str::FromStr,
+};
+
+use clap::ValueEnum;
+use hifitime::Duration;
+use reqwest::{
+    StatusCode,
+    Url,
+};
+use serde::Serialize;
+
+use crate::{
+    interleave,
+    normalize::PathNormalizer,
+    replay::Diff,
+    sketch::Sketch,
+};
+
+/// Percentiles that are reported for every set of statistics.
+const PERCENTILES: [f64; 4] = [50., 90., 95., 99.];
+
+/// Version of the format of `RunSummary`, incremented whenever it changes incompatibly.
+const SCHEMA_VERSION: u32 = 2;
+
+/// Dimension by which the statistics of a run are broken down, in addition to the overall statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum GroupBy {
+    /// Host (and port) the request was sent to.
+    Host,
+    /// First segment of the request path, e.g. `/api`.
+    PathPrefix,
+    /// Full request path, without the query, e.g. `/api/users/:id` with `--normalize-paths`.
+    Path,
+    /// Status code of the response.
+    Status,
+    /// Values of the `--tag-column` fields of the record, e.g. `experiment=a, tenant=b`.
+    Tags,
+    /// Input file the record was read from, with `--interleave`.
+    Stream,
+}
+
+impl GroupBy {
+    fn key(
+        self,
+        url: &Url,
+        tags: &BTreeMap<String, String>,
+        status: Option<StatusCode>,
+        normalizer: Option<&PathNormalizer>,
+    ) -> String {
+        let path = || match normalizer {
+            Some(normalizer) => normalizer.normalize(url.path()),
+            None => url.path().to_owned(),
+        };
+        match self {
+            GroupBy::Host => match (url.host_str(), url.port()) {
+                (Some(host), Some(port)) => format!("{}:{}", host, port),
+                (Some(host), None) => host.to_owned(),
+                (None, _) => "-".to_owned(),
+            },
+            GroupBy::PathPrefix => {
+                let path = path();
+                let first_segment = path.trim_start_matches('/').split('/').next();
+                format!("/{}", first_segment.unwrap_or_default())
+            }
+            GroupBy::Path => path(),
+            GroupBy::Status => status
+                .map(|status| status.as_u16().to_string())
+                .unwrap_or_else(|| "error".to_owned()),
+            GroupBy::Tags if tags.is_empty() => "-".to_owned(),
+            GroupBy::Tags => tags
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(", "),
+            GroupBy::Stream => tags
+                .get(interleave::STREAM_TAG)
+                .cloned()
+                .unwrap_or_else(|| "-".to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupBy::Host => f.write_str("host"),
+            GroupBy::PathPrefix => f.write_str("path-prefix"),
+            GroupBy::Path => f.write_str("path"),
+            GroupBy::Status => f.write_str("status"),
+            GroupBy::Tags => f.write_str("tags"),
+            GroupBy::Stream => f.write_str("stream"),
+        }
+    }
+}
+
+/// Outcome of a request, as the `outcome` of every line of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    /// The target responded with a successful status, see `is_success`.
+    Success,
+    /// The target responded with an error status.
+    HttpError,
+    /// Establishing the connection took longer than `--connect-timeout`.
+    ConnectTimeout,
+    /// The request took longer than `--request-timeout`.
+    Timeout,
+    /// The connection couldn't be established, e.g. because it was refused.
+    ConnectError,
+    /// The request couldn't be built, e.g. because of an invalid header.
+    BuildError,
+    /// The request failed otherwise, e.g. because the connection was closed before the response was received.
+    RequestError,
+}
+
+impl Outcome {
+    /// Outcome of a request that got a response with this status.
+    pub(crate) fn of_status(status: StatusCode) -> Self {
+        if is_success(status) {
+            Outcome::Success
+        } else {
+            Outcome::HttpError
+        }
+    }
+
+    /// Outcome of a request that failed with this error.
+    pub(crate) fn of_error(err: &anyhow::Error) -> Self {
+        let Some(err) = err.downcast_ref::<reqwest::Error>() else {
+            return Outcome::RequestError;
+        };
+        if err.status().is_some() {
+            Outcome::HttpError
+        } else if err.is_builder() {
+            Outcome::BuildError
+        } else if err.is_connect() && err.is_timeout() {
+            Outcome::ConnectTimeout
+        } else if err.is_timeout() {
+            Outcome::Timeout
+        } else if err.is_connect() {
+            Outcome::ConnectError
+        } else {
+            Outcome::RequestError
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Success => f.write_str("success"),
+            Outcome::HttpError => f.write_str("http_error"),
+            Outcome::ConnectTimeout => f.write_str("connect_timeout"),
+            Outcome::Timeout => f.write_str("timeout"),
+            Outcome::ConnectError => f.write_str("connect_error"),
+            Outcome::BuildError => f.write_str("build_error"),
+            Outcome::RequestError => f.write_str("request_error"),
+        }
+    }
+}
+
+/// Whether a request with this response status succeeded.
+///
+/// Besides the successful (2xx) statuses, this includes `101 Switching Protocols`, the expected response to the
+/// WebSocket handshakes of `--websocket handshake`.
+pub(crate) fn is_success(status: StatusCode) -> bool {
+    status.is_success() || status == StatusCode::SWITCHING_PROTOCOLS
+}
+
+/// Nearest-rank percentile of the given, ascendingly sorted values.
+pub(crate) fn percentile(sorted: &[f64], percentile: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percentile / 100. * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Latencies of the successful requests, in seconds, to compute their percentiles from.
+#[derive(Debug)]
+enum Latencies {
+    /// Every latency, with `--exact-percentiles`.
+    Exact(Vec<f64>),
+    /// Estimates the percentiles in bounded memory.
+    Sketch(Sketch),
+}
+
+impl Latencies {
+    fn new(exact: bool) -> Self {
+        if exact {
+            Latencies::Exact(Vec::new())
+        } else {
+            Latencies::Sketch(Sketch::default())
+        }
+    }
+
+    /// Add a latency, `count` times.
+    fn add(&mut self, latency: f64, count: u64) {
+        match self {
+            Latencies::Exact(latencies) => {
+                latencies.extend(std::iter::repeat_n(latency, count as usize))
+            }
+            Latencies::Sketch(sketch) => sketch.add(latency, count),
+        }
+    }
+
+    /// Pairs of the given percentiles and their latency, leaving out those that can't be computed yet.
+    fn percentiles(&self, percentiles: &[f64]) -> Vec<(f64, f64)> {
+        match self {
+            Latencies::Exact(latencies) => {
+                let mut sorted = latencies.clone();
+                sorted.sort_by(f64::total_cmp);
+                percentiles
+                    .iter()
+                    .filter_map(|p| Some((*p, percentile(&sorted, *p)?)))
+                    .collect()
+            }
+            Latencies::Sketch(sketch) => percentiles
+                .iter()
+                .filter_map(|p| Some((*p, sketch.percentile(*p)?)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stats {
+    requests: usize,
+    errors: usize,
+    latencies: Latencies,
+    /// Sum and number of the latencies of the successful requests, in seconds.
+    latency_sum: f64,
+    latency_count: usize,
+}
+
+impl Stats {
+    fn new(exact_percentiles: bool) -> Self {
+        Self {
+            requests: 0,
+            errors: 0,
+            latencies: Latencies::new(exact_percentiles),
+            latency_sum: 0.,
+            latency_count: 0,
+        }
+    }
+
+    /// Record a response, or a request that failed without one, as `weight` requests.
+    fn record(&mut self, status: Option<StatusCode>, required_time: Option<Duration>, weight: u64) {
+        self.requests += weight as usize;
+        if !status.is_some_and(is_success) {
+            self.errors += weight as usize;
+        } else if let Some(required_time) = required_time {
+            let latency = required_time.to_seconds();
+            self.latencies.add(latency, weight);
+            self.latency_sum += latency * weight as f64;
+            self.latency_count += weight as usize;
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.
+        } else {
+            self.errors as f64 / self.requests as f64 * 100.
+        }
+    }
+
+    /// The reported percentiles of the latencies, as pairs of the percentile and the latency in seconds.
+    fn percentiles(&self) -> Vec<(f64, f64)> {
+        self.latencies.percentiles(&PERCENTILES)
+    }
+
+    fn to_summary(&self) -> StatsSummary {
+        StatsSummary {
+            requests: self.requests,
+            errors: self.errors,
+            error_rate: self.error_rate(),
+            latency: self
+                .percentiles()
+                .into_iter()
+                .map(|(p, value)| (format!("p{}", p), value))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "requests={} errors={} ({:.2}%)",
+            self.requests,
+            self.errors,
+            self.error_rate()
+        )?;
+
+        for (p, value) in self.percentiles() {
+            write!(f, " p{}={}", p, Duration::from_seconds(value).approx())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Aggregate statistics over all requests of a run, optionally broken down into groups.
+#[derive(Debug)]
+pub(crate) struct Summary {
+    group_by: Option<GroupBy>,
+    /// Normalizes the paths used as keys of the groups, if requested.
+    normalizer: Option<PathNormalizer>,
+    /// Whether percentiles are computed from every latency rather than estimated, with `--exact-percentiles`.
+    exact_percentiles: bool,
+    overall: Stats,
+    groups: BTreeMap<String, Stats>,
+    /// Number of requests that failed without a response, by their outcome.
+    failures: BTreeMap<Outcome, usize>,
+    /// Changes of the latencies of the successful requests compared to the original ones, summed up in percent.
+    change_sum: f64,
+    changes: usize,
+    /// Number of requests sent, counting every attempt, of connections attempted for them, and of connections that
+    /// were opened.
+    connections: Option<(usize, usize, usize)>,
+}
+
+impl Summary {
+    pub(crate) fn new(
+        group_by: Option<GroupBy>,
+        normalizer: Option<PathNormalizer>,
+        exact_percentiles: bool,
+    ) -> Self {
+        Self {
+            group_by,
+            normalizer,
+            exact_percentiles,
+            overall: Stats::new(exact_percentiles),
+            groups: BTreeMap::new(),
+            failures: BTreeMap::new(),
+            change_sum: 0.,
+            changes: 0,
+            connections: None,
+        }
+    }
+
+    /// Record how many attempts of requests were sent over how many connections, once the run is over.
+    ///
+    /// Attempts for which a connection couldn't be established don't count as reusing one.
+    pub(crate) fn record_connections(&mut self, attempts: usize, attempted: usize, opened: usize) {
+        self.connections = Some((attempts, attempted, opened));
+    }
+
+    /// Percentage of the attempts that reused a connection rather than opening a new one.
+    fn connection_reuse_rate(&self) -> Option<f64> {
+        let (attempts, attempted, _) = self.connections?;
+        (attempts > 0).then(|| attempts.saturating_sub(attempted) as f64 / attempts as f64 * 100.)
+    }
+
+    /// Record the outcome of a request as `weight` requests, with `--weight-mode scale`, or as one. Requests without a
+    /// status failed before receiving a response.
+    pub(crate) fn record(
+        &mut self,
+        url: &Url,
+        tags: &BTreeMap<String, String>,
+        status: Option<StatusCode>,
+        required_time: Option<Duration>,
+        weight: u64,
+    ) {
+        self.overall.record(status, required_time, weight);
+        if let Some(group_by) = self.group_by {
+            self.groups
+                .entry(group_by.key(url, tags, status, self.normalizer.as_ref()))
+                .or_insert_with(|| Stats::new(self.exact_percentiles))
+                .record(status, required_time, weight);
+        }
+    }
+
+    /// Record the outcome of a failed request as `weight` requests, which only counts it by its outcome if it got no
+    /// response.
+    pub(crate) fn record_failure(&mut self, outcome: Outcome, weight: u64) {
+        if outcome != Outcome::HttpError {
+            *self.failures.entry(outcome).or_default() += weight as usize;
+        }
+    }
+
+    /// Record the change of the latency of a successful request compared to the original one, in percent, as
+    /// `weight` requests.
+    ///
+    /// Changes that aren't finite, because the original request took no time at all, are ignored.
+    pub(crate) fn record_change(&mut self, change_percentage: f64, weight: u64) {
+        if change_percentage.is_finite() {
+            self.change_sum += change_percentage * weight as f64;
+            self.changes += weight as usize;
+        }
+    }
+
+    /// Number of requests recorded so far.
+    pub(crate) fn requests(&self) -> usize {
+        self.overall.requests
+    }
+
+    /// Number of failed requests recorded so far.
+    pub(crate) fn errors(&self) -> usize {
+        self.overall.errors
+    }
+
+    /// Percentage of the requests that failed.
+    pub(crate) fn error_rate(&self) -> f64 {
+        self.overall.error_rate()
+    }
+
+    /// Percentile of the latencies of the successful requests, in seconds.
+    pub(crate) fn latency_percentile(&self, p: f64) -> Option<f64> {
+        self.overall
+            .latencies
+            .percentiles(&[p])
+            .first()
+            .map(|(_, latency)| *latency)
+    }
+
+    /// Mean of the latencies of the successful requests, in seconds.
+    pub(crate) fn mean_latency(&self) -> Option<f64> {
+        let overall = &self.overall;
+        (overall.latency_count > 0).then(|| overall.latency_sum / overall.latency_count as f64)
+    }
+
+    /// Mean change of the latencies of the successful requests compared to the original ones, in percent.
+    pub(crate) fn mean_change(&self) -> Option<f64> {
+        (self.changes > 0).then(|| self.change_sum / self.changes as f64)
+    }
+
+    /// Machine-readable form of the summary, `complete` being whether the run finished without being aborted.
+    pub(crate) fn to_run_summary(&self, complete: bool) -> RunSummary {
+        RunSummary {
+            schema_version: SCHEMA_VERSION,
+            complete,
+            overall: self.overall.to_summary(),
+            mean_change: self.mean_change(),
+            connections_opened: self.connections.map(|(_, _, opened)| opened),
+            connection_reuse_rate: self.connection_reuse_rate(),
+            failures: self
+                .failures
+                .iter()
+                .map(|(outcome, count)| (outcome.to_string(), *count))
+                .collect(),
+            group_by: self.group_by.map(|group_by| group_by.to_string()),
+            groups: self
+                .groups
+                .iter()
+                .map(|(key, stats)| (key.clone(), stats.to_summary()))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "Summary: {}", self.overall)?;
+        if !self.failures.is_empty() {
+            write!(writer, "Failures without response:")?;
+            for (outcome, count) in &self.failures {
+                write!(writer, " {}={}", outcome, count)?;
+            }
+            writeln!(writer)?;
+        }
+        if let (Some((attempts, _, opened)), Some(reuse_rate)) =
+            (self.connections, self.connection_reuse_rate())
+        {
+            writeln!(
+                writer,
+                "Connections: opened={} for {} requests, reused by {:.2}%",
+                opened, attempts, reuse_rate
+            )?;
+        }
+        if let Some(group_by) = self.group_by {
+            writeln!(writer, "By {}:", group_by)?;
+            for (key, stats) in &self.groups {
+                writeln!(writer, "  {}: {}", key, stats)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Statistics of a set of requests, as written by `--stats-out`.
+#[derive(Debug, Serialize)]
+pub(crate) struct StatsSummary {
+    requests: usize,
+    errors: usize,
+    /// Percentage of the requests that failed.
+    error_rate: f64,
+    /// Percentiles of the latencies of the successful requests in seconds, by their name, e.g. `p99`.
+    latency: BTreeMap<String, f64>,
+}
+
+/// Summary of a run, as written by `--stats-out`.
+#[derive(Debug, Serialize)]
+pub(crate) struct RunSummary {
+    schema_version: u32,
+    /// Whether the run finished, rather than being aborted with CTRL-C or by the circuit breaker.
+    complete: bool,
+    overall: StatsSummary,
+    /// Mean change of the latencies of the successful requests compared to the original ones, in percent.
+    mean_change: Option<f64>,
+    /// Number of connections opened to send the requests.
+    connections_opened: Option<usize>,
+    /// Percentage of the requests, counting every attempt, that reused a connection rather than opening a new one.
+    connection_reuse_rate: Option<f64>,
+    /// Number of requests that failed without a response, by their outcome.
+    failures: BTreeMap<String, usize>,
+    group_by: Option<String>,
+    groups: BTreeMap<String, StatsSummary>,
+}
+
+/// Number of the status mismatches that are listed in the summary.
+const LISTED_MISMATCHES: usize = 10;
+
+/// Statuses that are considered equivalent when comparing them to the original status, in the format `301,302`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StatusGroup(BTreeSet<u16>);
+
+impl FromStr for StatusGroup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let statuses = s
+            .split(',')
+            .map(|status| {
+                let status = status.trim();
+                status
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|status| (100..=599).contains(status))
+                    .ok_or_else(|| anyhow::anyhow!("invalid status `{}`", status))
+            })
+            .collect::<anyhow::Result<BTreeSet<_>>>()?;
+        if statuses.len() < 2 {
+            anyhow::bail!("expected at least two statuses, e.g. `301,302`");
+        }
+
+        Ok(Self(statuses))
+    }
+}
+
+/// Comparison of the statuses of the responses to those of the original responses, with `--assert-status-match`.
+#[derive(Debug, Default)]
+pub(crate) struct StatusMatches {
+    /// Groups of statuses that match each other, with `--status-equivalent`.
+    equivalent: Vec<StatusGroup>,
+    compared: usize,
+    without_original: usize,
+    mismatched: usize,
+    /// The first mismatches, as the original status, the status of the response, if there was one, and the URL.
+    listed: Vec<(u16, Option<StatusCode>, String)>,
+}
+
+impl StatusMatches {
+    pub(crate) fn new(equivalent: Vec<StatusGroup>) -> Self {
+        Self {
+            equivalent,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the status matches the original status, either exactly or by being in a group with it.
+    fn matches(&self, original_status: u16, status: StatusCode) -> bool {
+        let status = status.as_u16();
+        status == original_status
+            || self.equivalent.iter().any(|StatusGroup(group)| {
+                group.contains(&status) && group.contains(&original_status)
+            })
+    }
+
+    /// Record the status of a response, `None` if the request got none, returning whether it matches the original
+    /// status. Requests without an original status are skipped, and `None` is returned for them.
+    pub(crate) fn record(
+        &mut self,
+        url: &str,
+        original_status: Option<u16>,
+        status: Option<StatusCode>,
+    ) -> Option<bool> {
+        let Some(original_status) = original_status else {
+            self.without_original += 1;
+            return None;
+        };
+        self.compared += 1;
+        let matches = status.is_some_and(|status| self.matches(original_status, status));
+        if !matches {
+            self.mismatched += 1;
+            if self.listed.len() < LISTED_MISMATCHES {
+                self.listed.push((original_status, status, url.to_owned()));
+            }
+        }
+        Some(matches)
+    }
+
+    /// Number of responses whose status didn't match so far.
+    pub(crate) fn mismatched(&self) -> usize {
+        self.mismatched
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(
+            writer,
+            "Status mismatches: {} of {} requests",
+            self.mismatched, self.compared
+        )?;
+        if self.without_original > 0 {
+            write!(
+                writer,
+                ", {} requests without an original status were skipped",
+                self.without_original
+            )?;
+        }
+        writeln!(writer)?;
+        for (original_status, status, url) in &self.listed {
+            match status {
+                Some(status) => writeln!(
+                    writer,
+                    "  {} instead of {}: {}",
+                    status.as_u16(),
+                    original_status,
+                    url
+                )?,
+                None => writeln!(
+                    writer,
+                    "  no response instead of {}: {}",
+                    original_status, url
+                )?,
+            }
+        }
+        if self.mismatched > self.listed.len() {
+            writeln!(writer, "  and {} more", self.mismatched - self.listed.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of URLs that are listed as examples for every header of `--expect-header`.
+const EXAMPLES_PER_HEADER: usize = 3;
+
+/// Responses that violated the expectations of `--expect-header`, by header.
+#[derive(Debug, Default)]
+pub(crate) struct HeaderViolations {
+    checked: usize,
+    violating: usize,
+    /// Number of violations and the first URLs that violated it, by header name.
+    by_header: BTreeMap<String, (usize, Vec<String>)>,
+}
+
+impl HeaderViolations {
+    /// Record the headers a response violated the expectations for, none if it met all of them.
+    pub(crate) fn record(&mut self, url: &str, violations: &[String]) {
+        self.checked += 1;
+        if violations.is_empty() {
+            return;
+        }
+        self.violating += 1;
+        for header in violations {
+            let (count, examples) = self.by_header.entry(header.clone()).or_default();
+            *count += 1;
+            if examples.len() < EXAMPLES_PER_HEADER {
+                examples.push(url.to_owned());
+            }
+        }
+    }
+
+    /// Number of responses that violated any of the expectations so far.
+    pub(crate) fn violating(&self) -> usize {
+        self.violating
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Header violations: {} of {} responses",
+            self.violating, self.checked
+        )?;
+        for (header, (count, examples)) in &self.by_header {
+            writeln!(writer, "  {}: {} responses, e.g.", header, count)?;
+            for url in examples {
+                writeln!(writer, "    {}", url)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of the worst regressions that are listed in the summary.
+const WORST_REGRESSIONS: usize = 10;
+
+/// Successful requests whose latency regressed beyond a threshold compared to their original latency.
+#[derive(Debug)]
+pub(crate) struct Regressions {
+    /// Change of the latency, in percent, beyond which a request counts as regressed.
+    threshold: f64,
+    compared: usize,
+    without_original: usize,
+    regressed: usize,
+    /// The worst regressions, as pairs of the change in percent and the URL, the worst first.
+    worst: Vec<(f64, String)>,
+}
+
+impl Regressions {
+    pub(crate) fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            compared: 0,
+            without_original: 0,
+            regressed: 0,
+            worst: Vec::new(),
+        }
+    }
+
+    /// Record a successful request, returning whether it regressed.
+    ///
+    /// Requests without an original latency, i.e. one that isn't positive like the `-1` AWS Application Load Balancer
+    /// logs for requests that never reached a target, are skipped.
+    pub(crate) fn record(&mut self, url: &str, original_time: f64, change_percentage: f64) -> bool {
+        if original_time <= 0. || !change_percentage.is_finite() {
+            self.without_original += 1;
+            return false;
+        }
+        self.compared += 1;
+        if change_percentage <= self.threshold {
+            return false;
+        }
+
+        self.regressed += 1;
+        let position = self
+            .worst
+            .partition_point(|(worst, _)| *worst >= change_percentage);
+        if position < WORST_REGRESSIONS {
+            self.worst
+                .insert(position, (change_percentage, url.to_owned()));
+            self.worst.truncate(WORST_REGRESSIONS);
+        }
+        true
+    }
+
+    /// Number of requests that regressed so far.
+    pub(crate) fn regressed(&self) -> usize {
+        self.regressed
+    }
+
+    pub(crate) fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(
+            writer,
+            "Regressions beyond {}%: {} of {} requests",
+            self.threshold, self.regressed, self.compared
+        )?;
+        if self.without_original > 0 {
+            write!(
+                writer,
+                ", {} requests without an original latency were skipped",
+                self.without_original
+            )?;
+        }
+        writeln!(writer)?;
+        for (change_percentage, url) in &self.worst {
+            writeln!(writer, "  {:+.2}%: {}", change_percentage, url)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How the responses of the `--shadow-target` compare to those of the primary target.
+#[derive(Debug, Default)]
+pub(crate) struct ShadowComparison {
+    compared: usize,
+    status_mismatches: usize,
+    body_mismatches: usize,
+    /// Sum and number of the latency differences, in seconds, of the requests the shadow target responded to.
+    latency_difference_sum: f64,
+    latency_differences: usize,
+}
+
+impl ShadowComparison {
+    pub(crate) fn record(&mut self, diff: &Diff) {
+        self.compared += 1;
+        if !diff.status_matches {
+            self.status_mismatches += 1;
+        }
+        if diff.body_matches == Some(false) {
+            self.body_mismatches += 1;
+        }
+        if let Some(latency_difference) = diff.latency_difference {
+            self.latency_difference_sum += latency_difference;
+            self.latency_differences += 1;
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(
+            writer,
+            "Shadow: compared={} status_mismatches={} body_mismatches={}",
+            self.compared, self.status_mismatches, self.body_mismatches
+        )?;
+        if self.latency_differences > 0 {
+            write!(
+                writer,
+                " mean_latency_difference={}",
+                Duration::from_seconds(
+                    self.latency_difference_sum / self.latency_differences as f64
+                )
+                .approx()
+            )?;
+        }
+        writeln!(writer)
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use tokio::time::Instant;
+
+/// Longest time between two requests a limiter allows, which keeps the slots it hands out within what `Instant` can
+/// represent.
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Token bucket with a capacity of one token, refilled at a fixed rate.
+///
+/// Every caller reserves the next free slot and waits for it, so requests queue up in the order they arrive instead
+/// of being dropped when the rate is exceeded.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+    throttled: AtomicUsize,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing the given number of requests per second.
+    ///
+    /// Fails if the rate is below one request per day, as slower rates would just stall the replay.
+    pub(crate) fn new(requests_per_second: f64) -> Result<Self> {
+        let interval = Duration::try_from_secs_f64(1. / requests_per_second)
+            .ok()
+            .filter(|interval| *interval <= MAX_INTERVAL)
+            .with_context(|| {
+                format!(
+                    "rate of {:e} requests per second is below the minimum of one request per day",
+                    requests_per_second
+                )
+            })?;
+
+        Ok(Self {
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+            throttled: AtomicUsize::new(0),
+        })
+    }
+
+    /// Wait until the rate allows sending another request.
+    pub(crate) async fn acquire(&self) {
+        let now = Instant::now();
+        let slot = {
+            let mut next_slot = self.next_slot.lock().expect("lock shouldn't be poisoned");
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + self.interval;
+            slot
+        };
+        if slot > now {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep_until(slot).await;
+        }
+    }
+
+    /// Number of requests that had to wait for the limiter so far.
+    pub(crate) fn throttled(&self) -> usize {
+        self.throttled.load(Ordering::Relaxed)
+    }
+}
+
+/// Rate limit for the requests to a single host, in the format `host=rps`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HostRate {
+    pub(crate) host: String,
+    pub(crate) requests_per_second: f64,
+}
+
+impl FromStr for HostRate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((host, requests_per_second)) = s.split_once('=') else {
+            anyhow::bail!("expected `host=rps`");
+        };
+        if host.is_empty() {
+            anyhow::bail!("host must not be empty");
+        }
+
+        Ok(Self {
+            host: host.to_owned(),
+            requests_per_second: parse_rate(requests_per_second)?,
+        })
+    }
+}
+
+/// Parse a number of requests per second, which has to be positive and finite.
+pub(crate) fn parse_rate(s: &str) -> Result<f64> {
+    let requests_per_second = s
+        .parse::<f64>()
+        .with_context(|| format!("invalid rate `{}`", s))?;
+    if requests_per_second <= 0. || !requests_per_second.is_finite() {
+        anyhow::bail!("rate must be positive and finite");
+    }
+
+    Ok(requests_per_second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_positive_and_finite() {
+        assert_eq!(parse_rate("20").unwrap(), 20.);
+        assert_eq!(parse_rate("0.5").unwrap(), 0.5);
+        for invalid in ["0", "-1", "NaN", "inf", "-inf", "fast"] {
+            assert!(parse_rate(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn host_rate_parses() {
+        assert_eq!(
+            "fragile-backend.internal=20".parse::<HostRate>().unwrap(),
+            HostRate {
+                host: "fragile-backend.internal".to_owned(),
+                requests_per_second: 20.,
+            }
+        );
+        assert!("fragile-backend.internal=NaN".parse::<HostRate>().is_err());
+        assert!("=20".parse::<HostRate>().is_err());
+    }
+
+    #[test]
+    fn tiny_rates_are_rejected() {
+        assert_eq!(
+            RateLimiter::new(1e-300).unwrap_err().to_string(),
+            "rate of 1e-300 requests per second is below the minimum of one request per day"
+        );
+        assert_eq!(
+            RateLimiter::new(1e-19).unwrap_err().to_string(),
+            "rate of 1e-19 requests per second is below the minimum of one request per day"
+        );
+        assert!(RateLimiter::new(0.9 / 86_400.).is_err());
+        assert_eq!(
+            RateLimiter::new(1. / 86_400.).unwrap().interval,
+            MAX_INTERVAL
+        );
+        assert_eq!(RateLimiter::new(1e300).unwrap().interval, Duration::ZERO);
+        assert_eq!(
+            RateLimiter::new(4.).unwrap().interval,
+            Duration::from_millis(250)
+        );
+    }
+
+    #[tokio::test]
+    async fn slowest_rate_reserves_slots() {
+        let rate_limiter = RateLimiter::new(1. / 86_400.).unwrap();
+        rate_limiter.acquire().await;
+        // The next slots are a day apart, which only have to be reserved without overflowing.
+        for _ in 0..3 {
+            let acquire = tokio::time::timeout(Duration::from_millis(10), rate_limiter.acquire());
+            assert!(acquire.await.is_err());
+        }
+        assert_eq!(rate_limiter.throttled(), 3);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Additional root certificates to trust, from `--ca-cert` or the `SSL_CERT_FILE`/`SSL_CERT_DIR` environment
+//! variables.
+
+use std::{
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use reqwest::{
+    Certificate,
+    ClientBuilder,
+};
+
+/// Add the certificates of `ca_certs` to the root store of `builder`, or, if there are none, those that
+/// `SSL_CERT_FILE` and `SSL_CERT_DIR` point to.
+///
+/// The certificates are trusted in addition to the built-in roots of the TLS backend. How well the backend itself
+/// honors the environment variables varies, which is why they are loaded explicitly.
+pub(crate) fn add_root_certificates(
+    mut builder: ClientBuilder,
+    ca_certs: &[PathBuf],
+) -> Result<ClientBuilder> {
+    let certificates = if ca_certs.is_empty() {
+        from_environment()?
+    } else {
+        let mut certificates = Vec::new();
+        for path in ca_certs {
+            certificates.extend(read_bundle(path)?);
+        }
+        certificates
+    };
+    for certificate in certificates {
+        builder = builder.add_root_certificate(certificate);
+    }
+    Ok(builder)
+}
+
+/// Certificates of the bundle `SSL_CERT_FILE` points to, and of the files in the directories, separated like
+/// `PATH`, that `SSL_CERT_DIR` points to.
+///
+/// A variable that is set to a path that doesn't exist is an error, rather than silently trusting less than
+/// expected. Files in the directories that contain no certificates are skipped, as OpenSSL does.
+fn from_environment() -> Result<Vec<Certificate>> {
+    let mut certificates = Vec::new();
+    if let Some(file) = std::env::var_os("SSL_CERT_FILE").filter(|file| !file.is_empty()) {
+        certificates.extend(read_bundle(Path::new(&file)).context("Failed to load SSL_CERT_FILE")?);
+    }
+    if let Some(dirs) = std::env::var_os("SSL_CERT_DIR") {
+        for dir in std::env::split_paths(&dirs).filter(|dir| !dir.as_os_str().is_empty()) {
+            let entries = fs::read_dir(&dir).with_context(|| {
+                format!("Failed to read SSL_CERT_DIR directory {}", dir.display())
+            })?;
+            let mut loaded = 0;
+            for entry in entries {
+                let path = entry?.path();
+                if !path.is_file() {
+                    continue;
+                }
+                match read_bundle(&path) {
+                    Ok(bundle) => {
+                        loaded += bundle.len();
+                        certificates.extend(bundle);
+                    }
+                    Err(error) => tracing::debug!("Skipping {}: {:#}", path.display(), error),
+                }
+            }
+            tracing::debug!(
+                "Loaded {} certificates from SSL_CERT_DIR directory {}",
+                loaded,
+                dir.display()
+            );
+        }
+    }
+    Ok(certificates)
+}
+
+/// Certificates of the PEM file at `path`, which may contain several of them.
+fn read_bundle(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certificates = Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Failed to parse the certificates in {}", path.display()))?;
+    if certificates.is_empty() {
+        anyhow::bail!("{} contains no PEM certificates", path.display());
+    }
+    Ok(certificates)
+}
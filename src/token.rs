@@ -0,0 +1,152 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bearer tokens fetched by an external command, with `--token-command`, and fetched anew during the replay.
+//!
+//! A token is fetched anew once it is older than `--token-ttl`, and after the target responded with `401
+//! Unauthorized` to a request that was sent with it, so that long replays stay authenticated past the lifetime of
+//! a single token.
+
+use std::time::Instant;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hifitime::Duration;
+use reqwest::{
+    header::{
+        HeaderValue,
+        AUTHORIZATION,
+    },
+    Request,
+};
+use tokio::sync::Mutex;
+
+#[derive(Debug)]
+struct Token {
+    /// Value of the `Authorization` header, marked as sensitive so that it doesn't end up in logs.
+    authorization: HeaderValue,
+    fetched: Instant,
+    /// Counts the tokens fetched so far, to tell whether a `401` was caused by the current token.
+    generation: u64,
+    /// Whether the target rejected the token, so that it is fetched anew before the next request.
+    rejected: bool,
+}
+
+/// Authorizes requests with the token printed by a command.
+#[derive(Debug)]
+pub(crate) struct TokenSource {
+    command: String,
+    ttl: Option<Duration>,
+    token: Mutex<Token>,
+}
+
+impl TokenSource {
+    /// Token source with the first token already fetched, failing if the command doesn't print one.
+    pub(crate) async fn new(command: String, ttl: Option<Duration>) -> Result<Self> {
+        let authorization = fetch(&command)
+            .await
+            .context("Failed to fetch a token with --token-command")?;
+        tracing::info!("Authorizing requests with the token printed by --token-command");
+
+        Ok(Self {
+            command,
+            ttl,
+            token: Mutex::new(Token {
+                authorization,
+                fetched: Instant::now(),
+                generation: 0,
+                rejected: false,
+            }),
+        })
+    }
+
+    /// Set the `Authorization` header of the request to the current token, fetching a new one first if it expired
+    /// or was rejected, and return the generation of the token.
+    ///
+    /// If fetching a new token fails, the previous one is used until it is due to be fetched anew again.
+    pub(crate) async fn authorize(&self, request: &mut Request) -> u64 {
+        let mut token = self.token.lock().await;
+        let expired = self
+            .ttl
+            .is_some_and(|ttl| Duration::from(token.fetched.elapsed()) >= ttl);
+        if expired || token.rejected {
+            match fetch(&self.command).await {
+                Ok(authorization) => {
+                    tracing::debug!(
+                        "Fetched a new token with --token-command, as the previous one {}",
+                        if token.rejected { "was rejected" } else { "expired" }
+                    );
+                    token.authorization = authorization;
+                    token.generation += 1;
+                }
+                Err(err) => tracing::warn!(
+                    "Failed to fetch a new token with --token-command, continuing with the previous one: {:#}",
+                    err
+                ),
+            }
+            token.fetched = Instant::now();
+            token.rejected = false;
+        }
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, token.authorization.clone());
+        token.generation
+    }
+
+    /// Fetch a new token before the next request, as the target rejected the token of `generation`. Rejections of
+    /// tokens that have already been replaced are ignored.
+    pub(crate) async fn rejected(&self, generation: u64) {
+        let mut token = self.token.lock().await;
+        if token.generation == generation {
+            token.rejected = true;
+        }
+    }
+}
+
+/// Run the command with the shell and take the token from the first line of its output.
+async fn fetch(command: &str) -> Result<HeaderValue> {
+    #[cfg(unix)]
+    let output = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .await?;
+    #[cfg(not(unix))]
+    let output = tokio::process::Command::new("cmd")
+        .arg("/C")
+        .arg(command)
+        .output()
+        .await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.trim() {
+            "" => anyhow::bail!("`{}` exited with {}", command, output.status),
+            stderr => anyhow::bail!("`{}` exited with {}: {}", command, output.status, stderr),
+        }
+    }
+    let stdout = String::from_utf8(output.stdout).context("the token is not valid UTF-8")?;
+    let token = stdout.lines().next().unwrap_or_default().trim();
+    if token.is_empty() {
+        anyhow::bail!("`{}` printed no token", command);
+    }
+    let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token))
+        .context("the token is not a valid header value")?;
+    authorization.set_sensitive(true);
+
+    Ok(authorization)
+}
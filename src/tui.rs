@@ -0,0 +1,231 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    io::{
+        self,
+        Stderr,
+    },
+    time::Instant,
+};
+
+use anyhow::Result;
+use hifitime::Duration;
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::{
+            Hide,
+            Show,
+        },
+        execute,
+        terminal::{
+            Clear,
+            ClearType,
+            EnterAlternateScreen,
+            LeaveAlternateScreen,
+        },
+    },
+    layout::{
+        Constraint,
+        Layout,
+    },
+    widgets::{
+        Block,
+        Gauge,
+        List,
+        Paragraph,
+        Sparkline,
+    },
+    Terminal,
+};
+use reqwest::StatusCode;
+
+/// Number of most recent errors shown.
+const RECENT_ERRORS: usize = 10;
+/// Number of seconds shown in the latency graph.
+const LATENCY_SECONDS: usize = 120;
+
+/// Live dashboard of a run, drawn on standard error, as the responses are written to standard output.
+pub(crate) struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stderr>>,
+    started: Instant,
+    requests: usize,
+    completed: usize,
+    errors: usize,
+    /// Completion times of the requests that completed within the last second.
+    recent_completions: VecDeque<Instant>,
+    statuses: BTreeMap<String, usize>,
+    /// Sum and number of latencies of the successful requests, in milliseconds, for every second of the run.
+    latencies: VecDeque<(u64, f64, usize)>,
+    recent_errors: VecDeque<String>,
+}
+
+impl Dashboard {
+    pub(crate) fn new(requests: usize) -> Result<Self> {
+        let mut stderr = io::stderr();
+        execute!(stderr, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        let terminal = Terminal::new(CrosstermBackend::new(stderr))?;
+
+        Ok(Self {
+            terminal,
+            started: Instant::now(),
+            requests,
+            completed: 0,
+            errors: 0,
+            recent_completions: VecDeque::new(),
+            statuses: BTreeMap::new(),
+            latencies: VecDeque::new(),
+            recent_errors: VecDeque::new(),
+        })
+    }
+
+    /// Record the outcome of a request. Requests without a status failed before receiving a response.
+    pub(crate) fn record(&mut self, status: Option<StatusCode>, required_time: Option<Duration>) {
+        let now = Instant::now();
+        self.completed += 1;
+        self.recent_completions.push_back(now);
+        let key = status
+            .map(|status| status.as_u16().to_string())
+            .unwrap_or_else(|| "error".to_owned());
+        *self.statuses.entry(key).or_default() += 1;
+        match (status, required_time) {
+            (Some(status), Some(required_time)) if crate::summary::is_success(status) => {
+                let second = now.duration_since(self.started).as_secs();
+                let milliseconds = required_time.to_seconds() * 1000.;
+                match self.latencies.back_mut() {
+                    Some((last, sum, count)) if *last == second => {
+                        *sum += milliseconds;
+                        *count += 1;
+                    }
+                    _ => {
+                        self.latencies.push_back((second, milliseconds, 1));
+                        if self.latencies.len() > LATENCY_SECONDS {
+                            self.latencies.pop_front();
+                        }
+                    }
+                }
+            }
+            _ => self.errors += 1,
+        }
+    }
+
+    /// Record the error a request failed with, to be shown among the recent errors.
+    pub(crate) fn record_error(&mut self, error: String) {
+        self.recent_errors.push_front(error);
+        self.recent_errors.truncate(RECENT_ERRORS);
+    }
+
+    pub(crate) fn draw(&mut self, in_flight: usize, paused: bool) -> Result<()> {
+        let now = Instant::now();
+        while self
+            .recent_completions
+            .front()
+            .is_some_and(|completion| now.duration_since(*completion).as_secs_f64() > 1.)
+        {
+            self.recent_completions.pop_front();
+        }
+
+        let elapsed = Duration::from(now.duration_since(self.started));
+        let overview = format!(
+            "Elapsed: {}   Requests/s: {}   In flight: {}   Completed: {}/{}   Errors: {}",
+            elapsed.approx(),
+            self.recent_completions.len(),
+            in_flight,
+            self.completed,
+            self.requests,
+            self.errors
+        );
+        let statuses = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{}: {}", status, count))
+            .collect::<Vec<_>>()
+            .join("   ");
+        let latencies = self
+            .latencies
+            .iter()
+            .map(|(_, sum, count)| (sum / *count as f64).round() as u64)
+            .collect::<Vec<_>>();
+        let latest_latency = latencies.last().copied().unwrap_or_default();
+        let progress = if self.requests == 0 {
+            1.
+        } else {
+            self.completed as f64 / self.requests as f64
+        };
+        let recent_errors = self.recent_errors.iter().map(String::as_str);
+        let title = if paused {
+            format!(
+                "r7 (paused, send SIGCONT to resume: kill -CONT {})",
+                std::process::id()
+            )
+        } else {
+            "r7".to_owned()
+        };
+
+        self.terminal.draw(|frame| {
+            let [overview_area, progress_area, statuses_area, latency_area, errors_area] =
+                Layout::vertical([
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Min(6),
+                    Constraint::Length(RECENT_ERRORS as u16 + 2),
+                ])
+                .areas(frame.area());
+            frame.render_widget(
+                Paragraph::new(overview).block(Block::bordered().title(title)),
+                overview_area,
+            );
+            frame.render_widget(
+                Gauge::default()
+                    .block(Block::bordered().title("Progress"))
+                    .ratio(progress.clamp(0., 1.)),
+                progress_area,
+            );
+            frame.render_widget(
+                Paragraph::new(statuses).block(Block::bordered().title("Status codes")),
+                statuses_area,
+            );
+            frame.render_widget(
+                Sparkline::default()
+                    .block(Block::bordered().title(format!(
+                        "Average latency of successful requests per second (last: {} ms)",
+                        latest_latency
+                    )))
+                    .data(&latencies),
+                latency_area,
+            );
+            frame.render_widget(
+                List::new(recent_errors).block(Block::bordered().title("Recent errors")),
+                errors_area,
+            );
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        // Restoring the terminal is best effort, there is nothing left to do if it fails.
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen, Show);
+    }
+}
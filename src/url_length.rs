@@ -0,0 +1,72 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Handling of URLs longer than `--max-url-length`, which targets would otherwise reject with `414 URI Too Long`.
+
+use std::io::{
+    self,
+    Write,
+};
+
+use reqwest::Url;
+
+/// What to do with the records whose URL is longer than `--max-url-length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OverLength {
+    /// Don't send the request.
+    Skip,
+    /// Drop query parameters from the end until the URL is short enough, skipping the request if that isn't enough.
+    Truncate,
+}
+
+/// Shorten `url` to at most `max_length` bytes by dropping query parameters from the end, returning whether that
+/// was enough.
+pub(crate) fn truncate(url: &mut Url, max_length: usize) -> bool {
+    while url.as_str().len() > max_length {
+        let Some(query) = url.query() else {
+            return false;
+        };
+        match query.rsplit_once('&') {
+            Some((kept, _)) => {
+                let kept = kept.to_owned();
+                url.set_query(Some(&kept));
+            }
+            None => url.set_query(None),
+        }
+    }
+    true
+}
+
+/// Number of records whose URL was longer than `--max-url-length`.
+#[derive(Debug, Default)]
+pub(crate) struct OverLengthUrls {
+    pub(crate) skipped: usize,
+    pub(crate) truncated: usize,
+}
+
+impl OverLengthUrls {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.skipped == 0 && self.truncated == 0
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W, max_length: usize) -> io::Result<()> {
+        writeln!(
+            writer,
+            "URLs longer than {} bytes: skipped={} truncated={}",
+            max_length, self.skipped, self.truncated
+        )
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transforming every request with a WebAssembly module, with `--transform-wasm`.
+//!
+//! The module exports its `memory` and two functions:
+//!
+//! - `alloc(len: i32) -> i32` returns a pointer to `len` bytes the request is written to.
+//! - `transform(ptr: i32, len: i32) -> i64` reads the request as JSON from the `len` bytes at `ptr`, and returns the
+//!   transformed request as JSON, as a pointer to it in the upper 32 bits and its length in the lower 32 bits.
+//!
+//! The request is an object with the `method`, the `url`, the `headers` as an array of name and value pairs, and the
+//! `body` as a string, or `null` if it has none or it isn't UTF-8. The transformed request has the same fields, with
+//! a `body` of `null`, or none at all, keeping the body as it is. Headers with values that aren't UTF-8 are left out,
+//! and kept unless the module sets a header of the same name. The module doesn't get any imports.
+
+use std::path::Path;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use reqwest::{
+    header::{
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+        CONTENT_LENGTH,
+    },
+    Method,
+    Request,
+    Url,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use wasmi::{
+    Engine,
+    Linker,
+    Memory,
+    Module,
+    Store,
+    TypedFunc,
+};
+
+/// A request as it is passed to and returned by the module.
+#[derive(Debug, Serialize, Deserialize)]
+struct WasmRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// An instance of the module of `--transform-wasm`.
+pub(crate) struct Transform {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32), i64>,
+}
+
+impl std::fmt::Debug for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transform").finish_non_exhaustive()
+    }
+}
+
+impl Transform {
+    /// Load and instantiate the module, in the binary or the text format.
+    pub(crate) fn from_path(path: &Path) -> Result<Self> {
+        let wasm = std::fs::read(path)
+            .with_context(|| format!("Failed to read WASM module {}", path.display()))?;
+        Self::new(&wasm).with_context(|| format!("Invalid WASM module {}", path.display()))
+    }
+
+    /// Instantiate the module from its binary or text format.
+    fn new(wasm: &[u8]) -> Result<Self> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::<()>::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .context("Failed to instantiate the module")?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .context("The module doesn't export `memory`")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .context("The module doesn't export `alloc(i32) -> i32`")?;
+        let transform = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "transform")
+            .context("The module doesn't export `transform(i32, i32) -> i64`")?;
+
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            transform,
+        })
+    }
+
+    /// Replace the method, URL, headers and body of `request` by those the module returns for it.
+    pub(crate) fn apply(&mut self, request: &mut Request) -> Result<()> {
+        let input = serde_json::to_vec(&WasmRequest {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            headers: request
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_owned()))
+                })
+                .collect(),
+            body: request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .and_then(|body| std::str::from_utf8(body).ok())
+                .map(ToOwned::to_owned),
+        })?;
+        let len = i32::try_from(input.len()).context("Request is too large for the WASM module")?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, &input)
+            .context("`alloc` of the WASM module returned memory out of bounds")?;
+        let result = self.transform.call(&mut self.store, (ptr, len))? as u64;
+        let mut output = vec![0; (result & 0xffff_ffff) as usize];
+        self.memory
+            .read(&self.store, (result >> 32) as usize, &mut output)
+            .context("`transform` of the WASM module returned memory out of bounds")?;
+        let transformed = serde_json::from_slice::<WasmRequest>(&output)
+            .context("`transform` of the WASM module returned an invalid request")?;
+
+        *request.method_mut() = Method::from_bytes(transformed.method.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Invalid method `{}`", transformed.method))?;
+        *request.url_mut() = Url::parse(&transformed.url)
+            .with_context(|| format!("Invalid URL `{}`", transformed.url))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in transformed.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid header name `{}`", name))?;
+            let mut value = HeaderValue::from_str(&value)
+                .map_err(|_| anyhow::anyhow!("Invalid value for the `{}` header", name))?;
+            // Credentials stay hidden from the logs.
+            value.set_sensitive(
+                request
+                    .headers()
+                    .get_all(&name)
+                    .iter()
+                    .any(HeaderValue::is_sensitive),
+            );
+            headers.append(name, value);
+        }
+        // Headers the module couldn't see, since they aren't UTF-8, are kept unless it set them.
+        for (name, value) in request.headers() {
+            if value.to_str().is_err() && !headers.contains_key(name) {
+                headers.append(name, value.clone());
+            }
+        }
+        if let Some(body) = transformed.body {
+            // The length is set anew for the new body.
+            headers.remove(CONTENT_LENGTH);
+            *request.body_mut() = Some(body.into());
+        }
+        *request.headers_mut() = headers;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::AUTHORIZATION;
+
+    use super::*;
+
+    /// A module returning every request as it is.
+    const IDENTITY: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 1024)
+            (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len)))))
+    "#;
+
+    fn request() -> Request {
+        let mut value = HeaderValue::from_static("Bearer secret");
+        value.set_sensitive(true);
+        reqwest::Client::new()
+            .post("http://example.com/v1/items?x=1")
+            .header(AUTHORIZATION, value)
+            .header("x-binary", HeaderValue::from_bytes(b"\xff").unwrap())
+            .body("{}")
+            .build()
+            .unwrap()
+    }
+
+    /// A module returning `json` for every request.
+    fn returning(json: &str) -> String {
+        format!(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "transform") (param i32 i32) (result i64) i64.const {}))
+            "#,
+            json.replace('"', "\\\""),
+            json.len(),
+        )
+    }
+
+    #[test]
+    fn identity_keeps_request() {
+        let mut transform = Transform::new(IDENTITY.as_bytes()).unwrap();
+        let mut request = request();
+        transform.apply(&mut request).unwrap();
+
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.url().as_str(), "http://example.com/v1/items?x=1");
+        assert!(request.headers()[AUTHORIZATION].is_sensitive());
+        assert_eq!(request.headers()["x-binary"].as_bytes(), b"\xff");
+        assert_eq!(
+            request.body().and_then(reqwest::Body::as_bytes),
+            Some(&b"{}"[..])
+        );
+    }
+
+    #[test]
+    fn replaces_request() {
+        let module = returning(concat!(
+            r#"{"method":"PUT","url":"http://example.com/v2/items","#,
+            r#""headers":[["authorization","Bearer other"],["x-new","1"]],"body":"[]"}"#,
+        ));
+        let mut transform = Transform::new(module.as_bytes()).unwrap();
+        let mut request = request();
+        transform.apply(&mut request).unwrap();
+
+        assert_eq!(request.method(), Method::PUT);
+        assert_eq!(request.url().as_str(), "http://example.com/v2/items");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer other");
+        assert!(request.headers()[AUTHORIZATION].is_sensitive());
+        assert_eq!(request.headers()["x-new"], "1");
+        assert_eq!(request.headers()["x-binary"].as_bytes(), b"\xff");
+        assert_eq!(
+            request.body().and_then(reqwest::Body::as_bytes),
+            Some(&b"[]"[..])
+        );
+    }
+
+    #[test]
+    fn missing_body_keeps_body() {
+        let module = returning(r#"{"method":"GET","url":"http://example.com/","headers":[]}"#);
+        let mut transform = Transform::new(module.as_bytes()).unwrap();
+        let mut request = request();
+        transform.apply(&mut request).unwrap();
+
+        assert_eq!(request.method(), Method::GET);
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+        assert_eq!(
+            request.body().and_then(reqwest::Body::as_bytes),
+            Some(&b"{}"[..])
+        );
+    }
+
+    #[test]
+    fn rejects_missing_exports() {
+        let error = Transform::new(br#"(module (memory (export "memory") 1))"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The module doesn't export `alloc(i32) -> i32`"
+        );
+    }
+}
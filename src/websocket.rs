@@ -0,0 +1,98 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::hash::{
+    BuildHasher,
+    RandomState,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use reqwest::{
+    header::{
+        HeaderValue,
+        CONNECTION,
+        SEC_WEBSOCKET_KEY,
+        SEC_WEBSOCKET_VERSION,
+        UPGRADE,
+    },
+    RequestBuilder,
+    Url,
+};
+
+/// How requests that originally were WebSocket upgrades are replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum WebSocketMode {
+    /// Don't replay them.
+    Skip,
+    /// Send them as plain HTTP GET-requests, without asking for an upgrade.
+    AsHttp,
+    /// Perform the opening handshake, and close the connection as soon as the response has been received.
+    Handshake,
+}
+
+impl WebSocketMode {
+    /// Prepare the request for an upgrade request, according to the mode.
+    pub(crate) fn apply(self, builder: RequestBuilder) -> RequestBuilder {
+        match self {
+            WebSocketMode::Skip | WebSocketMode::AsHttp => builder,
+            WebSocketMode::Handshake => builder
+                .header(CONNECTION, HeaderValue::from_static("Upgrade"))
+                .header(UPGRADE, HeaderValue::from_static("websocket"))
+                .header(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"))
+                .header(SEC_WEBSOCKET_KEY, random_key()),
+        }
+    }
+}
+
+/// Replace the `ws` or `wss` scheme of a URL with `http` or `https`, which can be requested.
+pub(crate) fn http_url(mut url: Url) -> Result<Url> {
+    let scheme = match url.scheme() {
+        "ws" => "http",
+        "wss" => "https",
+        _ => return Ok(url),
+    };
+    url.set_scheme(scheme)
+        .map_err(|()| anyhow::anyhow!("Can't replace the scheme of {}", url))?;
+
+    Ok(url)
+}
+
+/// Random `Sec-WebSocket-Key`, the base64 encoding of 16 random bytes, as required by RFC 6455, section 4.1.
+fn random_key() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let random = RandomState::new();
+    let bytes = [random.hash_one(0), random.hash_one(1)]
+        .iter()
+        .flat_map(|random| random.to_be_bytes())
+        .collect::<Vec<_>>();
+    let mut key = String::with_capacity(24);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | u32::from(*byte) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                key.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                key.push('=');
+            }
+        }
+    }
+
+    key
+}
@@ -0,0 +1,118 @@
+// Copyright 2024 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writing records in the formats `r7` reads, for `convert` and `record`.
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde_json::Value;
+
+use crate::InputFormat;
+
+/// Writes records as CSV with a fixed set of columns, or as newline-delimited JSON objects with the fields under
+/// `_source`, like the exports `r7` reads.
+pub(crate) enum RecordWriter {
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+    Json(Box<dyn Write>),
+}
+
+impl RecordWriter {
+    /// Writer to `output`, or to standard output, in `format` or the format given by the extension of `output`.
+    ///
+    /// For CSV the header with the `columns` is written right away.
+    pub(crate) fn new(
+        format: Option<InputFormat>,
+        output: Option<&Path>,
+        columns: &[&str],
+    ) -> Result<Self> {
+        let format = match (format, output) {
+            (Some(format), _) => format,
+            (None, Some(output)) => match output.extension().and_then(|ext| ext.to_str()) {
+                Some("csv") => InputFormat::Csv,
+                Some("json") => InputFormat::Json,
+                _ => anyhow::bail!(
+                    "Can't determine the output format of {}, use --output-format",
+                    output.display()
+                ),
+            },
+            (None, None) => {
+                anyhow::bail!("--output-format is required when writing to standard output")
+            }
+        };
+        let output: Box<dyn Write> = match output {
+            Some(output) => Box::new(io::BufWriter::new(
+                File::create(output)
+                    .with_context(|| format!("Failed to create {}", output.display()))?,
+            )),
+            None => Box::new(io::BufWriter::new(io::stdout())),
+        };
+
+        Ok(match format {
+            InputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(output);
+                writer.write_record(columns)?;
+                RecordWriter::Csv(Box::new(writer))
+            }
+            InputFormat::Json => RecordWriter::Json(output),
+            InputFormat::Combined => {
+                anyhow::bail!("Records can't be written as a combined log, use csv or json")
+            }
+        })
+    }
+
+    /// Write a record, as pairs of the column and its value, in the order of the columns. `null` values are written
+    /// as empty CSV fields, and left out of JSON objects.
+    pub(crate) fn write(&mut self, fields: Vec<(&str, Value)>) -> Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => {
+                writer.write_record(fields.into_iter().map(|(_, field)| match field {
+                    Value::Null => String::new(),
+                    Value::String(field) => field,
+                    field => field.to_string(),
+                }))?;
+            }
+            RecordWriter::Json(writer) => {
+                let source = fields
+                    .into_iter()
+                    .filter(|(_, field)| !field.is_null())
+                    .map(|(column, field)| (column.to_owned(), field))
+                    .collect::<serde_json::Map<_, _>>();
+                serde_json::to_writer(&mut *writer, &serde_json::json!({ "_source": source }))?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer.flush()?,
+            RecordWriter::Json(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}